@@ -0,0 +1,21 @@
+#include <stdio.h>
+
+// Crashes the first time it is run with a given marker file, and exits cleanly on later runs
+// (once the file exists), like an intermittent crash that goes away when the program is rerun
+int main(int argc, char *argv[]) {
+    if (argc < 2) {
+        printf("Usage: %s <marker file>\n", argv[0]);
+        return 2;
+    }
+    FILE *marker = fopen(argv[1], "r");
+    if (marker == NULL) {
+        marker = fopen(argv[1], "w");
+        fclose(marker);
+        printf("First run: crashing\n");
+        fflush(stdout);
+        *(volatile int *)0 = 1;
+    }
+    fclose(marker);
+    printf("Marker file exists: exiting cleanly\n");
+    return 0;
+}
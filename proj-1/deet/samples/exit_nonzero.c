@@ -0,0 +1,6 @@
+#include <stdio.h>
+
+int main() {
+    printf("Exiting with status 3\n");
+    return 3;
+}
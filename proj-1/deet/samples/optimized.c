@@ -0,0 +1,18 @@
+#include <stdio.h>
+
+__attribute__((noinline)) int leaf(int n) {
+    int total = 0;
+    for (int i = 0; i < n; i++) {
+        total += i * i;
+    }
+    return total;
+}
+
+__attribute__((noinline)) int middle(int n) {
+    return leaf(n) + leaf(n / 2);
+}
+
+int main(int argc, char *argv[]) {
+    printf("%d\n", middle(argc * 1000));
+    return 0;
+}
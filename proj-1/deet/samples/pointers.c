@@ -0,0 +1,18 @@
+#include <stdio.h>
+
+int counter = 40;
+int *counter_ptr = &counter;
+
+void report(int *p, int count) {
+    printf("total = %d\n", *p * count);
+}
+
+int main() {
+    int x = 7;
+    int *ptr = &x;
+    int **ptr_ptr = &ptr;
+    const char *name = "deet";
+    report(*ptr_ptr, 3);
+    printf("%s\n", name);
+    return 0;
+}
@@ -0,0 +1,20 @@
+#include <stdio.h>
+
+// Writes each of its arguments after the first on its own line, to the file named by the first
+// argument (so tests can check exactly which argv the program received)
+int main(int argc, char *argv[]) {
+    if (argc < 2) {
+        fprintf(stderr, "usage: %s <output file> [args...]\n", argv[0]);
+        return 2;
+    }
+    FILE *out = fopen(argv[1], "w");
+    if (out == NULL) {
+        perror("fopen");
+        return 2;
+    }
+    for (int i = 2; i < argc; i++) {
+        fprintf(out, "%s\n", argv[i]);
+    }
+    fclose(out);
+    return 0;
+}
@@ -0,0 +1,19 @@
+#include <stdio.h>
+#include <stdlib.h>
+#include <unistd.h>
+
+// Prints $DEET_SAMPLE_VAR and the working directory, to the file named by the first argument if
+// there is one (so tests can check what the program saw) or else to stdout
+int main(int argc, char *argv[]) {
+    FILE *out = argc > 1 ? fopen(argv[1], "w") : stdout;
+    if (out == NULL) {
+        perror("fopen");
+        return 2;
+    }
+    const char *value = getenv("DEET_SAMPLE_VAR");
+    char cwd[4096];
+    fprintf(out, "DEET_SAMPLE_VAR=%s\n", value != NULL ? value : "(unset)");
+    fprintf(out, "cwd=%s\n", getcwd(cwd, sizeof(cwd)) != NULL ? cwd : "(unknown)");
+    fclose(out);
+    return 0;
+}
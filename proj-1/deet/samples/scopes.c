@@ -0,0 +1,21 @@
+#include <stdio.h>
+
+int counter = 0;
+
+// Variables declared in nested blocks, so tests can check which ones are in scope where
+int main() {
+    int outer = 1;
+    for (int i = 0; i < 2; i++) {
+        int loop_local = outer + i;
+        {
+            int inner = loop_local * 2;
+            counter += inner;
+        }
+        counter += loop_local;
+    }
+    {
+        int outer = counter;
+        printf("%d\n", outer);
+    }
+    return 0;
+}
@@ -1,8 +1,17 @@
 use crate::debugger_command::DebuggerCommand;
-use crate::inferior::{Inferior};
+use crate::inferior::{Frame, Inferior, LaunchEnvironment, Status};
 use crate::dwarf_data::{DwarfData, Error as DwarfError};
+use crate::expression;
+use crate::value_format::{format_value, ValueFormat};
 use rustyline::error::ReadlineError;
-use rustyline::Editor;
+use nix::sys::signal::Signal;
+use rustyline::{Config, Editor};
+use std::path::Path;
+
+/// Number of instructions printed by `disas`
+const DISASSEMBLE_INSTRUCTIONS: usize = 16;
+/// Maximum number of commands kept in ~/.deet_history; older ones are dropped
+const HISTORY_MAX_LEN: usize = 1000;
 
 pub struct Debugger {
     target: String,
@@ -11,6 +20,17 @@ pub struct Debugger {
     inferior: Option<Inferior>,
     debug_data: Option<DwarfData>,
     breakpoints: Vec<usize>,
+    /// The stack frame selected with up/down (0 is the innermost). Reset whenever the inferior runs.
+    current_frame: usize,
+    /// Arguments of the most recent `run`, reused by `restart` and by `run` with no arguments
+    last_run_args: Option<Vec<String>>,
+    /// How many times to rerun the program after it crashes (--rerun-on-crash; 0 = never)
+    rerun_on_crash: usize,
+    /// Reruns left for the current `run`
+    reruns_left: usize,
+    /// Working directory and environment changes from `set cwd`/`set env`/`unset env`, used by
+    /// every later run
+    launch: LaunchEnvironment,
 }
 
 fn parse_address(addr: &str) -> Option<usize> {
@@ -22,9 +42,80 @@ fn parse_address(addr: &str) -> Option<usize> {
     usize::from_str_radix(addr_without_0x, 16).ok()
 }
 
+/// Creates the line editor used to read commands. History is capped at HISTORY_MAX_LEN entries and
+/// a command identical to the previous one isn't added again.
+fn new_editor() -> Editor<()> {
+    let config = Config::builder()
+        .max_history_size(HISTORY_MAX_LEN)
+        .history_ignore_dups(true)
+        .build();
+    Editor::with_config(config)
+}
+
+/// Adds a command line to the history, returning whether it was added. Blank lines are skipped,
+/// as are consecutive duplicates.
+fn add_to_history(readline: &mut Editor<()>, line: &str) -> bool {
+    if line.trim().is_empty() {
+        return false;
+    }
+    readline.add_history_entry(line)
+}
+
+/// What commands that need a running program print when there isn't one
+const NO_INFERIOR: &str = "No inferior process running. Use 'run' first.";
+
+/// The inferior for a command that needs one (pass `self.inferior.as_ref()` or `.as_mut()`), or
+/// the message to print if the program hasn't been started.
+fn require_inferior<T>(inferior: Option<T>) -> Result<T, &'static str> {
+    inferior.ok_or(NO_INFERIOR)
+}
+
+/// Prints a stack frame for up/down/info frame
+fn print_frame(index: usize, frame: &Frame) {
+    println!("Frame {}: rip = {:#x}, rbp = {:#x}", index, frame.rip, frame.rbp);
+    let function = frame.function.as_deref().unwrap_or("??");
+    match &frame.line {
+        Some(line) => println!("  in {} ({}:{})", function, line.file, line.number),
+        None => println!("  in {}", function),
+    }
+}
+
+/// Signals that mean the program crashed, rather than being stopped to be looked at
+const CRASH_SIGNALS: [Signal; 5] = [
+    Signal::SIGSEGV,
+    Signal::SIGBUS,
+    Signal::SIGFPE,
+    Signal::SIGILL,
+    Signal::SIGABRT,
+];
+
+/// What DEET prints once it has loaded the target's debugging symbols
+fn startup_message(target: &str, debug_data: &DwarfData, verbose: bool) -> String {
+    let mut message = format!("Reading symbols from {}\n", target);
+    if verbose {
+        message += &debug_data.symbol_dump();
+    }
+    message
+}
+
+/// What DEET prints when the inferior exits or is killed by a signal, or None if it only stopped.
+/// Exit codes other than 0 are flagged as abnormal, and signals are shown by name and number.
+fn exit_message(status: &Status) -> Option<String> {
+    match status {
+        Status::Stopped(..) => None,
+        Status::Exited(0) => Some(String::from("Child exited (status 0)")),
+        Status::Exited(exit_code) => Some(format!("Child exited abnormally (status {})", exit_code)),
+        Status::Signaled(signal) => {
+            let name: &str = signal.as_ref();
+            Some(format!("Child terminated by {} (signal {})", name, *signal as i32))
+        }
+    }
+}
+
 impl Debugger {
-    /// Initializes the debugger.
-    pub fn new(target: &str) -> Debugger {
+    /// Initializes the debugger. If `verbose` is set, dumps all of the target's debugging symbols
+    /// at startup (the same dump `info symbols` prints).
+    pub fn new(target: &str, verbose: bool) -> Debugger {
         let debug_data = match DwarfData::from_file(target) {
             Ok(val) => val,
             Err(DwarfError::ErrorOpeningFile) => {
@@ -37,11 +128,10 @@ impl Debugger {
             }
         };
         
-        // Print debug information at startup
-        debug_data.print();
+        print!("{}", startup_message(target, &debug_data, verbose));
         
         let history_path = format!("{}/.deet_history", std::env::var("HOME").unwrap());
-        let mut readline = Editor::<()>::new();
+        let mut readline = new_editor();
         // Attempt to load history from ~/.deet_history if it exists
         let _ = readline.load_history(&history_path);
 
@@ -52,176 +142,253 @@ impl Debugger {
             inferior: None,
             debug_data: Some(debug_data),
             breakpoints: Vec::new(),
+            current_frame: 0,
+            last_run_args: None,
+            rerun_on_crash: 0,
+            reruns_left: 0,
+            launch: LaunchEnvironment::default(),
         }
     }
 
+    /// Makes `run` restart the program, with the same arguments and breakpoints, up to `times`
+    /// times when it crashes
+    pub fn set_rerun_on_crash(&mut self, times: usize) {
+        self.rerun_on_crash = times;
+    }
+
     pub fn run(&mut self) {
         loop {
-            match self.get_next_command() {
+            let command = self.get_next_command();
+            // Any command that lets the inferior run invalidates the selected frame
+            if matches!(
+                command,
+                DebuggerCommand::Continue
+                    | DebuggerCommand::Advance(_)
+                    | DebuggerCommand::StepInstruction
+            ) {
+                self.current_frame = 0;
+            }
+            match command {
                 DebuggerCommand::Run(args) => {
-                    // Kill any existing inferior process before starting a new one
-                    if let Some(ref mut inferior) = self.inferior {
-                        let _ = inferior.kill();
+                    // Like gdb, `run` with no arguments reuses the previous run's arguments
+                    let args = match (&self.last_run_args, args.is_empty()) {
+                        (Some(last_args), true) => last_args.clone(),
+                        _ => args,
+                    };
+                    self.reruns_left = self.rerun_on_crash;
+                    if let Some(status) = self.start_inferior(args) {
+                        self.report_status(status);
                     }
-                    
-                    if let Some(inferior) = Inferior::new(&self.target, &args,&self.breakpoints) {
-                        // Create the inferior
-                        self.inferior = Some(inferior);
-                        
-                        // Continue the inferior and print its status
-                        match self.inferior.as_mut().unwrap().cont() {
-                            Ok(status) => {
-                                match status {
-                                    crate::inferior::Status::Stopped(signal, rip) => {
-                                        println!("Child stopped (signal {})", signal);
-                                        if let Some(debug_data) = &self.debug_data {
-                                            let line = match debug_data.get_line_from_addr(rip) {
-                                                Some(line) => line,
-                                                None => {
-                                                    println!("Unknown function");
-                                                    break;
-                                                }
-                                            };
-                                            println!("Stopped at {}",line);
-                                        }
-                                    }
-                                    crate::inferior::Status::Exited(exit_code) => {
-                                        println!("Child exited (status {})", exit_code);
-                                    }
-                                    crate::inferior::Status::Signaled(signal) => {
-                                        println!("Child terminated (signal {})", signal);
-                                    }
-                                }
-                            }
-                            Err(err) => {
-                                println!("Error continuing inferior: {}", err);
-                            }
-                        }
+                }
+
+                DebuggerCommand::Restart => {
+                    if self.last_run_args.is_none() {
+                        println!("The program has not been run yet; use run");
                     } else {
-                        println!("Error starting subprocess");
+                        self.reruns_left = self.rerun_on_crash;
+                        if let Some(status) = self.restart() {
+                            self.report_status(status);
+                        }
                     }
                 }
                 
                 DebuggerCommand::Continue => {
-                    // Check if there is an inferior process running
-                    if let Some(ref mut inferior) = self.inferior {
-                        // Continue the inferior and print its status
-                        match inferior.cont() {
-                            Ok(status) => {
-                                match status {
-                                    crate::inferior::Status::Stopped(signal, rip) => {
-                                        println!("Child stopped (signal {})", signal);
-                                        if let Some(debug_data) = &self.debug_data {
-                                            if let Some(line) = debug_data.get_line_from_addr(rip) {
-                                                println!("Stopped at {}", line);
-                                            }
-                                        }
-                                    }
-                                    crate::inferior::Status::Exited(exit_code) => {
-                                        println!("Child exited (status {})", exit_code);
-                                    }
-                                    crate::inferior::Status::Signaled(signal) => {
-                                        println!("Child terminated (signal {})", signal);
-                                    }
-                                }
-                            }
+                    // Continue the inferior and print its status
+                    match require_inferior(self.inferior.as_mut()) {
+                        Ok(inferior) => match inferior.cont() {
+                            Ok(status) => self.report_status(status),
                             Err(err) => {
                                 println!("Error continuing inferior: {}", err);
                             }
-                        }
-                    } else {
-                        println!("No inferior process running");
+                        },
+                        Err(message) => println!("{}", message),
                     }
                 }
 
-               DebuggerCommand::Backtrace => {
-                    // return ;
-                    if let Some(inferior) = &self.inferior {
-                        if let Some(debug_data) = & self.debug_data{
-                            let _ = inferior.print_backtrace(debug_data);
+                DebuggerCommand::Advance(target) => match self.advance(&target) {
+                    Ok(status) => {
+                        if !matches!(status, Status::Stopped(..)) {
+                            println!("The program is no longer running; it never reached {}", target);
                         }
-                    }                
+                        self.report_status(status);
+                    }
+                    Err(message) => println!("{}", message),
+                },
+
+                DebuggerCommand::Backtrace => {
+                    if let Err(message) = self.print_backtrace() {
+                        println!("{}", message);
+                    }
                 }
 
-                DebuggerCommand::Print => {
-                    // Print variables at current location
-                    if let Some(inferior) = &self.inferior {
-                        if let Some(debug_data) = &self.debug_data {
-                            match inferior.print_variables(debug_data) {
-                                Ok(_) => {},
-                                Err(e) => {
-                                    println!("Error printing variables: {}", e);
-                                }
-                            }
-                        } else {
-                            println!("No debug information available");
+                DebuggerCommand::Print(format, Some(expression)) => {
+                    match self.print_expression(&expression, format) {
+                        Ok(line) => println!("{}", line),
+                        Err(message) => println!("{}", message),
+                    }
+                }
+
+                DebuggerCommand::Print(format, None) => {
+                    // Print all variables at current location
+                    let inferior = match require_inferior(self.inferior.as_ref()) {
+                        Ok(inferior) => inferior,
+                        Err(message) => {
+                            println!("{}", message);
+                            continue;
+                        }
+                    };
+                    if let Some(debug_data) = &self.debug_data {
+                        if let Err(e) = inferior.print_variables(debug_data, self.current_frame, format) {
+                            println!("Error printing variables: {}", e);
                         }
                     } else {
-                        println!("No inferior process running");
+                        println!("No debug information available");
                     }
                 }
                 
-                DebuggerCommand::Break(target) => {
-                    let addr = if target.starts_with('*') {
-                        // Raw address (starts with *)
-                        let addr_str = &target[1..];
-                        match parse_address(addr_str) {
-                            Some(addr) => Some(addr),
-                            None => {
-                                println!("Invalid address format: {}", addr_str);
-                                continue;
-                            }
+                DebuggerCommand::Break(targets) => {
+                    // The messages already name the target that couldn't be resolved
+                    for (_, message) in self.set_breakpoints(&targets) {
+                        println!("{}", message);
+                    }
+                }
+
+                DebuggerCommand::SaveBreakpoints(path) => match self.save_breakpoints(&path) {
+                    Ok(count) => println!("Saved {} breakpoints to {}", count, path),
+                    Err(err) => println!("Could not write {}: {}", path, err),
+                },
+
+                DebuggerCommand::Source(path) => match self.source_breakpoints(&path) {
+                    Ok(missing) => {
+                        for (location, message) in missing {
+                            println!("Skipping saved breakpoint {}: {}", location, message);
                         }
-                    } else if let Ok(line_number) = target.parse::<usize>() {
-                        // Line number
-                        if let Some(debug_data) = &self.debug_data {
-                            match debug_data.get_addr_for_line(None, line_number) {
-                                Some(addr) => Some(addr),
-                                None => {
-                                    println!("No code found at line {}", line_number);
-                                    continue;
-                                }
-                            }
-                        } else {
-                            println!("No debug information available");
+                    }
+                    Err(err) => println!("Could not read {}: {}", path, err),
+                },
+
+                command @ (DebuggerCommand::SetCwd(_)
+                | DebuggerCommand::SetEnv(..)
+                | DebuggerCommand::UnsetEnv(_)) => {
+                    println!("{}", self.update_launch(command));
+                }
+
+                DebuggerCommand::InfoLine(target) => {
+                    if !target.starts_with('*') {
+                        println!("Usage: info line *<address>");
+                        continue;
+                    }
+                    let addr = match parse_address(&target[1..]) {
+                        Some(addr) => addr,
+                        None => {
+                            println!("Invalid address format: {}", &target[1..]);
                             continue;
                         }
+                    };
+                    if let Some(debug_data) = &self.debug_data {
+                        match debug_data.get_location_string(addr) {
+                            Some(location) => println!("{:#x} is at {}", addr, location),
+                            None => println!("No line info for {:#x}", addr),
+                        }
                     } else {
-                        // Function name
-                        if let Some(debug_data) = &self.debug_data {
-                            match debug_data.get_addr_for_function(None, &target) {
-                                Some(addr) => Some(addr),
-                                None => {
-                                    println!("Function '{}' not found", target);
-                                    continue;
-                                }
-                            }
-                        } else {
-                            println!("No debug information available");
+                        println!("No debug information available");
+                    }
+                }
+
+                DebuggerCommand::StepInstruction => {
+                    let inferior = match require_inferior(self.inferior.as_mut()) {
+                        Ok(inferior) => inferior,
+                        Err(message) => {
+                            println!("{}", message);
                             continue;
                         }
                     };
+                    // Disassemble the instruction before executing it, so we can show what ran
+                    let executed = inferior.disassemble_at_rip(1).ok().and_then(|mut v| v.pop());
+                    match inferior.step_instruction() {
+                        Ok(crate::inferior::Status::Stopped(_, rip)) => {
+                            if let Some(instruction) = executed {
+                                println!("{:#x}:  {}", instruction.address, instruction.text);
+                            }
+                            let line = self
+                                .debug_data
+                                .as_ref()
+                                .and_then(|debug_data| debug_data.get_line_from_addr(rip));
+                            match line {
+                                Some(line) => println!("Stopped at {:#x} ({})", rip, line),
+                                None => println!("Stopped at {:#x}", rip),
+                            }
+                        }
+                        Ok(status) => {
+                            if let Some(message) = exit_message(&status) {
+                                println!("{}", message);
+                            }
+                        }
+                        Err(err) => {
+                            println!("Error stepping inferior: {}", err);
+                        }
+                    }
+                }
 
-                    if let Some(addr) = addr {
-                        // Add to breakpoints list
-                        self.breakpoints.push(addr);
-                        let breakpoint_num = self.breakpoints.len() - 1;
-                        println!("Set breakpoint {} at {:#x}", breakpoint_num, addr);
-                        
-                        // If there's a running inferior, install the breakpoint immediately
-                        if let Some(ref mut inferior) = self.inferior {
-                            match inferior.install_breakpoint(addr) {
-                                Ok(orig_byte) => {
-                                    println!("Installed breakpoint at {:#x} (original byte: {:#x})", addr, orig_byte);
-                                }
-                                Err(e) => {
-                                    eprintln!("Failed to install breakpoint at {:#x}: {}", addr, e);
-                                }
+                DebuggerCommand::Up | DebuggerCommand::Down | DebuggerCommand::InfoFrame => {
+                    let (inferior, debug_data) =
+                        match (require_inferior(self.inferior.as_ref()), &self.debug_data) {
+                            (Ok(inferior), Some(debug_data)) => (inferior, debug_data),
+                            (Err(message), _) => {
+                                println!("{}", message);
+                                continue;
+                            }
+                            (_, None) => {
+                                println!("No debug information available");
+                                continue;
                             }
+                        };
+                    let new_frame = match command {
+                        DebuggerCommand::Up => self.current_frame + 1,
+                        DebuggerCommand::Down if self.current_frame == 0 => {
+                            println!("Bottom (innermost) frame selected; you cannot go down.");
+                            continue;
                         }
+                        DebuggerCommand::Down => self.current_frame - 1,
+                        _ => self.current_frame,
+                    };
+                    match inferior.frame(debug_data, new_frame) {
+                        Ok(Some(frame)) => {
+                            self.current_frame = new_frame;
+                            print_frame(new_frame, &frame);
+                        }
+                        Ok(None) => println!("Initial frame selected; you cannot go up."),
+                        Err(e) => println!("Error reading stack frame: {}", e),
                     }
                 }
 
+                DebuggerCommand::InfoProc => match self.proc_info() {
+                    Ok(info) => print!("{}", info),
+                    Err(message) => println!("{}", message),
+                },
+
+                DebuggerCommand::InfoScope => match self.scope_info() {
+                    Ok(info) => print!("{}", info),
+                    Err(message) => println!("{}", message),
+                },
+
+                DebuggerCommand::InfoSymbols => {
+                    if let Some(debug_data) = &self.debug_data {
+                        debug_data.print();
+                    } else {
+                        println!("No debug information available");
+                    }
+                }
+
+                DebuggerCommand::Disassemble => match require_inferior(self.inferior.as_ref()) {
+                    Ok(inferior) => {
+                        if let Err(e) = inferior.print_disassembly(DISASSEMBLE_INSTRUCTIONS) {
+                            println!("Error disassembling: {}", e);
+                        }
+                    }
+                    Err(message) => println!("{}", message),
+                },
+
                 DebuggerCommand::Quit => {
                     // Kill any existing inferior process before quitting
                     if let Some(ref mut inferior) = self.inferior {
@@ -233,8 +400,353 @@ impl Debugger {
         }
     }
 
+    /// Prints the backtrace for `bt`, or returns the message to print instead if there is no
+    /// inferior or no debug information.
+    fn print_backtrace(&self) -> Result<(), String> {
+        let inferior = require_inferior(self.inferior.as_ref())?;
+        let debug_data = self.debug_data.as_ref().ok_or("No debug information available")?;
+        inferior
+            .print_backtrace(debug_data)
+            .map_err(|err| format!("Error reading backtrace: {}", err))
+    }
+
+    /// What `print <expression>` prints (`sum - a = 5`), evaluated in the selected stack frame.
+    /// Returns the message to print instead if the expression can't be evaluated.
+    fn print_expression(&self, expression: &str, format: ValueFormat) -> Result<String, String> {
+        let inferior = require_inferior(self.inferior.as_ref())?;
+        let debug_data = self.debug_data.as_ref().ok_or("No debug information available")?;
+        let parsed = expression::parse(expression)?;
+        let value = expression::evaluate(&parsed, &inferior.frame_context(debug_data, self.current_frame))?;
+        Ok(format!(
+            "{} = {}",
+            expression,
+            format_value(&value.bytes, &value.value_type.name, format)
+        ))
+    }
+
+    /// What `info proc` prints: the inferior's pid, whether it is running, stopped or has exited,
+    /// and the program and arguments it was started with. Returns the message to print instead if
+    /// there is no inferior.
+    fn proc_info(&self) -> Result<String, &'static str> {
+        let inferior = require_inferior(self.inferior.as_ref())?;
+        let args = self.last_run_args.as_deref().unwrap_or(&[]);
+        Ok(format!(
+            "process {}\n  status: {}\n  exe: {}\n  args: {:?}\n",
+            inferior.pid(),
+            inferior.run_state(),
+            self.target,
+            args
+        ))
+    }
+
+    /// What `info scope` prints: a `name: type` line for each variable in lexical scope where the
+    /// selected frame is stopped, locals before globals. Nothing is read from the inferior's
+    /// memory.
+    fn scope_info(&self) -> Result<String, String> {
+        let inferior = require_inferior(self.inferior.as_ref())?;
+        let debug_data = self.debug_data.as_ref().ok_or("No debug information available")?;
+        let frame = match inferior.frame(debug_data, self.current_frame) {
+            Ok(Some(frame)) => frame,
+            Ok(None) => return Err(String::from("No stack frame selected")),
+            Err(e) => return Err(format!("Error reading stack frame: {}", e)),
+        };
+        // A caller's rip is the return address, which may already be past the end of the block
+        // holding the call
+        let addr = if self.current_frame == 0 { frame.rip } else { frame.rip - 1 };
+        let vars = debug_data
+            .get_variables_in_scope(addr)
+            .ok_or("No symbol table info available.")?;
+        if vars.is_empty() {
+            return Ok(String::from("No variables in scope.\n"));
+        }
+        let mut out = String::new();
+        for var in vars {
+            out.push_str(&format!("{}: {}\n", var.name, var.entity_type.name));
+        }
+        Ok(out)
+    }
+
+    /// Kills any existing inferior, then starts the target with the given arguments and the current
+    /// breakpoints and continues it until it stops. Returns the status it stopped with, or None if
+    /// it couldn't be started or continued (after printing why).
+    fn start_inferior(&mut self, args: Vec<String>) -> Option<Status> {
+        // Kill any existing inferior process before starting a new one
+        if let Some(ref mut inferior) = self.inferior {
+            let _ = inferior.kill();
+        }
+        self.current_frame = 0;
+
+        let inferior = match Inferior::new(&self.target, &args, &self.breakpoints, &self.launch) {
+            Some(inferior) => self.inferior.insert(inferior),
+            None => {
+                println!("Error starting subprocess");
+                self.inferior = None;
+                return None;
+            }
+        };
+        self.last_run_args = Some(args);
+
+        match inferior.cont() {
+            Ok(status) => Some(status),
+            Err(err) => {
+                println!("Error continuing inferior: {}", err);
+                None
+            }
+        }
+    }
+
+    /// Resolves a breakpoint target (`*<address>`, `<line>`, `<file>:<line>`, or `<function>`)
+    /// to an address. On failure, returns the message to show the user.
+    fn resolve_breakpoint(&self, target: &str) -> Result<usize, String> {
+        if let Some(addr_str) = target.strip_prefix('*') {
+            return parse_address(addr_str)
+                .ok_or_else(|| format!("Invalid address format: {}", addr_str));
+        }
+        let debug_data = self
+            .debug_data
+            .as_ref()
+            .ok_or_else(|| String::from("No debug information available"))?;
+        let file_and_line = target
+            .rsplit_once(':')
+            .and_then(|(file, line)| Some((file, line.parse::<usize>().ok()?)));
+        if let Ok(line_number) = target.parse::<usize>() {
+            debug_data
+                .get_addr_for_line(None, line_number)
+                .ok_or_else(|| format!("No code found at line {}", line_number))
+        } else if let Some((file, line_number)) = file_and_line {
+            debug_data
+                .get_addr_for_line(Some(file), line_number)
+                .ok_or_else(|| format!("No code found at {}:{}", file, line_number))
+        } else {
+            debug_data
+                .get_addr_for_function(None, target)
+                .ok_or_else(|| format!("Function '{}' not found", target))
+        }
+    }
+
+    /// Continues the inferior until it reaches `target` (anything `break` accepts), using a
+    /// temporary breakpoint there unless one is already set. The temporary breakpoint is removed
+    /// as soon as the inferior stops, even if it stopped somewhere else first. Returns the status
+    /// the inferior stopped with, or the message to show the user.
+    fn advance(&mut self, target: &str) -> Result<Status, String> {
+        let addr = self.resolve_breakpoint(target)?;
+        let temporary = !self.breakpoints.contains(&addr);
+        let inferior = require_inferior(self.inferior.as_mut())?;
+        if temporary {
+            inferior
+                .install_breakpoint(addr)
+                .map_err(|e| format!("Failed to install breakpoint at {:#x}: {}", addr, e))?;
+        }
+        let status = inferior
+            .cont()
+            .map_err(|e| format!("Error continuing inferior: {}", e))?;
+        // Once the inferior has exited there is no memory left to restore
+        if temporary && matches!(status, Status::Stopped(..)) {
+            inferior
+                .remove_breakpoint(addr)
+                .map_err(|e| format!("Failed to remove breakpoint at {:#x}: {}", addr, e))?;
+        }
+        Ok(status)
+    }
+
+    /// Adds a breakpoint, installing it right away if the inferior is running
+    fn set_breakpoint(&mut self, addr: usize) {
+        self.breakpoints.push(addr);
+        let breakpoint_num = self.breakpoints.len() - 1;
+        println!("Set breakpoint {} at {:#x}", breakpoint_num, addr);
+
+        if let Some(ref mut inferior) = self.inferior {
+            match inferior.install_breakpoint(addr) {
+                Ok(orig_byte) => {
+                    println!("Installed breakpoint at {:#x} (original byte: {:#x})", addr, orig_byte);
+                }
+                Err(e) => {
+                    eprintln!("Failed to install breakpoint at {:#x}: {}", addr, e);
+                }
+            }
+        }
+    }
+
+    /// Sets a breakpoint at each target of a `break` command, in order. A target that can't be
+    /// resolved doesn't stop the others from being set; those are returned with the reason.
+    fn set_breakpoints(&mut self, targets: &[String]) -> Vec<(String, String)> {
+        let mut failed = Vec::new();
+        for target in targets {
+            match self.resolve_breakpoint(target) {
+                Ok(addr) => self.set_breakpoint(addr),
+                Err(message) => failed.push((target.clone(), message)),
+            }
+        }
+        failed
+    }
+
+    /// Describes a breakpoint address in a form that survives recompiling the target: the
+    /// function name if it's a function's entry point, otherwise `file:line`. Falls back to the
+    /// raw address if there's no debug info for it.
+    fn breakpoint_location(&self, addr: usize) -> String {
+        if let Some(debug_data) = &self.debug_data {
+            if let Some(function) = debug_data.get_function_from_addr(addr) {
+                if debug_data.get_addr_for_function(None, &function) == Some(addr) {
+                    return function;
+                }
+            }
+            if let Some(line) = debug_data.get_line_from_addr(addr) {
+                // Only the file name, so the file still matches if the source tree moves
+                let file = Path::new(&line.file)
+                    .file_name()
+                    .map_or(line.file.clone(), |name| name.to_string_lossy().to_string());
+                return format!("{}:{}", file, line.number);
+            }
+        }
+        format!("*{:#x}", addr)
+    }
+
+    /// Writes every breakpoint to `path` as a `break <location>` line. Returns how many were
+    /// written.
+    fn save_breakpoints(&self, path: &str) -> std::io::Result<usize> {
+        let contents: String = self
+            .breakpoints
+            .iter()
+            .map(|&addr| format!("break {}\n", self.breakpoint_location(addr)))
+            .collect();
+        std::fs::write(path, contents)?;
+        Ok(self.breakpoints.len())
+    }
+
+    /// Sets the breakpoints listed in a file written by `save_breakpoints`, resolving each one
+    /// against the current debug info. Blank lines and `#` comments are ignored, and the `break`
+    /// prefix is optional. Returns the locations that couldn't be resolved, with the reason.
+    fn source_breakpoints(&mut self, path: &str) -> std::io::Result<Vec<(String, String)>> {
+        let contents = std::fs::read_to_string(path)?;
+        let mut missing = Vec::new();
+        for line in contents.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let location = line
+                .strip_prefix("break ")
+                .or_else(|| line.strip_prefix("b "))
+                .unwrap_or(line)
+                .trim();
+            match self.resolve_breakpoint(location) {
+                Ok(addr) => self.set_breakpoint(addr),
+                Err(message) => missing.push((location.to_string(), message)),
+            }
+        }
+        Ok(missing)
+    }
+
+    /// Applies a `set cwd`, `set env` or `unset env` command to the environment the next run is
+    /// launched with, and returns the message to show the user.
+    fn update_launch(&mut self, command: DebuggerCommand) -> String {
+        match command {
+            DebuggerCommand::SetCwd(path) => {
+                let message = format!("Working directory for the next run: {}", path);
+                self.launch.cwd = Some(path);
+                message
+            }
+            DebuggerCommand::SetEnv(key, value) => {
+                let message = format!("Environment for the next run: {}={}", key, value);
+                self.launch.env.insert(key, Some(value));
+                message
+            }
+            DebuggerCommand::UnsetEnv(key) => {
+                let message = format!("Environment for the next run: {} unset", key);
+                self.launch.env.insert(key, None);
+                message
+            }
+            _ => unreachable!("not a set/unset command"),
+        }
+    }
+
+    /// Relaunches the target with the arguments of the previous run. Breakpoints are reinstalled
+    /// because start_inferior always passes the full breakpoint list to the new inferior.
+    fn restart(&mut self) -> Option<Status> {
+        let args = self.last_run_args.clone()?;
+        self.start_inferior(args)
+    }
+
+    /// If `status` means the program crashed (stopped by one of CRASH_SIGNALS, or killed by any
+    /// signal), describes the crash: the signal and, if the program is still there to look at, a
+    /// backtrace. Returns None for any other status.
+    fn crash_report(&self, status: &Status) -> Option<String> {
+        match status {
+            Status::Stopped(signal, rip) if CRASH_SIGNALS.contains(signal) => {
+                let mut report = format!("Child crashed with {} at {:#x}\n", signal, rip);
+                if let (Some(inferior), Some(debug_data)) = (&self.inferior, &self.debug_data) {
+                    match inferior.format_backtrace(debug_data) {
+                        Ok(backtrace) => report += &backtrace,
+                        Err(err) => report += &format!("Error reading backtrace: {}\n", err),
+                    }
+                }
+                Some(report)
+            }
+            Status::Signaled(_) => Some(format!("{}\n", exit_message(status)?)),
+            _ => None,
+        }
+    }
+
+    /// With --rerun-on-crash, restarts the program each time it crashes (printing the crash
+    /// report first) until it stops for another reason or the reruns for this `run` are used up.
+    /// Returns the status to report, or None if the program couldn't be restarted.
+    fn rerun_crashes(&mut self, mut status: Status) -> Option<Status> {
+        while self.reruns_left > 0 {
+            let report = match self.crash_report(&status) {
+                Some(report) => report,
+                None => break,
+            };
+            print!("{}", report);
+            self.reruns_left -= 1;
+            println!(
+                "Rerunning after crash ({} of {})",
+                self.rerun_on_crash - self.reruns_left,
+                self.rerun_on_crash
+            );
+            status = self.restart()?;
+        }
+        Some(status)
+    }
+
+    /// Reports the status the program stopped with after run/continue, rerunning it first if it
+    /// crashed and --rerun-on-crash allows
+    fn report_status(&mut self, status: Status) {
+        if let Some(status) = self.rerun_crashes(status) {
+            self.print_status(&status);
+        }
+    }
+
+    /// Reports why the inferior stopped, including the source line if we know it.
+    fn print_status(&self, status: &Status) {
+        match status {
+            Status::Stopped(signal, rip) => {
+                println!("Child stopped (signal {})", signal);
+                // Anything but a SIGTRAP comes from the program itself, not one of our breakpoints
+                if *signal != Signal::SIGTRAP {
+                    match self.inferior.as_ref().map(Inferior::fault_address) {
+                        Some(Ok(Some(addr))) => println!(
+                            "Program received {} at {:#x} (fault address {:#x})",
+                            signal, rip, addr
+                        ),
+                        _ => println!("Program received {} at {:#x}", signal, rip),
+                    }
+                }
+                if let Some(debug_data) = &self.debug_data {
+                    match debug_data.get_line_from_addr(*rip) {
+                        Some(line) => println!("Stopped at {}", line),
+                        None => println!("Stopped at unknown location {:#x}", rip),
+                    }
+                }
+            }
+            Status::Exited(_) | Status::Signaled(_) => {
+                if let Some(message) = exit_message(status) {
+                    println!("{}", message);
+                }
+            }
+        }
+    }
+
     /// This function prompts the user to enter a command, and continues re-prompting until the user
-    /// enters a valid command. It uses DebuggerCommand::from_tokens to do the command parsing.
+    /// enters a valid command. It uses DebuggerCommand::from_line to do the command parsing.
     ///
     /// You don't need to read, understand, or modify this function.
     fn get_next_command(&mut self) -> DebuggerCommand {
@@ -256,15 +768,15 @@ impl Debugger {
                     if line.trim().len() == 0 {
                         continue;
                     }
-                    self.readline.add_history_entry(line.as_str());
-                    if let Err(err) = self.readline.save_history(&self.history_path) {
-                        println!(
-                            "Warning: failed to save history file at {}: {}",
-                            self.history_path, err
-                        );
+                    if add_to_history(&mut self.readline, &line) {
+                        if let Err(err) = self.readline.save_history(&self.history_path) {
+                            println!(
+                                "Warning: failed to save history file at {}: {}",
+                                self.history_path, err
+                            );
+                        }
                     }
-                    let tokens: Vec<&str> = line.split_whitespace().collect();
-                    if let Some(cmd) = DebuggerCommand::from_tokens(&tokens) {
+                    if let Some(cmd) = DebuggerCommand::from_line(&line) {
                         return cmd;
                     } else {
                         println!("Unrecognized command.");
@@ -274,3 +786,391 @@ impl Debugger {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load_sample(name: &str) -> (String, DwarfData) {
+        // The samples are built by running `make` in the deet directory
+        let path = format!("{}/samples/{}", env!("CARGO_MANIFEST_DIR"), name);
+        let debug_data = DwarfData::from_file(&path)
+            .unwrap_or_else(|err| panic!("Could not load {} (did you run make?): {:?}", path, err));
+        (path, debug_data)
+    }
+
+    #[test]
+    fn test_startup_is_quiet_by_default() {
+        let (path, debug_data) = load_sample("function_calls");
+        let message = startup_message(&path, &debug_data, false);
+        assert_eq!(message, format!("Reading symbols from {}\n", path));
+        assert!(!message.contains("Functions:"));
+    }
+
+    #[test]
+    fn test_startup_dumps_symbols_when_verbose() {
+        let (path, debug_data) = load_sample("function_calls");
+        let message = startup_message(&path, &debug_data, true);
+        assert!(message.starts_with(&format!("Reading symbols from {}\n", path)));
+        assert!(message.ends_with(&debug_data.symbol_dump()));
+        assert!(message.contains("  * func2 (declared on line 9"));
+    }
+
+    #[test]
+    fn test_exit_messages() {
+        let run_to_exit = |name: &str| {
+            let (path, _) = load_sample(name);
+            let mut debugger = Debugger::new(&path, false);
+            let mut status = debugger.start_inferior(Vec::new()).unwrap();
+            // segfault stops at the faulting instruction first; continuing delivers the signal
+            while let Status::Stopped(..) = status {
+                assert_eq!(exit_message(&status), None);
+                status = debugger.inferior.as_mut().unwrap().cont().unwrap();
+            }
+            exit_message(&status).unwrap()
+        };
+        assert_eq!(run_to_exit("exit"), "Child exited (status 0)");
+        assert_eq!(run_to_exit("exit_nonzero"), "Child exited abnormally (status 3)");
+        assert_eq!(run_to_exit("segfault"), "Child terminated by SIGSEGV (signal 11)");
+    }
+
+    #[test]
+    fn test_restart_reuses_args_and_breakpoints() {
+        let (path, debug_data) = load_sample("function_calls");
+        let func3 = debug_data.get_addr_for_function(None, "func3").unwrap();
+        let mut debugger = Debugger::new(&path, false);
+        debugger.breakpoints.push(func3);
+        let args = vec![String::from("first"), String::from("second")];
+
+        // rip is just past the 0xcc of the breakpoint we hit
+        match debugger.start_inferior(args.clone()) {
+            Some(Status::Stopped(Signal::SIGTRAP, rip)) => assert_eq!(rip - 1, func3),
+            other => panic!("Expected to stop at func3, got {:?}", other),
+        }
+        match debugger.restart() {
+            Some(Status::Stopped(Signal::SIGTRAP, rip)) => assert_eq!(rip - 1, func3),
+            other => panic!("Expected to stop at func3 after restarting, got {:?}", other),
+        }
+        assert_eq!(debugger.last_run_args, Some(args));
+        debugger.inferior.as_mut().unwrap().kill().unwrap();
+    }
+
+    #[test]
+    fn test_save_and_source_breakpoints() {
+        let (path, debug_data) = load_sample("function_calls");
+        let mut debugger = Debugger::new(&path, false);
+        for target in ["func2", "function_calls.c:12", "func3"] {
+            let addr = debugger.resolve_breakpoint(target).unwrap();
+            debugger.set_breakpoint(addr);
+        }
+        let saved = debugger.breakpoints.clone();
+        assert_eq!(saved[0], debug_data.get_addr_for_function(None, "func2").unwrap());
+
+        let file = std::env::temp_dir().join(format!("deet-breakpoints-{}", std::process::id()));
+        let file = file.to_str().unwrap();
+        assert_eq!(debugger.save_breakpoints(file).unwrap(), 3);
+        let contents = std::fs::read_to_string(file).unwrap();
+        assert_eq!(contents, "break func2\nbreak function_calls.c:12\nbreak func3\n");
+
+        debugger.breakpoints.clear();
+        let missing = debugger.source_breakpoints(file).unwrap();
+        assert!(missing.is_empty(), "{:?}", missing);
+        assert_eq!(debugger.breakpoints, saved);
+
+        // Locations that no longer exist are reported and skipped
+        std::fs::write(file, "# saved breakpoints\nbreak func_that_was_removed\n\nfunc1\n").unwrap();
+        debugger.breakpoints.clear();
+        let missing = debugger.source_breakpoints(file).unwrap();
+        std::fs::remove_file(file).unwrap();
+        assert_eq!(missing.len(), 1);
+        assert_eq!(missing[0].0, "func_that_was_removed");
+        assert_eq!(
+            debugger.breakpoints,
+            vec![debug_data.get_addr_for_function(None, "func1").unwrap()]
+        );
+    }
+
+    #[test]
+    fn test_backtrace_before_run() {
+        let (path, _) = load_sample("function_calls");
+        let debugger = Debugger::new(&path, false);
+        let tokens = vec!["bt"];
+        assert!(matches!(
+            DebuggerCommand::from_tokens(&tokens),
+            Some(DebuggerCommand::Backtrace)
+        ));
+        assert_eq!(
+            debugger.print_backtrace(),
+            Err(String::from("No inferior process running. Use 'run' first."))
+        );
+    }
+
+    #[test]
+    fn test_info_proc() {
+        let (path, debug_data) = load_sample("function_calls");
+        let mut debugger = Debugger::new(&path, false);
+        assert_eq!(debugger.proc_info(), Err(NO_INFERIOR));
+
+        debugger.breakpoints.push(debug_data.get_addr_for_function(None, "func3").unwrap());
+        let args = vec![String::from("one"), String::from("two words")];
+        debugger.start_inferior(args).unwrap();
+        let pid = debugger.inferior.as_ref().unwrap().pid();
+        assert_eq!(
+            debugger.proc_info().unwrap(),
+            format!(
+                "process {}\n  status: stopped\n  exe: {}\n  args: [\"one\", \"two words\"]\n",
+                pid, path
+            )
+        );
+
+        // Once the program has run to completion it is reported as exited
+        let inferior = debugger.inferior.as_mut().unwrap();
+        while let Ok(Status::Stopped(..)) = inferior.cont() {}
+        assert!(debugger.proc_info().unwrap().contains("status: exited"));
+    }
+
+    #[test]
+    fn test_info_scope() {
+        let (path, debug_data) = load_sample("scopes");
+        let mut debugger = Debugger::new(&path, false);
+        assert_eq!(debugger.scope_info(), Err(String::from(NO_INFERIOR)));
+
+        // Stopped after the inner block: inner is no longer in scope
+        debugger.breakpoints.push(debug_data.get_addr_for_line(None, 14).unwrap());
+        debugger.start_inferior(Vec::new()).unwrap();
+        assert_eq!(
+            debugger.scope_info().unwrap(),
+            "outer: int\ni: int\nloop_local: int\ncounter: int\n"
+        );
+        debugger.inferior.as_mut().unwrap().kill().unwrap();
+    }
+
+    #[test]
+    fn test_advance() {
+        let (path, debug_data) = load_sample("scopes");
+        let mut debugger = Debugger::new(&path, false);
+        assert_eq!(debugger.advance("12").unwrap_err(), NO_INFERIOR);
+
+        debugger.breakpoints.push(debug_data.get_addr_for_function(None, "main").unwrap());
+        debugger.start_inferior(Vec::new()).unwrap();
+        let stopped_line = |debugger: &Debugger| {
+            let inferior = debugger.inferior.as_ref().unwrap();
+            inferior.frame(&debug_data, 0).unwrap().unwrap().line.unwrap().number
+        };
+        assert!(matches!(debugger.advance("12"), Ok(Status::Stopped(Signal::SIGTRAP, _))));
+        assert_eq!(stopped_line(&debugger), 12);
+        // Line 12 runs again in the loop's second iteration, but the temporary breakpoint is gone
+        assert!(matches!(debugger.advance("scopes.c:18"), Ok(Status::Stopped(Signal::SIGTRAP, _))));
+        assert_eq!(stopped_line(&debugger), 18);
+        // The loop is over, so the program exits before getting back to line 12
+        assert!(matches!(debugger.advance("12"), Ok(Status::Exited(0))));
+
+        assert_eq!(debugger.advance("nope").unwrap_err(), "Function 'nope' not found");
+    }
+
+    #[test]
+    fn test_print_expressions() {
+        let (path, debug_data) = load_sample("function_calls");
+        let mut debugger = Debugger::new(&path, false);
+        debugger.breakpoints.push(debug_data.get_addr_for_function(None, "func3").unwrap());
+        debugger.start_inferior(Vec::new()).unwrap();
+        // func3 was called from func2(42, global), where sum is 47
+        debugger.current_frame = 1;
+        let print = |expression| debugger.print_expression(expression, ValueFormat::Natural);
+        assert_eq!(print("sum + 3"), Ok(String::from("sum + 3 = 50")));
+        assert_eq!(print("sum - a"), Ok(String::from("sum - a = 5")));
+        assert_eq!(print("a * global"), Ok(String::from("a * global = 210")));
+        assert_eq!(print("sum / b"), Ok(String::from("sum / b = 9")));
+        assert_eq!(print("sum"), Ok(String::from("sum = 47")));
+        assert_eq!(
+            debugger.print_expression("sum + 1", ValueFormat::Hex),
+            Ok(String::from("sum + 1 = 0x30"))
+        );
+        assert_eq!(print("sum / 0"), Err(String::from("Division by zero")));
+        assert_eq!(
+            print("nope - 1"),
+            Err(String::from("No symbol \"nope\" in current context."))
+        );
+        debugger.inferior.as_mut().unwrap().kill().unwrap();
+    }
+
+    #[test]
+    fn test_print_dereference() {
+        let (path, debug_data) = load_sample("pointers");
+        let mut debugger = Debugger::new(&path, false);
+        debugger.breakpoints.push(debug_data.get_addr_for_function(None, "report").unwrap());
+        debugger.start_inferior(Vec::new()).unwrap();
+        // Globals are visible from report; main's locals are one frame up
+        let print = |debugger: &Debugger, expression| {
+            debugger.print_expression(expression, ValueFormat::Natural).unwrap()
+        };
+        assert_eq!(print(&debugger, "*counter_ptr"), "*counter_ptr = 40");
+        debugger.current_frame = 1;
+        assert_eq!(print(&debugger, "*ptr"), "*ptr = 7");
+        assert_eq!(print(&debugger, "**ptr_ptr + x"), "**ptr_ptr + x = 14");
+        assert_eq!(print(&debugger, "*name"), "*name = 100 ('d')");
+        assert!(print(&debugger, "ptr").starts_with("ptr = 0x"));
+        assert_eq!(
+            debugger.print_expression("*x", ValueFormat::Natural),
+            Err(String::from("Attempt to take contents of a non-pointer value."))
+        );
+        debugger.inferior.as_mut().unwrap().kill().unwrap();
+    }
+
+    #[test]
+    fn test_rerun_on_crash() {
+        let (path, _) = load_sample("crash_once");
+        let marker = std::env::temp_dir().join(format!("deet-crash-once-{}", std::process::id()));
+        let _ = std::fs::remove_file(&marker);
+        let args = vec![marker.to_str().unwrap().to_string()];
+
+        // Without --rerun-on-crash the crash is left for the user to inspect
+        let mut debugger = Debugger::new(&path, false);
+        let status = debugger.start_inferior(args.clone()).unwrap();
+        let report = debugger.crash_report(&status).expect("Expected a crash");
+        assert!(report.starts_with("Child crashed with SIGSEGV at 0x"), "{}", report);
+        // addr2line reports the full path of the source file
+        assert!(
+            report
+                .lines()
+                .any(|line| line.starts_with("main (") && line.contains("crash_once.c:")),
+            "{}",
+            report
+        );
+        assert!(matches!(
+            debugger.rerun_crashes(status),
+            Some(Status::Stopped(Signal::SIGSEGV, _))
+        ));
+        debugger.inferior.as_mut().unwrap().kill().unwrap();
+
+        // The first run crashed and created the marker, so the rerun exits cleanly
+        std::fs::remove_file(&marker).unwrap();
+        let mut debugger = Debugger::new(&path, false);
+        debugger.set_rerun_on_crash(3);
+        debugger.reruns_left = 3;
+        let status = debugger.start_inferior(args).unwrap();
+        assert!(matches!(debugger.rerun_crashes(status), Some(Status::Exited(0))));
+        assert_eq!(debugger.reruns_left, 2);
+        std::fs::remove_file(&marker).unwrap();
+
+        // Stopping at a breakpoint or exiting isn't a crash
+        assert!(debugger.crash_report(&Status::Exited(1)).is_none());
+        assert!(debugger.crash_report(&Status::Stopped(Signal::SIGTRAP, 0)).is_none());
+        assert_eq!(
+            debugger.crash_report(&Status::Signaled(Signal::SIGKILL)),
+            Some(String::from("Child terminated by SIGKILL (signal 9)\n"))
+        );
+    }
+
+    #[test]
+    fn test_set_cwd_and_env() {
+        let (path, _) = load_sample("print_env");
+        let output = std::env::temp_dir().join(format!("deet-print-env-{}", std::process::id()));
+        let args = vec![output.to_str().unwrap().to_string()];
+        let mut debugger = Debugger::new(&path, false);
+        let run = |debugger: &mut Debugger, line: &str| {
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            let command = DebuggerCommand::from_tokens(&tokens).expect(line);
+            debugger.update_launch(command)
+        };
+
+        assert_eq!(
+            run(&mut debugger, "set env DEET_SAMPLE_VAR=hello world=1"),
+            "Environment for the next run: DEET_SAMPLE_VAR=hello world=1"
+        );
+        assert_eq!(run(&mut debugger, "set cwd /tmp"), "Working directory for the next run: /tmp");
+        assert!(matches!(debugger.start_inferior(args.clone()), Some(Status::Exited(0))));
+        let printed = std::fs::read_to_string(&output).unwrap();
+        assert_eq!(printed, "DEET_SAMPLE_VAR=hello world=1\ncwd=/tmp\n");
+
+        assert_eq!(
+            run(&mut debugger, "unset env DEET_SAMPLE_VAR"),
+            "Environment for the next run: DEET_SAMPLE_VAR unset"
+        );
+        assert!(matches!(debugger.start_inferior(args), Some(Status::Exited(0))));
+        let printed = std::fs::read_to_string(&output).unwrap();
+        std::fs::remove_file(&output).unwrap();
+        assert_eq!(printed, "DEET_SAMPLE_VAR=(unset)\ncwd=/tmp\n");
+
+        for line in ["set env =value", "set env NOEQUALS", "set cwd", "unset env", "set foo bar"] {
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            assert!(DebuggerCommand::from_tokens(&tokens).is_none(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn test_run_with_quoted_args() {
+        let (path, _) = load_sample("print_args");
+        let output = std::env::temp_dir().join(format!("deet-print-args-{}", std::process::id()));
+        let line = format!(
+            r#"run {} --name "John Doe" 'single quoted' escaped\ space plain"#,
+            output.to_str().unwrap()
+        );
+        let args = match DebuggerCommand::from_line(&line) {
+            Some(DebuggerCommand::Run(args)) => args,
+            _ => panic!("Expected a run command"),
+        };
+        let mut debugger = Debugger::new(&path, false);
+        assert!(matches!(debugger.start_inferior(args), Some(Status::Exited(0))));
+        let printed = std::fs::read_to_string(&output).unwrap();
+        std::fs::remove_file(&output).unwrap();
+        assert_eq!(printed, "--name\nJohn Doe\nsingle quoted\nescaped space\nplain\n");
+    }
+
+    #[test]
+    fn test_history_skips_blank_lines_and_duplicates() {
+        let mut readline = new_editor();
+        let lines = ["break main", "break main", "", "   ", "continue", "break main", "break main"];
+        let added: Vec<bool> = lines
+            .iter()
+            .map(|line| add_to_history(&mut readline, line))
+            .collect();
+        assert_eq!(added, vec![true, false, false, false, true, true, false]);
+        assert_eq!(readline.history().len(), 3);
+
+        // The oldest commands are dropped once the history is full
+        for i in 0..HISTORY_MAX_LEN + 10 {
+            add_to_history(&mut readline, &format!("print {}", i));
+        }
+        assert_eq!(readline.history().len(), HISTORY_MAX_LEN);
+    }
+
+    #[test]
+    fn test_break_with_multiple_targets() {
+        let (path, debug_data) = load_sample("function_calls");
+        let mut debugger = Debugger::new(&path, false);
+        let func3 = debug_data.get_addr_for_function(None, "func3").unwrap();
+        let line = format!("break func1 function_calls.c:12 *{:#x}", func3);
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let targets = match DebuggerCommand::from_tokens(&tokens) {
+            Some(DebuggerCommand::Break(targets)) => targets,
+            _ => panic!("Expected a break command"),
+        };
+        assert_eq!(targets.len(), 3);
+
+        let failed = debugger.set_breakpoints(&targets);
+        assert!(failed.is_empty(), "{:?}", failed);
+        assert_eq!(
+            debugger.breakpoints,
+            vec![
+                debug_data.get_addr_for_function(None, "func1").unwrap(),
+                debug_data.get_addr_for_line(Some("function_calls.c"), 12).unwrap(),
+                func3,
+            ]
+        );
+
+        // Bad targets are reported without affecting the good ones
+        debugger.breakpoints.clear();
+        let targets: Vec<String> = ["no_such_function", "func2", "*zz"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let failed = debugger.set_breakpoints(&targets);
+        let failed_targets: Vec<&str> = failed.iter().map(|(target, _)| target.as_str()).collect();
+        assert_eq!(failed_targets, vec!["no_such_function", "*zz"]);
+        assert_eq!(
+            debugger.breakpoints,
+            vec![debug_data.get_addr_for_function(None, "func2").unwrap()]
+        );
+    }
+}
@@ -1,13 +1,150 @@
+use crate::value_format::ValueFormat;
+
 pub enum DebuggerCommand {
     Quit,
     Run(Vec<String>),
     Continue,
+    /// `advance <target>` / `until <target>`: continue until the target is reached, using a
+    /// breakpoint that is removed again once the inferior stops
+    Advance(String),
     Backtrace,
-    Break(String),
-    Print,
+    Break(Vec<String>),
+    /// `print[/fmt] [<expression>]`; without an expression, every variable in scope is printed
+    Print(ValueFormat, Option<String>),
+    InfoLine(String),
+    Disassemble,
+    StepInstruction,
+    InfoFrame,
+    Up,
+    Down,
+    InfoSymbols,
+    InfoProc,
+    /// `info scope`: the names and types of the variables visible in the selected frame
+    InfoScope,
+    Restart,
+    SaveBreakpoints(String),
+    Source(String),
+    /// `set cwd <path>`: the directory later runs start in
+    SetCwd(String),
+    /// `set env KEY=VALUE`: a variable set in the environment of later runs
+    SetEnv(String, String),
+    /// `unset env KEY`: a variable removed from the environment of later runs
+    UnsetEnv(String),
+}
+
+/// Splits the arguments of `run` the way a shell would: whitespace separates arguments, except
+/// inside single quotes (taken literally) or double quotes (where only `\"` and `\\` are escapes),
+/// and a backslash outside quotes escapes the next character, e.g. `a\ b`. Returns an error for
+/// an unterminated quote.
+pub fn split_args(line: &str) -> Result<Vec<String>, &'static str> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    // Whether we're inside an argument. Tracked separately from `current` so `""` is an argument
+    let mut in_arg = false;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if in_arg {
+                    args.push(std::mem::take(&mut current));
+                    in_arg = false;
+                }
+            }
+            '\'' => {
+                in_arg = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => current.push(c),
+                        None => return Err("unterminated single quote"),
+                    }
+                }
+            }
+            '"' => {
+                in_arg = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c) if c == '"' || c == '\\' => current.push(c),
+                            Some(c) => {
+                                current.push('\\');
+                                current.push(c);
+                            }
+                            None => return Err("unterminated double quote"),
+                        },
+                        Some(c) => current.push(c),
+                        None => return Err("unterminated double quote"),
+                    }
+                }
+            }
+            '\\' => {
+                in_arg = true;
+                // A trailing backslash has nothing to escape, so keep it
+                current.push(chars.next().unwrap_or('\\'));
+            }
+            c => {
+                in_arg = true;
+                current.push(c);
+            }
+        }
+    }
+    if in_arg {
+        args.push(current);
+    }
+    Ok(args)
+}
+
+/// Everything after `print`, so expressions can contain spaces (`print sum - a`)
+fn print_expression(tokens: &[&str]) -> Option<String> {
+    if tokens.len() < 2 {
+        None
+    } else {
+        Some(tokens[1..].join(" "))
+    }
+}
+
+/// Parses the arguments of `set <what> ...`, e.g. `cwd /tmp` or `env KEY=VALUE`. Everything after
+/// `cwd`/`env` is joined back together, so paths and values can contain spaces.
+fn set_command(tokens: &[&str]) -> Option<DebuggerCommand> {
+    let rest = tokens.get(2..).map(|rest| rest.join(" ")).unwrap_or_default();
+    match tokens.get(1) {
+        Some(&"cwd") if !rest.is_empty() => Some(DebuggerCommand::SetCwd(rest)),
+        Some(&"env") => match rest.split_once('=') {
+            Some((key, value)) if !key.is_empty() => {
+                Some(DebuggerCommand::SetEnv(key.to_string(), value.to_string()))
+            }
+            _ => {
+                println!("Usage: set env KEY=VALUE");
+                None
+            }
+        },
+        _ => {
+            println!("Usage: set cwd <path> | set env KEY=VALUE");
+            None
+        }
+    }
 }
 
 impl DebuggerCommand {
+    /// Parses one line of user input. Arguments to `run` are split with `split_args`, so they can
+    /// be quoted; every other command is split on whitespace and parsed by `from_tokens`.
+    pub fn from_line(line: &str) -> Option<DebuggerCommand> {
+        let line = line.trim_start();
+        let (command, rest) = line.split_at(line.find(char::is_whitespace).unwrap_or(line.len()));
+        match command {
+            "r" | "run" => match split_args(rest) {
+                Ok(args) => Some(DebuggerCommand::Run(args)),
+                Err(err) => {
+                    println!("Error parsing arguments: {}", err);
+                    None
+                }
+            },
+            "" => None,
+            _ => DebuggerCommand::from_tokens(&line.split_whitespace().collect()),
+        }
+    }
+
     pub fn from_tokens(tokens: &Vec<&str>) -> Option<DebuggerCommand> {
         match tokens[0] {
             "q" | "quit" => Some(DebuggerCommand::Quit),
@@ -17,24 +154,138 @@ impl DebuggerCommand {
                     args.iter().map(|s| s.to_string()).collect(),
                 ))
             }
+            "restart" => Some(DebuggerCommand::Restart),
             // Default case:
             "c" | "cont" | "continue" => {
                 Some(DebuggerCommand::Continue)
             }
+            "advance" | "until" => match tokens.get(1) {
+                Some(target) if tokens.len() == 2 => Some(DebuggerCommand::Advance(target.to_string())),
+                _ => {
+                    println!("Usage: {} <target>", tokens[0]);
+                    None
+                }
+            },
             "bt" | "back" | "backtrace" => {
                 Some(DebuggerCommand::Backtrace)
             }
             "b" | "break" => {
                 if tokens.len() < 2 {
-                    println!("Usage: break <target>");
+                    println!("Usage: break <target> [<target>...]");
                     return None;
                 }
-                Some(DebuggerCommand::Break(tokens[1].to_string()))
+                Some(DebuggerCommand::Break(
+                    tokens[1..].iter().map(|s| s.to_string()).collect(),
+                ))
+            }
+            "save-breakpoints" => {
+                if tokens.len() < 2 {
+                    println!("Usage: save-breakpoints <file>");
+                    return None;
+                }
+                Some(DebuggerCommand::SaveBreakpoints(tokens[1].to_string()))
+            }
+            "source" => {
+                if tokens.len() < 2 {
+                    println!("Usage: source <file>");
+                    return None;
+                }
+                Some(DebuggerCommand::Source(tokens[1].to_string()))
+            }
+            "p" | "print" => Some(DebuggerCommand::Print(ValueFormat::Natural, print_expression(tokens))),
+            command if command.starts_with("p/") || command.starts_with("print/") => {
+                let suffix = &command[command.find('/').unwrap() + 1..];
+                match ValueFormat::from_suffix(suffix) {
+                    Some(format) => Some(DebuggerCommand::Print(format, print_expression(tokens))),
+                    None => {
+                        println!("Unknown print format /{}; use x, d, c or t", suffix);
+                        None
+                    }
+                }
+            }
+            "set" => set_command(tokens),
+            "unset" => match (tokens.get(1), tokens.get(2)) {
+                (Some(&"env"), Some(key)) if tokens.len() == 3 => {
+                    Some(DebuggerCommand::UnsetEnv(key.to_string()))
+                }
+                _ => {
+                    println!("Usage: unset env KEY");
+                    None
+                }
+            },
+            "si" | "stepi" => {
+                Some(DebuggerCommand::StepInstruction)
             }
-            "p" | "print" => {
-                Some(DebuggerCommand::Print)
+            "disas" | "disassemble" => {
+                Some(DebuggerCommand::Disassemble)
             }
+            "up" => Some(DebuggerCommand::Up),
+            "down" => Some(DebuggerCommand::Down),
+            "i" | "info" => match tokens.get(1) {
+                Some(&"frame") => Some(DebuggerCommand::InfoFrame),
+                Some(&"symbols") => Some(DebuggerCommand::InfoSymbols),
+                Some(&"proc") => Some(DebuggerCommand::InfoProc),
+                Some(&"scope") => Some(DebuggerCommand::InfoScope),
+                Some(&"line") => {
+                    if tokens.len() < 3 {
+                        println!("Usage: info line *<address>");
+                        return None;
+                    }
+                    Some(DebuggerCommand::InfoLine(tokens[2].to_string()))
+                }
+                _ => {
+                    println!("Usage: info line *<address> | info frame | info symbols | info proc | info scope");
+                    None
+                }
+            },
             _ => None,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_args() {
+        assert_eq!(
+            split_args(r#"  --name "John Doe" 'it''s' a\ b "say \"hi\" \n" '' end "#).unwrap(),
+            vec!["--name", "John Doe", "its", "a b", "say \"hi\" \\n", "", "end"]
+        );
+        assert_eq!(split_args("").unwrap(), Vec::<String>::new());
+        assert_eq!(split_args(r#"pre"mid dle"post"#).unwrap(), vec!["premid dlepost"]);
+        assert_eq!(split_args("trailing\\").unwrap(), vec!["trailing\\"]);
+        assert!(split_args("'unterminated").is_err());
+        assert!(split_args("\"unterminated").is_err());
+    }
+
+    #[test]
+    fn test_from_line_quotes_only_run_args() {
+        match DebuggerCommand::from_line(r#"run --name "John Doe""#) {
+            Some(DebuggerCommand::Run(args)) => assert_eq!(args, vec!["--name", "John Doe"]),
+            _ => panic!("Expected a run command"),
+        }
+        match DebuggerCommand::from_line("  r") {
+            Some(DebuggerCommand::Run(args)) => assert!(args.is_empty()),
+            _ => panic!("Expected a run command"),
+        }
+        assert!(DebuggerCommand::from_line("run 'oops").is_none());
+        assert!(matches!(
+            DebuggerCommand::from_line("  backtrace  "),
+            Some(DebuggerCommand::Backtrace)
+        ));
+    }
+
+    #[test]
+    fn test_advance_needs_one_target() {
+        for line in ["advance *0x401136", "until *0x401136"] {
+            match DebuggerCommand::from_line(line) {
+                Some(DebuggerCommand::Advance(target)) => assert_eq!(target, "*0x401136"),
+                _ => panic!("Expected an advance command for {:?}", line),
+            }
+        }
+        assert!(DebuggerCommand::from_line("until").is_none());
+        assert!(DebuggerCommand::from_line("advance 12 13").is_none());
+    }
+}
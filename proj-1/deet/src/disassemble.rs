@@ -0,0 +1,56 @@
+use iced_x86::{Decoder, DecoderOptions, Formatter, Instruction, NasmFormatter};
+
+/// The longest an x86-64 instruction can be, in bytes
+pub const MAX_INSTRUCTION_LENGTH: usize = 15;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisassembledInstruction {
+    pub address: usize,
+    pub text: String,
+}
+
+/// Disassembles up to `max_instructions` x86-64 instructions from `bytes`, which were read from
+/// memory starting at `start_addr`. Stops early if it runs out of bytes.
+pub fn disassemble(
+    bytes: &[u8],
+    start_addr: usize,
+    max_instructions: usize,
+) -> Vec<DisassembledInstruction> {
+    let mut decoder = Decoder::with_ip(64, bytes, start_addr as u64, DecoderOptions::NONE);
+    let mut formatter = NasmFormatter::new();
+    let mut instruction = Instruction::default();
+    let mut instructions = Vec::new();
+    while decoder.can_decode() && instructions.len() < max_instructions {
+        decoder.decode_out(&mut instruction);
+        let mut text = String::new();
+        formatter.format(&instruction, &mut text);
+        instructions.push(DisassembledInstruction {
+            address: instruction.ip() as usize,
+            text,
+        });
+    }
+    instructions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disassemble_small_function() {
+        // push rbp; mov rbp, rsp; pop rbp; ret
+        let bytes = [0x55, 0x48, 0x89, 0xe5, 0x5d, 0xc3];
+        let instructions = disassemble(&bytes, 0x401000, 16);
+        assert_eq!(instructions.len(), 4);
+        assert_eq!(instructions[0].address, 0x401000);
+        assert!(instructions[0].text.starts_with("push"), "got {}", instructions[0].text);
+        assert_eq!(instructions[1].address, 0x401001);
+        assert!(instructions[3].text.starts_with("ret"), "got {}", instructions[3].text);
+    }
+
+    #[test]
+    fn test_disassemble_stops_at_limit() {
+        let bytes = [0x90; 8]; // nop sled
+        assert_eq!(disassemble(&bytes, 0, 3).len(), 3);
+    }
+}
@@ -1,7 +1,9 @@
 use crate::gimli_wrapper;
+use crate::unwind::{CallFrameInfo, UnwindRule};
 use addr2line::Context;
 use object::Object;
 use std::convert::TryInto;
+use std::fmt::Write as _;
 use std::{fmt, fs};
 
 #[derive(Debug)]
@@ -13,6 +15,7 @@ pub enum Error {
 pub struct DwarfData {
     files: Vec<File>,
     addr2line: Context<addr2line::gimli::EndianRcSlice<addr2line::gimli::RunTimeEndian>>,
+    call_frames: CallFrameInfo,
 }
 
 impl fmt::Debug for DwarfData {
@@ -41,9 +44,16 @@ impl DwarfData {
         Ok(DwarfData {
             files: gimli_wrapper::load_file(&object, endian)?,
             addr2line: Context::new(&object).or_else(|e| Err(gimli_wrapper::Error::from(e)))?,
+            call_frames: CallFrameInfo::load(&object, endian),
         })
     }
 
+    /// How to find the caller's registers when stopped at `addr`, from the executable's call frame
+    /// information. Returns None if there is no usable CFI for that address.
+    pub fn get_unwind_rule(&self, addr: usize) -> Option<UnwindRule> {
+        self.call_frames.rule_for_address(addr)
+    }
+
     #[allow(dead_code)]
     fn get_target_file(&self, file: &str) -> Option<&File> {
         self.files.iter().find(|f| {
@@ -111,40 +121,63 @@ impl DwarfData {
         Some(frame.function?.raw_name().ok()?.to_string())
     }
 
-    #[allow(dead_code)]
-    pub fn print(&self) {
+    /// Formats the source location of an address as `file:line:function`. Returns None if the
+    /// address isn't covered by the line tables; the function name is `??` if it can't be found.
+    pub fn get_location_string(&self, curr_addr: usize) -> Option<String> {
+        let line = self.get_line_from_addr(curr_addr)?;
+        let function = self
+            .get_function_from_addr(curr_addr)
+            .unwrap_or_else(|| "??".to_string());
+        Some(format!("{}:{}", line, function))
+    }
+
+    /// Formats everything we know about the target: each file's global variables, functions (with
+    /// their variables), and line table. Writing to a String can't fail, hence the unwraps.
+    pub fn symbol_dump(&self) -> String {
+        let mut out = String::new();
         for file in &self.files {
-            println!("------");
-            println!("{}", file.name);
-            println!("------");
+            writeln!(out, "------").unwrap();
+            writeln!(out, "{}", file.name).unwrap();
+            writeln!(out, "------").unwrap();
 
-            println!("Global variables:");
+            writeln!(out, "Global variables:").unwrap();
             for var in &file.global_variables {
-                println!(
+                writeln!(
+                    out,
                     "  * {} ({}, located at {}, declared at line {})",
                     var.name, var.entity_type.name, var.location, var.line_number
-                );
+                )
+                .unwrap();
             }
 
-            println!("Functions:");
+            writeln!(out, "Functions:").unwrap();
             for func in &file.functions {
-                println!(
+                writeln!(
+                    out,
                     "  * {} (declared on line {}, located at {:#x}, {} bytes long)",
                     func.name, func.line_number, func.address, func.text_length
-                );
+                )
+                .unwrap();
                 for var in &func.variables {
-                    println!(
+                    writeln!(
+                        out,
                         "    * Variable: {} ({}, located at {}, declared at line {})",
                         var.name, var.entity_type.name, var.location, var.line_number
-                    );
+                    )
+                    .unwrap();
                 }
             }
 
-            println!("Line numbers:");
+            writeln!(out, "Line numbers:").unwrap();
             for line in &file.lines {
-                println!("  * {} (at {:#x})", line.number, line.address);
+                writeln!(out, "  * {} (at {:#x})", line.number, line.address).unwrap();
             }
         }
+        out
+    }
+
+    pub fn print(&self) {
+        print!("{}", self.symbol_dump());
     }
 
     /// Get all variables (global and local) available at a given address
@@ -165,12 +198,50 @@ impl DwarfData {
         }
         None
     }
+
+    /// Returns the variables in lexical scope at `addr`: the locals of the enclosing function whose
+    /// block contains `addr`, followed by the globals of its file. Where several share a name, only
+    /// the one in the innermost scope is kept, so shadowed variables are left out. Returns None if
+    /// `addr` isn't inside a known function.
+    pub fn get_variables_in_scope(&self, addr: usize) -> Option<Vec<Variable>> {
+        let (file, func) = self.files.iter().find_map(|file| {
+            let func = file
+                .functions
+                .iter()
+                .find(|func| addr >= func.address && addr < func.address + func.text_length)?;
+            Some((file, func))
+        })?;
+        // Narrower ranges are nested inside wider ones, so the shortest range is the innermost
+        let width = |var: &Variable| var.scope.map_or(usize::MAX, |(start, end)| end - start);
+        let locals: Vec<&Variable> = func
+            .variables
+            .iter()
+            .filter(|var| var.scope.is_none_or(|(start, end)| addr >= start && addr < end))
+            .collect();
+        let mut visible: Vec<Variable> = locals
+            .iter()
+            .filter(|var| {
+                !locals
+                    .iter()
+                    .any(|other| other.name == var.name && width(other) < width(var))
+            })
+            .map(|var| (*var).clone())
+            .collect();
+        for var in &file.global_variables {
+            if !visible.iter().any(|local| local.name == var.name) {
+                visible.push(var.clone());
+            }
+        }
+        Some(visible)
+    }
 }
 
 #[derive(Debug, Clone, Default)]
 pub struct Type {
     pub name: String,
     pub size: usize,
+    /// For pointer types, the type pointed to
+    pub pointee: Option<Box<Type>>,
 }
 
 impl Type {
@@ -178,6 +249,16 @@ impl Type {
         Type {
             name: name,
             size: size,
+            pointee: None,
+        }
+    }
+
+    /// A pointer of `size` bytes to a value of type `pointee`, named like C does (`int *`)
+    pub fn pointer_to(pointee: Type, size: usize) -> Self {
+        Type {
+            name: format!("{} *", pointee.name),
+            size,
+            pointee: Some(Box::new(pointee)),
         }
     }
 }
@@ -210,6 +291,9 @@ pub struct Variable {
     pub entity_type: Type,
     pub location: Location,
     pub line_number: usize, // Line number in source file
+    /// For variables declared in a nested block, the address range (start, end) of the innermost
+    /// block; None for globals and variables visible in the whole function
+    pub scope: Option<(usize, usize)>,
 }
 
 #[derive(Debug, Default, Clone)]
@@ -243,3 +327,71 @@ impl fmt::Display for Line {
 }
 
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The samples are built by running `make` in the deet directory
+    fn load_sample(name: &str) -> DwarfData {
+        let path = format!("{}/samples/{}", env!("CARGO_MANIFEST_DIR"), name);
+        DwarfData::from_file(&path)
+            .unwrap_or_else(|err| panic!("Could not load {} (did you run make?): {:?}", path, err))
+    }
+
+    #[test]
+    fn test_location_of_function_entry() {
+        let debug_data = load_sample("function_calls");
+        let addr = debug_data.get_addr_for_function(None, "func2").unwrap();
+        let location = debug_data.get_location_string(addr).unwrap();
+        assert!(location.ends_with("function_calls.c:9:func2"), "got {}", location);
+    }
+
+    #[test]
+    fn test_location_of_unknown_address() {
+        let debug_data = load_sample("function_calls");
+        assert_eq!(debug_data.get_location_string(0), None);
+    }
+
+    #[test]
+    fn test_unwind_rule_at_function_entry() {
+        use crate::unwind::CfaRegister;
+        // At the first instruction of a function, rsp points at the return address the call pushed
+        let debug_data = load_sample("optimized");
+        let addr = debug_data.get_addr_for_function(None, "leaf").unwrap();
+        let rule = debug_data.get_unwind_rule(addr).unwrap();
+        assert_eq!(rule.cfa_register, CfaRegister::Rsp);
+        assert_eq!(rule.cfa_offset, 8);
+        assert_eq!(rule.return_address_offset, -8);
+        assert_eq!(rule.rbp_offset, None);
+        assert_eq!(debug_data.get_unwind_rule(0), None);
+    }
+
+    fn names_in_scope(debug_data: &DwarfData, line: usize) -> Vec<String> {
+        let addr = debug_data.get_addr_for_line(None, line).unwrap();
+        let vars = debug_data.get_variables_in_scope(addr).unwrap();
+        vars.into_iter().map(|var| var.name).collect()
+    }
+
+    #[test]
+    fn test_variables_in_scope() {
+        let debug_data = load_sample("scopes");
+        // Inside the innermost block of the loop body
+        assert_eq!(
+            names_in_scope(&debug_data, 12),
+            vec!["outer", "i", "loop_local", "inner", "counter"]
+        );
+        // After that block has ended, but still in the loop
+        assert_eq!(
+            names_in_scope(&debug_data, 14),
+            vec!["outer", "i", "loop_local", "counter"]
+        );
+        // The second block's outer shadows the function's
+        let vars = names_in_scope(&debug_data, 18);
+        assert_eq!(vars, vec!["outer", "counter"]);
+        let addr = debug_data.get_addr_for_line(None, 18).unwrap();
+        let outer = &debug_data.get_variables_in_scope(addr).unwrap()[0];
+        assert_eq!(outer.line_number, 17);
+        assert!(debug_data.get_variables_in_scope(0).is_none());
+    }
+}
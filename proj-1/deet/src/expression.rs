@@ -0,0 +1,342 @@
+//! A tiny evaluator for the integer expressions `print` accepts, like `sum - a`, `count * 2` or
+//! `*ptr + 1`. Expressions are parsed by recursive descent with the usual C precedence:
+//!
+//! ```text
+//! expr    := term (('+' | '-') term)*
+//! term    := unary (('*' | '/') unary)*
+//! unary   := '-' unary | '*' unary | primary
+//! primary := number | variable | '(' expr ')'
+//! ```
+
+use crate::dwarf_data::Type;
+use crate::value_format;
+
+/// The type of integer constants and of the result of arithmetic
+const LONG_SIZE: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Number(i64),
+    Variable(String),
+    Negate(Box<Expr>),
+    Deref(Box<Expr>),
+    Binary(Box<Expr>, BinaryOp, Box<Expr>),
+}
+
+/// A typed value: the result of evaluating an expression
+#[derive(Debug, Clone)]
+pub struct Value {
+    pub value_type: Type,
+    pub bytes: Vec<u8>,
+}
+
+impl Value {
+    fn long(value: i64) -> Value {
+        Value {
+            value_type: Type::new(String::from("long"), LONG_SIZE),
+            bytes: value.to_le_bytes().to_vec(),
+        }
+    }
+
+    /// The integer this value holds, for arithmetic
+    fn as_integer(&self) -> Result<i64, String> {
+        if self.value_type.pointee.is_some() {
+            return Err(String::from("Pointer arithmetic is not supported"));
+        }
+        if !value_format::is_integer_type(&self.value_type.name) {
+            return Err(format!("Cannot do arithmetic on a value of type {}", self.value_type.name));
+        }
+        value_format::decode_integer(&self.bytes, &self.value_type.name)
+            .map(|value| value as i64)
+            .ok_or_else(|| format!("Cannot do arithmetic on a value of type {}", self.value_type.name))
+    }
+}
+
+/// Where expressions get variables and memory from (a stack frame of the inferior)
+pub trait Context {
+    /// The type and raw bytes of a variable, or None if there is no variable with that name
+    fn variable(&self, name: &str) -> Result<Option<(Type, Vec<u8>)>, String>;
+    fn read_memory(&self, addr: usize, len: usize) -> Result<Vec<u8>, String>;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(i64),
+    Identifier(String),
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    LeftParen,
+    RightParen,
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        let token = match c {
+            c if c.is_whitespace() => {
+                chars.next();
+                continue;
+            }
+            '+' => Token::Plus,
+            '-' => Token::Minus,
+            '*' => Token::Star,
+            '/' => Token::Slash,
+            '(' => Token::LeftParen,
+            ')' => Token::RightParen,
+            c if c.is_ascii_alphanumeric() || c == '_' => {
+                let mut end = start;
+                while let Some(&(i, c)) = chars.peek() {
+                    if !(c.is_ascii_alphanumeric() || c == '_') {
+                        break;
+                    }
+                    end = i + c.len_utf8();
+                    chars.next();
+                }
+                let word = &input[start..end];
+                tokens.push(if c.is_ascii_digit() {
+                    Token::Number(parse_number(word)?)
+                } else {
+                    Token::Identifier(word.to_string())
+                });
+                continue;
+            }
+            c => return Err(format!("Invalid character '{}' in expression", c)),
+        };
+        chars.next();
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+/// Parses a decimal or 0x-prefixed hex constant
+fn parse_number(word: &str) -> Result<i64, String> {
+    let parsed = match word.strip_prefix("0x").or_else(|| word.strip_prefix("0X")) {
+        Some(hex) => i64::from_str_radix(hex, 16),
+        None => word.parse(),
+    };
+    parsed.map_err(|_| format!("Invalid number {}", word))
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn expr(&mut self) -> Result<Expr, String> {
+        let mut left = self.term()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Plus) => BinaryOp::Add,
+                Some(Token::Minus) => BinaryOp::Sub,
+                _ => return Ok(left),
+            };
+            self.next();
+            left = Expr::Binary(Box::new(left), op, Box::new(self.term()?));
+        }
+    }
+
+    fn term(&mut self) -> Result<Expr, String> {
+        let mut left = self.unary()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Star) => BinaryOp::Mul,
+                Some(Token::Slash) => BinaryOp::Div,
+                _ => return Ok(left),
+            };
+            self.next();
+            left = Expr::Binary(Box::new(left), op, Box::new(self.unary()?));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.peek() {
+            Some(Token::Minus) => {
+                self.next();
+                Ok(Expr::Negate(Box::new(self.unary()?)))
+            }
+            Some(Token::Star) => {
+                self.next();
+                Ok(Expr::Deref(Box::new(self.unary()?)))
+            }
+            _ => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Number(value)) => Ok(Expr::Number(value)),
+            Some(Token::Identifier(name)) => Ok(Expr::Variable(name)),
+            Some(Token::LeftParen) => {
+                let inner = self.expr()?;
+                match self.next() {
+                    Some(Token::RightParen) => Ok(inner),
+                    _ => Err(String::from("Missing ')' in expression")),
+                }
+            }
+            Some(token) => Err(format!("Unexpected {:?} in expression", token)),
+            None => Err(String::from("Incomplete expression")),
+        }
+    }
+}
+
+/// Parses an expression. Returns a message describing the problem if it isn't valid.
+pub fn parse(input: &str) -> Result<Expr, String> {
+    let mut parser = Parser {
+        tokens: tokenize(input)?,
+        position: 0,
+    };
+    let expr = parser.expr()?;
+    match parser.peek() {
+        None => Ok(expr),
+        Some(token) => Err(format!("Unexpected {:?} in expression", token)),
+    }
+}
+
+/// Evaluates an expression. Arithmetic is done on 64-bit integers and wraps on overflow, like
+/// the inferior's own arithmetic would.
+pub fn evaluate(expr: &Expr, context: &impl Context) -> Result<Value, String> {
+    match expr {
+        Expr::Number(value) => Ok(Value::long(*value)),
+        Expr::Variable(name) => match context.variable(name)? {
+            Some((value_type, bytes)) => Ok(Value { value_type, bytes }),
+            None => Err(format!("No symbol \"{}\" in current context.", name)),
+        },
+        Expr::Negate(inner) => {
+            let value = evaluate(inner, context)?.as_integer()?;
+            Ok(Value::long(value.wrapping_neg()))
+        }
+        Expr::Deref(inner) => {
+            let pointer = evaluate(inner, context)?;
+            let pointee = match &pointer.value_type.pointee {
+                Some(pointee) if pointee.size > 0 => (**pointee).clone(),
+                Some(_) => return Err(format!("Cannot dereference a {}", pointer.value_type.name)),
+                None => return Err(String::from("Attempt to take contents of a non-pointer value.")),
+            };
+            let addr = value_format::decode_integer(&pointer.bytes, "unsigned long")
+                .ok_or_else(|| format!("Cannot dereference a {}", pointer.value_type.name))?
+                as usize;
+            let bytes = context.read_memory(addr, pointee.size)?;
+            Ok(Value {
+                value_type: pointee,
+                bytes,
+            })
+        }
+        Expr::Binary(left, op, right) => {
+            let left = evaluate(left, context)?.as_integer()?;
+            let right = evaluate(right, context)?.as_integer()?;
+            let result = match op {
+                BinaryOp::Add => left.wrapping_add(right),
+                BinaryOp::Sub => left.wrapping_sub(right),
+                BinaryOp::Mul => left.wrapping_mul(right),
+                BinaryOp::Div if right == 0 => return Err(String::from("Division by zero")),
+                BinaryOp::Div => left.wrapping_div(right),
+            };
+            Ok(Value::long(result))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    /// Variables `a` = 47, `b` = 5, `c` = 'A', and `p` pointing at `a` (at address 0x1000)
+    struct FakeFrame {
+        variables: HashMap<&'static str, (Type, Vec<u8>)>,
+        memory: HashMap<usize, Vec<u8>>,
+    }
+
+    impl FakeFrame {
+        fn new() -> FakeFrame {
+            let int = Type::new(String::from("int"), 4);
+            let mut variables = HashMap::new();
+            variables.insert("a", (int.clone(), 47_i32.to_le_bytes().to_vec()));
+            variables.insert("b", (int.clone(), 5_i32.to_le_bytes().to_vec()));
+            variables.insert("c", (Type::new(String::from("char"), 1), vec![b'A']));
+            variables.insert("p", (Type::pointer_to(int, 8), 0x1000_u64.to_le_bytes().to_vec()));
+            let mut memory = HashMap::new();
+            memory.insert(0x1000, 47_i32.to_le_bytes().to_vec());
+            FakeFrame { variables, memory }
+        }
+    }
+
+    impl Context for FakeFrame {
+        fn variable(&self, name: &str) -> Result<Option<(Type, Vec<u8>)>, String> {
+            Ok(self.variables.get(name).cloned())
+        }
+
+        fn read_memory(&self, addr: usize, len: usize) -> Result<Vec<u8>, String> {
+            match self.memory.get(&addr) {
+                Some(bytes) => Ok(bytes[..len].to_vec()),
+                None => Err(format!("Cannot access memory at address {:#x}", addr)),
+            }
+        }
+    }
+
+    fn eval(input: &str) -> Result<i64, String> {
+        let value = evaluate(&parse(input)?, &FakeFrame::new())?;
+        value.as_integer()
+    }
+
+    #[test]
+    fn test_operators() {
+        assert_eq!(eval("a + 3"), Ok(50));
+        assert_eq!(eval("a - b"), Ok(42));
+        assert_eq!(eval("a * b"), Ok(235));
+        assert_eq!(eval("a / b"), Ok(9));
+        assert_eq!(eval("-a / b"), Ok(-9));
+        assert_eq!(eval("c + 1"), Ok(66));
+    }
+
+    #[test]
+    fn test_precedence_and_parentheses() {
+        assert_eq!(eval("1 + 2 * 3"), Ok(7));
+        assert_eq!(eval("(1 + 2) * 3"), Ok(9));
+        assert_eq!(eval("10 - 4 - 3"), Ok(3));
+        assert_eq!(eval("0x10+1"), Ok(17));
+    }
+
+    #[test]
+    fn test_dereference() {
+        let value = evaluate(&parse("*p").unwrap(), &FakeFrame::new()).unwrap();
+        assert_eq!(value.value_type.name, "int");
+        assert_eq!(value.bytes, 47_i32.to_le_bytes().to_vec());
+        assert_eq!(eval("*p * 2"), Ok(94));
+        assert_eq!(eval("*p - a"), Ok(0));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(eval("a / 0"), Err(String::from("Division by zero")));
+        assert_eq!(eval("nope + 1"), Err(String::from("No symbol \"nope\" in current context.")));
+        assert_eq!(eval("*a"), Err(String::from("Attempt to take contents of a non-pointer value.")));
+        assert_eq!(eval("p + 1"), Err(String::from("Pointer arithmetic is not supported")));
+        assert!(eval("a +").is_err());
+        assert!(eval("(a + 1").is_err());
+        assert!(eval("a b").is_err());
+        assert!(eval("a % b").is_err());
+    }
+}
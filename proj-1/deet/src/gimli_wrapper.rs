@@ -39,22 +39,26 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
     // Create `EndianSlice`s for all of the sections.
     let dwarf = dwarf_cow.borrow(&borrow_section);
 
-    // Define a mapping from type offsets to type structs
-    let mut offset_to_type: HashMap<usize, Type> = HashMap::new();
-
     let mut compilation_units: Vec<File> = Vec::new();
 
     // Iterate over the compilation units.
     let mut iter = dwarf.units();
     while let Some(header) = iter.next()? {
         let unit = dwarf.unit(header)?;
+        // Define a mapping from type offsets to type structs
+        let offset_to_type = collect_types(&unit, &dwarf)?;
 
         // Iterate over the Debugging Information Entries (DIEs) in the unit.
         let mut depth = 0;
+        // The lexical blocks enclosing the current entry, innermost last, with their depth and
+        // address range (start, end)
+        let mut blocks: Vec<(isize, Option<(usize, usize)>)> = Vec::new();
         let mut entries = unit.entries();
         while let Some((delta_depth, entry)) = entries.next_dfs()? {
             depth += delta_depth;
-            // Update the offset_to_type mapping for types
+            while blocks.last().is_some_and(|(block_depth, _)| *block_depth >= depth) {
+                blocks.pop();
+            }
             // Update the variable list for formal params/variables
             match entry.tag() {
                 gimli::DW_TAG_compile_unit => {
@@ -92,33 +96,6 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                         lines: Vec::new(),
                     });
                 }
-                gimli::DW_TAG_base_type => {
-                    let name = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_name) {
-                        if let Ok(DebugValue::Str(name)) = get_attr_value(&attr, &unit, &dwarf) {
-                            name
-                        } else {
-                            "<unknown>".to_string()
-                        }
-                    } else {
-                        "<unknown>".to_string()
-                    };
-                    let byte_size = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_byte_size) {
-                        if let Ok(DebugValue::Uint(byte_size)) =
-                            get_attr_value(&attr, &unit, &dwarf)
-                        {
-                            byte_size
-                        } else {
-                            // TODO: report error?
-                            0
-                        }
-                    } else {
-                        // TODO: report error?
-                        0
-                    };
-                    let type_offset = entry.offset().0;
-                    offset_to_type
-                        .insert(type_offset, Type::new(name, byte_size.try_into().unwrap()));
-                }
                 gimli::DW_TAG_subprogram => {
                     let mut func: Function = Default::default();
                     let mut attrs = entry.attrs();
@@ -152,6 +129,30 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                     }
                     compilation_units.last_mut().unwrap().functions.push(func);
                 }
+                gimli::DW_TAG_lexical_block => {
+                    let mut low_pc = None;
+                    let mut length: Option<usize> = None;
+                    let mut attrs = entry.attrs();
+                    while let Some(attr) = attrs.next()? {
+                        match (attr.name(), get_attr_value(&attr, &unit, &dwarf)) {
+                            (gimli::DW_AT_low_pc, Ok(DebugValue::Uint(val))) => {
+                                low_pc = Some(val.try_into().unwrap())
+                            }
+                            (gimli::DW_AT_high_pc, Ok(DebugValue::Uint(val))) => {
+                                length = Some(val.try_into().unwrap())
+                            }
+                            _ => {}
+                        }
+                    }
+                    // Blocks described with DW_AT_ranges (or without addresses) keep the scope of
+                    // the enclosing block, so their variables are visible a little too widely
+                    // rather than not at all
+                    let scope = match (low_pc, length) {
+                        (Some(start), Some(length)) => Some((start, start + length)),
+                        _ => blocks.last().and_then(|(_, scope)| *scope),
+                    };
+                    blocks.push((depth, scope));
+                }
                 gimli::DW_TAG_formal_parameter | gimli::DW_TAG_variable => {
                     let mut name = String::new();
                     let mut entity_type: Option<Type> = None;
@@ -193,6 +194,7 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                             entity_type: entity_type.unwrap(),
                             location: location.unwrap(),
                             line_number: line_number.try_into().unwrap(),
+                            scope: blocks.last().and_then(|(_, scope)| *scope),
                         };
                         if depth == 1 {
                             compilation_units
@@ -274,6 +276,108 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
     Ok(compilation_units)
 }
 
+/// A pointer, const, volatile or typedef DIE, which is defined in terms of another type
+struct DerivedType {
+    tag: gimli::DwTag,
+    size: usize,
+    /// Offset of the type this one is derived from (None for `void *`)
+    target: Option<usize>,
+}
+
+/// How many derived types deep resolve_derived_type will go (e.g. `const char **` is three)
+const MAX_DERIVED_TYPE_DEPTH: usize = 16;
+
+/// Collects the types declared in a unit, keyed by their .debug_info offset (which is what
+/// DW_AT_type attributes hold). This is done before reading variables, because gcc often declares a
+/// pointer type after the first variable that uses it.
+fn collect_types<R: Reader>(
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Result<HashMap<usize, Type>, Error> {
+    let mut types = HashMap::new();
+    let mut derived = HashMap::new();
+    let mut entries = unit.entries();
+    while let Some((_, entry)) = entries.next_dfs()? {
+        let tag = entry.tag();
+        if !matches!(
+            tag,
+            gimli::DW_TAG_base_type
+                | gimli::DW_TAG_pointer_type
+                | gimli::DW_TAG_const_type
+                | gimli::DW_TAG_volatile_type
+                | gimli::DW_TAG_typedef
+        ) {
+            continue;
+        }
+        let offset = match entry.offset().to_unit_section_offset(unit) {
+            UnitSectionOffset::DebugInfoOffset(goff) => goff.0,
+            UnitSectionOffset::DebugTypesOffset(goff) => goff.0,
+        };
+        let mut name = "<unknown>".to_string();
+        let mut size = 0;
+        let mut target = None;
+        let mut attrs = entry.attrs();
+        while let Some(attr) = attrs.next()? {
+            match (attr.name(), get_attr_value(&attr, unit, dwarf)) {
+                (gimli::DW_AT_name, Ok(DebugValue::Str(attr_name))) => name = attr_name,
+                (gimli::DW_AT_byte_size, Ok(DebugValue::Uint(byte_size))) => {
+                    size = byte_size.try_into().unwrap()
+                }
+                (gimli::DW_AT_type, Ok(DebugValue::Size(type_offset))) => target = Some(type_offset),
+                _ => {}
+            }
+        }
+        if tag == gimli::DW_TAG_base_type {
+            types.insert(offset, Type::new(name, size));
+        } else {
+            derived.insert(offset, DerivedType { tag, size, target });
+        }
+    }
+    let offsets: Vec<usize> = derived.keys().copied().collect();
+    for offset in offsets {
+        if let Some(resolved) = resolve_derived_type(offset, &derived, &types, 0) {
+            types.insert(offset, resolved);
+        }
+    }
+    Ok(types)
+}
+
+/// Works out a derived type from the type it refers to, resolving that one first if it is derived
+/// too. Returns None if it refers to a type we don't parse (a pointer to a struct, say).
+fn resolve_derived_type(
+    offset: usize,
+    derived: &HashMap<usize, DerivedType>,
+    types: &HashMap<usize, Type>,
+    depth: usize,
+) -> Option<Type> {
+    if let Some(known) = types.get(&offset) {
+        return Some(known.clone());
+    }
+    let entry = derived.get(&offset)?;
+    if depth >= MAX_DERIVED_TYPE_DEPTH {
+        return None;
+    }
+    let target = match entry.target {
+        Some(target) => Some(resolve_derived_type(target, derived, types, depth + 1)?),
+        None => None,
+    };
+    match entry.tag {
+        gimli::DW_TAG_pointer_type => {
+            let pointee = target.unwrap_or_else(|| Type::new("void".to_string(), 0));
+            Some(Type::pointer_to(pointee, entry.size))
+        }
+        gimli::DW_TAG_const_type | gimli::DW_TAG_volatile_type => {
+            let mut qualified = target?;
+            let qualifier = if entry.tag == gimli::DW_TAG_const_type { "const" } else { "volatile" };
+            qualified.name = format!("{} {}", qualifier, qualified.name);
+            Some(qualified)
+        }
+        // Values are formatted by the name of their type, so a typedef keeps the name of the type
+        // it stands for (an int32_t prints like an int)
+        _ => target,
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum DebugValue {
     Str(String),
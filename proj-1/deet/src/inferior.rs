@@ -2,14 +2,18 @@ use nix::sys::ptrace;
 use nix::sys::signal;
 use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
 use nix::unistd::Pid;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::mem::size_of;
 use std::process::Child;
 use std::process::Command;
 #[cfg(unix)]
 use std::os::unix::process::CommandExt;
 
-use crate::dwarf_data::DwarfData;
+use crate::disassemble::{self, DisassembledInstruction};
+use crate::expression;
+use crate::value_format::{self, ValueFormat};
+use crate::dwarf_data::{DwarfData, Line, Type};
+use crate::unwind::{CfaRegister, UnwindRule};
 
 fn align_addr_to_word(addr: usize) -> usize {
     addr & (-(size_of::<usize>() as isize) as usize)
@@ -21,6 +25,57 @@ struct Breakpoint {
     orig_byte: u8,
 }
 
+/// Backtraces stop after this many frames, in case the frame pointer chain loops
+const MAX_BACKTRACE_FRAMES: usize = 128;
+
+/// One stack frame found while walking the stack
+#[derive(Debug)]
+pub struct Frame {
+    pub rip: usize,
+    /// The frame pointer while this frame is executing
+    pub rbp: usize,
+    /// The canonical frame address, i.e. the caller's rsp before the call instruction. gcc emits
+    /// DW_AT_frame_base as DW_OP_call_frame_cfa, so this is what variables' locations are
+    /// relative to
+    pub cfa: usize,
+    pub function: Option<String>,
+    pub line: Option<Line>,
+}
+
+/// The registers needed to find a frame's caller
+#[derive(Debug, Clone, Copy)]
+struct UnwindRegisters {
+    rip: usize,
+    rsp: usize,
+    rbp: usize,
+}
+
+/// The result of unwinding one frame
+enum Unwound {
+    Caller(UnwindRegisters),
+    /// There is no caller: this is the outermost frame
+    End,
+    /// The saved registers don't look like a real stack
+    Inconsistent,
+}
+
+/// The CFA of the frame whose registers are `regs`, according to its call frame information
+fn cfa_with_cfi(regs: UnwindRegisters, rule: &UnwindRule) -> usize {
+    let base = match rule.cfa_register {
+        CfaRegister::Rsp => regs.rsp,
+        CfaRegister::Rbp => regs.rbp,
+    };
+    base.wrapping_add(rule.cfa_offset as usize)
+}
+
+#[derive(Debug)]
+pub struct Backtrace {
+    /// Frames from innermost to outermost
+    pub frames: Vec<Frame>,
+    /// Set if the walk was cut short because the frames looked inconsistent
+    pub unreliable: bool,
+}
+
 #[derive(Debug)]
 pub enum Status {
     /// Indicates inferior stopped. Contains the signal that stopped the process, as well as the
@@ -35,6 +90,30 @@ pub enum Status {
     Signaled(signal::Signal),
 }
 
+/// Changes to deet's own working directory and environment that the inferior is launched with,
+/// set by `set cwd`, `set env` and `unset env`
+#[derive(Debug, Clone, Default)]
+pub struct LaunchEnvironment {
+    /// Directory to start the inferior in (None = deet's working directory)
+    pub cwd: Option<String>,
+    /// Variables to set (Some) or remove (None); everything else is inherited from deet
+    pub env: BTreeMap<String, Option<String>>,
+}
+
+impl LaunchEnvironment {
+    fn apply(&self, command: &mut Command) {
+        if let Some(cwd) = &self.cwd {
+            command.current_dir(cwd);
+        }
+        for (key, value) in &self.env {
+            match value {
+                Some(value) => command.env(key, value),
+                None => command.env_remove(key),
+            };
+        }
+    }
+}
+
 /// This function calls ptrace with PTRACE_TRACEME to enable debugging on a process. You should use
 /// pre_exec with Command to call this in the child process.
 fn child_traceme() -> Result<(), std::io::Error> {
@@ -47,6 +126,9 @@ fn child_traceme() -> Result<(), std::io::Error> {
 pub struct Inferior {
     child: Child,
     breakpoints: HashMap<usize, Breakpoint>,
+    /// A signal other than SIGTRAP that stopped the inferior. It's delivered when the inferior is
+    /// continued, like gdb does; otherwise e.g. a segfaulting instruction would just run again.
+    pending_signal: Option<signal::Signal>,
 }
 
 impl Inferior {
@@ -75,12 +157,28 @@ impl Inferior {
         Ok(orig_byte)
     }
 
-    /// Attempts to start a new inferior process. Returns Some(Inferior) if successful, or None if
-    /// an error is encountered.
-    pub fn new(target: &str, args: &Vec<String>, breakpoints: &Vec<usize>) -> Option<Inferior> {
+    /// Removes the breakpoint at `addr`, if there is one, putting the original byte back. The
+    /// byte may already be back if the inferior is stopped at this breakpoint; writing it again
+    /// is harmless.
+    pub fn remove_breakpoint(&mut self, addr: usize) -> Result<(), nix::Error> {
+        if let Some(breakpoint) = self.breakpoints.remove(&addr) {
+            self.write_byte(addr, breakpoint.orig_byte)?;
+        }
+        Ok(())
+    }
+
+    /// Attempts to start a new inferior process in the given working directory and environment.
+    /// Returns Some(Inferior) if successful, or None if an error is encountered.
+    pub fn new(
+        target: &str,
+        args: &Vec<String>,
+        breakpoints: &Vec<usize>,
+        launch: &LaunchEnvironment,
+    ) -> Option<Inferior> {
         // Create a new command to execute the target program
         let mut command = Command::new(target);
         command.args(args);
+        launch.apply(&mut command);
         
         // Use pre_exec to call ptrace TRACEME in the child process before exec
         #[cfg(unix)]
@@ -101,6 +199,7 @@ impl Inferior {
         let mut inferior = Inferior { 
             child,
             breakpoints: HashMap::new(),
+            pending_signal: None,
         };
         
         // Wait for the child to stop (it will stop immediately after exec due to PTRACE_TRACEME)
@@ -138,6 +237,24 @@ impl Inferior {
         nix::unistd::Pid::from_raw(self.child.id() as i32)
     }
 
+    /// Describes what the inferior is doing, from the state letter in /proc/<pid>/stat: "stopped"
+    /// (e.g. at a breakpoint), "running", or "exited" once it has exited and been waited on.
+    pub fn run_state(&self) -> &'static str {
+        let stat = match std::fs::read_to_string(format!("/proc/{}/stat", self.pid())) {
+            Ok(stat) => stat,
+            Err(_) => return "exited",
+        };
+        // The command name in parentheses may contain spaces, so look after the last ')'
+        let state = stat
+            .rfind(')')
+            .and_then(|end| stat[end + 1..].split_whitespace().next());
+        match state {
+            Some("t") | Some("T") => "stopped",
+            Some("Z") | Some("X") | None => "exited",
+            Some(_) => "running",
+        }
+    }
+
     /// Calls waitpid on this inferior and returns a Status to indicate the state of the process
     /// after the waitpid call.
     pub fn wait(&self, options: Option<WaitPidFlag>) -> Result<Status, nix::Error> {
@@ -155,38 +272,27 @@ impl Inferior {
     /// Continues execution of the inferior process and waits until it stops or terminates.
     /// Returns the status of the inferior after it stops.
     pub fn cont(&mut self) -> Result<Status, nix::Error> {
-        // Step 1: Check if we're currently at a breakpoint
-        let mut regs = ptrace::getregs(self.pid())?;
-        let rip = regs.rip as usize;
-        
-        // When we hit a breakpoint (0xcc INT instruction), the CPU executes it,
-        // triggers SIGTRAP, and rip points to the next instruction.
-        // So we check if (rip - 1) is a breakpoint address.
-        if self.breakpoints.contains_key(&(rip - 1)) {
-            let breakpoint_addr = rip - 1;
-            
-            // Step 2: Single-step to execute the next instruction
-            ptrace::step(self.pid(), None)?;
-            
-            // Step 3: Wait for the step to complete
-            match self.wait(None)? {
-                Status::Exited(exit_code) => return Ok(Status::Exited(exit_code)),
-                Status::Signaled(signal) => return Ok(Status::Signaled(signal)),
-                Status::Stopped(_, _) => {
-                    // Step 4: Restore the breakpoint (write 0xcc back)
-                    self.write_byte(breakpoint_addr, 0xcc)?;
-                }
-            }
+        // Steps 1-4: If we're stopped at a breakpoint, execute the original instruction there
+        // and put the breakpoint back
+        match self.step_over_breakpoint()? {
+            Some(Status::Stopped(_, _)) | None => {}
+            Some(status) => return Ok(status),
         }
         
         // Step 5: Continue normal execution
-        ptrace::cont(self.pid(), None)?;
+        ptrace::cont(self.pid(), self.pending_signal.take())?;
         
         // Step 6: Wait for the inferior to stop or terminate
         let status = self.wait(None)?;
         
-        // Step 7: Check if we stopped at a breakpoint
+        // Step 7: Check if we stopped at a breakpoint. Only a SIGTRAP can come from our 0xcc; any
+        // other signal is reported as is, without touching memory or registers, so the inferior
+        // can be inspected where it stopped.
         match status {
+            Status::Stopped(signal, _) if signal != signal::Signal::SIGTRAP => {
+                self.pending_signal = Some(signal);
+                Ok(status)
+            }
             Status::Stopped(signal, rip) => {
                 // Check if this is a breakpoint hit (rip - 1 matches a breakpoint)
                 if self.breakpoints.contains_key(&(rip - 1)) {
@@ -210,70 +316,270 @@ impl Inferior {
         }
     }
 
+    /// If the inferior is stopped at an installed breakpoint, executes the original instruction
+    /// there with a single step and then reinstalls the 0xcc. Returns the status after the step,
+    /// or None if the inferior wasn't at a breakpoint (in which case nothing was executed).
+    fn step_over_breakpoint(&mut self) -> Result<Option<Status>, nix::Error> {
+        let rip = ptrace::getregs(self.pid())?.rip as usize;
+        let breakpoint = match self.breakpoints.get(&rip) {
+            Some(breakpoint) => breakpoint.clone(),
+            None => return Ok(None),
+        };
+
+        // cont() already restores the original byte when a breakpoint is hit, but the breakpoint
+        // may also have been installed while we were stopped here
+        self.write_byte(breakpoint.addr, breakpoint.orig_byte)?;
+        ptrace::step(self.pid(), None)?;
+        let status = self.wait(None)?;
+        if let Status::Stopped(_, _) = status {
+            self.write_byte(breakpoint.addr, 0xcc)?;
+        }
+        Ok(Some(status))
+    }
+
+    /// Executes exactly one machine instruction and returns the status of the inferior afterwards.
+    pub fn step_instruction(&mut self) -> Result<Status, nix::Error> {
+        if let Some(status) = self.step_over_breakpoint()? {
+            return Ok(status);
+        }
+        ptrace::step(self.pid(), None)?;
+        self.wait(None)
+    }
+
+    /// If the inferior is stopped by a fault (SIGSEGV, SIGBUS, SIGFPE, or SIGILL), returns the
+    /// faulting memory address the kernel reported for it.
+    pub fn fault_address(&self) -> Result<Option<usize>, nix::Error> {
+        match self.pending_signal {
+            Some(signal::Signal::SIGSEGV)
+            | Some(signal::Signal::SIGBUS)
+            | Some(signal::Signal::SIGFPE)
+            | Some(signal::Signal::SIGILL) => {
+                let siginfo = ptrace::getsiginfo(self.pid())?;
+                // si_addr is valid for exactly these signals
+                Ok(Some(unsafe { siginfo.si_addr() } as usize))
+            }
+            _ => Ok(None),
+        }
+    }
+
     pub fn kill(&mut self) -> Result<(), std::io::Error> {
         println!("Killing running inferior (pid {})", self.pid());
         self.child.kill()
     }
 
-    pub fn print_backtrace(&self, debug_data: &DwarfData) -> Result<(), nix::Error> {
-        // Get the register values using ptrace::getregs
+    /// Reads one word of the inferior's memory.
+    fn read_word(&self, addr: usize) -> Result<usize, nix::Error> {
+        Ok(ptrace::read(self.pid(), addr as ptrace::AddressType)? as usize)
+    }
+
+    /// Finds the caller of the frame whose registers are `regs` using the call frame information
+    /// for `regs.rip`.
+    fn unwind_with_cfi(&self, regs: UnwindRegisters, rule: &UnwindRule) -> Unwound {
+        let cfa = cfa_with_cfi(regs, rule);
+        let rip = match self.read_word(cfa.wrapping_add(rule.return_address_offset as usize)) {
+            Ok(rip) => rip,
+            Err(_) => return Unwound::Inconsistent,
+        };
+        let rbp = match rule.rbp_offset {
+            Some(offset) => match self.read_word(cfa.wrapping_add(offset as usize)) {
+                Ok(rbp) => rbp,
+                Err(_) => return Unwound::Inconsistent,
+            },
+            None => regs.rbp,
+        };
+        if rip == 0 {
+            return Unwound::End;
+        }
+        // The caller's rsp is the CFA, which is always above the callee's stack pointer
+        if cfa <= regs.rsp {
+            return Unwound::Inconsistent;
+        }
+        Unwound::Caller(UnwindRegisters { rip, rsp: cfa, rbp })
+    }
+
+    /// Finds the caller of the frame whose registers are `regs` by following the saved frame
+    /// pointer. This only works if the frame keeps rbp pointing at [saved rbp, return address].
+    fn unwind_with_frame_pointer(&self, regs: UnwindRegisters) -> Unwound {
+        // Read the return address (saved rip) from [rbp + 8] and the saved frame pointer
+        // (previous rbp) from [rbp]. If rbp doesn't point into mapped memory, it isn't a frame
+        // pointer.
+        let (rip, rbp) = match (self.read_word(regs.rbp.wrapping_add(8)), self.read_word(regs.rbp)) {
+            (Ok(rip), Ok(rbp)) => (rip, rbp),
+            _ => return Unwound::Inconsistent,
+        };
+
+        // If rbp or rip is 0, we've reached the end of the stack
+        if rbp == 0 || rip == 0 {
+            return Unwound::End;
+        }
+
+        // The stack grows down, so every caller's frame is at a higher address than its
+        // callee's. Anything else means we're following garbage and would likely loop.
+        if rbp <= regs.rbp {
+            return Unwound::Inconsistent;
+        }
+        Unwound::Caller(UnwindRegisters {
+            rip,
+            rsp: regs.rbp + 16,
+            rbp,
+        })
+    }
+
+    /// Walks the inferior's stack. Each frame is unwound using the executable's DWARF call frame
+    /// information (`.eh_frame`/`.debug_frame`) if it has any for that address, which also works
+    /// for code compiled without frame pointers; otherwise the walk follows the saved frame
+    /// pointer. The walk stops at main, at a frame without debug info, or as soon as the stack
+    /// stops looking real, in which case the result is marked unreliable. Only reading the
+    /// registers themselves can fail.
+    pub fn backtrace(&self, debug_data: &DwarfData) -> Result<Backtrace, nix::Error> {
         let regs = ptrace::getregs(self.pid())?;
-        let mut rip = regs.rip as usize;
-        let mut rbp = regs.rbp as usize;
-        
-        loop {
-            // Get the function name from the current instruction address
-            let function_name = match debug_data.get_function_from_addr(rip) {
-                Some(name) => name,
-                None => {
-                    println!("Unknown function at {:#x}", rip);
-                    break;
-                }
+        let mut regs = UnwindRegisters {
+            rip: regs.rip as usize,
+            rsp: regs.rsp as usize,
+            rbp: regs.rbp as usize,
+        };
+        let mut backtrace = Backtrace {
+            frames: Vec::new(),
+            unreliable: false,
+        };
+
+        while backtrace.frames.len() < MAX_BACKTRACE_FRAMES {
+            let function = debug_data.get_function_from_addr(regs.rip);
+            let line = debug_data.get_line_from_addr(regs.rip);
+            let last_frame =
+                function.is_none() || line.is_none() || function.as_deref() == Some("main");
+            // Callers' rip is a return address, which may already belong to the next function if
+            // the call was the last instruction of the caller, so look up the CFI for the call
+            // instruction itself
+            let lookup_addr = if backtrace.frames.is_empty() {
+                regs.rip
+            } else {
+                regs.rip - 1
             };
-            
-            // Get the source file and line number from the current instruction address
-            let line = match debug_data.get_line_from_addr(rip) {
-                Some(line) => line,
-                None => {
-                    println!("Unknown location for function {}", function_name);
-                    break;
-                }
+            let rule = debug_data.get_unwind_rule(lookup_addr);
+            // Without CFI, assume the frame has pushed rbp right below the return address
+            let cfa = match &rule {
+                Some(rule) => cfa_with_cfi(regs, rule),
+                None => regs.rbp.wrapping_add(16),
             };
-            
-            // Print the backtrace information
-            println!("{} ({}:{})", function_name, line.file, line.number);
-            
-            // Check if we've reached the main function
-            if function_name == "main" {
-                break;
+            backtrace.frames.push(Frame {
+                rip: regs.rip,
+                rbp: regs.rbp,
+                cfa,
+                function,
+                line,
+            });
+            if last_frame {
+                return Ok(backtrace);
             }
-    
-            // Read the return address (saved rip) from [rbp + 8]
-            rip = ptrace::read(self.pid(), (rbp + 8) as ptrace::AddressType)? as usize;
-            
-            // Read the saved frame pointer (previous rbp) from [rbp]
-            rbp = ptrace::read(self.pid(), rbp as ptrace::AddressType)? as usize;
-            
-            // Safety check: if rbp is 0 or rip is 0, we've reached the end of the stack
-            if rbp == 0 || rip == 0 {
-                break;
+
+            let unwound = match rule {
+                Some(rule) => self.unwind_with_cfi(regs, &rule),
+                None => self.unwind_with_frame_pointer(regs),
+            };
+            match unwound {
+                Unwound::Caller(caller) => regs = caller,
+                Unwound::End => return Ok(backtrace),
+                Unwound::Inconsistent => {
+                    backtrace.unreliable = true;
+                    return Ok(backtrace);
+                }
             }
         }
-        
+
+        // A real program could recurse this deep, but far more likely the stack is corrupt
+        backtrace.unreliable = true;
+        Ok(backtrace)
+    }
+
+    pub fn print_backtrace(&self, debug_data: &DwarfData) -> Result<(), nix::Error> {
+        print!("{}", self.format_backtrace(debug_data)?);
+        Ok(())
+    }
+
+    /// The backtrace as `print_backtrace` prints it, one line per frame
+    pub fn format_backtrace(&self, debug_data: &DwarfData) -> Result<String, nix::Error> {
+        let backtrace = self.backtrace(debug_data)?;
+        let mut output = String::new();
+        for frame in &backtrace.frames {
+            let line = match (&frame.function, &frame.line) {
+                (Some(function_name), Some(line)) => {
+                    format!("{} ({}:{})", function_name, line.file, line.number)
+                }
+                (Some(function_name), None) => format!("Unknown location for function {}", function_name),
+                (None, _) => format!("Unknown function at {:#x}", frame.rip),
+            };
+            output += &line;
+            output.push('\n');
+        }
+        if backtrace.unreliable {
+            output += "Backtrace may be unreliable (the stack looks inconsistent; was the program compiled with -fomit-frame-pointer and without unwind tables?)\n";
+        }
+        Ok(output)
+    }
+
+    /// Reads `len` bytes of the inferior's memory starting at `addr`. Any installed breakpoints in
+    /// that range are replaced with the original bytes, so the result is what the program's code
+    /// actually looks like. If part of the range can't be read (e.g. it runs off the end of a
+    /// mapping), the bytes read so far are returned.
+    pub fn read_memory(&self, addr: usize, len: usize) -> Result<Vec<u8>, nix::Error> {
+        let mut bytes = Vec::with_capacity(len);
+        let start = align_addr_to_word(addr);
+        let mut word_addr = start;
+        while word_addr < addr + len {
+            let word = match ptrace::read(self.pid(), word_addr as ptrace::AddressType) {
+                Ok(word) => word as u64,
+                Err(e) if word_addr == start => return Err(e),
+                Err(_) => break,
+            };
+            bytes.extend_from_slice(&word.to_le_bytes());
+            word_addr += size_of::<usize>();
+        }
+        for breakpoint in self.breakpoints.values() {
+            if breakpoint.addr >= start && breakpoint.addr < start + bytes.len() {
+                bytes[breakpoint.addr - start] = breakpoint.orig_byte;
+            }
+        }
+        let offset = addr - start;
+        bytes.drain(..offset);
+        bytes.truncate(len);
+        Ok(bytes)
+    }
+
+    /// Disassembles the next `num_instructions` instructions starting at the current instruction
+    /// pointer.
+    pub fn disassemble_at_rip(
+        &self,
+        num_instructions: usize,
+    ) -> Result<Vec<DisassembledInstruction>, nix::Error> {
+        let rip = ptrace::getregs(self.pid())?.rip as usize;
+        let bytes = self.read_memory(rip, num_instructions * disassemble::MAX_INSTRUCTION_LENGTH)?;
+        Ok(disassemble::disassemble(&bytes, rip, num_instructions))
+    }
+
+    /// Prints the next `num_instructions` instructions starting at the current instruction
+    /// pointer, with an arrow marking where the inferior is stopped.
+    pub fn print_disassembly(&self, num_instructions: usize) -> Result<(), nix::Error> {
+        let rip = ptrace::getregs(self.pid())?.rip as usize;
+        for instruction in self.disassemble_at_rip(num_instructions)? {
+            let marker = if instruction.address == rip { "=>" } else { "  " };
+            println!("{} {:#x}:  {}", marker, instruction.address, instruction.text);
+        }
         Ok(())
     }
 
     /// Read variable value from inferior's memory based on location
-    fn read_variable_value(&self, location: &crate::dwarf_data::Location, rbp: usize, size: usize) -> Result<Vec<u8>, nix::Error> {
+    /// `frame_base` is the CFA of the frame the variable belongs to
+    fn read_variable_value(&self, location: &crate::dwarf_data::Location, frame_base: usize, size: usize) -> Result<Vec<u8>, nix::Error> {
         use crate::dwarf_data::Location;
         
         let addr = match location {
             Location::Address(addr) => *addr,
             Location::FramePointerOffset(offset) => {
                 if *offset >= 0 {
-                    rbp + (*offset as usize)
+                    frame_base + (*offset as usize)
                 } else {
-                    rbp - ((-*offset) as usize)
+                    frame_base - ((-*offset) as usize)
                 }
             }
         };
@@ -294,11 +600,74 @@ impl Inferior {
         Ok(bytes)
     }
 
-    /// Print all variables available at the current instruction pointer
-    pub fn print_variables(&self, debug_data: &DwarfData) -> Result<(), nix::Error> {
-        let regs = ptrace::getregs(self.pid())?;
-        let rip = regs.rip as usize;
-        let rbp = regs.rbp as usize;
+    /// Returns the given stack frame (0 is the innermost), or None if the stack isn't that deep.
+    pub fn frame(&self, debug_data: &DwarfData, frame_index: usize) -> Result<Option<Frame>, nix::Error> {
+        Ok(self.backtrace(debug_data)?.frames.into_iter().nth(frame_index))
+    }
+
+    /// Reads the value of the variable `name` as seen from the given stack frame. Returns None if
+    /// the frame doesn't exist or no such variable is in scope there.
+    #[cfg(test)]
+    pub fn read_variable(
+        &self,
+        debug_data: &DwarfData,
+        frame_index: usize,
+        name: &str,
+    ) -> Result<Option<Vec<u8>>, nix::Error> {
+        Ok(self
+            .read_typed_variable(debug_data, frame_index, name)?
+            .map(|(_, bytes)| bytes))
+    }
+
+    /// Like `read_variable`, but also returns the variable's type so the value can be formatted.
+    pub fn read_typed_variable(
+        &self,
+        debug_data: &DwarfData,
+        frame_index: usize,
+        name: &str,
+    ) -> Result<Option<(Type, Vec<u8>)>, nix::Error> {
+        let frame = match self.frame(debug_data, frame_index)? {
+            Some(frame) => frame,
+            None => return Ok(None),
+        };
+        let (global_vars, local_vars) = match debug_data.get_variables_at_addr(frame.rip) {
+            Some(vars) => vars,
+            None => return Ok(None),
+        };
+        // Locals shadow globals
+        match local_vars.iter().chain(global_vars.iter()).find(|var| var.name == name) {
+            Some(var) => Ok(Some((
+                var.entity_type.clone(),
+                self.read_variable_value(&var.location, frame.cfa, var.entity_type.size)?,
+            ))),
+            None => Ok(None),
+        }
+    }
+
+    /// The variables and memory `print` expressions see in the given stack frame
+    pub fn frame_context<'a>(&'a self, debug_data: &'a DwarfData, frame_index: usize) -> FrameContext<'a> {
+        FrameContext {
+            inferior: self,
+            debug_data,
+            frame_index,
+        }
+    }
+
+    /// Print all variables available in the given stack frame (0 is the innermost), each formatted
+    /// with `format`
+    pub fn print_variables(
+        &self,
+        debug_data: &DwarfData,
+        frame_index: usize,
+        format: ValueFormat,
+    ) -> Result<(), nix::Error> {
+        let (rip, cfa) = match self.frame(debug_data, frame_index)? {
+            Some(frame) => (frame.rip, frame.cfa),
+            None => {
+                println!("No frame {}", frame_index);
+                return Ok(());
+            }
+        };
         
         // Get variables at the current address
         if let Some((global_vars, local_vars)) = debug_data.get_variables_at_addr(rip) {
@@ -308,10 +677,10 @@ impl Inferior {
             if !global_vars.is_empty() {
                 println!("Global variables:");
                 for var in &global_vars {
-                    match self.read_variable_value(&var.location, rbp, var.entity_type.size) {
+                    match self.read_variable_value(&var.location, cfa, var.entity_type.size) {
                         Ok(bytes) => {
                             print!("  {} ({}, {} bytes) = ", var.name, var.entity_type.name, var.entity_type.size);
-                            self.print_formatted_value(&bytes, &var.entity_type.name);
+                            println!("{}", value_format::format_value(&bytes, &var.entity_type.name, format));
                         }
                         Err(e) => {
                             println!("  {} ({}, {} bytes) = <error reading: {}>", 
@@ -325,10 +694,10 @@ impl Inferior {
             if !local_vars.is_empty() {
                 println!("Local variables:");
                 for var in &local_vars {
-                    match self.read_variable_value(&var.location, rbp, var.entity_type.size) {
+                    match self.read_variable_value(&var.location, cfa, var.entity_type.size) {
                         Ok(bytes) => {
                             print!("  {} ({}, {} bytes) = ", var.name, var.entity_type.name, var.entity_type.size);
-                            self.print_formatted_value(&bytes, &var.entity_type.name);
+                            println!("{}", value_format::format_value(&bytes, &var.entity_type.name, format));
                         }
                         Err(e) => {
                             println!("  {} ({}, {} bytes) = <error reading: {}>", 
@@ -347,47 +716,180 @@ impl Inferior {
         
         Ok(())
     }
+}
+
+/// Evaluates `print` expressions against one stack frame of an inferior
+pub struct FrameContext<'a> {
+    inferior: &'a Inferior,
+    debug_data: &'a DwarfData,
+    frame_index: usize,
+}
+
+impl expression::Context for FrameContext<'_> {
+    fn variable(&self, name: &str) -> Result<Option<(Type, Vec<u8>)>, String> {
+        self.inferior
+            .read_typed_variable(self.debug_data, self.frame_index, name)
+            .map_err(|err| format!("Error reading {}: {}", name, err))
+    }
 
-    /// Format and print value based on type
-    fn print_formatted_value(&self, bytes: &[u8], type_name: &str) {
-        if bytes.is_empty() {
-            println!("<empty>");
-            return;
+    fn read_memory(&self, addr: usize, len: usize) -> Result<Vec<u8>, String> {
+        match self.inferior.read_memory(addr, len) {
+            Ok(bytes) if bytes.len() == len => Ok(bytes),
+            _ => Err(format!("Cannot access memory at address {:#x}", addr)),
         }
-        
-        // Try to interpret based on type name
-        match type_name {
-            "int" | "i32" if bytes.len() == 4 => {
-                let value = i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
-                println!("{}", value);
-            }
-            "long" | "i64" | "long int" if bytes.len() == 8 => {
-                let value = i64::from_le_bytes([
-                    bytes[0], bytes[1], bytes[2], bytes[3],
-                    bytes[4], bytes[5], bytes[6], bytes[7]
-                ]);
-                println!("{}", value);
-            }
-            "char" | "i8" if bytes.len() == 1 => {
-                let value = bytes[0] as i8;
-                if value >= 32 && value <= 126 {
-                    println!("{} ('{}')", value, value as u8 as char);
-                } else {
-                    println!("{}", value);
-                }
-            }
-            "short" | "i16" if bytes.len() == 2 => {
-                let value = i16::from_le_bytes([bytes[0], bytes[1]]);
-                println!("{}", value);
-            }
-            _ => {
-                // Default: print as hex bytes
-                print!("0x");
-                for byte in bytes.iter().rev() {
-                    print!("{:02x}", byte);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The samples are built by running `make` in the deet directory
+    fn load_sample(name: &str) -> (String, DwarfData) {
+        let path = format!("{}/samples/{}", env!("CARGO_MANIFEST_DIR"), name);
+        let debug_data = DwarfData::from_file(&path)
+            .unwrap_or_else(|err| panic!("Could not load {} (did you run make?): {:?}", path, err));
+        (path, debug_data)
+    }
+
+    #[test]
+    fn test_backtrace_terminates_without_frame_pointers() {
+        // Built with -O2 -fomit-frame-pointer
+        let (path, debug_data) = load_sample("optimized");
+        let leaf = debug_data.get_addr_for_function(None, "leaf").unwrap();
+        let mut inferior =
+            Inferior::new(&path, &Vec::new(), &vec![leaf], &LaunchEnvironment::default()).unwrap();
+        match inferior.cont().unwrap() {
+            Status::Stopped(signal::Signal::SIGTRAP, _) => {}
+            other => panic!("Expected to stop at leaf, got {:?}", other),
+        }
+
+        let backtrace = inferior.backtrace(&debug_data).unwrap();
+        assert!(backtrace.frames.len() <= MAX_BACKTRACE_FRAMES);
+        assert_eq!(backtrace.frames[0].function.as_deref(), Some("leaf"));
+        inferior.kill().unwrap();
+    }
+
+    #[test]
+    fn test_cfi_backtrace_without_frame_pointers() {
+        // Built with -O2 -fomit-frame-pointer, so only the CFI can find leaf's callers
+        let (path, debug_data) = load_sample("optimized");
+        let leaf = debug_data.get_addr_for_function(None, "leaf").unwrap();
+        let mut inferior =
+            Inferior::new(&path, &Vec::new(), &vec![leaf], &LaunchEnvironment::default()).unwrap();
+        match inferior.cont().unwrap() {
+            Status::Stopped(signal::Signal::SIGTRAP, _) => {}
+            other => panic!("Expected to stop at leaf, got {:?}", other),
+        }
+
+        let backtrace = inferior.backtrace(&debug_data).unwrap();
+        let functions: Vec<_> = backtrace
+            .frames
+            .iter()
+            .map(|frame| frame.function.as_deref().unwrap_or("?"))
+            .collect();
+        assert_eq!(functions, ["leaf", "middle", "main"]);
+        assert!(!backtrace.unreliable);
+        inferior.kill().unwrap();
+    }
+
+    #[test]
+    fn test_step_instruction_off_breakpoint() {
+        let (path, debug_data) = load_sample("function_calls");
+        let func3 = debug_data.get_addr_for_function(None, "func3").unwrap();
+        let mut inferior =
+            Inferior::new(&path, &Vec::new(), &vec![func3], &LaunchEnvironment::default()).unwrap();
+        match inferior.cont().unwrap() {
+            Status::Stopped(signal::Signal::SIGTRAP, _) => {}
+            other => panic!("Expected to stop at func3, got {:?}", other),
+        }
+
+        let mut prev_rip = func3;
+        for _ in 0..3 {
+            match inferior.step_instruction().unwrap() {
+                Status::Stopped(signal::Signal::SIGTRAP, rip) => {
+                    assert_ne!(rip, prev_rip);
+                    prev_rip = rip;
                 }
-                println!();
+                other => panic!("Expected to stop after one instruction, got {:?}", other),
             }
         }
+
+        // The breakpoint must be back in place after stepping off it
+        let aligned_addr = align_addr_to_word(func3);
+        let word = ptrace::read(inferior.pid(), aligned_addr as ptrace::AddressType).unwrap() as u64;
+        assert_eq!((word >> (8 * (func3 - aligned_addr))) & 0xff, 0xcc);
+        inferior.kill().unwrap();
+    }
+
+    #[test]
+    fn test_read_variable_in_caller_frame() {
+        let (path, debug_data) = load_sample("function_calls");
+        let func3 = debug_data.get_addr_for_function(None, "func3").unwrap();
+        let mut inferior =
+            Inferior::new(&path, &Vec::new(), &vec![func3], &LaunchEnvironment::default()).unwrap();
+        match inferior.cont().unwrap() {
+            Status::Stopped(signal::Signal::SIGTRAP, _) => {}
+            other => panic!("Expected to stop at func3, got {:?}", other),
+        }
+
+        // func3 was first called from func2(42, global), after it computed sum
+        let caller = inferior.frame(&debug_data, 1).unwrap().unwrap();
+        assert_eq!(caller.function.as_deref(), Some("func2"));
+        let sum = inferior.read_variable(&debug_data, 1, "sum").unwrap().unwrap();
+        assert_eq!(sum, 47_i32.to_le_bytes().to_vec());
+        let a = inferior.read_variable(&debug_data, 1, "a").unwrap().unwrap();
+        assert_eq!(a, 42_i32.to_le_bytes().to_vec());
+        assert_eq!(inferior.read_variable(&debug_data, 0, "sum").unwrap(), None);
+        inferior.kill().unwrap();
+    }
+
+    #[test]
+    fn test_print_variable_in_decimal_and_hex() {
+        let (path, debug_data) = load_sample("function_calls");
+        let func3 = debug_data.get_addr_for_function(None, "func3").unwrap();
+        let mut inferior =
+            Inferior::new(&path, &Vec::new(), &vec![func3], &LaunchEnvironment::default()).unwrap();
+        match inferior.cont().unwrap() {
+            Status::Stopped(signal::Signal::SIGTRAP, _) => {}
+            other => panic!("Expected to stop at func3, got {:?}", other),
+        }
+
+        let (sum_type, bytes) = inferior.read_typed_variable(&debug_data, 1, "sum").unwrap().unwrap();
+        assert_eq!(sum_type.name, "int");
+        let format = |format| value_format::format_value(&bytes, &sum_type.name, format);
+        assert_eq!(format(ValueFormat::Natural), "47");
+        assert_eq!(format(ValueFormat::Decimal), "47");
+        assert_eq!(format(ValueFormat::Hex), "0x2f");
+        inferior.kill().unwrap();
+    }
+
+    #[test]
+    fn test_stop_on_segfault() {
+        let (path, debug_data) = load_sample("segfault");
+        let mut inferior =
+            Inferior::new(&path, &Vec::new(), &Vec::new(), &LaunchEnvironment::default()).unwrap();
+        let rip = match inferior.cont().unwrap() {
+            Status::Stopped(signal::Signal::SIGSEGV, rip) => rip,
+            other => panic!("Expected a SIGSEGV, got {:?}", other),
+        };
+        assert_eq!(debug_data.get_function_from_addr(rip).as_deref(), Some("func2"));
+        assert_eq!(inferior.fault_address().unwrap(), Some(0));
+
+        // The inferior is left stopped at the faulting instruction, so the stack is intact
+        let backtrace = inferior.backtrace(&debug_data).unwrap();
+        let functions: Vec<_> = backtrace
+            .frames
+            .iter()
+            .map(|frame| frame.function.as_deref().unwrap())
+            .collect();
+        assert_eq!(functions, ["func2", "func1", "main"]);
+        assert!(!backtrace.unreliable);
+
+        // Continuing delivers the signal instead of retrying the faulting instruction
+        match inferior.cont().unwrap() {
+            Status::Signaled(signal::Signal::SIGSEGV) => {}
+            other => panic!("Expected the inferior to die from the SIGSEGV, got {:?}", other),
+        }
     }
 }
@@ -1,8 +1,12 @@
 mod debugger;
 mod debugger_command;
+mod disassemble;
 mod inferior;
 mod dwarf_data;
+mod expression;
 mod gimli_wrapper;
+mod unwind;
+mod value_format;
 
 use crate::debugger::Debugger;
 use nix::sys::signal::{signal, SigHandler, Signal};
@@ -10,15 +14,34 @@ use std::env;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        println!("Usage: {} <target program>", args[0]);
+    let mut verbose = false;
+    let mut rerun_on_crash = 0;
+    let mut positional_args = Vec::new();
+    let mut options = args[1..].iter();
+    while let Some(arg) = options.next() {
+        match arg.as_str() {
+            "-v" | "--verbose" => verbose = true,
+            "--rerun-on-crash" => match options.next().and_then(|times| times.parse().ok()) {
+                Some(times) => rerun_on_crash = times,
+                None => {
+                    println!("--rerun-on-crash needs the number of times to rerun the program");
+                    std::process::exit(1);
+                }
+            },
+            _ => positional_args.push(arg),
+        }
+    }
+    if positional_args.len() != 1 {
+        println!("Usage: {} [-v|--verbose] [--rerun-on-crash N] <target program>", args[0]);
         std::process::exit(1);
     }
-    let target = &args[1];
+    let target = positional_args[0];
 
     // Disable handling of ctrl+c in this process (so that ctrl+c only gets delivered to child
     // processes)
     unsafe { signal(Signal::SIGINT, SigHandler::SigIgn) }.expect("Error disabling SIGINT handling");
 
-    Debugger::new(target).run();
+    let mut debugger = Debugger::new(target, verbose);
+    debugger.set_rerun_on_crash(rerun_on_crash);
+    debugger.run();
 }
@@ -0,0 +1,126 @@
+//! Reads the call frame information (CFI) in `.eh_frame` and `.debug_frame`, which describes for
+//! every instruction how to find the caller's registers. Unlike following saved frame pointers,
+//! this also works for code compiled with -fomit-frame-pointer.
+
+use gimli::UnwindSection;
+use object::{Object, ObjectSection};
+use std::rc::Rc;
+
+type Reader = gimli::EndianRcSlice<gimli::RunTimeEndian>;
+
+// DWARF register numbers on x86_64
+const DWARF_RBP: gimli::Register = gimli::Register(6);
+const DWARF_RSP: gimli::Register = gimli::Register(7);
+const DWARF_RETURN_ADDRESS: gimli::Register = gimli::Register(16);
+
+/// The register the canonical frame address (CFA) is computed from. The CFA is the value rsp had in
+/// the caller just before the call instruction.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CfaRegister {
+    Rsp,
+    Rbp,
+}
+
+/// How to recover the caller's rip, rsp and rbp at one address. Only the simple rules compilers
+/// emit for ordinary functions are supported; anything else (e.g. DWARF expressions) is treated as
+/// missing CFI.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnwindRule {
+    pub cfa_register: CfaRegister,
+    pub cfa_offset: i64,
+    /// The return address (the caller's rip) is saved at CFA + this offset
+    pub return_address_offset: i64,
+    /// The caller's rbp is saved at CFA + this offset, or None if this function hasn't changed rbp
+    pub rbp_offset: Option<i64>,
+}
+
+pub struct CallFrameInfo {
+    eh_frame: Option<(gimli::EhFrame<Reader>, gimli::BaseAddresses)>,
+    debug_frame: Option<gimli::DebugFrame<Reader>>,
+}
+
+impl CallFrameInfo {
+    /// Loads whichever of `.eh_frame` and `.debug_frame` the executable has. Neither is required;
+    /// without them, `rule_for_address` always returns None.
+    pub fn load(object: &object::File, endian: gimli::RunTimeEndian) -> CallFrameInfo {
+        let section = |name: &str| {
+            let address = object.section_by_name(name)?.address();
+            let data = object.section_data_by_name(name)?;
+            if data.is_empty() {
+                return None;
+            }
+            Some((gimli::EndianRcSlice::new(Rc::from(&*data), endian), address))
+        };
+        let eh_frame = section(".eh_frame").map(|(data, address)| {
+            let mut bases = gimli::BaseAddresses::default().set_eh_frame(address);
+            if let Some(text) = object.section_by_name(".text") {
+                bases = bases.set_text(text.address());
+            }
+            if let Some(got) = object.section_by_name(".got") {
+                bases = bases.set_got(got.address());
+            }
+            (gimli::EhFrame::from(data), bases)
+        });
+        let debug_frame = section(".debug_frame").map(|(data, _)| {
+            let mut debug_frame = gimli::DebugFrame::from(data);
+            debug_frame.set_address_size(8);
+            debug_frame
+        });
+        CallFrameInfo {
+            eh_frame,
+            debug_frame,
+        }
+    }
+
+    /// How to unwind from `addr`, or None if there is no usable CFI for it
+    pub fn rule_for_address(&self, addr: usize) -> Option<UnwindRule> {
+        let mut ctx = gimli::UninitializedUnwindContext::new();
+        if let Some((eh_frame, bases)) = &self.eh_frame {
+            if let Ok(row) = eh_frame.unwind_info_for_address(
+                bases,
+                &mut ctx,
+                addr as u64,
+                gimli::EhFrame::cie_from_offset,
+            ) {
+                return to_rule(&row);
+            }
+        }
+        let debug_frame = self.debug_frame.as_ref()?;
+        let row = debug_frame
+            .unwind_info_for_address(
+                &gimli::BaseAddresses::default(),
+                &mut ctx,
+                addr as u64,
+                gimli::DebugFrame::cie_from_offset,
+            )
+            .ok()?;
+        to_rule(&row)
+    }
+}
+
+fn to_rule(row: &gimli::UnwindTableRow<Reader>) -> Option<UnwindRule> {
+    let (cfa_register, cfa_offset) = match row.cfa() {
+        gimli::CfaRule::RegisterAndOffset { register, offset } if *register == DWARF_RSP => {
+            (CfaRegister::Rsp, *offset)
+        }
+        gimli::CfaRule::RegisterAndOffset { register, offset } if *register == DWARF_RBP => {
+            (CfaRegister::Rbp, *offset)
+        }
+        _ => return None,
+    };
+    let return_address_offset = match row.register(DWARF_RETURN_ADDRESS) {
+        gimli::RegisterRule::Offset(offset) => offset,
+        _ => return None,
+    };
+    let rbp_offset = match row.register(DWARF_RBP) {
+        gimli::RegisterRule::Offset(offset) => Some(offset),
+        gimli::RegisterRule::Undefined | gimli::RegisterRule::SameValue => None,
+        _ => return None,
+    };
+    Some(UnwindRule {
+        cfa_register,
+        cfa_offset,
+        return_address_offset,
+        rbp_offset,
+    })
+}
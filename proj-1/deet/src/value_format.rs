@@ -0,0 +1,141 @@
+/// How `print` displays a value, chosen with a gdb-style suffix (`print/x`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueFormat {
+    /// The value's own type decides (plain `print`)
+    Natural,
+    /// print/x
+    Hex,
+    /// print/d
+    Decimal,
+    /// print/c
+    Char,
+    /// print/t
+    Binary,
+}
+
+impl ValueFormat {
+    /// Parses the part of `print/x` after the slash
+    pub fn from_suffix(suffix: &str) -> Option<ValueFormat> {
+        match suffix {
+            "x" => Some(ValueFormat::Hex),
+            "d" => Some(ValueFormat::Decimal),
+            "c" => Some(ValueFormat::Char),
+            "t" => Some(ValueFormat::Binary),
+            _ => None,
+        }
+    }
+}
+
+/// Decodes a little-endian integer of 1, 2, 4 or 8 bytes, sign-extending it unless the type is
+/// unsigned. Returns None for other sizes.
+pub fn decode_integer(bytes: &[u8], type_name: &str) -> Option<i128> {
+    let mut buf = [0_u8; 8];
+    match bytes.len() {
+        1 | 2 | 4 | 8 => buf[..bytes.len()].copy_from_slice(bytes),
+        _ => return None,
+    }
+    let unsigned = u64::from_le_bytes(buf);
+    if is_unsigned(type_name) {
+        return Some(unsigned as i128);
+    }
+    // Shift the value's sign bit up to bit 63 and back down to sign-extend it
+    let unused_bits = 64 - 8 * bytes.len() as u32;
+    Some((((unsigned << unused_bits) as i64) >> unused_bits) as i128)
+}
+
+fn is_unsigned(type_name: &str) -> bool {
+    type_name.contains("unsigned") || matches!(type_name, "u8" | "u16" | "u32" | "u64")
+}
+
+/// The raw bytes as one hex number, most significant byte first
+fn hex_bytes(bytes: &[u8]) -> String {
+    let digits: String = bytes.iter().rev().map(|byte| format!("{:02x}", byte)).collect();
+    format!("0x{}", digits)
+}
+
+/// A character literal like gdb's `'A'`, escaping anything that isn't printable ASCII
+fn char_literal(byte: u8) -> String {
+    if (32..=126).contains(&byte) {
+        format!("'{}'", byte as char)
+    } else {
+        format!("'\\{:o}'", byte)
+    }
+}
+
+/// Formats a variable's raw bytes for `print`. Hex and binary show the bit pattern (so -1 in an
+/// int is 0xffffffff), decimal and char show the integer value. Values that aren't 1, 2, 4 or 8
+/// bytes long, and pointers printed without a format, are shown as hex bytes.
+pub fn format_value(bytes: &[u8], type_name: &str, format: ValueFormat) -> String {
+    if bytes.is_empty() {
+        return String::from("<empty>");
+    }
+    let value = match decode_integer(bytes, type_name) {
+        Some(value) => value,
+        None => return hex_bytes(bytes),
+    };
+    // The bit pattern, without the sign extension
+    let bits = value as u64 & (u64::MAX >> (64 - 8 * bytes.len() as u32));
+    match format {
+        ValueFormat::Natural if type_name.ends_with('*') => hex_bytes(bytes),
+        ValueFormat::Natural if type_name.contains("char") && bytes.len() == 1 => {
+            if (32..=126).contains(&bits) {
+                format!("{} ('{}')", value, bits as u8 as char)
+            } else {
+                format!("{}", value)
+            }
+        }
+        ValueFormat::Natural if is_integer_type(type_name) => format!("{}", value),
+        ValueFormat::Natural => hex_bytes(bytes),
+        ValueFormat::Decimal => format!("{}", value),
+        ValueFormat::Hex => format!("{:#x}", bits),
+        ValueFormat::Binary => format!("{:b}", bits),
+        // Like a cast to char: only the low byte counts
+        ValueFormat::Char => {
+            let byte = bits as u8;
+            let char_value = if is_unsigned(type_name) { byte as i16 } else { byte as i8 as i16 };
+            format!("{} {}", char_value, char_literal(byte))
+        }
+    }
+}
+
+/// Types whose values `print` shows as integers by default
+pub fn is_integer_type(type_name: &str) -> bool {
+    ["int", "long", "short", "char", "i8", "i16", "i32", "i64", "u8", "u16", "u32", "u64"]
+        .iter()
+        .any(|name| type_name.split_whitespace().any(|word| word == *name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_integer_formats() {
+        let bytes = 47_i32.to_le_bytes();
+        assert_eq!(format_value(&bytes, "int", ValueFormat::Natural), "47");
+        assert_eq!(format_value(&bytes, "int", ValueFormat::Decimal), "47");
+        assert_eq!(format_value(&bytes, "int", ValueFormat::Hex), "0x2f");
+        assert_eq!(format_value(&bytes, "int", ValueFormat::Binary), "101111");
+        assert_eq!(format_value(&bytes, "int", ValueFormat::Char), "47 '/'");
+    }
+
+    #[test]
+    fn test_negative_and_unsigned() {
+        let bytes = (-1_i32).to_le_bytes();
+        assert_eq!(format_value(&bytes, "int", ValueFormat::Natural), "-1");
+        assert_eq!(format_value(&bytes, "int", ValueFormat::Hex), "0xffffffff");
+        assert_eq!(format_value(&bytes, "unsigned int", ValueFormat::Natural), "4294967295");
+        assert_eq!(format_value(&(-2_i16).to_le_bytes(), "short", ValueFormat::Decimal), "-2");
+        assert_eq!(format_value(&[200], "char", ValueFormat::Char), "-56 '\\310'");
+    }
+
+    #[test]
+    fn test_natural_format_falls_back_to_hex() {
+        assert_eq!(format_value(&[65], "char", ValueFormat::Natural), "65 ('A')");
+        let pointer = 0x401136_u64.to_le_bytes();
+        assert_eq!(format_value(&pointer, "char *", ValueFormat::Natural), "0x0000000000401136");
+        assert_eq!(format_value(&pointer, "char *", ValueFormat::Decimal), "4198710");
+        assert_eq!(format_value(&[1, 2, 3], "struct point", ValueFormat::Hex), "0x030201");
+        assert_eq!(ValueFormat::from_suffix("q"), None);
+    }
+}
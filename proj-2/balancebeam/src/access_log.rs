@@ -0,0 +1,233 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::request;
+use crate::response;
+
+/// 每个请求记录什么样的访问日志（--log-format）。text 在收到请求和发送响应时各记录一条 log::info!；
+/// clf 和 json 在发送响应之后向标准输出打印一行，不带日志前缀，方便日志收集系统直接解析
+#[derive(clap::ValueEnum, serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogFormat {
+    Text,
+    Clf,
+    Json,
+}
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// 一个请求的访问日志。读取请求之后创建，发送响应之后调用 finish 写出
+pub struct RequestLog<'a> {
+    format: LogFormat,
+    client_ip: &'a str,
+    /// 请求的方法、URI 和 HTTP 版本（请求无法解析时为 None）
+    request: Option<(http::Method, http::Uri, http::Version)>,
+    /// 转发这个请求的上游服务器（没有转发时为 None，例如被限流或者无法连接任何上游服务器）
+    upstream: Option<String>,
+    /// 收到请求的时间，用于日志中的时间戳
+    received_at: SystemTime,
+    /// 用于计算延迟
+    started: Instant,
+}
+
+impl<'a> RequestLog<'a> {
+    /// 开始记录一个请求。text 格式立即记录请求行
+    pub fn new(
+        format: LogFormat,
+        client_ip: &'a str,
+        request: Option<&http::Request<Vec<u8>>>,
+    ) -> RequestLog<'a> {
+        if let (LogFormat::Text, Some(request)) = (format, request) {
+            log::info!("{} -> {}", client_ip, request::format_request_line(request));
+        }
+        RequestLog {
+            format,
+            client_ip,
+            request: request.map(|request| {
+                (request.method().clone(), request.uri().clone(), request.version())
+            }),
+            upstream: None,
+            received_at: SystemTime::now(),
+            started: Instant::now(),
+        }
+    }
+
+    pub fn set_upstream(&mut self, upstream: &str) {
+        self.upstream = Some(upstream.to_string());
+    }
+
+    /// 记录发送给客户端的响应。body_bytes 是发送的响应体字节数（转发响应体时出错则为 None）
+    pub fn finish(&self, response: &http::Response<Vec<u8>>, body_bytes: Option<u64>) {
+        match self.format {
+            LogFormat::Text => {
+                log::info!("{} <- {}", self.client_ip, response::format_response_line(response))
+            }
+            LogFormat::Clf => println!("{}", self.clf_line(response.status(), body_bytes)),
+            LogFormat::Json => println!(
+                "{}",
+                self.json_line(response.status(), body_bytes, self.started.elapsed())
+            ),
+        }
+    }
+
+    /// Common Log Format：`client - - [10/Oct/2000:13:55:36 +0000] "GET / HTTP/1.1" 200 2326`
+    fn clf_line(&self, status: http::StatusCode, body_bytes: Option<u64>) -> String {
+        let (year, month, day, hour, minute, second) = utc_fields(unix_seconds(self.received_at));
+        let request_line = match &self.request {
+            Some((method, uri, version)) => format!("{} {} {:?}", method, uri, version),
+            None => String::from("-"),
+        };
+        format!(
+            "{} - - [{:02}/{}/{}:{:02}:{:02}:{:02} +0000] \"{}\" {} {}",
+            self.client_ip,
+            day,
+            MONTHS[month as usize - 1],
+            year,
+            hour,
+            minute,
+            second,
+            request_line,
+            status.as_u16(),
+            body_bytes.map_or(String::from("-"), |bytes| bytes.to_string())
+        )
+    }
+
+    /// 一个 JSON 对象，缺少的值为 null
+    fn json_line(&self, status: http::StatusCode, body_bytes: Option<u64>, latency: Duration) -> String {
+        let string_or_null = |value: Option<String>| value.map_or(String::from("null"), |value| json_string(&value));
+        format!(
+            "{{\"ts\":{},\"client_ip\":{},\"method\":{},\"uri\":{},\"status\":{},\"upstream\":{},\"latency_ms\":{:.3},\"bytes\":{}}}",
+            json_string(&rfc3339(self.received_at)),
+            json_string(self.client_ip),
+            string_or_null(self.request.as_ref().map(|(method, _, _)| method.to_string())),
+            string_or_null(self.request.as_ref().map(|(_, uri, _)| uri.to_string())),
+            status.as_u16(),
+            string_or_null(self.upstream.clone()),
+            latency.as_secs_f64() * 1000.0,
+            body_bytes.map_or(String::from("null"), |bytes| bytes.to_string())
+        )
+    }
+}
+
+/// 转义为 JSON 字符串（包括两边的引号）
+fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+/// 自 UNIX 纪元以来的秒数（系统时钟早于纪元时为 0）
+fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |since_epoch| since_epoch.as_secs())
+}
+
+/// 精确到毫秒的 UTC 时间，例如 2026-10-14T17:46:40.250Z
+fn rfc3339(time: SystemTime) -> String {
+    let millis = time.duration_since(UNIX_EPOCH).map_or(0, |since_epoch| since_epoch.subsec_millis());
+    let (year, month, day, hour, minute, second) = utc_fields(unix_seconds(time));
+    format!(
+        "{}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year, month, day, hour, minute, second, millis
+    )
+}
+
+/// 把 UNIX 时间戳转换成 UTC 的（年, 月, 日, 时, 分, 秒）。日期部分使用 Howard Hinnant 的
+/// civil_from_days 算法（没有引入日期库）
+fn utc_fields(secs: u64) -> (u64, u32, u32, u32, u32, u32) {
+    let days = secs / 86400;
+    let time_of_day = secs % 86400;
+    // 从 0000-03-01 开始计算，这样闰日在每年的最后
+    let z = days + 719468;
+    let era = z / 146097;
+    let day_of_era = z % 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_from_march + 2) / 5 + 1) as u32;
+    let month = if month_from_march < 10 { month_from_march + 3 } else { month_from_march - 9 } as u32;
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    (
+        year,
+        month,
+        day,
+        (time_of_day / 3600) as u32,
+        (time_of_day % 3600 / 60) as u32,
+        (time_of_day % 60) as u32,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2026-10-14T17:46:40.250Z
+    fn example_log(request: Option<&http::Request<Vec<u8>>>) -> RequestLog<'static> {
+        let mut log = RequestLog::new(LogFormat::Json, "10.0.0.1", request);
+        log.received_at = UNIX_EPOCH + Duration::from_millis(1_792_000_000_250);
+        log
+    }
+
+    #[test]
+    fn test_utc_fields() {
+        assert_eq!(utc_fields(0), (1970, 1, 1, 0, 0, 0));
+        assert_eq!(utc_fields(951_782_400), (2000, 2, 29, 0, 0, 0));
+        assert_eq!(utc_fields(1_792_000_000), (2026, 10, 14, 17, 46, 40));
+        assert_eq!(rfc3339(UNIX_EPOCH + Duration::from_millis(1_792_000_000_250)), "2026-10-14T17:46:40.250Z");
+    }
+
+    #[test]
+    fn test_json_line() {
+        let request = http::Request::builder()
+            .method("POST")
+            .uri("/search?q=rust")
+            .body(Vec::new())
+            .unwrap();
+        let mut log = example_log(Some(&request));
+        log.set_upstream("127.0.0.1:8080");
+        assert_eq!(
+            log.json_line(http::StatusCode::OK, Some(12), Duration::from_micros(1500)),
+            "{\"ts\":\"2026-10-14T17:46:40.250Z\",\"client_ip\":\"10.0.0.1\",\"method\":\"POST\",\
+             \"uri\":\"/search?q=rust\",\"status\":200,\"upstream\":\"127.0.0.1:8080\",\
+             \"latency_ms\":1.500,\"bytes\":12}"
+        );
+
+        // 无法解析的请求没有方法、URI 和上游服务器
+        let log = example_log(None);
+        let line = log.json_line(http::StatusCode::BAD_REQUEST, None, Duration::ZERO);
+        assert!(line.contains("\"method\":null,\"uri\":null,\"status\":400,\"upstream\":null"));
+        assert!(line.ends_with("\"bytes\":null}"));
+    }
+
+    #[test]
+    fn test_clf_line() {
+        let request = http::Request::builder().uri("/index.html").body(Vec::new()).unwrap();
+        let log = example_log(Some(&request));
+        assert_eq!(
+            log.clf_line(http::StatusCode::OK, Some(2326)),
+            "10.0.0.1 - - [14/Oct/2026:17:46:40 +0000] \"GET /index.html HTTP/1.1\" 200 2326"
+        );
+        let log = example_log(None);
+        assert_eq!(
+            log.clf_line(http::StatusCode::BAD_REQUEST, None),
+            "10.0.0.1 - - [14/Oct/2026:17:46:40 +0000] \"-\" 400 -"
+        );
+    }
+
+    #[test]
+    fn test_json_string_escapes() {
+        assert_eq!(json_string("a\"b\\c\nd\u{1}"), "\"a\\\"b\\\\c\\nd\\u0001\"");
+    }
+}
@@ -0,0 +1,232 @@
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+
+use crate::error::ProxyError;
+use crate::limits::ParseLimits;
+use crate::stream::{self, ClientStream};
+
+/// 块大小行（包括块扩展）最多允许的字节数
+const MAX_CHUNK_SIZE_LINE: usize = 1024;
+
+/// 转发分块编码的响应体时可能出现的错误。这时响应头已经发送给了客户端，无论哪一边出错都只能关闭客户端连接，
+/// 但需要区分是哪一边出的错
+#[derive(Debug)]
+pub enum RelayError {
+    /// 上游服务器发送的响应体无效、太大，或者读取时出错
+    Upstream(ProxyError),
+    /// 写给客户端时出错
+    Client(std::io::Error),
+}
+
+/// 从上游服务器读取分块编码的数据。buffer 保存已经读取但还没有处理的字节。读取时还需要知道客户端
+/// 是否已经断开（见 stream::read_upstream），所以客户端连接也放在这里
+struct ChunkedReader<'a, S, C> {
+    stream: &'a mut S,
+    client: &'a mut C,
+    buffer: Vec<u8>,
+}
+
+impl<S: AsyncRead + Unpin, C: ClientStream> ChunkedReader<'_, S, C> {
+    /// 从上游服务器读取更多字节追加到 buffer。上游服务器在响应体结束之前挂断时返回 IncompleteResponse
+    async fn fill(&mut self) -> Result<(), RelayError> {
+        let mut chunk = [0_u8; 512];
+        let bytes_read = stream::read_upstream(self.stream, &mut chunk, self.client).await?;
+        if bytes_read == 0 {
+            return Err(RelayError::Upstream(ProxyError::IncompleteResponse));
+        }
+        self.buffer.extend_from_slice(&chunk[..bytes_read]);
+        Ok(())
+    }
+
+    /// 读取一个块大小行（例如 "1a;ext=1\r\n"），返回块的大小
+    async fn read_chunk_size(&mut self) -> Result<u64, RelayError> {
+        loop {
+            match httparse::parse_chunk_size(&self.buffer) {
+                Ok(httparse::Status::Complete((line_len, size))) => {
+                    self.buffer.drain(..line_len);
+                    return Ok(size);
+                }
+                Ok(httparse::Status::Partial) if self.buffer.len() < MAX_CHUNK_SIZE_LINE => {
+                    self.fill().await?
+                }
+                _ => return Err(RelayError::Upstream(ProxyError::InvalidChunkedBody)),
+            }
+        }
+    }
+
+    /// 读取块数据后面的 CRLF
+    async fn read_chunk_end(&mut self) -> Result<(), RelayError> {
+        while self.buffer.len() < 2 {
+            self.fill().await?;
+        }
+        if &self.buffer[..2] != b"\r\n" {
+            return Err(RelayError::Upstream(ProxyError::InvalidChunkedBody));
+        }
+        self.buffer.drain(..2);
+        Ok(())
+    }
+
+    /// 读取最后一个块之后的 trailer 部分（包括结束的空行），检查格式之后返回原始字节。
+    /// trailer 的数量和大小与响应头使用同样的限制。
+    async fn read_trailers(&mut self, limits: &ParseLimits) -> Result<Vec<u8>, RelayError> {
+        loop {
+            let mut headers = vec![httparse::EMPTY_HEADER; limits.max_headers];
+            match httparse::parse_headers(&self.buffer, &mut headers) {
+                Ok(httparse::Status::Complete((len, _))) => {
+                    return Ok(self.buffer.drain(..len).collect())
+                }
+                Ok(httparse::Status::Partial) if self.buffer.len() < limits.max_header_bytes => {
+                    self.fill().await?
+                }
+                Ok(httparse::Status::Partial) | Err(httparse::Error::TooManyHeaders) => {
+                    return Err(RelayError::Upstream(ProxyError::ResponseHeadersTooLarge))
+                }
+                Err(err) => return Err(RelayError::Upstream(ProxyError::MalformedResponse(err))),
+            }
+        }
+    }
+}
+
+/// 将 data 作为一个块写给客户端，并立即 flush
+async fn write_chunk(
+    client: &mut (impl AsyncWrite + Unpin),
+    data: &[u8],
+) -> Result<(), std::io::Error> {
+    client.write_all(format!("{:x}\r\n", data.len()).as_bytes()).await?;
+    client.write_all(data).await?;
+    client.write_all(b"\r\n").await?;
+    client.flush().await
+}
+
+/// 从上游服务器读取分块编码的响应体，解码之后重新以分块编码写给客户端。每收到一段数据就立即转发
+/// （不等待整个块，更不等待整个响应体），所以流式响应（例如 server-sent events）不会被缓冲，块的边界
+/// 可能与上游服务器发送的不同。trailer 原样转发。
+///
+/// initial 是读取响应头时已经读入的响应体开头部分。解码后的响应体超过 max_response_body 时返回
+/// ResponseBodyTooLarge（这时客户端已经收到了一部分响应体）。返回解码后的响应体字节数。
+pub async fn relay(
+    upstream: &mut (impl AsyncRead + Unpin),
+    initial: Vec<u8>,
+    client: &mut impl ClientStream,
+    limits: &ParseLimits,
+) -> Result<u64, RelayError> {
+    let mut reader = ChunkedReader {
+        stream: upstream,
+        client,
+        buffer: initial,
+    };
+    let mut body_len: u64 = 0;
+    loop {
+        let chunk_size = reader.read_chunk_size().await?;
+        if chunk_size == 0 {
+            break;
+        }
+        // 块大小来自上游服务器，可能大到让 body_len 溢出，溢出时同样视为超过限制
+        body_len = match body_len.checked_add(chunk_size) {
+            Some(len) if len <= limits.max_response_body as u64 => len,
+            _ => return Err(RelayError::Upstream(ProxyError::ResponseBodyTooLarge)),
+        };
+        // 上面检查过 body_len，所以 chunk_size 一定能放进 usize
+        let mut remaining = chunk_size as usize;
+        while remaining > 0 {
+            if reader.buffer.is_empty() {
+                reader.fill().await?;
+            }
+            let len = remaining.min(reader.buffer.len());
+            write_chunk(reader.client, &reader.buffer[..len])
+                .await
+                .map_err(RelayError::Client)?;
+            reader.buffer.drain(..len);
+            remaining -= len;
+        }
+        reader.read_chunk_end().await?;
+    }
+    let trailers = reader.read_trailers(limits).await?;
+    let client = reader.client;
+    client.write_all(b"0\r\n").await.map_err(RelayError::Client)?;
+    client.write_all(&trailers).await.map_err(RelayError::Client)?;
+    client.flush().await.map_err(RelayError::Client)?;
+    Ok(body_len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn relay_bytes(initial: &[u8], rest: &[u8]) -> (Result<u64, RelayError>, Vec<u8>) {
+        let mut upstream = rest;
+        let mut client = Vec::new();
+        let result =
+            relay(&mut upstream, initial.to_vec(), &mut client, &ParseLimits::default()).await;
+        (result, client)
+    }
+
+    #[tokio::test]
+    async fn test_relay_reencodes_chunks() {
+        let (result, client) =
+            relay_bytes(b"5;name=val\r\nhel", b"lo\r\nA\r\n, world!!!\r\n0\r\n\r\n").await;
+        assert_eq!(result.unwrap(), 15);
+        // 已经读入的部分先作为一个块转发
+        assert_eq!(client, b"3\r\nhel\r\n2\r\nlo\r\na\r\n, world!!!\r\n0\r\n\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_relay_preserves_trailers() {
+        let (result, client) =
+            relay_bytes(b"", b"2\r\nhi\r\n0\r\nx-checksum: abc\r\n\r\nextra").await;
+        assert_eq!(result.unwrap(), 2);
+        assert_eq!(client, b"2\r\nhi\r\n0\r\nx-checksum: abc\r\n\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_relay_invalid_bodies() {
+        let bodies = [
+            &b"zz\r\nhi\r\n0\r\n\r\n"[..],
+            &b"2\r\nhiX\r\n0\r\n\r\n"[..],
+            &b"0\r\nbad header\r\n\r\n"[..],
+        ];
+        for body in bodies {
+            let (result, _) = relay_bytes(b"", body).await;
+            assert!(
+                matches!(
+                    result,
+                    Err(RelayError::Upstream(
+                        ProxyError::InvalidChunkedBody | ProxyError::MalformedResponse(_)
+                    ))
+                ),
+                "{:?}",
+                result
+            );
+        }
+        // 上游服务器在最后一个块之前挂断
+        let (result, client) = relay_bytes(b"", b"5\r\nhello\r\n").await;
+        assert!(matches!(result, Err(RelayError::Upstream(ProxyError::IncompleteResponse))));
+        assert_eq!(client, b"5\r\nhello\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_relay_body_too_large() {
+        let mut upstream = &b"4\r\nabcd\r\n4\r\nefgh\r\n0\r\n\r\n"[..];
+        let mut client = Vec::new();
+        let limits = ParseLimits {
+            max_response_body: 6,
+            ..ParseLimits::default()
+        };
+        let result = relay(&mut upstream, Vec::new(), &mut client, &limits).await;
+        assert!(matches!(result, Err(RelayError::Upstream(ProxyError::ResponseBodyTooLarge))));
+        assert_eq!(client, b"4\r\nabcd\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_relay_chunk_size_overflow() {
+        // 第二个块的大小加上第一个块会让 u64 溢出，即使不限制响应体大小也不能回绕
+        let mut upstream = &b"1\r\na\r\nffffffffffffffff\r\nb"[..];
+        let mut client = Vec::new();
+        let limits = ParseLimits {
+            max_response_body: usize::MAX,
+            ..ParseLimits::default()
+        };
+        let result = relay(&mut upstream, Vec::new(), &mut client, &limits).await;
+        assert!(matches!(result, Err(RelayError::Upstream(ProxyError::ResponseBodyTooLarge))));
+        assert_eq!(client, b"1\r\na\r\n");
+    }
+}
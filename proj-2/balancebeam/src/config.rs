@@ -0,0 +1,190 @@
+//! --config 指定的 TOML 配置文件。键与命令行选项的长名字相同（例如 `max-retries = 3`，也可以写成
+//! `max_retries`），可以重复的选项写成数组（例如 `strip-request-header = ["x-debug"]`）。上游服务器写在
+//! `upstreams` 中，每一项是一个地址，或者一个带权重的表：
+//!
+//! ```toml
+//! upstreams = ["10.0.0.1:80", { address = "10.0.0.2:80", weight = 3 }]
+//! ```
+//!
+//! 文件被反序列化为 Config，然后在 main 中与命令行参数合并：命令行中明确给出的选项优先。
+
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+use crate::access_log::LogFormat;
+use crate::upstreams::LoadBalanceAlgorithm;
+
+/// 配置文件中的设置。没有出现的键为 None，使用命令行中的值（或者默认值）
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    pub bind: Option<String>,
+    pub reuse_port: Option<bool>,
+    /// 也可以写成 upstream，与命令行选项 --upstream 相同
+    #[serde(alias = "upstream")]
+    pub upstreams: Option<Vec<UpstreamConfig>>,
+    pub load_balance_algorithm: Option<LoadBalanceAlgorithm>,
+    pub upstream_file: Option<String>,
+    pub drain_timeout: Option<u64>,
+    pub upstream_keepalive: Option<bool>,
+    pub active_health_check_interval: Option<usize>,
+    pub active_health_check_path: Option<String>,
+    pub health_check_expect_status: Option<u16>,
+    pub health_check_expect_body: Option<String>,
+    pub health_check_concurrency: Option<usize>,
+    pub wait_for_upstream: Option<bool>,
+    pub wait_for_upstream_timeout: Option<u64>,
+    pub max_requests_per_minute: Option<usize>,
+    pub ratelimit_fail_open: Option<bool>,
+    pub ratelimit_ipv6_prefix: Option<u8>,
+    pub num_threads: Option<usize>,
+    pub max_retries: Option<usize>,
+    pub keepalive_timeout: Option<u64>,
+    pub request_timeout: Option<u64>,
+    pub max_headers: Option<usize>,
+    pub max_header_bytes: Option<usize>,
+    pub max_request_body: Option<usize>,
+    pub max_response_body: Option<usize>,
+    pub stream_threshold: Option<usize>,
+    pub read_buffer_size: Option<usize>,
+    pub cors_allow_origin: Option<String>,
+    pub cors_allow_methods: Option<String>,
+    pub cors_allow_headers: Option<String>,
+    pub stats_path: Option<String>,
+    pub max_5xx_before_eject: Option<usize>,
+    pub stats_interval: Option<u64>,
+    pub reject_body_on_get: Option<bool>,
+    pub sticky_cookie: Option<String>,
+    pub preserve_host: Option<bool>,
+    pub set_host: Option<String>,
+    pub strip_request_header: Option<Vec<String>>,
+    pub strip_response_header: Option<Vec<String>>,
+    pub max_connection_bytes: Option<u64>,
+    pub max_requests_per_connection: Option<usize>,
+    pub log_format: Option<LogFormat>,
+    pub error_page_dir: Option<PathBuf>,
+}
+
+/// upstreams 中的一项：只有地址（权重为 1），或者带权重
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum UpstreamConfig {
+    Address(String),
+    Weighted(WeightedUpstream),
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct WeightedUpstream {
+    pub address: String,
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+impl UpstreamConfig {
+    /// 地址和权重
+    pub fn to_upstream(&self) -> (String, u32) {
+        match self {
+            UpstreamConfig::Address(address) => (address.clone(), default_weight()),
+            UpstreamConfig::Weighted(upstream) => (upstream.address.clone(), upstream.weight),
+        }
+    }
+}
+
+impl Config {
+    /// 解析配置文件的内容。键中的 _ 被当作 -，所以两种写法都可以
+    pub fn parse(text: &str) -> Result<Config, String> {
+        let table: toml::Table = toml::from_str(text).map_err(|err| err.to_string())?;
+        let table: toml::Table = table
+            .into_iter()
+            .map(|(key, value)| (key.replace('_', "-"), value))
+            .collect();
+        let config: Config = toml::Value::Table(table)
+            .try_into()
+            .map_err(|err: toml::de::Error| err.message().to_string())?;
+        if let Some(upstreams) = &config.upstreams {
+            if upstreams.iter().any(|upstream| upstream.to_upstream().1 == 0) {
+                return Err(String::from("upstream weights must be at least 1"));
+            }
+        }
+        Ok(config)
+    }
+
+    /// 读取并解析 path。出错时返回包含文件名的错误信息
+    pub fn load(path: &Path) -> Result<Config, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|err| format!("Could not read config file {}: {}", path.display(), err))?;
+        Config::parse(&text).map_err(|err| format!("Invalid config file {}: {}", path.display(), err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let text = r#"
+            # 注释和空行被忽略
+            bind = "127.0.0.1:8080"   # 行尾注释
+            max_retries = 3
+            max-request-body = 10_000_000
+            max-5xx-before-eject = 5
+            reuse-port = true
+            load-balance-algorithm = "least-connections"
+            upstreams = [
+                "a:80",
+                { address = "b:80", weight = 3 },
+                { address = "c:80" }, # 最后一个元素后面可以有逗号
+            ]
+            strip-request-header = ["x-debug"]
+        "#;
+        let config = Config::parse(text).unwrap();
+        assert_eq!(
+            config,
+            Config {
+                bind: Some(String::from("127.0.0.1:8080")),
+                max_retries: Some(3),
+                max_request_body: Some(10_000_000),
+                max_5xx_before_eject: Some(5),
+                reuse_port: Some(true),
+                load_balance_algorithm: Some(LoadBalanceAlgorithm::LeastConnections),
+                upstreams: Some(vec![
+                    UpstreamConfig::Address(String::from("a:80")),
+                    UpstreamConfig::Weighted(WeightedUpstream { address: String::from("b:80"), weight: 3 }),
+                    UpstreamConfig::Weighted(WeightedUpstream { address: String::from("c:80"), weight: 1 }),
+                ]),
+                strip_request_header: Some(vec![String::from("x-debug")]),
+                ..Config::default()
+            }
+        );
+        let upstreams: Vec<(String, u32)> =
+            config.upstreams.unwrap().iter().map(UpstreamConfig::to_upstream).collect();
+        assert_eq!(
+            upstreams,
+            [(String::from("a:80"), 1), (String::from("b:80"), 3), (String::from("c:80"), 1)]
+        );
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+        assert_eq!(
+            Config::parse("upstream = [\"a:80\"]").unwrap().upstreams,
+            Some(vec![UpstreamConfig::Address(String::from("a:80"))])
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = |text| Config::parse(text).unwrap_err();
+        assert!(error("no-such-option = 1").starts_with("unknown field `no-such-option`"));
+        assert!(error("max-headers = \"lots\"").contains("invalid type: string \"lots\", expected usize"));
+        assert!(error("reuse-port = 1").contains("expected a boolean"));
+        assert!(error("log-format = \"xml\"").starts_with("unknown variant `xml`"));
+        assert_eq!(error("upstreams = [{ address = \"a:80\", weight = 0 }]"), "upstream weights must be at least 1");
+        // upstreams 中的表不能有其他键
+        assert!(error("upstreams = [{ address = \"a:80\", wieght = 2 }]").contains("did not match any variant"));
+        assert!(!error("bind = ").is_empty());
+    }
+}
@@ -0,0 +1,28 @@
+use async_trait::async_trait;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+
+/// 到上游服务器的连接。request.rs 和 response.rs 的读写函数只需要 AsyncRead/AsyncWrite，
+/// 所以测试可以用内存中的流（例如 tokio::io::duplex）代替真正的 TcpStream。
+pub trait UpstreamConnection: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> UpstreamConnection for T {}
+
+pub type Connection = Box<dyn UpstreamConnection>;
+
+/// 建立到上游服务器的连接。ProxyState 通过这个 trait 连接上游服务器，这样选择和故障转移的逻辑
+/// 不依赖于真正的网络 I/O，测试时可以换成按脚本返回成功/失败的实现。
+#[async_trait]
+pub trait UpstreamConnector: Send + Sync {
+    async fn connect(&self, address: &str) -> std::io::Result<Connection>;
+}
+
+/// 通过 TCP 连接上游服务器（实际运行时使用的实现）
+pub struct TcpConnector;
+
+#[async_trait]
+impl UpstreamConnector for TcpConnector {
+    async fn connect(&self, address: &str) -> std::io::Result<Connection> {
+        Ok(Box::new(TcpStream::connect(address).await?))
+    }
+}
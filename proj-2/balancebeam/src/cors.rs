@@ -0,0 +1,51 @@
+/// CORS 配置。设置了 --cors-allow-origin 时，balancebeam 自己回答 CORS 预检请求，
+/// 并在转发给客户端的响应中注入 Access-Control-Allow-Origin 头。
+pub struct CorsConfig {
+    allow_origin: http::HeaderValue,
+    allow_methods: http::HeaderValue,
+    allow_headers: http::HeaderValue,
+}
+
+impl CorsConfig {
+    /// 从命令行参数构建 CORS 配置。如果某个值不是合法的 HTTP 头值，返回 Err 并说明是哪一个参数。
+    pub fn new(
+        allow_origin: &str,
+        allow_methods: &str,
+        allow_headers: &str,
+    ) -> Result<CorsConfig, String> {
+        let to_header_value = |flag: &str, value: &str| {
+            http::HeaderValue::from_str(value)
+                .map_err(|_| format!("Invalid value for {}: {:?}", flag, value))
+        };
+        Ok(CorsConfig {
+            allow_origin: to_header_value("--cors-allow-origin", allow_origin)?,
+            allow_methods: to_header_value("--cors-allow-methods", allow_methods)?,
+            allow_headers: to_header_value("--cors-allow-headers", allow_headers)?,
+        })
+    }
+
+    /// 判断请求是否是 CORS 预检请求（带有 Access-Control-Request-Method 头的 OPTIONS 请求）
+    pub fn is_preflight(request: &http::Request<Vec<u8>>) -> bool {
+        request.method() == http::Method::OPTIONS
+            && request.headers().contains_key("access-control-request-method")
+    }
+
+    /// 构建对预检请求的 204 响应
+    pub fn preflight_response(&self) -> http::Response<Vec<u8>> {
+        http::Response::builder()
+            .status(http::StatusCode::NO_CONTENT)
+            .header("Access-Control-Allow-Origin", self.allow_origin.clone())
+            .header("Access-Control-Allow-Methods", self.allow_methods.clone())
+            .header("Access-Control-Allow-Headers", self.allow_headers.clone())
+            .version(http::Version::HTTP_11)
+            .body(Vec::new())
+            .unwrap()
+    }
+
+    /// 在转发给客户端的响应中注入（或覆盖）Access-Control-Allow-Origin 头
+    pub fn apply(&self, response: &mut http::Response<Vec<u8>>) {
+        response
+            .headers_mut()
+            .insert("access-control-allow-origin", self.allow_origin.clone());
+    }
+}
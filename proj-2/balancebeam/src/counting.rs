@@ -0,0 +1,183 @@
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// 包装一个流，统计从中读取和向其写入的字节数。request.rs 和 response.rs 中的读写函数接受任何
+/// AsyncRead/AsyncWrite，所以可以直接把 CountingStream 传给它们。
+pub struct CountingStream<S> {
+    inner: S,
+    bytes_read: u64,
+    bytes_written: u64,
+}
+
+impl<S> CountingStream<S> {
+    pub fn new(inner: S) -> CountingStream<S> {
+        CountingStream {
+            inner,
+            bytes_read: 0,
+            bytes_written: 0,
+        }
+    }
+
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for CountingStream<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled_before = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = result {
+            self.bytes_read += (buf.filled().len() - filled_before) as u64;
+        }
+        result
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for CountingStream<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let result = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = result {
+            self.bytes_written += written as u64;
+        }
+        result
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// 一个客户端连接（以及为它建立的所有上游连接）传输的字节数
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionBytes {
+    /// 从客户端读取的字节数
+    pub client_read: u64,
+    /// 写给客户端的字节数
+    pub client_written: u64,
+    /// 从上游服务器读取的字节数
+    pub upstream_read: u64,
+    /// 写给上游服务器的字节数
+    pub upstream_written: u64,
+}
+
+impl ConnectionBytes {
+    /// 加上一个上游连接传输的字节数，然后关闭这个连接
+    pub fn close_upstream<S>(&mut self, upstream_conn: CountingStream<S>) {
+        self.upstream_read += upstream_conn.bytes_read();
+        self.upstream_written += upstream_conn.bytes_written();
+    }
+
+    /// 与 close_upstream 相同，但是不关闭连接，而是把它返回（放回连接池）
+    pub fn release_upstream<S>(&mut self, upstream_conn: CountingStream<S>) -> S {
+        self.upstream_read += upstream_conn.bytes_read();
+        self.upstream_written += upstream_conn.bytes_written();
+        upstream_conn.into_inner()
+    }
+}
+
+/// 所有已关闭的连接传输的字节总数。使用原子计数器，这样多个连接任务可以同时更新而不需要加锁。
+#[derive(Debug, Default)]
+pub struct ByteTotals {
+    client_read: AtomicU64,
+    client_written: AtomicU64,
+    upstream_read: AtomicU64,
+    upstream_written: AtomicU64,
+}
+
+impl ByteTotals {
+    /// 将一个已关闭连接的字节数计入总数
+    pub fn add(&self, bytes: &ConnectionBytes) {
+        self.client_read.fetch_add(bytes.client_read, Ordering::Relaxed);
+        self.client_written.fetch_add(bytes.client_written, Ordering::Relaxed);
+        self.upstream_read.fetch_add(bytes.upstream_read, Ordering::Relaxed);
+        self.upstream_written.fetch_add(bytes.upstream_written, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> ConnectionBytes {
+        ConnectionBytes {
+            client_read: self.client_read.load(Ordering::Relaxed),
+            client_written: self.client_written.load(Ordering::Relaxed),
+            upstream_read: self.upstream_read.load(Ordering::Relaxed),
+            upstream_written: self.upstream_written.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// 将字节总数格式化为一行纯文本，附加在统计信息的末尾
+pub fn render_byte_totals(totals: &ConnectionBytes) -> String {
+    format!(
+        "bytes client_read={} client_written={} upstream_read={} upstream_written={}\n",
+        totals.client_read, totals.client_written, totals.upstream_read, totals.upstream_written
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_counts_reads_and_writes() {
+        let (client, server) = tokio::io::duplex(64);
+        let mut client = CountingStream::new(client);
+        let mut server = CountingStream::new(server);
+
+        client.write_all(b"hello world").await.unwrap();
+        let mut buffer = [0_u8; 5];
+        server.read_exact(&mut buffer).await.unwrap();
+        assert_eq!(server.bytes_read(), 5);
+        server.write_all(b"ok").await.unwrap();
+        drop(server);
+
+        let mut received = Vec::new();
+        client.read_to_end(&mut received).await.unwrap();
+        assert_eq!(received, b"ok");
+        assert_eq!(client.bytes_written(), 11);
+        assert_eq!(client.bytes_read(), 2);
+    }
+
+    #[test]
+    fn test_totals_accumulate() {
+        let totals = ByteTotals::default();
+        let bytes = ConnectionBytes {
+            client_read: 1,
+            client_written: 2,
+            upstream_read: 3,
+            upstream_written: 4,
+        };
+        totals.add(&bytes);
+        totals.add(&bytes);
+        assert_eq!(
+            render_byte_totals(&totals.snapshot()),
+            "bytes client_read=2 client_written=4 upstream_read=6 upstream_written=8\n"
+        );
+    }
+}
@@ -0,0 +1,228 @@
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::watch;
+
+/// 一个上游服务器正在处理的请求数，以及重新加载时把它移除之后用来强制关闭这些请求的连接的开关。
+///
+/// 与 status_counts 一样，重新加载后仍然存在的服务器保留同一个 InFlight。
+#[derive(Debug)]
+pub struct InFlight {
+    count: AtomicUsize,
+    /// 重新加载时服务器已被移除：之后的请求不会再选择它
+    removed: AtomicBool,
+    /// 排空时间已过：所有仍在使用的连接在下一次读写时返回 ConnectionAborted
+    closed: watch::Sender<bool>,
+}
+
+impl Default for InFlight {
+    fn default() -> InFlight {
+        InFlight {
+            count: AtomicUsize::new(0),
+            removed: AtomicBool::new(false),
+            closed: watch::channel(false).0,
+        }
+    }
+}
+
+impl InFlight {
+    /// 正在使用的连接数
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
+
+    pub fn is_removed(&self) -> bool {
+        self.removed.load(Ordering::SeqCst)
+    }
+
+    /// 标记服务器已被移除。之后 connect_to_upstream 不会再选择它，已有的请求不受影响
+    pub fn remove(&self) {
+        self.removed.store(true, Ordering::SeqCst);
+    }
+
+    /// 强制关闭所有仍在使用的连接
+    pub fn close(&self) {
+        self.closed.send_replace(true);
+    }
+
+    /// 包装一个到这个服务器的连接：在连接被丢弃之前计入 count，并在 close() 之后读写失败
+    pub fn track<S>(self: &Arc<Self>, inner: S) -> TrackedStream<S> {
+        self.count.fetch_add(1, Ordering::SeqCst);
+        let mut closed = self.closed.subscribe();
+        TrackedStream {
+            inner,
+            _guard: InFlightGuard(Arc::clone(self)),
+            closed: Some(Box::pin(async move {
+                // 发送端在 InFlight 中，而 TrackedStream 持有 InFlight，所以这里不会因为发送端被丢弃而返回
+                let _ = closed.wait_for(|closed| *closed).await;
+            })),
+        }
+    }
+}
+
+/// 把一个已被移除的服务器上剩下的请求排空：最多等待 drain_timeout，然后强制关闭仍在使用的连接。
+/// 没有正在处理的请求时立即返回。
+pub async fn drain(address: String, in_flight: Arc<InFlight>, drain_timeout: Duration) {
+    let remaining = in_flight.count();
+    if remaining == 0 {
+        return;
+    }
+    log::info!(
+        "Draining {} in-flight request(s) to removed upstream {} for up to {:?}",
+        remaining,
+        address,
+        drain_timeout
+    );
+    tokio::time::sleep(drain_timeout).await;
+    let remaining = in_flight.count();
+    if remaining > 0 {
+        log::warn!(
+            "Closing {} in-flight request(s) to removed upstream {} after the drain timeout",
+            remaining,
+            address
+        );
+        in_flight.close();
+    }
+}
+
+/// 在被丢弃之前计入 InFlight::count
+struct InFlightGuard(Arc<InFlight>);
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0.count.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// InFlight::track 返回的连接
+pub struct TrackedStream<S> {
+    inner: S,
+    _guard: InFlightGuard,
+    /// 等待 close() 的 future；连接被强制关闭之后为 None（已经完成的 future 不能再次 poll）
+    closed: Option<Pin<Box<dyn Future<Output = ()> + Send>>>,
+}
+
+impl<S> TrackedStream<S> {
+    /// 不再计入 count，返回原来的连接（例如放回连接池时）
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    /// 如果连接已被强制关闭，返回 ConnectionAborted 错误；否则注册唤醒，这样 close() 会唤醒正在等待读写的任务
+    fn check_closed(&mut self, cx: &mut Context<'_>) -> io::Result<()> {
+        if let Some(closed) = self.closed.as_mut() {
+            if closed.as_mut().poll(cx).is_pending() {
+                return Ok(());
+            }
+            self.closed = None;
+        }
+        Err(io::Error::new(
+            io::ErrorKind::ConnectionAborted,
+            "upstream was removed and its drain timeout expired",
+        ))
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for TrackedStream<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        self.check_closed(cx)?;
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for TrackedStream<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.check_closed(cx)?;
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.check_closed(cx)?;
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_track_counts_connections() {
+        let in_flight = Arc::new(InFlight::default());
+        let (a, _a_peer) = tokio::io::duplex(64);
+        let (b, _b_peer) = tokio::io::duplex(64);
+        let a = in_flight.track(a);
+        let b = in_flight.track(b);
+        assert_eq!(in_flight.count(), 2);
+        drop(a);
+        assert_eq!(in_flight.count(), 1);
+        drop(b);
+        assert_eq!(in_flight.count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_into_inner_stops_counting() {
+        let in_flight = Arc::new(InFlight::default());
+        let (stream, _peer) = tokio::io::duplex(64);
+        let stream = in_flight.track(stream).into_inner();
+        assert_eq!(in_flight.count(), 0);
+        drop(stream);
+        assert_eq!(in_flight.count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_close_aborts_pending_read() {
+        let in_flight = Arc::new(InFlight::default());
+        let (stream, mut peer) = tokio::io::duplex(64);
+        let mut stream = in_flight.track(stream);
+        peer.write_all(b"hi").await.unwrap();
+        let mut buf = [0_u8; 2];
+        stream.read_exact(&mut buf).await.unwrap();
+
+        // 对端不再发送数据，读取会一直等待，直到 close() 唤醒它
+        let closer = Arc::clone(&in_flight);
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            closer.close();
+        });
+        let err = stream.read(&mut buf).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionAborted);
+        assert_eq!(
+            stream.write_all(b"x").await.unwrap_err().kind(),
+            io::ErrorKind::ConnectionAborted
+        );
+    }
+
+    #[tokio::test]
+    async fn test_drain_waits_for_timeout() {
+        let in_flight = Arc::new(InFlight::default());
+        // 没有请求时立即返回，不关闭
+        drain(String::from("a:1"), Arc::clone(&in_flight), Duration::from_secs(60)).await;
+        assert!(!*in_flight.closed.borrow());
+
+        let (stream, _peer) = tokio::io::duplex(64);
+        let _stream = in_flight.track(stream);
+        let start = std::time::Instant::now();
+        drain(String::from("a:1"), Arc::clone(&in_flight), Duration::from_millis(100)).await;
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert!(*in_flight.closed.borrow());
+    }
+}
@@ -0,0 +1,182 @@
+use std::fmt;
+
+/// 读取/解析请求和响应时可能出现的所有错误。request.rs 和 response.rs 都返回这个类型，
+/// 这样 main.rs 只需要调用 status_code() 就能决定返回给客户端的 HTTP 状态码。
+#[derive(Debug)]
+pub enum ProxyError {
+    /// 客户端在发送完整请求之前挂断。IncompleteRequest 包含客户端挂断前成功读取的字节数
+    IncompleteRequest(usize),
+    /// 上游服务器在发送完整响应之前挂断
+    IncompleteResponse,
+    /// 客户端发送了无效的 HTTP 请求。httparse::Error 包含更多详细信息
+    MalformedRequest(httparse::Error),
+    /// 上游服务器发送了无效的 HTTP 响应。httparse::Error 包含更多详细信息
+    MalformedResponse(httparse::Error),
+    /// Content-Length 头存在，但不包含有效的数字值
+    InvalidContentLength,
+    /// Content-Length 头与发送的消息体大小不匹配
+    ContentLengthMismatch,
+    /// 请求头的数量或总字节数超过了配置的限制
+    RequestHeadersTooLarge,
+    /// 响应头的数量或总字节数超过了配置的限制
+    ResponseHeadersTooLarge,
+    /// 请求体大于配置的 --max-request-body
+    RequestBodyTooLarge,
+    /// 设置了 --reject-body-on-get 时，GET/HEAD 请求（包含的是请求方法）带有请求体。请求体没有被读取，
+    /// 所以之后不能再从这个连接读取请求
+    UnexpectedRequestBody(http::Method),
+    /// 响应体大于配置的 --max-response-body
+    ResponseBodyTooLarge,
+    /// 分块编码的响应体格式无效（块大小无法解析，或者块数据后面没有 CRLF）
+    InvalidChunkedBody,
+    /// 读取/写入 TcpStream 时遇到 I/O 错误
+    ConnectionError(std::io::Error),
+}
+
+impl ProxyError {
+    /// 返回应该发送给客户端的 HTTP 状态码。客户端的错误请求返回 4xx；上游服务器的错误响应返回 502。
+    pub fn status_code(&self) -> http::StatusCode {
+        match self {
+            ProxyError::IncompleteRequest(_)
+            | ProxyError::MalformedRequest(_)
+            | ProxyError::InvalidContentLength
+            | ProxyError::ContentLengthMismatch
+            | ProxyError::UnexpectedRequestBody(_) => http::StatusCode::BAD_REQUEST,
+            ProxyError::RequestHeadersTooLarge => {
+                http::StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE
+            }
+            ProxyError::RequestBodyTooLarge => http::StatusCode::PAYLOAD_TOO_LARGE,
+            ProxyError::IncompleteResponse
+            | ProxyError::MalformedResponse(_)
+            | ProxyError::ResponseHeadersTooLarge
+            | ProxyError::ResponseBodyTooLarge
+            | ProxyError::InvalidChunkedBody => http::StatusCode::BAD_GATEWAY,
+            ProxyError::ConnectionError(_) => http::StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+}
+
+/// 将构建 http::Request/http::Response 时的错误转换为最接近的 httparse::Error。httparse 接受的某些输入
+/// （例如 URI 中的 '<'）会被 http crate 拒绝，这样这些情况也可以用 MalformedRequest/MalformedResponse 表示。
+pub fn httparse_error_from(err: &http::Error) -> httparse::Error {
+    if err.is::<http::header::InvalidHeaderName>() {
+        httparse::Error::HeaderName
+    } else if err.is::<http::header::InvalidHeaderValue>() {
+        httparse::Error::HeaderValue
+    } else if err.is::<http::status::InvalidStatusCode>() {
+        httparse::Error::Status
+    } else {
+        // 非法的方法或 URI
+        httparse::Error::Token
+    }
+}
+
+impl fmt::Display for ProxyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProxyError::IncompleteRequest(bytes_read) => write!(
+                f,
+                "client hung up after sending {} bytes of an incomplete request",
+                bytes_read
+            ),
+            ProxyError::IncompleteResponse => {
+                write!(f, "upstream hung up before sending a complete response")
+            }
+            ProxyError::MalformedRequest(err) => write!(f, "malformed request: {}", err),
+            ProxyError::MalformedResponse(err) => write!(f, "malformed response: {}", err),
+            ProxyError::InvalidContentLength => write!(f, "invalid Content-Length header"),
+            ProxyError::ContentLengthMismatch => {
+                write!(f, "body length does not match Content-Length header")
+            }
+            ProxyError::RequestHeadersTooLarge => write!(f, "request headers are too large"),
+            ProxyError::ResponseHeadersTooLarge => write!(f, "response headers are too large"),
+            ProxyError::RequestBodyTooLarge => write!(f, "request body is too large"),
+            ProxyError::UnexpectedRequestBody(method) => write!(f, "{} request has a body", method),
+            ProxyError::ResponseBodyTooLarge => write!(f, "response body is too large"),
+            ProxyError::InvalidChunkedBody => write!(f, "invalid chunked response body"),
+            ProxyError::ConnectionError(err) => write!(f, "connection error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ProxyError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ProxyError::MalformedRequest(err) | ProxyError::MalformedResponse(err) => Some(err),
+            ProxyError::ConnectionError(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for ProxyError {
+    fn from(err: std::io::Error) -> Self {
+        ProxyError::ConnectionError(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_errors_map_to_4xx() {
+        assert_eq!(
+            ProxyError::IncompleteRequest(10).status_code(),
+            http::StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            ProxyError::MalformedRequest(httparse::Error::Token).status_code(),
+            http::StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            ProxyError::InvalidContentLength.status_code(),
+            http::StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            ProxyError::ContentLengthMismatch.status_code(),
+            http::StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            ProxyError::UnexpectedRequestBody(http::Method::GET).status_code(),
+            http::StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            ProxyError::RequestHeadersTooLarge.status_code(),
+            http::StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE
+        );
+        assert_eq!(
+            ProxyError::RequestBodyTooLarge.status_code(),
+            http::StatusCode::PAYLOAD_TOO_LARGE
+        );
+    }
+
+    #[test]
+    fn test_upstream_errors_map_to_bad_gateway() {
+        assert_eq!(
+            ProxyError::IncompleteResponse.status_code(),
+            http::StatusCode::BAD_GATEWAY
+        );
+        assert_eq!(
+            ProxyError::MalformedResponse(httparse::Error::Status).status_code(),
+            http::StatusCode::BAD_GATEWAY
+        );
+        assert_eq!(
+            ProxyError::ResponseHeadersTooLarge.status_code(),
+            http::StatusCode::BAD_GATEWAY
+        );
+        assert_eq!(
+            ProxyError::ResponseBodyTooLarge.status_code(),
+            http::StatusCode::BAD_GATEWAY
+        );
+    }
+
+    #[test]
+    fn test_connection_error_maps_to_service_unavailable() {
+        let err = ProxyError::from(std::io::Error::new(
+            std::io::ErrorKind::ConnectionReset,
+            "reset",
+        ));
+        assert_eq!(err.status_code(), http::StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
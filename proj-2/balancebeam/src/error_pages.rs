@@ -0,0 +1,117 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::response;
+
+/// 自定义错误页面的 Content-Type（页面文件都是 <状态码>.html）
+const PAGE_CONTENT_TYPE: &str = "text/html; charset=utf-8";
+
+/// --error-page-dir 中的自定义错误页面，按状态码索引。启动时全部读入内存，生成错误响应时不需要读文件。
+/// 没有设置 --error-page-dir（或者某个状态码没有对应的页面）时使用 make_http_error 的纯文本响应体。
+#[derive(Debug, Default)]
+pub struct ErrorPages {
+    pages: HashMap<http::StatusCode, Vec<u8>>,
+}
+
+impl ErrorPages {
+    /// 读取 dir 中所有名为 `<状态码>.html` 的文件（例如 502.html），忽略其他文件
+    pub fn load(dir: &Path) -> std::io::Result<ErrorPages> {
+        let mut pages = HashMap::new();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            let status = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_suffix(".html"))
+                .filter(|code| code.len() == 3)
+                .and_then(|code| http::StatusCode::from_bytes(code.as_bytes()).ok());
+            if let Some(status) = status {
+                pages.insert(status, std::fs::read(&path)?);
+            }
+        }
+        Ok(ErrorPages { pages })
+    }
+
+    /// 有页面的状态码（按数值排序），用于启动时的日志
+    pub fn statuses(&self) -> Vec<u16> {
+        let mut statuses: Vec<u16> = self.pages.keys().map(|status| status.as_u16()).collect();
+        statuses.sort();
+        statuses
+    }
+
+    /// 与 response::make_http_error 相同，但是如果这个状态码有自定义页面，响应体是这个页面
+    pub fn make_http_error(&self, status: http::StatusCode) -> http::Response<Vec<u8>> {
+        if !self.pages.contains_key(&status) {
+            return response::make_http_error(status);
+        }
+        self.make_http_error_with_headers(status, Vec::new())
+    }
+
+    /// 与 response::make_http_error_with_headers 相同，但是如果这个状态码有自定义页面，响应体是这个页面
+    pub fn make_http_error_with_headers(
+        &self,
+        status: http::StatusCode,
+        headers: Vec<(http::HeaderName, http::HeaderValue)>,
+    ) -> http::Response<Vec<u8>> {
+        let page = match self.pages.get(&status) {
+            Some(page) => page,
+            None => return response::make_http_error_with_headers(status, headers),
+        };
+        let mut response = http::Response::builder()
+            .status(status)
+            .header("Content-Type", PAGE_CONTENT_TYPE)
+            .header("Content-Length", page.len().to_string())
+            .version(http::Version::HTTP_11)
+            .body(page.clone())
+            .unwrap();
+        for (name, value) in headers {
+            response.headers_mut().insert(name, value);
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_and_serve_pages() {
+        let dir =
+            std::env::temp_dir().join(format!("balancebeam-error-pages-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("502.html"), "<h1>Back soon</h1>").unwrap();
+        std::fs::write(dir.join("429.html"), "<h1>Slow down</h1>").unwrap();
+        // 这些文件不是错误页面
+        std::fs::write(dir.join("README.txt"), "not a page").unwrap();
+        std::fs::write(dir.join("5021.html"), "not a page").unwrap();
+        std::fs::write(dir.join("abc.html"), "not a page").unwrap();
+        let pages = ErrorPages::load(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(pages.statuses(), vec![429, 502]);
+
+        let response = pages.make_http_error(http::StatusCode::BAD_GATEWAY);
+        assert_eq!(response.status(), http::StatusCode::BAD_GATEWAY);
+        assert_eq!(response.headers()["content-type"], PAGE_CONTENT_TYPE);
+        assert_eq!(response.headers()["content-length"], "18");
+        assert_eq!(response.body(), b"<h1>Back soon</h1>");
+
+        let response = pages.make_http_error_with_headers(
+            http::StatusCode::TOO_MANY_REQUESTS,
+            vec![(http::header::RETRY_AFTER, http::HeaderValue::from(42))],
+        );
+        assert_eq!(response.body(), b"<h1>Slow down</h1>");
+        assert_eq!(response.headers()["retry-after"], "42");
+
+        // 没有页面的状态码仍然使用纯文本响应体
+        let response = pages.make_http_error(http::StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(response.headers()["content-type"], "text/plain");
+        assert_eq!(response.body(), b"HTTP 504 Gateway Timeout");
+    }
+
+    #[test]
+    fn test_missing_dir() {
+        assert!(ErrorPages::load(Path::new("/nonexistent/balancebeam-error-pages")).is_err());
+        assert!(ErrorPages::default().statuses().is_empty());
+    }
+}
@@ -0,0 +1,155 @@
+use std::io::Write;
+
+/// 把 httparse 解析出的头复制到 HeaderMap 中。先按头的数量预留空间，这样插入时 HeaderMap 不会反复扩容。
+/// 头名或头值无效时返回对应的 httparse 错误（与通过 http::request::Builder 逐个添加时相同）。
+pub fn copy_parsed_headers(
+    parsed: &[httparse::Header<'_>],
+    headers: &mut http::HeaderMap,
+) -> Result<(), httparse::Error> {
+    headers.reserve(parsed.len());
+    for header in parsed {
+        let name = http::HeaderName::from_bytes(header.name.as_bytes())
+            .map_err(|_| httparse::Error::HeaderName)?;
+        let value =
+            http::HeaderValue::from_bytes(header.value).map_err(|_| httparse::Error::HeaderValue)?;
+        headers.append(name, value);
+    }
+    Ok(())
+}
+
+/// --strip-request-header 或 --strip-response-header 指定的一组头名。HeaderName 总是小写的，
+/// 所以按名字删除不区分大小写。
+#[derive(Debug, Default)]
+pub struct StripHeaders {
+    names: Vec<http::HeaderName>,
+}
+
+impl StripHeaders {
+    /// names 中有无效的头名时返回这个头名
+    pub fn new(names: &[String]) -> Result<StripHeaders, String> {
+        let names = names
+            .iter()
+            .map(|name| http::HeaderName::from_bytes(name.as_bytes()).map_err(|_| name.clone()))
+            .collect::<Result<_, _>>()?;
+        Ok(StripHeaders { names })
+    }
+
+    /// 删除 headers 中所有（包括重复出现的）这些头
+    pub fn apply(&self, headers: &mut http::HeaderMap) {
+        for name in &self.names {
+            headers.remove(name);
+        }
+    }
+}
+
+/// 预估的起始行长度。只用于预留空间，起始行更长时 Vec 会再扩容一次
+const START_LINE_ESTIMATE: usize = 64;
+
+/// 序列化起始行和 headers 大约需要的字节数：每个头是 `name: value\r\n`，再加上结束的空行，
+/// 起始行按 START_LINE_ESTIMATE 估计
+pub fn head_capacity(headers: &http::HeaderMap) -> usize {
+    START_LINE_ESTIMATE
+        + headers
+            .iter()
+            .map(|(name, value)| name.as_str().len() + value.len() + 4)
+            .sum::<usize>()
+        + 2
+}
+
+/// 在 bytes 后面写入起始行（请求行或状态行，不包括 \r\n）、所有头和结束的空行。起始行直接格式化到
+/// bytes 中，不经过中间的 String
+pub fn write_head(bytes: &mut Vec<u8>, start_line: std::fmt::Arguments<'_>, headers: &http::HeaderMap) {
+    bytes
+        .write_fmt(start_line)
+        .expect("Writing to a Vec cannot fail");
+    bytes.extend_from_slice(b"\r\n");
+    for (name, value) in headers {
+        bytes.extend_from_slice(name.as_str().as_bytes());
+        bytes.extend_from_slice(b": ");
+        bytes.extend_from_slice(value.as_bytes());
+        bytes.extend_from_slice(b"\r\n");
+    }
+    bytes.extend_from_slice(b"\r\n");
+}
+
+/// Connection 头（可能出现多次，每个值是逗号分隔的列表）中是否包含 close
+pub fn connection_close(headers: &http::HeaderMap) -> bool {
+    headers
+        .get_all(http::header::CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|token| token.trim().eq_ignore_ascii_case("close"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_copy_parsed_headers() {
+        let parsed = [
+            httparse::Header { name: "Host", value: b"example.com" },
+            httparse::Header { name: "X-Tag", value: b"a" },
+            httparse::Header { name: "x-tag", value: b"b" },
+        ];
+        let mut headers = http::HeaderMap::new();
+        copy_parsed_headers(&parsed, &mut headers).unwrap();
+        assert_eq!(headers.len(), 3);
+        let tags: Vec<_> = headers.get_all("x-tag").iter().collect();
+        assert_eq!(tags, vec!["a", "b"]);
+
+        let bad_value = [httparse::Header { name: "X-Bad", value: b"line\nbreak" }];
+        assert_eq!(
+            copy_parsed_headers(&bad_value, &mut http::HeaderMap::new()),
+            Err(httparse::Error::HeaderValue)
+        );
+        let bad_name = [httparse::Header { name: "Bad Name", value: b"x" }];
+        assert_eq!(
+            copy_parsed_headers(&bad_name, &mut http::HeaderMap::new()),
+            Err(httparse::Error::HeaderName)
+        );
+    }
+
+    #[test]
+    fn test_strip_headers() {
+        let strip = StripHeaders::new(&[String::from("X-Internal"), String::from("server")]).unwrap();
+        let mut headers = http::HeaderMap::new();
+        headers.append("x-internal", http::HeaderValue::from_static("1"));
+        headers.append("X-INTERNAL", http::HeaderValue::from_static("2"));
+        headers.append("Server", http::HeaderValue::from_static("nginx"));
+        headers.append("X-Tag", http::HeaderValue::from_static("a"));
+        strip.apply(&mut headers);
+        assert_eq!(headers.len(), 1);
+        assert_eq!(headers["x-tag"], "a");
+
+        assert_eq!(
+            StripHeaders::new(&[String::from("Bad Name")]).unwrap_err(),
+            "Bad Name"
+        );
+    }
+
+    #[test]
+    fn test_connection_close() {
+        let mut headers = http::HeaderMap::new();
+        assert!(!connection_close(&headers));
+        headers.append("connection", http::HeaderValue::from_static("keep-alive"));
+        assert!(!connection_close(&headers));
+        headers.append("connection", http::HeaderValue::from_static("Upgrade, Close"));
+        assert!(connection_close(&headers));
+    }
+
+    #[test]
+    fn test_write_head() {
+        let mut headers = http::HeaderMap::new();
+        headers.insert("host", http::HeaderValue::from_static("example.com"));
+        headers.append("x-tag", http::HeaderValue::from_static("a"));
+        let mut bytes = Vec::new();
+        write_head(&mut bytes, format_args!("GET / HTTP/1.1"), &headers);
+        assert_eq!(bytes, b"GET / HTTP/1.1\r\nhost: example.com\r\nx-tag: a\r\n\r\n");
+        assert_eq!(
+            head_capacity(&headers) - bytes.len(),
+            START_LINE_ESTIMATE - "GET / HTTP/1.1\r\n".len()
+        );
+    }
+}
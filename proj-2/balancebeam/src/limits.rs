@@ -0,0 +1,39 @@
+/// 默认最多允许的头数量
+pub const DEFAULT_MAX_HEADERS: usize = 32;
+/// 默认请求行/状态行加上所有头最多允许的字节数
+pub const DEFAULT_MAX_HEADER_BYTES: usize = 8000;
+/// 默认最多允许的请求体/响应体字节数
+pub const DEFAULT_MAX_BODY_BYTES: usize = 10000000;
+/// 默认读取响应体时每次 read 最多读取的字节数
+pub const DEFAULT_READ_BUFFER_SIZE: usize = 16 * 1024;
+
+/// 解析 HTTP 请求和响应时使用的大小限制。request.rs 和 response.rs 共用这些限制，
+/// 它们可以通过命令行参数配置。
+#[derive(Debug, Clone)]
+pub struct ParseLimits {
+    /// 最多允许的头数量
+    pub max_headers: usize,
+    /// 请求行/状态行加上所有头最多允许的字节数
+    pub max_header_bytes: usize,
+    /// 请求体最多允许的字节数，超过时返回 413
+    pub max_request_body: usize,
+    /// 响应体最多允许的字节数，超过时返回 502
+    pub max_response_body: usize,
+    /// 是否拒绝带有请求体的 GET/HEAD 请求（--reject-body-on-get）
+    pub reject_body_on_get: bool,
+    /// 读取响应体时使用的缓冲区大小，也就是每次 read 最多读取的字节数（至少为 1）
+    pub read_buffer_size: usize,
+}
+
+impl Default for ParseLimits {
+    fn default() -> Self {
+        ParseLimits {
+            max_headers: DEFAULT_MAX_HEADERS,
+            max_header_bytes: DEFAULT_MAX_HEADER_BYTES,
+            max_request_body: DEFAULT_MAX_BODY_BYTES,
+            max_response_body: DEFAULT_MAX_BODY_BYTES,
+            reject_body_on_get: false,
+            read_buffer_size: DEFAULT_READ_BUFFER_SIZE,
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use std::net::SocketAddr;
+use tokio::net::{TcpListener, TcpSocket};
+
+/// 与 tokio::net::TcpListener::bind 的默认值相同
+const LISTEN_BACKLOG: u32 = 1024;
+
+/// 监听 address。reuse_port 为 false 时与 TcpListener::bind 完全相同；为 true 时在绑定之前设置
+/// SO_REUSEPORT，这样多个 balancebeam 进程可以监听同一个端口，由内核把新连接分配给它们。
+/// address 解析出多个地址时（例如主机名），依次尝试，返回第一个绑定成功的监听套接字。
+pub async fn bind(address: &str, reuse_port: bool) -> std::io::Result<TcpListener> {
+    if !reuse_port {
+        return TcpListener::bind(address).await;
+    }
+    let mut last_error = None;
+    for addr in tokio::net::lookup_host(address).await? {
+        match bind_reuse_port(addr) {
+            Ok(listener) => return Ok(listener),
+            Err(err) => last_error = Some(err),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("{} did not resolve to any address", address),
+        )
+    }))
+}
+
+fn bind_reuse_port(addr: SocketAddr) -> std::io::Result<TcpListener> {
+    let socket = match addr {
+        SocketAddr::V4(_) => TcpSocket::new_v4()?,
+        SocketAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+    // TcpListener::bind 也会设置 SO_REUSEADDR
+    socket.set_reuseaddr(true)?;
+    socket.set_reuseport(true)?;
+    socket.bind(addr)?;
+    socket.listen(LISTEN_BACKLOG)
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use tokio::net::TcpStream;
+
+    #[tokio::test]
+    async fn test_reuse_port_listeners_share_port() {
+        let first = bind("127.0.0.1:0", true).await.unwrap();
+        let address = first.local_addr().unwrap().to_string();
+        let second = bind(&address, true).await.unwrap();
+        // 没有设置 SO_REUSEPORT 的套接字仍然不能绑定已经被占用的端口
+        assert!(bind(&address, false).await.is_err());
+
+        // 内核按照连接的四元组选择监听套接字，所以多连接几次，两个监听套接字都应该接受到连接
+        let (accepted_tx, mut accepted_rx) = tokio::sync::mpsc::unbounded_channel();
+        for (idx, listener) in [first, second].into_iter().enumerate() {
+            let accepted_tx = accepted_tx.clone();
+            tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    let _ = accepted_tx.send((idx, stream));
+                }
+            });
+        }
+        let mut accepted_by = HashSet::new();
+        let mut connections = Vec::new();
+        for _ in 0..200 {
+            connections.push(TcpStream::connect(&address).await.unwrap());
+            let (idx, stream) = accepted_rx.recv().await.unwrap();
+            accepted_by.insert(idx);
+            connections.push(stream);
+            if accepted_by.len() == 2 {
+                break;
+            }
+        }
+        assert_eq!(accepted_by.len(), 2, "Only listener {:?} accepted connections", accepted_by);
+    }
+
+    #[tokio::test]
+    async fn test_without_reuse_port() {
+        let listener = bind("127.0.0.1:0", false).await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let client = TcpStream::connect(&address);
+        let (client, accepted) = tokio::join!(client, listener.accept());
+        assert_eq!(client.unwrap().local_addr().unwrap(), accepted.unwrap().1);
+    }
+}
@@ -1,19 +1,67 @@
+mod access_log;
+mod chunked;
+mod config;
+mod connector;
+mod cors;
+mod counting;
+mod drain;
+mod error;
+mod error_pages;
+mod headers;
+mod limits;
+mod listener;
+mod pool;
+mod rate_limit;
 mod request;
 mod response;
+mod stats;
+mod stream;
+mod sticky;
+mod upstreams;
 
-use clap::Parser;
-use rand::{Rng, SeedableRng};
-use tokio::net::{TcpListener, TcpStream};
+use access_log::{LogFormat, RequestLog};
+use chunked::RelayError;
+use connector::{Connection, TcpConnector, UpstreamConnector};
+use cors::CorsConfig;
+use counting::{ByteTotals, ConnectionBytes, CountingStream};
+use drain::TrackedStream;
+use error::ProxyError;
+use error_pages::ErrorPages;
+use headers::StripHeaders;
+use limits::ParseLimits;
+use pool::UpstreamPool;
+use rate_limit::{Decision, RateLimiter};
+use request::HostRewrite;
+use response::BodyKind;
+use sticky::StickyCookie;
+use upstreams::{LoadBalanceAlgorithm, UpstreamList};
+use clap::parser::ValueSource;
+use clap::{CommandFactory, FromArgMatches, Parser};
+use config::{Config, UpstreamConfig};
+use rand::SeedableRng;
+use tokio::net::TcpStream;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use tokio::sync::RwLock;
 use std::collections::HashSet;
+use tokio::signal::unix::{signal, SignalKind};
 use std::time::Duration;
 use tokio::time::timeout;
 
+/// 一次主动健康检查（连接、发送请求、读取响应）最多花费的时间
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+/// --wait-for-upstream 时，一轮健康检查没有服务器通过之后隔多久再检查一轮
+const WAIT_FOR_UPSTREAM_RETRY: Duration = Duration::from_millis(500);
+
 /// 包含从命令行调用 balancebeam 时解析的信息。Clap 宏提供了一种自动构建命令行参数解析器的便捷方式。
 #[derive(Parser, Debug)]
 #[clap(about = "Fun with load balancing")]
 struct CmdOptions {
+    #[clap(
+        long,
+        help = "Read settings from this TOML file, named like the long options below (e.g. bind = \"0.0.0.0:8080\"); upstreams are listed as upstreams = [\"a:80\", { address = \"b:80\", weight = 3 }], and a weighted upstream is picked proportionally more often. Options given on the command line take precedence"
+    )]
+    config: Option<String>,
     #[clap(
         short,
         long,
@@ -21,11 +69,39 @@ struct CmdOptions {
         default_value = "0.0.0.0:1100"
     )]
     bind: String,
+    #[clap(
+        long,
+        help = "Set SO_REUSEPORT on the listening socket so several balancebeam processes can bind the same address; the kernel spreads new connections across them"
+    )]
+    reuse_port: bool,
     #[clap(short, long, help = "Upstream host to forward requests to")]
     upstream: Vec<String>,
     #[clap(
         long,
-        help = "Perform active health checks on this interval (in seconds)",
+        value_enum,
+        help = "How to pick an upstream for each request: random, or least-connections (the live upstream with the fewest requests in flight, breaking ties randomly)",
+        default_value = "random"
+    )]
+    load_balance_algorithm: LoadBalanceAlgorithm,
+    #[clap(
+        long,
+        help = "File listing additional upstream hosts, one per line; re-read on SIGHUP"
+    )]
+    upstream_file: Option<String>,
+    #[clap(
+        long,
+        help = "When a reload removes an upstream, give its in-flight requests this many seconds to finish before closing their connections (0 = never close them)",
+        default_value = "0"
+    )]
+    drain_timeout: u64,
+    #[clap(
+        long,
+        help = "Keep connections to upstreams open and reuse them for later requests, sending Connection: keep-alive (by default every forwarded request carries Connection: close)"
+    )]
+    upstream_keepalive: bool,
+    #[clap(
+        long,
+        help = "Perform active health checks on this interval (in seconds; 0 = never)",
         default_value = "10"
     )]
     active_health_check_interval: usize,
@@ -35,18 +111,197 @@ struct CmdOptions {
     default_value = "/"
     )]
     active_health_check_path: String,
+    #[clap(
+        long,
+        help = "HTTP status an upstream must return to an active health check to count as healthy",
+        default_value = "200"
+    )]
+    health_check_expect_status: u16,
+    #[clap(
+        long,
+        help = "Only count an upstream as healthy if the body of its active health check response also contains this string (for backends that answer 200 even when degraded)"
+    )]
+    health_check_expect_body: Option<String>,
+    #[clap(
+        long,
+        help = "Maximum number of upstreams to health check at the same time",
+        default_value = "8"
+    )]
+    health_check_concurrency: usize,
+    #[clap(
+        long,
+        help = "Don't bind or accept connections until at least one upstream passes an active health check, so clients don't get 502s while upstreams are still starting"
+    )]
+    wait_for_upstream: bool,
+    #[clap(
+        long,
+        help = "With --wait-for-upstream, start accepting connections anyway after this many seconds without a healthy upstream (0 = wait forever)",
+        default_value = "30"
+    )]
+    wait_for_upstream_timeout: u64,
     #[clap(
         long,
         help = "Maximum number of requests to accept per IP per minute (0 = unlimited)",
         default_value = "0"
     )]
     max_requests_per_minute: usize,
+    #[clap(
+        long,
+        help = "Allow requests when the rate limiter can't be consulted promptly (by default they get a 429)"
+    )]
+    ratelimit_fail_open: bool,
+    #[clap(
+        long,
+        help = "Rate limit IPv6 clients by network prefix of this many bits rather than by full address (max 128)",
+        default_value = "64"
+    )]
+    ratelimit_ipv6_prefix: u8,
     #[clap(
         long,
         help = "Number of worker threads in the thread pool",
         default_value = "4"
     )]
     num_threads: usize,
+    #[clap(
+        long,
+        help = "Maximum number of attempts to forward each request (defaults to the number of upstreams)"
+    )]
+    max_retries: Option<usize>,
+    #[clap(
+        long,
+        help = "Close client connections that send no new request within this many seconds (0 = never)",
+        default_value = "0"
+    )]
+    keepalive_timeout: u64,
+    #[clap(
+        long,
+        help = "Answer with 504 if forwarding a request and sending its response takes longer than this many seconds, including retries (0 = never)",
+        default_value = "0"
+    )]
+    request_timeout: u64,
+    #[clap(
+        long,
+        help = "Maximum number of headers allowed in a request or response",
+        default_value = "32"
+    )]
+    max_headers: usize,
+    #[clap(
+        long,
+        help = "Maximum size (in bytes) of the request/status line plus headers",
+        default_value = "8000"
+    )]
+    max_header_bytes: usize,
+    #[clap(
+        long,
+        help = "Maximum size (in bytes) of a request body; larger requests get a 413",
+        default_value = "10000000"
+    )]
+    max_request_body: usize,
+    #[clap(
+        long,
+        help = "Maximum size (in bytes) of a response body; larger responses get a 502",
+        default_value = "10000000"
+    )]
+    max_response_body: usize,
+    #[clap(
+        long,
+        help = "Stream response bodies of at least this many bytes (or of unknown length) to the client as they arrive instead of buffering them (0 = always buffer). Buffered bodies are read in full before anything is sent, so only streamed responses stop reading from the upstream as soon as the client disconnects",
+        default_value = "0"
+    )]
+    stream_threshold: usize,
+    #[clap(
+        long,
+        help = "Size (in bytes) of the buffer used to read buffered response bodies from upstreams; larger buffers mean fewer reads for big bodies",
+        default_value = "16384"
+    )]
+    read_buffer_size: usize,
+    #[clap(
+        long,
+        help = "Answer CORS preflight requests and allow this origin (disabled if not set)"
+    )]
+    cors_allow_origin: Option<String>,
+    #[clap(
+        long,
+        help = "Value of Access-Control-Allow-Methods in CORS preflight responses",
+        default_value = "GET, POST, PUT, DELETE, OPTIONS"
+    )]
+    cors_allow_methods: String,
+    #[clap(
+        long,
+        help = "Value of Access-Control-Allow-Headers in CORS preflight responses",
+        default_value = "Content-Type, Authorization"
+    )]
+    cors_allow_headers: String,
+    #[clap(
+        long,
+        help = "Answer GET requests for this path with per-upstream response statistics instead of forwarding them"
+    )]
+    stats_path: Option<String>,
+    #[clap(
+        long,
+        help = "Mark an upstream as dead after this many consecutive 5xx responses (0 = never)",
+        default_value = "0"
+    )]
+    max_5xx_before_eject: usize,
+    #[clap(
+        long,
+        help = "Log the number of requests handled every this many seconds (0 = never)",
+        default_value = "0"
+    )]
+    stats_interval: u64,
+    #[clap(
+        long,
+        help = "Reply 400 to GET/HEAD requests that carry a body instead of forwarding them"
+    )]
+    reject_body_on_get: bool,
+    #[clap(
+        long,
+        help = "Send all requests carrying this cookie's value to the same upstream; set it on responses to clients that lack it"
+    )]
+    sticky_cookie: Option<String>,
+    #[clap(
+        long,
+        help = "Forward the client's Host header unchanged; with false, rewrite it to the selected upstream's address",
+        default_value_t = true,
+        action = clap::ArgAction::Set
+    )]
+    preserve_host: bool,
+    #[clap(long, help = "Rewrite the Host header of every forwarded request to this value")]
+    set_host: Option<String>,
+    #[clap(
+        long,
+        help = "Remove this header (case-insensitive) from client requests before forwarding them; may be repeated"
+    )]
+    strip_request_header: Vec<String>,
+    #[clap(
+        long,
+        help = "Remove this header (case-insensitive) from upstream responses before sending them to the client; may be repeated"
+    )]
+    strip_response_header: Vec<String>,
+    #[clap(
+        long,
+        help = "Answer with 413 and close a client connection once its request and response bodies exceed this many bytes in total (0 = unlimited)",
+        default_value = "0"
+    )]
+    max_connection_bytes: u64,
+    #[clap(
+        long,
+        help = "Close a client connection after answering this many requests on it, so long-lived keep-alive clients reconnect and get re-balanced (0 = unlimited)",
+        default_value = "0"
+    )]
+    max_requests_per_connection: usize,
+    #[clap(
+        long,
+        value_enum,
+        help = "How to log requests: text (a log line when each request arrives and when it is answered), clf (Common Log Format) or json (one JSON object per request); clf and json lines are printed to stdout",
+        default_value = "text"
+    )]
+    log_format: LogFormat,
+    #[clap(
+        long,
+        help = "Directory of custom error pages named <status>.html (e.g. 502.html), served as text/html instead of the plain-text body for those statuses"
+    )]
+    error_page_dir: Option<std::path::PathBuf>,
 }
 
 /// 包含有关 balancebeam 状态的信息（例如，我们当前代理到哪些服务器，哪些服务器失败了，速率限制计数等）
@@ -54,19 +309,105 @@ struct CmdOptions {
 /// 您应该在后续里程碑中向此结构体添加字段。
 struct ProxyState {
     /// 检查上游服务器是否存活的频率（里程碑 4）
-    #[allow(dead_code)]
     active_health_check_interval: usize,
     /// 执行主动健康检查时应该发送请求的路径（里程碑 4）
-    #[allow(dead_code)]
     active_health_check_path: String,
-    /// 单个 IP 在一分钟内可以发出的最大请求数（里程碑 5）
-    #[allow(dead_code)]
-    max_requests_per_minute: usize,
-    /// 我们正在代理到的服务器地址
-    upstream_addresses: Vec<String>,
-    /// 存储已失败的上游服务器索引（里程碑 3）
-    /// 使用 RwLock 允许多个任务同时读取，只有在标记服务器失败时才需要写锁
-    dead_upstreams: RwLock<HashSet<usize>>,
+    /// 主动健康检查的响应必须是这个状态码，上游服务器才被视为存活
+    health_check_expect_status: http::StatusCode,
+    /// 主动健康检查的响应体还必须包含这个字符串（未设置 --health-check-expect-body 时为 None）
+    health_check_expect_body: Option<String>,
+    /// 每一轮主动健康检查最多同时检查多少个上游服务器
+    health_check_concurrency: usize,
+    /// 按 IP 限制每分钟的请求数（里程碑 5；--max-requests-per-minute 为 0 时为 None）
+    rate_limiter: Option<RateLimiter>,
+    /// 我们正在代理到的服务器，以及它们的健康状态和统计信息。收到 SIGHUP 时整体替换为新列表；
+    /// 读取时克隆 Arc 即可得到一份不会变化的快照
+    upstreams: RwLock<Arc<UpstreamList>>,
+    /// 如何在可用的上游服务器中选择一个
+    load_balance_algorithm: LoadBalanceAlgorithm,
+    /// 每个请求最多尝试转发的次数（与上游服务器数量无关）
+    max_retries: usize,
+    /// 客户端在两个请求之间最多可以空闲多少秒（0 表示不限制）
+    keepalive_timeout: u64,
+    /// 转发一个请求并发送响应最多花费多少秒（0 表示不限制）
+    request_timeout: u64,
+    /// 解析请求和响应时使用的头数量/大小限制
+    parse_limits: ParseLimits,
+    /// Content-Length 不小于这个值（或者长度未知）的响应体边读边转发（0 表示总是先完整读入）
+    stream_threshold: usize,
+    /// CORS 配置（未设置 --cors-allow-origin 时为 None）
+    cors: Option<CorsConfig>,
+    /// 查询统计信息的路径（未设置 --stats-path 时为 None）
+    stats_path: Option<String>,
+    /// 上游服务器连续返回多少次 5xx 后将其标记为失败（0 表示不检查）
+    max_5xx_before_eject: usize,
+    /// 自上次记录吞吐量日志以来处理的请求数（仅在设置了 --stats-interval 时使用）
+    requests_handled: AtomicUsize,
+    /// 会话保持使用的 cookie（未设置 --sticky-cookie 时为 None）
+    sticky_cookie: Option<StickyCookie>,
+    /// 所有已关闭的客户端连接与客户端和上游服务器之间传输的字节总数
+    bytes_transferred: ByteTotals,
+    /// 转发请求之前如何处理 Host 头
+    host_rewrite: HostRewrite,
+    /// 转发请求之前从中删除的头（--strip-request-header）
+    strip_request_headers: StripHeaders,
+    /// 发送响应之前从上游服务器的响应中删除的头（--strip-response-header）
+    strip_response_headers: StripHeaders,
+    /// 一个客户端连接上的请求体和响应体总共最多允许多少字节（0 表示不限制）
+    max_connection_bytes: u64,
+    /// 一个客户端连接上最多处理多少个请求，之后关闭连接（0 表示不限制）
+    max_requests_per_connection: usize,
+    /// 用来连接上游服务器（运行时是 TcpConnector，测试中可以换成假的实现）
+    connector: Box<dyn UpstreamConnector>,
+    /// 到上游服务器的空闲 keep-alive 连接（未设置 --upstream-keepalive 时为 None）
+    upstream_pool: Option<UpstreamPool>,
+    /// 当前打开的客户端连接数
+    active_connections: AtomicUsize,
+    /// 访问日志的格式
+    log_format: LogFormat,
+    /// 我们自己生成的错误响应使用的自定义页面（未设置 --error-page-dir 时为空）
+    error_pages: ErrorPages,
+}
+
+impl ProxyState {
+    /// 返回当前状态的可读快照：上游服务器及其健康状态、每个 IP 的速率限制计数、当前打开的客户端连接数。
+    /// 只短暂地持有读锁（复制失败服务器集合之后立即释放），不会阻塞请求处理；速率限制器的锁被占用时
+    /// 不等待，直接跳过计数。
+    async fn debug_dump(&self) -> String {
+        let upstreams = Arc::clone(&*self.upstreams.read().await);
+        let dead = upstreams.dead.read().await.clone();
+        let mut dump = format!(
+            "ProxyState snapshot\n  upstreams ({} total, {} dead):\n",
+            upstreams.addresses.len(),
+            dead.len()
+        );
+        for (idx, address) in upstreams.addresses.iter().enumerate() {
+            let health = if dead.contains(&idx) { "dead" } else { "alive" };
+            dump += &format!(
+                "    [{}] {} {} {}\n",
+                idx,
+                address,
+                health,
+                upstreams.errors[idx].summary()
+            );
+        }
+        if let Some(rate_limiter) = &self.rate_limiter {
+            match rate_limiter.counts() {
+                Some(counts) => {
+                    dump += &format!("  rate limit counts ({} clients):\n", counts.len());
+                    for (client_ip, count) in counts {
+                        dump += &format!("    {} {}\n", client_ip, count);
+                    }
+                }
+                None => dump += "  rate limit counts: unavailable (limiter busy)\n",
+            }
+        }
+        dump += &format!(
+            "  active connections: {}\n",
+            self.active_connections.load(Ordering::Relaxed)
+        );
+        dump
+    }
 }
 
 #[tokio::main]
@@ -78,15 +419,30 @@ async fn main() {
     }
     pretty_env_logger::init();
 
-    // 解析传递给该程序的命令行参数
-    let options = CmdOptions::parse();
-    if options.upstream.len() < 1 {
-        log::error!("At least one upstream server must be specified using the --upstream option.");
-        std::process::exit(1);
+    // 解析传递给该程序的命令行参数。--help 和无效的参数像以前一样由 clap 处理（打印信息后退出）
+    let matches = CmdOptions::command().get_matches();
+    let (options, weights) = match merge_config_file(&matches) {
+        Ok(merged) => merged,
+        Err(err) => {
+            log::error!("{}", err);
+            std::process::exit(1);
+        }
+    };
+    let state = match build_state(&options, &weights) {
+        Ok(state) => Arc::new(state),
+        Err(err) => {
+            log::error!("{}", err);
+            std::process::exit(1);
+        }
+    };
+
+    // 等到至少一个上游服务器通过健康检查之后才开始监听，这样刚启动时的请求不会因为还没有可用的服务器而得到 502
+    if options.wait_for_upstream {
+        wait_for_upstream(&state, options.wait_for_upstream_timeout).await;
     }
 
     // 开始监听连接
-    let listener = match TcpListener::bind(&options.bind).await {
+    let listener = match listener::bind(&options.bind, options.reuse_port).await {
         Ok(listener) => listener,
         Err(err) => {
             log::error!("Could not bind to {}: {}", options.bind, err);
@@ -95,14 +451,40 @@ async fn main() {
     };
     log::info!("Listening for requests on {}", options.bind);
 
-    // 处理传入的连接
-    let state = Arc::new(ProxyState {
-        upstream_addresses: options.upstream,
-        active_health_check_interval: options.active_health_check_interval,
-        active_health_check_path: options.active_health_check_path,
-        max_requests_per_minute: options.max_requests_per_minute,
-        dead_upstreams: RwLock::new(HashSet::new()),
-    });
+    // 定期对上游服务器进行主动健康检查
+    if state.active_health_check_interval > 0 {
+        let state = Arc::clone(&state);
+        tokio::spawn(async move {
+            active_health_check(state).await;
+        });
+    }
+
+    // 定期记录吞吐量
+    if options.stats_interval > 0 {
+        let state = Arc::clone(&state);
+        let stats_interval = options.stats_interval;
+        tokio::spawn(async move {
+            log_request_rate(&state, stats_interval).await;
+        });
+    }
+
+    // 收到 SIGUSR2 时记录状态快照
+    {
+        let state = Arc::clone(&state);
+        tokio::spawn(async move {
+            dump_state_on_sigusr2(&state).await;
+        });
+    }
+
+    // 收到 SIGHUP 时重新读取 --upstream-file
+    if let Some(upstream_file) = options.upstream_file {
+        let state = Arc::clone(&state);
+        let static_upstreams = options.upstream.clone();
+        let drain_timeout = options.drain_timeout;
+        tokio::spawn(async move {
+            reload_upstreams_on_sighup(&state, static_upstreams, upstream_file, drain_timeout).await;
+        });
+    }
     
     loop {
         match listener.accept().await {
@@ -120,6 +502,415 @@ async fn main() {
     }
 }
 
+/// 校验合并后的选项并构建 ProxyState。weights 是 options.upstream 中每个服务器的权重（来自配置文件，
+/// 可以比 options.upstream 短，缺少的为 1）。选项无效时返回要显示的错误信息。
+fn build_state(options: &CmdOptions, weights: &[u32]) -> Result<ProxyState, String> {
+    let upstream_addresses = load_upstreams(&options.upstream, options.upstream_file.as_deref())
+        .map_err(|err| format!("Could not read upstream file: {}", err))?;
+    if upstream_addresses.is_empty() {
+        return Err(String::from(
+            "At least one upstream server must be specified using the --upstream or --upstream-file option.",
+        ));
+    }
+
+    // 未指定 --max-retries 时，保持原有行为：每个上游服务器尝试一次
+    let max_retries = options.max_retries.unwrap_or(upstream_addresses.len());
+    if max_retries < 1 {
+        return Err(String::from("--max-retries must be at least 1."));
+    }
+    if options.max_headers < 1 || options.max_header_bytes < 1 {
+        return Err(String::from("--max-headers and --max-header-bytes must be at least 1."));
+    }
+    if options.read_buffer_size < 1 {
+        return Err(String::from("--read-buffer-size must be at least 1."));
+    }
+
+    let cors = match &options.cors_allow_origin {
+        Some(origin) => Some(CorsConfig::new(
+            origin,
+            &options.cors_allow_methods,
+            &options.cors_allow_headers,
+        )?),
+        None => None,
+    };
+
+    if options.ratelimit_ipv6_prefix > 128 {
+        return Err(String::from("--ratelimit-ipv6-prefix must be at most 128"));
+    }
+
+    if options.health_check_concurrency == 0 {
+        return Err(String::from("--health-check-concurrency must be at least 1"));
+    }
+
+    let health_check_expect_status = http::StatusCode::from_u16(options.health_check_expect_status)
+        .map_err(|_| {
+            format!(
+                "Invalid value for --health-check-expect-status: {}",
+                options.health_check_expect_status
+            )
+        })?;
+
+    // --set-host 优先于 --preserve-host
+    let host_rewrite = match &options.set_host {
+        Some(host) => match http::HeaderValue::from_str(host) {
+            Ok(host) => HostRewrite::Fixed(host),
+            Err(_) => return Err(format!("Invalid value for --set-host: {:?}", host)),
+        },
+        None if options.preserve_host => HostRewrite::Preserve,
+        None => HostRewrite::Upstream,
+    };
+
+    let strip_headers = |names: &[String], option: &str| {
+        StripHeaders::new(names).map_err(|name| format!("Invalid header name for {}: {:?}", option, name))
+    };
+    let strip_request_headers = strip_headers(&options.strip_request_header, "--strip-request-header")?;
+    let strip_response_headers = strip_headers(&options.strip_response_header, "--strip-response-header")?;
+
+    let sticky_cookie = match &options.sticky_cookie {
+        Some(name) => Some(StickyCookie::new(name)?),
+        None => None,
+    };
+
+    let error_pages = match &options.error_page_dir {
+        Some(dir) => {
+            let error_pages = ErrorPages::load(dir)
+                .map_err(|err| format!("Failed to load error pages from {}: {}", dir.display(), err))?;
+            log::info!("Loaded error pages for statuses {:?}", error_pages.statuses());
+            error_pages
+        }
+        None => ErrorPages::default(),
+    };
+
+    // --upstream-file 中的服务器排在 --upstream 之后，权重为 1
+    let upstreams = upstream_addresses
+        .into_iter()
+        .zip(weights.iter().copied().chain(std::iter::repeat(1)))
+        .collect();
+
+    Ok(ProxyState {
+        upstreams: RwLock::new(Arc::new(UpstreamList::weighted(upstreams))),
+        load_balance_algorithm: options.load_balance_algorithm,
+        active_health_check_interval: options.active_health_check_interval,
+        active_health_check_path: options.active_health_check_path.clone(),
+        health_check_expect_status,
+        health_check_expect_body: options.health_check_expect_body.clone(),
+        health_check_concurrency: options.health_check_concurrency,
+        rate_limiter: match options.max_requests_per_minute {
+            0 => None,
+            limit => Some(RateLimiter::new(
+                limit,
+                options.ratelimit_ipv6_prefix,
+                options.ratelimit_fail_open,
+            )),
+        },
+        max_retries,
+        keepalive_timeout: options.keepalive_timeout,
+        request_timeout: options.request_timeout,
+        parse_limits: ParseLimits {
+            max_headers: options.max_headers,
+            max_header_bytes: options.max_header_bytes,
+            max_request_body: options.max_request_body,
+            max_response_body: options.max_response_body,
+            reject_body_on_get: options.reject_body_on_get,
+            read_buffer_size: options.read_buffer_size,
+        },
+        stream_threshold: options.stream_threshold,
+        cors,
+        stats_path: options.stats_path.clone(),
+        max_5xx_before_eject: options.max_5xx_before_eject,
+        requests_handled: AtomicUsize::new(0),
+        sticky_cookie,
+        bytes_transferred: ByteTotals::default(),
+        host_rewrite,
+        strip_request_headers,
+        strip_response_headers,
+        max_connection_bytes: options.max_connection_bytes,
+        max_requests_per_connection: options.max_requests_per_connection,
+        connector: Box::new(TcpConnector),
+        upstream_pool: options.upstream_keepalive.then(UpstreamPool::default),
+        active_connections: AtomicUsize::new(0),
+        log_format: options.log_format,
+        error_pages,
+    })
+}
+
+/// 根据命令行参数返回 CmdOptions，以及 options.upstream 中每个服务器的权重。指定了 --config 时读取配置文件：
+/// 命令行中没有明确给出的选项（matches 中来源不是 CommandLine 的）使用配置文件中的值。配置文件中的 upstreams
+/// 代替 --upstream（命令行中给出了 --upstream 时则整体忽略 upstreams），只有它们可以带权重。
+fn merge_config_file(matches: &clap::ArgMatches) -> Result<(CmdOptions, Vec<u32>), String> {
+    let mut options = CmdOptions::from_arg_matches(matches).map_err(|err| err.to_string())?;
+    let config = match &options.config {
+        Some(path) => Config::load(std::path::Path::new(path))?,
+        None => Config::default(),
+    };
+    let from_file = |id: &str| matches.value_source(id) != Some(ValueSource::CommandLine);
+    // 配置文件中的值直接代替选项的值；optional 中的选项本身就是 Option
+    macro_rules! merge {
+        ($($field:ident),* ; optional: $($optional:ident),*) => {
+            $(
+                if let (Some(value), true) = (config.$field, from_file(stringify!($field))) {
+                    options.$field = value;
+                }
+            )*
+            $(
+                if let (Some(value), true) = (config.$optional, from_file(stringify!($optional))) {
+                    options.$optional = Some(value);
+                }
+            )*
+        };
+    }
+    merge!(
+        bind, reuse_port, load_balance_algorithm, drain_timeout, upstream_keepalive,
+        active_health_check_interval, active_health_check_path, health_check_expect_status,
+        health_check_concurrency, wait_for_upstream, wait_for_upstream_timeout, max_requests_per_minute,
+        ratelimit_fail_open, ratelimit_ipv6_prefix, num_threads, keepalive_timeout, request_timeout,
+        max_headers, max_header_bytes, max_request_body, max_response_body, stream_threshold,
+        read_buffer_size, cors_allow_methods, cors_allow_headers, max_5xx_before_eject, stats_interval,
+        reject_body_on_get, preserve_host, strip_request_header, strip_response_header,
+        max_connection_bytes, max_requests_per_connection, log_format;
+        optional: upstream_file, health_check_expect_body, max_retries, cors_allow_origin, stats_path,
+        sticky_cookie, set_host, error_page_dir
+    );
+    let weights = match config.upstreams {
+        Some(upstreams) if from_file("upstream") => {
+            let (addresses, weights) = upstreams.iter().map(UpstreamConfig::to_upstream).unzip();
+            options.upstream = addresses;
+            weights
+        }
+        _ => vec![1; options.upstream.len()],
+    };
+    Ok((options, weights))
+}
+
+/// 返回 --upstream 指定的上游服务器，再加上 --upstream-file 中列出的服务器（如果有的话）
+fn load_upstreams(
+    static_upstreams: &[String],
+    upstream_file: Option<&str>,
+) -> std::io::Result<Vec<String>> {
+    let mut addresses = static_upstreams.to_vec();
+    if let Some(path) = upstream_file {
+        addresses.extend(upstreams::read_upstream_file(path)?);
+    }
+    Ok(addresses)
+}
+
+/// 每次收到 SIGHUP 时重新读取上游服务器列表并替换 ProxyState 中的列表。如果文件无法读取或者
+/// 列表为空，则记录错误并继续使用原来的列表。
+///
+/// 被移除的服务器不会再被新的请求选择；drain_timeout 不为 0 时，它们剩下的请求最多还有 drain_timeout 秒
+/// 可以完成，之后连接被强制关闭。
+async fn reload_upstreams_on_sighup(
+    state: &ProxyState,
+    static_upstreams: Vec<String>,
+    upstream_file: String,
+    drain_timeout: u64,
+) {
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(err) => {
+            log::error!("Could not install SIGHUP handler: {}", err);
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        let addresses = match load_upstreams(&static_upstreams, Some(&upstream_file)) {
+            Ok(addresses) => addresses,
+            Err(err) => {
+                log::error!("Could not reload {}: {}. Keeping current upstreams", upstream_file, err);
+                continue;
+            }
+        };
+        if addresses.is_empty() {
+            log::error!("{} lists no upstreams. Keeping current upstreams", upstream_file);
+            continue;
+        }
+        let mut upstreams = state.upstreams.write().await;
+        let reloaded = upstreams.reloaded(addresses).await;
+        log::info!("Reloaded upstreams: {:?}", reloaded.addresses);
+        for (address, in_flight) in upstreams.removed_in(&reloaded) {
+            in_flight.remove();
+            if drain_timeout > 0 {
+                tokio::spawn(drain::drain(address, in_flight, Duration::from_secs(drain_timeout)));
+            }
+        }
+        *upstreams = Arc::new(reloaded);
+    }
+}
+
+/// 每次收到 SIGUSR2 时记录 ProxyState 的快照
+async fn dump_state_on_sigusr2(state: &ProxyState) {
+    let mut signals = match signal(SignalKind::user_defined2()) {
+        Ok(signals) => signals,
+        Err(err) => {
+            log::error!("Could not install SIGUSR2 handler: {}", err);
+            return;
+        }
+    };
+    while signals.recv().await.is_some() {
+        for line in state.debug_dump().await.lines() {
+            log::info!("{}", line);
+        }
+    }
+}
+
+/// 每隔 active_health_check_interval 秒向每个上游服务器的 active_health_check_path 发送一个 GET 请求。
+/// 状态码等于 health_check_expect_status（并且设置了 health_check_expect_body 时，响应体包含它）的服务器
+/// 被视为存活（如果之前被标记为失败则将其恢复），其他服务器被标记为失败。
+///
+/// 每一轮最多同时检查 health_check_concurrency 个服务器，所以即使有很多服务器，一轮检查也只需要
+/// 大约 (服务器数量 / health_check_concurrency) 个 HEALTH_CHECK_TIMEOUT。探测任务需要 'static，所以这里接受 Arc。
+async fn active_health_check(state: Arc<ProxyState>) {
+    let interval = Duration::from_secs(state.active_health_check_interval as u64);
+    loop {
+        tokio::time::sleep(interval).await;
+        health_check_pass(&state).await;
+    }
+}
+
+/// 对所有上游服务器进行一轮主动健康检查并更新它们的失败状态，返回通过检查的服务器数量
+async fn health_check_pass(state: &Arc<ProxyState>) -> usize {
+    // 使用当前上游服务器列表的快照，这样即使检查期间重新加载了列表，索引仍然指向同一个服务器
+    let upstreams = Arc::clone(&*state.upstreams.read().await);
+    let mut probes = tokio::task::JoinSet::new();
+    let mut results = Vec::with_capacity(upstreams.addresses.len());
+    for (upstream_idx, upstream_ip) in upstreams.addresses.iter().enumerate() {
+        if probes.len() >= state.health_check_concurrency {
+            results.extend(probes.join_next().await.and_then(Result::ok));
+        }
+        let state = Arc::clone(state);
+        let upstream_ip = upstream_ip.clone();
+        probes.spawn(async move {
+            let response = probe_upstream(&upstream_ip, &state).await;
+            (upstream_idx, response)
+        });
+    }
+    while let Some(result) = probes.join_next().await {
+        results.extend(result.ok());
+    }
+
+    // 所有结果都出来之后只获取一次写锁
+    let mut healthy = 0;
+    let mut dead_upstreams = upstreams.dead.write().await;
+    let mut ejected_upstreams = upstreams.ejected.write().await;
+    for (upstream_idx, response) in results {
+        let upstream_ip = &upstreams.addresses[upstream_idx];
+        let status = response.as_ref().map(|response| response.status());
+        let failure = if status != Some(state.health_check_expect_status) {
+            Some(format!(
+                "status {:?}, expected {}",
+                status.map(|status| status.as_u16()),
+                state.health_check_expect_status.as_u16()
+            ))
+        } else {
+            match (&state.health_check_expect_body, &response) {
+                (Some(expected), Some(response)) if !body_contains(response.body(), expected) => {
+                    Some(format!("response body does not contain {:?}", expected))
+                }
+                _ => None,
+            }
+        };
+        match failure {
+            None => {
+                healthy += 1;
+                ejected_upstreams.remove(&upstream_idx);
+                if dead_upstreams.remove(&upstream_idx) {
+                    log::info!(
+                        "Upstream {} (index {}) passed a health check. Restoring it.",
+                        upstream_ip, upstream_idx
+                    );
+                }
+            }
+            Some(failure) => {
+                ejected_upstreams.insert(upstream_idx);
+                if dead_upstreams.insert(upstream_idx) {
+                    log::warn!(
+                        "Upstream {} (index {}) failed a health check ({}). Marking as dead.",
+                        upstream_ip, upstream_idx, failure
+                    );
+                }
+            }
+        }
+    }
+    healthy
+}
+
+/// 反复进行健康检查（每轮之间间隔 WAIT_FOR_UPSTREAM_RETRY），直到至少一个上游服务器通过检查。
+/// wait_timeout 秒之后仍然没有时记录警告并返回，这样 balancebeam 仍然会开始接受连接（0 表示一直等待）。
+/// 没有通过检查的服务器保持被标记为失败的状态。
+async fn wait_for_upstream(state: &Arc<ProxyState>, wait_timeout: u64) {
+    log::info!("Waiting for an upstream to pass a health check before accepting connections");
+    let wait = async {
+        while health_check_pass(state).await == 0 {
+            tokio::time::sleep(WAIT_FOR_UPSTREAM_RETRY).await;
+        }
+    };
+    if wait_timeout == 0 {
+        wait.await;
+    } else if timeout(Duration::from_secs(wait_timeout), wait).await.is_err() {
+        log::warn!(
+            "No upstream passed a health check within {} seconds. Accepting connections anyway",
+            wait_timeout
+        );
+        return;
+    }
+    log::info!("An upstream passed a health check");
+}
+
+/// 健康检查的响应体是否包含 expected。按字节比较，所以响应体不必是有效的 UTF-8
+fn body_contains(body: &[u8], expected: &str) -> bool {
+    let expected = expected.as_bytes();
+    expected.is_empty() || body.windows(expected.len()).any(|window| window == expected)
+}
+
+/// 对一个上游服务器进行一次健康检查，返回它的响应（连接失败或超时时返回 None）
+async fn probe_upstream(upstream_ip: &str, state: &ProxyState) -> Option<http::Response<Vec<u8>>> {
+    match timeout(HEALTH_CHECK_TIMEOUT, health_check_response(upstream_ip, state)).await {
+        Ok(Ok(response)) => Some(response),
+        Ok(Err(err)) => {
+            log::debug!("Health check of upstream {} failed: {}", upstream_ip, err);
+            None
+        }
+        Err(_) => {
+            log::debug!("Health check of upstream {} timed out", upstream_ip);
+            None
+        }
+    }
+}
+
+/// 向上游服务器发送一个健康检查请求，返回完整的响应（包括响应体）
+async fn health_check_response(
+    upstream_ip: &str,
+    state: &ProxyState,
+) -> Result<http::Response<Vec<u8>>, ProxyError> {
+    let mut upstream_conn = state.connector.connect(upstream_ip).await?;
+    let request = http::Request::builder()
+        .method(http::Method::GET)
+        .uri(&state.active_health_check_path)
+        .header("Host", upstream_ip)
+        .version(http::Version::HTTP_11)
+        .body(Vec::new())
+        .map_err(|err| ProxyError::MalformedRequest(error::httparse_error_from(&err)))?;
+    request::write_to_stream(&request, &mut upstream_conn).await?;
+    response::read_from_stream(&mut upstream_conn, request.method(), &state.parse_limits).await
+}
+
+/// 每隔 interval_secs 秒记录一次这段时间内处理的请求总数以及每秒请求数
+async fn log_request_rate(state: &ProxyState, interval_secs: u64) {
+    let mut interval = tokio::time::interval(Duration::from_secs(interval_secs));
+    // 第一次 tick 立即完成，跳过它，这样每次记录的都是完整的一个周期
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        let requests = state.requests_handled.swap(0, Ordering::Relaxed);
+        log::info!(
+            "Handled {} requests in the last {} seconds ({:.2} requests/second)",
+            requests,
+            interval_secs,
+            requests as f64 / interval_secs as f64
+        );
+    }
+}
+
 /// 尝试连接到一个存活的上游服务器，如果选中的服务器失败则自动故障转移到其他服务器
 /// 
 /// 该函数实现被动健康检查：
@@ -127,24 +918,53 @@ async fn main() {
 /// 2. 如果连接失败，将该服务器标记为失败
 /// 3. 重试其他存活的服务器
 /// 4. 如果所有服务器都失败，返回错误
-async fn connect_to_upstream(state: &ProxyState) -> Result<(TcpStream, usize), std::io::Error> {
+///
+/// 如果所有服务器都已被标记为失败，则再给因为连接失败而被标记的服务器一次机会，而不是直接返回错误。
+/// 这样在只有一个上游服务器时，重试也能重新连接到同一个服务器。被摘除的服务器（ejected）不在此列，
+/// 它们只能由主动健康检查恢复。
+///
+/// 如果指定了 preferred（会话保持），只要该服务器存活并且还没尝试过就优先选择它，否则按 algorithm 选择
+/// （默认随机）。
+///
+/// 如果传入了 pool（--upstream-keepalive），选中的服务器在连接池中有空闲连接时直接使用它，不建立新的连接。
+///
+/// 返回的索引指向传入的 upstreams 列表；最后的布尔值表示连接是否来自连接池。
+async fn connect_to_upstream(
+    connector: &dyn UpstreamConnector,
+    upstreams: &UpstreamList,
+    preferred: Option<usize>,
+    algorithm: LoadBalanceAlgorithm,
+    pool: Option<&UpstreamPool>,
+) -> Result<(Connection, usize, bool), std::io::Error> {
     let mut rng = rand::rngs::StdRng::from_entropy();
     
     // 获取所有上游服务器的索引
-    let total_upstreams = state.upstream_addresses.len();
+    let total_upstreams = upstreams.addresses.len();
     
     // 尝试连接到存活的服务器
     let mut tried_upstreams = HashSet::new();
     
     while tried_upstreams.len() < total_upstreams {
         // 每次重新读取失败服务器列表（确保获取最新状态）
-        let dead_upstreams = state.dead_upstreams.read().await;
+        let dead_upstreams = upstreams.dead.read().await;
+        let ejected_upstreams = upstreams.ejected.read().await;
         
         // 构建存活且未尝试过的服务器索引列表
-        let available_upstreams: Vec<usize> = (0..total_upstreams)
+        // 重新加载时已被移除的服务器（这次尝试开始之后才被移除）不再选择
+        let mut available_upstreams: Vec<usize> = (0..total_upstreams)
             .filter(|idx| !dead_upstreams.contains(idx) && !tried_upstreams.contains(idx))
+            .filter(|&idx| !upstreams.in_flight[idx].is_removed())
             .collect();
         
+        // 所有服务器都已失败时，重新尝试那些本次还没尝试过、也没有被摘除的服务器
+        if available_upstreams.is_empty() && dead_upstreams.len() == total_upstreams {
+            available_upstreams = (0..total_upstreams)
+                .filter(|idx| !ejected_upstreams.contains(idx) && !tried_upstreams.contains(idx))
+                .filter(|&idx| !upstreams.in_flight[idx].is_removed())
+                .collect();
+        }
+        
+        drop(ejected_upstreams);
         drop(dead_upstreams);
         
         // 如果没有可用的服务器，返回错误
@@ -156,34 +976,53 @@ async fn connect_to_upstream(state: &ProxyState) -> Result<(TcpStream, usize), s
             ));
         }
         
-        // 随机选择一个可用的服务器
-        let random_idx = rng.gen_range(0..available_upstreams.len());
-        let upstream_idx = available_upstreams[random_idx];
-        let upstream_ip = &state.upstream_addresses[upstream_idx];
+        // 优先选择会话对应的服务器，否则按负载均衡算法选择一个可用的服务器
+        let upstream_idx = match preferred.filter(|idx| available_upstreams.contains(idx)) {
+            Some(idx) => idx,
+            None => upstreams.choose(algorithm, &available_upstreams, &mut rng),
+        };
+        let upstream_ip = &upstreams.addresses[upstream_idx];
         
         tried_upstreams.insert(upstream_idx);
+
+        if let Some(stream) = pool.and_then(|pool| pool.take(upstream_ip)) {
+            log::debug!("Reusing idle connection to upstream {} (index {})", upstream_ip, upstream_idx);
+            return Ok((stream, upstream_idx, true));
+        }
         
         log::debug!("Attempting to connect to upstream {} (index {})", upstream_ip, upstream_idx);
         
         // 设置连接超时为2秒
         let connect_result = timeout(
             Duration::from_secs(2),
-            TcpStream::connect(upstream_ip)
+            connector.connect(upstream_ip)
         ).await;
         
         match connect_result {
             Ok(Ok(stream)) => {
                 log::info!("Successfully connected to upstream {}", upstream_ip);
-                return Ok((stream, upstream_idx));
+                // 如果该服务器之前因为连接失败被标记为失败，现在它又可以连接了，将其恢复。
+                // 被摘除的服务器能连接不说明它恢复了（在选择之后才被摘除的也一样），留给主动健康检查
+                // （先用读锁检查，避免每次连接成功都获取写锁）
+                if upstreams.dead.read().await.contains(&upstream_idx) {
+                    let mut dead_upstreams = upstreams.dead.write().await;
+                    if !upstreams.ejected.read().await.contains(&upstream_idx)
+                        && dead_upstreams.remove(&upstream_idx)
+                    {
+                        log::info!("Upstream {} (index {}) is reachable again", upstream_ip, upstream_idx);
+                    }
+                }
+                return Ok((stream, upstream_idx, false));
             }
             Ok(Err(err)) => {
                 log::warn!(
                     "Failed to connect to upstream {} (index {}): {}. Marking as dead.",
                     upstream_ip, upstream_idx, err
                 );
+                upstreams.errors[upstream_idx].record_connect_failure(format!("connect: {}", err));
                 
                 // 将该服务器标记为失败
-                let mut dead_upstreams = state.dead_upstreams.write().await;
+                let mut dead_upstreams = upstreams.dead.write().await;
                 dead_upstreams.insert(upstream_idx);
                 drop(dead_upstreams);
                 
@@ -196,9 +1035,11 @@ async fn connect_to_upstream(state: &ProxyState) -> Result<(TcpStream, usize), s
                     "Timeout connecting to upstream {} (index {}). Marking as dead.",
                     upstream_ip, upstream_idx
                 );
+                upstreams.errors[upstream_idx]
+                    .record_connect_failure(String::from("connect: timed out after 2 seconds"));
                 
                 // 将该服务器标记为失败
-                let mut dead_upstreams = state.dead_upstreams.write().await;
+                let mut dead_upstreams = upstreams.dead.write().await;
                 dead_upstreams.insert(upstream_idx);
                 drop(dead_upstreams);
                 
@@ -216,138 +1057,739 @@ async fn connect_to_upstream(state: &ProxyState) -> Result<(TcpStream, usize), s
     ))
 }
 
-async fn send_response(client_conn: &mut TcpStream, response: &http::Response<Vec<u8>>) {
-    let client_ip = client_conn.peer_addr().unwrap().ip().to_string();
-    log::info!("{} <- {}", client_ip, response::format_response_line(&response));
+/// 把响应发送给客户端，然后写出这个请求的访问日志
+async fn send_response(
+    client_conn: &mut CountingStream<TcpStream>,
+    request_log: &RequestLog<'_>,
+    response: &http::Response<Vec<u8>>,
+) {
     if let Err(error) = response::write_to_stream(&response, client_conn).await {
         log::warn!("Failed to send response to client: {}", error);
-        return;
     }
+    request_log.finish(response, Some(response.body().len() as u64));
 }
 
-async fn handle_connection(mut client_conn: TcpStream, state: &ProxyState) {
-    let client_ip = client_conn.peer_addr().unwrap().ip().to_string();
+async fn handle_connection(client_conn: TcpStream, state: &ProxyState) {
+    // 每个连接只取一次客户端地址，之后处理请求和记录日志都使用这个字符串。客户端可能在我们接受连接之后立即断开，
+    // 这时 peer_addr 会失败。监听 IPv6 地址时 IPv4 客户端的地址形如 ::ffff:1.2.3.4，转换回 IPv4 地址，
+    // 这样它们和直接连接的 IPv4 客户端一样记录日志和计数
+    let client_ip = match client_conn.peer_addr() {
+        Ok(addr) => addr.ip().to_canonical().to_string(),
+        Err(err) => {
+            log::info!("Could not get the address of a new client: {}", err);
+            return;
+        }
+    };
     log::info!("Connection received from {}", client_ip);
 
+    let mut client_conn = CountingStream::new(client_conn);
+    let mut bytes = ConnectionBytes::default();
+    state.active_connections.fetch_add(1, Ordering::Relaxed);
+    handle_requests(&mut client_conn, &client_ip, state, &mut bytes).await;
+    state.active_connections.fetch_sub(1, Ordering::Relaxed);
+    bytes.client_read = client_conn.bytes_read();
+    bytes.client_written = client_conn.bytes_written();
+    log::info!(
+        "Connection from {} closed. Client: {} bytes read, {} bytes written. Upstreams: {} bytes read, {} bytes written",
+        client_ip,
+        bytes.client_read,
+        bytes.client_written,
+        bytes.upstream_read,
+        bytes.upstream_written
+    );
+    state.bytes_transferred.add(&bytes);
+}
+
+/// 一个响应读完之后处理它的上游连接：启用了 --upstream-keepalive 并且连接还可以继续使用（reusable）时
+/// 放回连接池，否则关闭
+fn finish_upstream(
+    state: &ProxyState,
+    bytes: &mut ConnectionBytes,
+    upstream_conn: CountingStream<TrackedStream<Connection>>,
+    address: &str,
+    reusable: bool,
+) {
+    match &state.upstream_pool {
+        Some(pool) if reusable => pool.put(address, bytes.release_upstream(upstream_conn).into_inner()),
+        _ => bytes.close_upstream(upstream_conn),
+    }
+}
+
+/// 处理客户端在一个连接上发送的所有请求，直到客户端挂断或我们遇到错误。为这些请求建立的上游连接
+/// 传输的字节数累加到 bytes 中。
+async fn handle_requests(
+    client_conn: &mut CountingStream<TcpStream>,
+    client_ip: &str,
+    state: &ProxyState,
+    bytes: &mut ConnectionBytes,
+) {
+    // 这个连接上已经转发的请求体和响应体的总字节数（用于 --max-connection-bytes）
+    let mut body_bytes: u64 = 0;
+    // 每个请求都要把客户端 IP 加到 X-Forwarded-For 中；HeaderValue 的克隆是共享的，不需要每次重新分配
+    let forwarded_for = http::HeaderValue::from_str(client_ip).expect("IP addresses are valid header values");
+    // 这个连接上已经处理的请求数（用于 --max-requests-per-connection；无法解析的请求不计入）
+    let mut connection_requests: usize = 0;
+    // 上一个请求带有 Connection: close，回复之后关闭连接
+    let mut client_close = false;
+
     // 客户端现在可能会向我们发送一个或多个请求。继续尝试读取请求，直到客户端挂断或我们遇到错误。
     loop {
-        // 从客户端读取请求
-        let mut request = match request::read_from_stream(&mut client_conn).await {
+        if client_close {
+            log::debug!("{} asked to close the connection after its last request", client_ip);
+            return;
+        }
+        if state.max_requests_per_connection > 0 && connection_requests >= state.max_requests_per_connection {
+            log::debug!(
+                "Handled {} requests from {} on this connection. Closing it",
+                connection_requests, client_ip
+            );
+            return;
+        }
+
+        // 从客户端读取请求。如果设置了 keepalive 超时，客户端空闲太久时就像客户端挂断一样关闭连接
+        let read_result = if state.keepalive_timeout > 0 {
+            match timeout(
+                Duration::from_secs(state.keepalive_timeout),
+                request::read_from_stream(client_conn, &state.parse_limits),
+            )
+            .await
+            {
+                Ok(result) => result,
+                Err(_) => {
+                    log::debug!(
+                        "Client idle for more than {} seconds. Shutting down connection",
+                        state.keepalive_timeout
+                    );
+                    return;
+                }
+            }
+        } else {
+            request::read_from_stream(client_conn, &state.parse_limits).await
+        };
+        let mut request = match read_result {
             Ok(request) => request,
             // 处理客户端关闭连接且不再发送请求的情况
-            Err(request::Error::IncompleteRequest(0)) => {
+            Err(ProxyError::IncompleteRequest(0)) => {
                 log::debug!("Client finished sending requests. Shutting down connection");
                 return;
             }
             // 处理从客户端读取时的 I/O 错误
-            Err(request::Error::ConnectionError(io_err)) => {
+            Err(ProxyError::ConnectionError(io_err)) => {
                 log::info!("Error reading request from client stream: {}", io_err);
                 return;
             }
+            // 请求体还留在流中没有读取，无法找到下一个请求的开始位置，所以回复之后关闭连接
+            Err(ProxyError::UnexpectedRequestBody(method)) => {
+                log::debug!("Rejecting {} request with a body", method);
+                let mut response = state
+                    .error_pages
+                    .make_http_error(http::StatusCode::BAD_REQUEST);
+                response::strip_body_for_head(&mut response, &method);
+                response::set_connection_close(&mut response);
+                let request_log = RequestLog::new(state.log_format, client_ip, None);
+                send_response(client_conn, &request_log, &response).await;
+                return;
+            }
             Err(error) => {
                 log::debug!("Error parsing request: {:?}", error);
-                let response = response::make_http_error(match error {
-                    request::Error::IncompleteRequest(_)
-                    | request::Error::MalformedRequest(_)
-                    | request::Error::InvalidContentLength
-                    | request::Error::ContentLengthMismatch => http::StatusCode::BAD_REQUEST,
-                    request::Error::RequestBodyTooLarge => http::StatusCode::PAYLOAD_TOO_LARGE,
-                    request::Error::ConnectionError(_) => http::StatusCode::SERVICE_UNAVAILABLE,
-                });
-                send_response(&mut client_conn, &response).await;
+                let response = state.error_pages.make_http_error(error.status_code());
+                let request_log = RequestLog::new(state.log_format, client_ip, None);
+                send_response(client_conn, &request_log, &response).await;
                 continue;
             }
         };
-        log::info!(
-            "{} -> {}",
-            client_ip,
-            request::format_request_line(&request)
-        );
+        let mut request_log = RequestLog::new(state.log_format, client_ip, Some(&request));
+        state.requests_handled.fetch_add(1, Ordering::Relaxed);
+        connection_requests += 1;
+        // 这个连接上的最后一个请求：它的响应（无论是转发的还是我们自己生成的）带上 Connection: close
+        client_close = headers::connection_close(request.headers());
+        let last_request = client_close
+            || (state.max_requests_per_connection > 0
+                && connection_requests >= state.max_requests_per_connection);
+
+        // 这个 IP 在这一分钟内发送了太多请求
+        if let Some(rate_limiter) = &state.rate_limiter {
+            if let Decision::Reject { retry_after } = rate_limiter.allow(client_ip).await {
+                log::info!("Rate limiting request from {}", client_ip);
+                // Retry-After 只能是整数秒，向上取整，这样客户端重试时窗口一定已经结束了
+                let headers = retry_after
+                    .map(|retry_after| {
+                        let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+                        (http::header::RETRY_AFTER, http::HeaderValue::from(seconds))
+                    })
+                    .into_iter()
+                    .collect();
+                let mut response = state
+                    .error_pages
+                    .make_http_error_with_headers(http::StatusCode::TOO_MANY_REQUESTS, headers);
+                response::strip_body_for_head(&mut response, request.method());
+                if last_request {
+                    response::set_connection_close(&mut response);
+                }
+                send_response(client_conn, &request_log, &response).await;
+                continue;
+            }
+        }
+
+        // 这个连接之前的请求已经超过了字节数限制，拒绝这个请求并关闭连接
+        if state.max_connection_bytes > 0 && body_bytes > state.max_connection_bytes {
+            log::info!(
+                "Connection from {} transferred {} body bytes, more than the limit of {}. Closing it",
+                client_ip, body_bytes, state.max_connection_bytes
+            );
+            let mut response = state.error_pages.make_http_error(http::StatusCode::PAYLOAD_TOO_LARGE);
+            response::strip_body_for_head(&mut response, request.method());
+            response::set_connection_close(&mut response);
+            send_response(client_conn, &request_log, &response).await;
+            return;
+        }
+        body_bytes += request.body().len() as u64;
+
+        // 如果请求的是统计信息路径，直接返回统计信息，而不转发给上游服务器
+        if let Some(stats_path) = &state.stats_path {
+            if request.method() == http::Method::GET && request.uri().path() == stats_path {
+                let upstreams = Arc::clone(&*state.upstreams.read().await);
+                let mut body = stats::render_status_counts(&upstreams.addresses, &upstreams.status_counts);
+                body += &stats::render_upstream_errors(&upstreams.addresses, &upstreams.errors);
+                body += &counting::render_byte_totals(&state.bytes_transferred.snapshot());
+                let mut response = response::make_text_response(http::StatusCode::OK, body);
+                if last_request {
+                    response::set_connection_close(&mut response);
+                }
+                send_response(client_conn, &request_log, &response).await;
+                continue;
+            }
+        }
+
+        // 如果启用了 CORS，直接回答预检请求，而不转发给上游服务器
+        if let Some(cors) = &state.cors {
+            if CorsConfig::is_preflight(&request) {
+                let mut response = cors.preflight_response();
+                if last_request {
+                    response::set_connection_close(&mut response);
+                }
+                send_response(client_conn, &request_log, &response).await;
+                continue;
+            }
+        }
+
+        // 先删除 --strip-request-header 指定的头，这样客户端也不能伪造下面由我们添加的头
+        state.strip_request_headers.apply(request.headers_mut());
 
         // 添加 X-Forwarded-For 头
-        request::extend_header_value(&mut request, "x-forwarded-for", &client_ip);
+        request::extend_header_value(&mut request, "x-forwarded-for", &forwarded_for);
 
-        // 尝试将请求转发到上游服务器，如果失败则重试其他服务器
-        let max_retries = state.upstream_addresses.len();
-        let mut retry_count = 0;
-        let mut success = false;
+        // 客户端的 Connection 头只描述客户端与我们之间的连接（上面已经记下了它是否要求关闭）。与上游服务器之间的连接
+        // 由 --upstream-keepalive 决定，明确告诉服务器，而不依赖于它的默认行为
+        let upstream_connection = if state.upstream_pool.is_some() { "keep-alive" } else { "close" };
+        request
+            .headers_mut()
+            .insert(http::header::CONNECTION, http::HeaderValue::from_static(upstream_connection));
+
+        // 会话保持：使用请求中的会话 ID；客户端还没有会话时生成一个新的，并在响应中设置 cookie
+        let mut new_session = false;
+        let session_id = state.sticky_cookie.as_ref().map(|sticky| {
+            sticky.session_id(&request).unwrap_or_else(|| {
+                new_session = true;
+                StickyCookie::new_session_id()
+            })
+        });
+
+        // 请求解析成功之后我们自己生成的错误响应也要遵守 HEAD 的规则。下面转发前还要修改请求的
+        // Host 头，所以这里使用方法的副本而不借用请求
+        let request_method = request.method().clone();
+        let make_http_error = |status| {
+            let mut response = state.error_pages.make_http_error(status);
+            response::strip_body_for_head(&mut response, &request_method);
+            if last_request {
+                response::set_connection_close(&mut response);
+            }
+            response
+        };
+
+        // 转发请求并把响应发送给客户端。返回 false 表示应该关闭客户端连接。设置了 --request-timeout 时，
+        // 整个过程（包括所有重试）不能超过这个时间
+        let written_before = client_conn.bytes_written();
+        let forward = async {
+            // 尝试将请求转发到上游服务器，如果失败则重试其他服务器
+            let max_retries = state.max_retries;
+            let mut retry_count = 0;
+            // 是否已经给客户端发送了响应
+            let mut responded = false;
         
-        while retry_count < max_retries && !success {
-            retry_count += 1;
-            log::debug!("Request forwarding attempt {} of {}", retry_count, max_retries);
+            while retry_count < max_retries && !responded {
+                retry_count += 1;
+                log::debug!("Request forwarding attempt {} of {}", retry_count, max_retries);
             
-            // 为每个请求建立新的上游连接
-            let (mut upstream_conn, upstream_idx) = match connect_to_upstream(state).await {
-                Ok((stream, idx)) => (stream, idx),
-                Err(_error) => {
-                    log::warn!("Failed to connect to any upstream server on attempt {}", retry_count);
-                    if retry_count >= max_retries {
-                        let response = response::make_http_error(http::StatusCode::BAD_GATEWAY);
-                        send_response(&mut client_conn, &response).await;
-                        return;
+                // 为每个请求建立新的上游连接。这次尝试使用当前上游服务器列表的快照，这样即使在此期间
+                // 重新加载了列表，下面的索引仍然指向同一个服务器
+                let upstreams = Arc::clone(&*state.upstreams.read().await);
+                let preferred = session_id
+                    .as_deref()
+                    .and_then(|session_id| sticky::preferred_upstream(session_id, &upstreams.addresses));
+                let connect_result = connect_to_upstream(
+                    &*state.connector,
+                    &upstreams,
+                    preferred,
+                    state.load_balance_algorithm,
+                    state.upstream_pool.as_ref(),
+                ).await;
+                let (mut upstream_conn, upstream_idx, reused) = match connect_result {
+                    Ok((stream, idx, reused)) => (CountingStream::new(upstreams.in_flight[idx].track(stream)), idx, reused),
+                    Err(_error) => {
+                        log::warn!("Failed to connect to any upstream server on attempt {}", retry_count);
+                        if retry_count >= max_retries {
+                            let mut response = make_http_error(http::StatusCode::BAD_GATEWAY);
+                            response::set_connection_close(&mut response);
+                            send_response(client_conn, &request_log, &response).await;
+                            return false;
+                        }
+                        continue;
                     }
-                    continue;
-                }
-            };
-            let upstream_ip = upstream_conn.peer_addr().unwrap().ip().to_string();
-            log::info!("Forwarding request to upstream {}", upstream_ip);
-
-            // 将请求转发到服务器
-            if let Err(error) = request::write_to_stream(&request, &mut upstream_conn).await {
-                log::error!("Failed to send request to upstream {}: {}", upstream_ip, error);
-                drop(upstream_conn);
-                // 标记这个upstream为失败
-                let mut dead_upstreams = state.dead_upstreams.write().await;
-                dead_upstreams.insert(upstream_idx);
-                drop(dead_upstreams);
-                continue; // 重试其他服务器
-            }
-            log::debug!("Forwarded request to server");
+                };
+                let upstream_ip = &upstreams.addresses[upstream_idx];
+                log::info!("Forwarding request to upstream {}", upstream_ip);
+                request_log.set_upstream(upstream_ip);
 
-            // 读取服务器的响应（设置超时为1秒）
-            let response_result = timeout(
-                Duration::from_secs(1),
-                response::read_from_stream(&mut upstream_conn, request.method())
-            ).await;
-            
-            match response_result {
-                Ok(Ok(response)) => {
-                    // 成功读取响应
-                    log::debug!("Received response from upstream");
-                    send_response(&mut client_conn, &response).await;
-                    log::debug!("Forwarded response to client");
-                    drop(upstream_conn);
-                    success = true;
-                }
-                Ok(Err(error)) => {
-                    log::error!("Error reading response from server {}: {:?}", upstream_ip, error);
-                    drop(upstream_conn);
+                // 将请求转发到服务器
+                state.host_rewrite.apply(&mut request, upstream_ip);
+                if let Err(error) = request::write_to_stream(&request, &mut upstream_conn).await {
+                    bytes.close_upstream(upstream_conn);
+                    if reused {
+                        // 空闲的连接可能已经被服务器关闭了，这不说明服务器失败了。用新的连接重试，不计入重试次数
+                        log::debug!(
+                            "Idle connection to upstream {} failed: {}. Retrying on a new connection",
+                            upstream_ip, error
+                        );
+                        retry_count -= 1;
+                        continue;
+                    }
+                    log::error!("Failed to send request to upstream {}: {}", upstream_ip, error);
+                    upstreams.errors[upstream_idx].record_error(format!("send request: {}", error));
                     // 标记这个upstream为失败
-                    let mut dead_upstreams = state.dead_upstreams.write().await;
+                    let mut dead_upstreams = upstreams.dead.write().await;
                     dead_upstreams.insert(upstream_idx);
                     drop(dead_upstreams);
-                    // 重试其他服务器
-                    continue;
+                    continue; // 重试其他服务器
                 }
-                Err(_) => {
-                    log::error!("Timeout reading response from upstream {}", upstream_ip);
-                    drop(upstream_conn);
-                    // 标记这个upstream为失败
-                    let mut dead_upstreams = state.dead_upstreams.write().await;
-                    dead_upstreams.insert(upstream_idx);
-                    drop(dead_upstreams);
-                    // 重试其他服务器
-                    continue;
+                log::debug!("Forwarded request to server");
+
+                // 读取服务器的响应（设置超时为1秒）。分块编码的响应体，以及设置了 --stream-threshold 时的大响应体
+                // 不在这里读取，而是在下面边读边转发。其他响应体在这里完整读取，这期间不检查客户端是否已经断开
+                // （读取时间受上面的超时和 --max-response-body 限制），断开的客户端要到发送响应时才会发现
+                let response_result = timeout(
+                    Duration::from_secs(1),
+                    response::read_head_from_stream(
+                        &mut upstream_conn,
+                        request.method(),
+                        &state.parse_limits,
+                        (state.stream_threshold > 0).then_some(state.stream_threshold),
+                    )
+                ).await;
+            
+                match response_result {
+                    Ok(Ok((mut response, body_kind))) => {
+                        // 成功读取响应
+                        log::debug!("Received response from upstream");
+                        let consecutive_5xx = upstreams.status_counts[upstream_idx].record(response.status());
+                        // 被动健康检查只能发现连接失败；如果上游服务器连续返回太多 5xx，也将其标记为失败
+                        if state.max_5xx_before_eject > 0 && consecutive_5xx >= state.max_5xx_before_eject {
+                            log::warn!(
+                                "Upstream {} (index {}) returned {} consecutive 5xx responses. Marking as dead.",
+                                upstream_ip, upstream_idx, consecutive_5xx
+                            );
+                            upstreams.status_counts[upstream_idx].reset_consecutive_server_errors();
+                            upstreams.eject(upstream_idx).await;
+                        }
+                        // 上游服务器的 Connection 头描述的是它与我们之间的连接，不转发给客户端
+                        let reusable = !headers::connection_close(response.headers())
+                            && response::is_delimited(&response, request.method());
+                        response.headers_mut().remove(http::header::CONNECTION);
+                        state.strip_response_headers.apply(response.headers_mut());
+                        if last_request {
+                            response::set_connection_close(&mut response);
+                        }
+                        if let Some(cors) = &state.cors {
+                            cors.apply(&mut response);
+                        }
+                        if let (Some(sticky), Some(session_id), true) =
+                            (&state.sticky_cookie, &session_id, new_session)
+                        {
+                            sticky.set_cookie(&mut response, session_id);
+                        }
+                        if body_kind == BodyKind::Buffered {
+                            response::strip_body_for_head(&mut response, request.method());
+                            send_response(client_conn, &request_log, &response).await;
+                            body_bytes += response.body().len() as u64;
+                            finish_upstream(state, bytes, upstream_conn, upstream_ip, reusable);
+                        } else {
+                            // 响应头发送之后就不能再回复错误响应了，所以转发响应体时出错只能关闭客户端连接
+                            let initial_body = std::mem::take(response.body_mut());
+                            if body_kind == BodyKind::UntilClose {
+                                // 客户端只能通过连接关闭知道响应体结束
+                                response::set_connection_close(&mut response);
+                            }
+                            if let Err(error) = response::write_head_to_stream(&response, client_conn).await {
+                                log::warn!("Failed to send response to client: {}", error);
+                                request_log.finish(&response, None);
+                                bytes.close_upstream(upstream_conn);
+                                return false;
+                            }
+                            let relay_result = match body_kind {
+                                BodyKind::Chunked => {
+                                    chunked::relay(&mut upstream_conn, initial_body, client_conn, &state.parse_limits)
+                                        .await
+                                }
+                                BodyKind::Length(len) => {
+                                    stream::relay_length(&mut upstream_conn, initial_body, client_conn, len).await
+                                }
+                                BodyKind::UntilClose => {
+                                    stream::relay_until_close(
+                                        &mut upstream_conn,
+                                        initial_body,
+                                        client_conn,
+                                        state.parse_limits.max_response_body,
+                                    )
+                                    .await
+                                }
+                                BodyKind::Buffered => unreachable!(),
+                            };
+                            // 转发出错（包括客户端中途断开）时响应体可能还没有读完，这里直接关闭上游连接，不再读取剩下的部分
+                            finish_upstream(state, bytes, upstream_conn, upstream_ip, reusable && relay_result.is_ok());
+                            request_log.finish(&response, relay_result.as_ref().ok().copied());
+                            match relay_result {
+                                Ok(body_len) => body_bytes += body_len,
+                                Err(RelayError::Upstream(error)) => {
+                                    log::error!(
+                                        "Error relaying response body from upstream {}: {}",
+                                        upstream_ip, error
+                                    );
+                                    upstreams.errors[upstream_idx]
+                                        .record_error(format!("relay response body: {}", error));
+                                    return false;
+                                }
+                                Err(RelayError::Client(error)) => {
+                                    log::warn!("Failed to send response to client: {}", error);
+                                    return false;
+                                }
+                            }
+                            if body_kind == BodyKind::UntilClose {
+                                log::debug!("Forwarded response to client");
+                                return false;
+                            }
+                        }
+                        log::debug!("Forwarded response to client");
+                        responded = true;
+                    }
+                    Ok(Err(error))
+                        if reused
+                            && upstream_conn.bytes_read() == 0
+                            && matches!(error, ProxyError::IncompleteResponse | ProxyError::ConnectionError(_)) =>
+                    {
+                        // 与发送失败时一样：服务器没有回复任何内容就关闭了空闲的连接，用新的连接重试
+                        log::debug!(
+                            "Idle connection to upstream {} closed before responding: {:?}. Retrying on a new connection",
+                            upstream_ip, error
+                        );
+                        bytes.close_upstream(upstream_conn);
+                        retry_count -= 1;
+                        continue;
+                    }
+                    Ok(Err(error)) => {
+                        log::error!("Error reading response from server {}: {:?}", upstream_ip, error);
+                        upstreams.errors[upstream_idx].record_error(format!("read response: {}", error));
+                        bytes.close_upstream(upstream_conn);
+                        // 标记这个upstream为失败
+                        let mut dead_upstreams = upstreams.dead.write().await;
+                        dead_upstreams.insert(upstream_idx);
+                        drop(dead_upstreams);
+                        // 响应体太大时重试其他服务器也无济于事，直接告诉客户端
+                        if matches!(error, ProxyError::ResponseBodyTooLarge) {
+                            let response = make_http_error(error.status_code());
+                            send_response(client_conn, &request_log, &response).await;
+                            responded = true;
+                        }
+                        // 否则重试其他服务器
+                        continue;
+                    }
+                    Err(_) => {
+                        log::error!("Timeout reading response from upstream {}", upstream_ip);
+                        upstreams.errors[upstream_idx]
+                            .record_read_timeout(String::from("read response: timed out after 1 second"));
+                        bytes.close_upstream(upstream_conn);
+                        // 标记这个upstream为失败
+                        let mut dead_upstreams = upstreams.dead.write().await;
+                        dead_upstreams.insert(upstream_idx);
+                        drop(dead_upstreams);
+                        // 重试其他服务器
+                        continue;
+                    }
                 }
             }
-        }
         
-        // 如果所有重试都失败了
-        if !success {
-            log::error!("Failed to forward request after {} attempts", max_retries);
-            let response = response::make_http_error(http::StatusCode::BAD_GATEWAY);
-            send_response(&mut client_conn, &response).await;
+            // 如果所有重试都失败了
+            if !responded {
+                log::error!("Failed to forward request after {} attempts", max_retries);
+                // 回复之后关闭连接，所以告诉客户端不要再复用它
+                let mut response = make_http_error(http::StatusCode::BAD_GATEWAY);
+                response::set_connection_close(&mut response);
+                send_response(client_conn, &request_log, &response).await;
+                return false;
+            }
+            true
+        };
+        let keep_open = if state.request_timeout > 0 {
+            match timeout(Duration::from_secs(state.request_timeout), forward).await {
+                Ok(keep_open) => keep_open,
+                // 超时后 forward 被丢弃，上游连接随之关闭（这次尝试传输的字节不计入 bytes）
+                Err(_) => {
+                    log::warn!(
+                        "Request from {} not answered within {} seconds",
+                        client_ip, state.request_timeout
+                    );
+                    // 如果已经开始发送响应（例如正在转发分块编码的响应体），就不能再回复 504 了
+                    if client_conn.bytes_written() == written_before {
+                        let response = make_http_error(http::StatusCode::GATEWAY_TIMEOUT);
+                        send_response(client_conn, &request_log, &response).await;
+                    }
+                    false
+                }
+            }
+        } else {
+            forward.await
+        };
+        if !keep_open {
             return;
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use std::sync::Mutex;
+
+    /// 按脚本连接上游服务器：reachable 中的地址连接成功（返回内存中的流），其他地址返回 ConnectionRefused。
+    /// 记录每次尝试连接的地址。
+    struct ScriptedConnector {
+        reachable: Vec<String>,
+        attempts: Mutex<Vec<String>>,
+    }
+
+    impl ScriptedConnector {
+        fn new(reachable: &[&str]) -> ScriptedConnector {
+            ScriptedConnector {
+                reachable: reachable.iter().map(|addr| addr.to_string()).collect(),
+                attempts: Mutex::new(Vec::new()),
+            }
+        }
+
+        fn attempts(&self) -> Vec<String> {
+            self.attempts.lock().unwrap().clone()
+        }
+    }
+
+    #[async_trait]
+    impl UpstreamConnector for ScriptedConnector {
+        async fn connect(&self, address: &str) -> std::io::Result<Connection> {
+            self.attempts.lock().unwrap().push(address.to_string());
+            if self.reachable.iter().any(|reachable| reachable == address) {
+                let (conn, _) = tokio::io::duplex(64);
+                Ok(Box::new(conn))
+            } else {
+                Err(std::io::Error::from(std::io::ErrorKind::ConnectionRefused))
+            }
+        }
+    }
+
+    fn upstream_list(addresses: &[&str]) -> UpstreamList {
+        UpstreamList::new(addresses.iter().map(|addr| addr.to_string()).collect())
+    }
+
+    #[tokio::test]
+    async fn test_failover_to_second_upstream() {
+        let connector = ScriptedConnector::new(&["b:2"]);
+        let upstreams = upstream_list(&["a:1", "b:2"]);
+        // 优先选择 a:1，保证第一次尝试的是连接不上的服务器
+        let (_, idx, _) = connect_to_upstream(&connector, &upstreams, Some(0), LoadBalanceAlgorithm::Random, None).await.unwrap();
+        assert_eq!(idx, 1);
+        assert_eq!(connector.attempts(), ["a:1", "b:2"]);
+        assert_eq!(*upstreams.dead.read().await, HashSet::from([0]));
+        // 连接失败记录在 a:1 的错误统计中
+        assert_eq!(upstreams.errors[0].connect_failures(), 1);
+        assert_eq!(
+            upstreams.errors[0].last_error().as_deref(),
+            Some("connect: connection refused")
+        );
+        assert_eq!(upstreams.errors[1].connect_failures(), 0);
+        assert_eq!(upstreams.errors[1].last_error(), None);
+    }
+
+    #[tokio::test]
+    async fn test_all_upstreams_dead() {
+        let connector = ScriptedConnector::new(&[]);
+        let upstreams = upstream_list(&["a:1", "b:2", "c:3"]);
+        assert!(connect_to_upstream(&connector, &upstreams, None, LoadBalanceAlgorithm::Random, None).await.is_err());
+        // 每个服务器只尝试一次，然后全部被标记为失败
+        let mut attempts = connector.attempts();
+        attempts.sort();
+        assert_eq!(attempts, ["a:1", "b:2", "c:3"]);
+        assert_eq!(*upstreams.dead.read().await, HashSet::from([0, 1, 2]));
+    }
+
+    #[tokio::test]
+    async fn test_dead_upstream_revived_when_reachable() {
+        let connector = ScriptedConnector::new(&["a:1"]);
+        let upstreams = upstream_list(&["a:1"]);
+        upstreams.dead.write().await.insert(0);
+        // 所有服务器都失败时再给它们一次机会；连接成功后恢复该服务器
+        let (_, idx, _) = connect_to_upstream(&connector, &upstreams, None, LoadBalanceAlgorithm::Random, None).await.unwrap();
+        assert_eq!(idx, 0);
+        assert!(upstreams.dead.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_ejected_upstream_not_revived_when_reachable() {
+        let connector = ScriptedConnector::new(&["a:1", "b:2"]);
+        let upstreams = upstream_list(&["a:1", "b:2"]);
+        upstreams.dead.write().await.insert(0);
+        upstreams.eject(1).await;
+        // 被摘除的服务器能接受连接，但所有服务器都失败时也不再尝试它，只重试因为连接失败而被标记的 a:1
+        let (_, idx, _) = connect_to_upstream(&connector, &upstreams, Some(1), LoadBalanceAlgorithm::Random, None).await.unwrap();
+        assert_eq!(idx, 0);
+        assert_eq!(connector.attempts(), ["a:1"]);
+        assert_eq!(*upstreams.dead.read().await, HashSet::from([1]));
+
+        // 唯一的服务器被摘除时直接失败，不去连接它
+        let connector = ScriptedConnector::new(&["a:1"]);
+        let upstreams = upstream_list(&["a:1"]);
+        upstreams.eject(0).await;
+        assert!(connect_to_upstream(&connector, &upstreams, None, LoadBalanceAlgorithm::Random, None).await.is_err());
+        assert!(connector.attempts().is_empty());
+        assert_eq!(*upstreams.dead.read().await, HashSet::from([0]));
+    }
+
+    #[tokio::test]
+    async fn test_removed_upstream_not_chosen() {
+        let connector = ScriptedConnector::new(&["a:1", "b:2"]);
+        let upstreams = upstream_list(&["a:1", "b:2"]);
+        // 这个快照是在重新加载之前取得的，但 a:1 已被移除，即使会话保持指向它也不再选择
+        upstreams.in_flight[0].remove();
+        let (_, idx, _) = connect_to_upstream(&connector, &upstreams, Some(0), LoadBalanceAlgorithm::Random, None).await.unwrap();
+        assert_eq!(idx, 1);
+        assert_eq!(connector.attempts(), ["b:2"]);
+        upstreams.in_flight[1].remove();
+        assert!(connect_to_upstream(&connector, &upstreams, None, LoadBalanceAlgorithm::Random, None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_idle_connection_reused() {
+        let connector = ScriptedConnector::new(&["a:1"]);
+        let upstreams = upstream_list(&["a:1"]);
+        let pool = UpstreamPool::default();
+        let (conn, _peer) = tokio::io::duplex(64);
+        pool.put("a:1", Box::new(conn));
+        // 连接池中有空闲连接时不建立新的连接
+        let (_, idx, reused) =
+            connect_to_upstream(&connector, &upstreams, None, LoadBalanceAlgorithm::Random, Some(&pool)).await.unwrap();
+        assert_eq!(idx, 0);
+        assert!(reused);
+        assert!(connector.attempts().is_empty());
+        // 空闲连接用完之后照常连接
+        let (_, _, reused) =
+            connect_to_upstream(&connector, &upstreams, None, LoadBalanceAlgorithm::Random, Some(&pool)).await.unwrap();
+        assert!(!reused);
+        assert_eq!(connector.attempts(), ["a:1"]);
+    }
+
+    /// 把 config 写入临时文件，然后像 main 一样解析 "balancebeam --config <文件> cli_args..."
+    fn options_with_config(config: &str, cli_args: &[&str]) -> Result<(CmdOptions, Vec<u32>), String> {
+        let path = std::env::temp_dir().join(format!("balancebeam-config-{}.toml", rand::random::<u32>()));
+        std::fs::write(&path, config).unwrap();
+        let mut args = vec![String::from("balancebeam"), String::from("--config"), path.display().to_string()];
+        args.extend(cli_args.iter().map(|arg| arg.to_string()));
+        let matches = CmdOptions::command().try_get_matches_from(&args).unwrap();
+        let result = merge_config_file(&matches);
+        std::fs::remove_file(&path).unwrap();
+        result
+    }
+
+    #[test]
+    fn test_body_contains() {
+        assert!(body_contains(b"{\"status\": \"ok\"}", "\"ok\""));
+        assert!(!body_contains(b"{\"status\": \"degraded\"}", "\"ok\""));
+        assert!(!body_contains(b"ok", "okay"));
+        assert!(body_contains(b"\xff\xfe ok", "ok"));
+        assert!(body_contains(b"", ""));
+    }
+
+    #[tokio::test]
+    async fn test_config_file() {
+        let config = r#"
+            bind = "127.0.0.1:8080"
+            upstreams = ["a:80", { address = "b:80", weight = 3 }]
+            keepalive_timeout = 30
+            request-timeout = 10
+            max-requests-per-minute = 100
+            active-health-check-path = "/healthz"
+            health-check-expect-status = 204
+            reuse-port = true
+            preserve-host = false
+            load-balance-algorithm = "least-connections"
+            strip-request-header = ["x-debug"]
+            sticky-cookie = "session"
+        "#;
+        let (options, weights) = options_with_config(config, &[]).unwrap();
+        assert_eq!(options.bind, "127.0.0.1:8080");
+        assert!(options.reuse_port);
+        let state = build_state(&options, &weights).unwrap();
+        let upstreams = Arc::clone(&*state.upstreams.read().await);
+        assert_eq!(upstreams.addresses, ["a:80", "b:80"]);
+        assert_eq!(upstreams.weights, [1, 3]);
+        assert_eq!(state.keepalive_timeout, 30);
+        assert_eq!(state.request_timeout, 10);
+        assert!(state.rate_limiter.is_some());
+        assert_eq!(state.active_health_check_path, "/healthz");
+        assert_eq!(state.health_check_expect_status, http::StatusCode::NO_CONTENT);
+        assert!(matches!(state.host_rewrite, HostRewrite::Upstream));
+        assert_eq!(state.load_balance_algorithm, LoadBalanceAlgorithm::LeastConnections);
+        assert!(state.sticky_cookie.is_some());
+        let mut headers = http::HeaderMap::new();
+        headers.insert("x-debug", http::HeaderValue::from_static("1"));
+        state.strip_request_headers.apply(&mut headers);
+        assert!(headers.is_empty());
+        // 配置文件中没有的选项使用默认值；未指定 --max-retries 时每个上游服务器尝试一次
+        assert_eq!(state.parse_limits.max_headers, 32);
+        assert_eq!(state.max_retries, 2);
+        assert!(state.cors.is_none());
+
+        // 命令行中的选项优先；--upstream 整体代替配置文件中的列表（包括权重）
+        let (options, weights) = options_with_config(
+            config,
+            &["--keepalive-timeout", "5", "--upstream", "c:80", "--preserve-host", "true"],
+        )
+        .unwrap();
+        let state = build_state(&options, &weights).unwrap();
+        let upstreams = Arc::clone(&*state.upstreams.read().await);
+        assert_eq!(upstreams.addresses, ["c:80"]);
+        assert_eq!(upstreams.weights, [1]);
+        assert_eq!(state.keepalive_timeout, 5);
+        assert_eq!(state.request_timeout, 10);
+        assert!(matches!(state.host_rewrite, HostRewrite::Preserve));
+    }
+
+    #[test]
+    fn test_invalid_config_file() {
+        let error = options_with_config("max-headers = \"lots\"", &[]).unwrap_err();
+        assert!(error.contains("invalid type: string \"lots\", expected usize"), "{}", error);
+        let error = options_with_config("upstream-weights = [1]", &[]).unwrap_err();
+        assert!(error.contains("unknown field `upstream-weights`"), "{}", error);
+        let error = options_with_config("bind = ", &[]).unwrap_err();
+        assert!(error.starts_with("Invalid config file "), "{}", error);
+        // 配置文件能解析，但其中的值无效
+        let (options, weights) = options_with_config("upstreams = [\"a:80\"]\nmax-retries = 0", &[]).unwrap();
+        let error = build_state(&options, &weights).err().unwrap();
+        assert_eq!(error, "--max-retries must be at least 1.");
+    }
+}
@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::connector::Connection;
+
+/// 空闲连接在连接池中最多保留多久。很多服务器会关闭空闲了几秒的 keep-alive 连接（例如 Node.js 默认 5 秒），
+/// 这里取得短一些，尽量不拿到已经被服务器关闭的连接
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(4);
+
+/// 每个上游服务器最多保留多少个空闲连接，多出来的直接关闭
+pub const MAX_IDLE_PER_UPSTREAM: usize = 16;
+
+/// 到上游服务器的空闲 keep-alive 连接（--upstream-keepalive），按地址分组。
+///
+/// 放进来的连接已经完整读完了上一个响应；取出时优先使用最近放进来的连接，超过 idle_timeout 的连接被丢弃。
+/// 只在取出和放回时短暂持有锁，不会在持有锁时等待 I/O。
+pub struct UpstreamPool {
+    idle: Mutex<HashMap<String, Vec<(Connection, Instant)>>>,
+    idle_timeout: Duration,
+    max_idle_per_upstream: usize,
+}
+
+impl Default for UpstreamPool {
+    fn default() -> UpstreamPool {
+        UpstreamPool::new(IDLE_TIMEOUT, MAX_IDLE_PER_UPSTREAM)
+    }
+}
+
+impl UpstreamPool {
+    pub fn new(idle_timeout: Duration, max_idle_per_upstream: usize) -> UpstreamPool {
+        UpstreamPool {
+            idle: Mutex::new(HashMap::new()),
+            idle_timeout,
+            max_idle_per_upstream,
+        }
+    }
+
+    /// 取出一个到 address 的空闲连接；没有（或者都已过期）时返回 None
+    pub fn take(&self, address: &str) -> Option<Connection> {
+        let mut idle = self.idle.lock().unwrap();
+        let conns = idle.get_mut(address)?;
+        conns.retain(|(_, since)| since.elapsed() < self.idle_timeout);
+        let conn = conns.pop().map(|(conn, _)| conn);
+        if conns.is_empty() {
+            idle.remove(address);
+        }
+        conn
+    }
+
+    /// 把一个到 address 的连接放回连接池，之后的请求可以继续使用它
+    pub fn put(&self, address: &str, conn: Connection) {
+        let mut idle = self.idle.lock().unwrap();
+        let conns = idle.entry(address.to_string()).or_default();
+        conns.retain(|(_, since)| since.elapsed() < self.idle_timeout);
+        if conns.len() < self.max_idle_per_upstream {
+            conns.push((conn, Instant::now()));
+        }
+    }
+
+    /// 连接池中到 address 的空闲连接数（包括已过期但还没被丢弃的）
+    #[cfg(test)]
+    pub fn idle_count(&self, address: &str) -> usize {
+        self.idle.lock().unwrap().get(address).map_or(0, Vec::len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn connection() -> (Connection, tokio::io::DuplexStream) {
+        let (conn, peer) = tokio::io::duplex(64);
+        (Box::new(conn), peer)
+    }
+
+    #[tokio::test]
+    async fn test_take_returns_put_connection() {
+        let pool = UpstreamPool::default();
+        assert!(pool.take("a:1").is_none());
+        let (conn, mut peer) = connection();
+        pool.put("a:1", conn);
+        assert_eq!(pool.idle_count("a:1"), 1);
+        // 连接按地址分组
+        assert!(pool.take("b:2").is_none());
+
+        let mut conn = pool.take("a:1").unwrap();
+        assert_eq!(pool.idle_count("a:1"), 0);
+        conn.write_all(b"hi").await.unwrap();
+        let mut buf = [0_u8; 2];
+        peer.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hi");
+    }
+
+    #[tokio::test]
+    async fn test_expired_connections_dropped() {
+        let pool = UpstreamPool::new(Duration::from_millis(50), MAX_IDLE_PER_UPSTREAM);
+        let (conn, mut peer) = connection();
+        pool.put("a:1", conn);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(pool.take("a:1").is_none());
+        // 过期的连接被关闭了
+        let mut buf = [0_u8; 1];
+        assert_eq!(peer.read(&mut buf).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_max_idle_per_upstream() {
+        let pool = UpstreamPool::new(IDLE_TIMEOUT, 2);
+        let mut peers = Vec::new();
+        for _ in 0..3 {
+            let (conn, peer) = connection();
+            pool.put("a:1", conn);
+            peers.push(peer);
+        }
+        assert_eq!(pool.idle_count("a:1"), 2);
+        // 第三个连接没有放进连接池，而是被关闭了
+        let mut buf = [0_u8; 1];
+        assert_eq!(peers[2].read(&mut buf).await.unwrap(), 0);
+    }
+}
@@ -0,0 +1,263 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv6Addr};
+use std::sync::{Mutex, TryLockError};
+use std::time::{Duration, Instant};
+
+/// 速率限制的窗口长度
+const WINDOW: Duration = Duration::from_secs(60);
+/// 检查速率限制时最多等待锁多久。超过这个时间（或者锁已经中毒）时按 fail_open 策略处理请求，
+/// 这样速率限制器出问题时不会卡住请求处理
+const LOCK_BUDGET: Duration = Duration::from_millis(10);
+/// 等待锁时两次尝试之间的间隔
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(1);
+
+/// RateLimiter::allow 的结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    Allow,
+    /// 拒绝这个请求。retry_after 是距离当前窗口结束（计数清零）还有多久；无法查询速率限制器时为 None
+    Reject { retry_after: Option<Duration> },
+}
+
+/// 按客户端 IP 限制每分钟的请求数（固定窗口：每个窗口开始时所有计数清零）
+pub struct RateLimiter {
+    max_requests_per_minute: usize,
+    /// IPv6 客户端按这么长的网络前缀计数（--ratelimit-ipv6-prefix），IPv4 客户端按完整地址计数
+    ipv6_prefix: u8,
+    /// 无法及时查询速率限制器时是否放行请求（--ratelimit-fail-open）；为 false 时返回 429
+    fail_open: bool,
+    window: Mutex<Window>,
+}
+
+/// 当前窗口的开始时间和每个 IP 在这个窗口内的请求数
+struct Window {
+    start: Instant,
+    counts: HashMap<String, usize>,
+}
+
+impl Window {
+    /// 记录来自 client_ip 的一个请求。如果这个 IP 在当前窗口内已经发送了 limit 个请求，则拒绝（不计入这个请求）
+    fn record(&mut self, client_ip: &str, now: Instant, limit: usize) -> Decision {
+        if now.duration_since(self.start) >= WINDOW {
+            self.start = now;
+            self.counts.clear();
+        }
+        let count = self.counts.entry(client_ip.to_string()).or_insert(0);
+        if *count >= limit {
+            // 固定窗口：这个窗口结束时所有 IP 的计数一起清零
+            return Decision::Reject {
+                retry_after: Some(WINDOW.saturating_sub(now.duration_since(self.start))),
+            };
+        }
+        *count += 1;
+        Decision::Allow
+    }
+}
+
+/// 客户端 IP 计入哪个计数：IPv4 地址是它本身；IPv6 地址是它所在的网络，例如前缀长度为 64 时
+/// 2001:db8::1 和 2001:db8::2 都计入 `2001:db8::/64`，这样客户端无法通过轮换同一个 /64 内的地址绕过限制。
+/// 无法解析的字符串原样使用。
+fn bucket_key(client_ip: &str, ipv6_prefix: u8) -> String {
+    match client_ip.parse::<IpAddr>() {
+        Ok(IpAddr::V6(ip)) if ipv6_prefix < 128 => {
+            let mask = u128::MAX.checked_shl(128 - u32::from(ipv6_prefix)).unwrap_or(0);
+            format!("{}/{}", Ipv6Addr::from(u128::from(ip) & mask), ipv6_prefix)
+        }
+        _ => client_ip.to_string(),
+    }
+}
+
+impl RateLimiter {
+    /// ipv6_prefix 必须不大于 128
+    pub fn new(max_requests_per_minute: usize, ipv6_prefix: u8, fail_open: bool) -> RateLimiter {
+        assert!(ipv6_prefix <= 128, "IPv6 prefix length must be at most 128");
+        RateLimiter {
+            max_requests_per_minute,
+            ipv6_prefix,
+            fail_open,
+            window: Mutex::new(Window {
+                start: Instant::now(),
+                counts: HashMap::new(),
+            }),
+        }
+    }
+
+    /// 记录来自 client_ip 的一个请求，返回是否允许处理这个请求。如果在 LOCK_BUDGET 内拿不到锁，
+    /// 或者锁已经中毒，则根据 fail_open 决定。
+    pub async fn allow(&self, client_ip: &str) -> Decision {
+        let key = bucket_key(client_ip, self.ipv6_prefix);
+        let deadline = Instant::now() + LOCK_BUDGET;
+        loop {
+            // 不能跨越 .await 持有 MutexGuard（TryLockError::Poisoned 中也有一个），所以先在这里得到结果
+            let attempt = match self.window.try_lock() {
+                Ok(mut window) => {
+                    Ok(window.record(&key, Instant::now(), self.max_requests_per_minute))
+                }
+                Err(TryLockError::WouldBlock) => Err(None),
+                Err(err) => Err(Some(err.to_string())),
+            };
+            match attempt {
+                Ok(decision) => return decision,
+                Err(None) if Instant::now() < deadline => {
+                    tokio::time::sleep(LOCK_RETRY_INTERVAL).await;
+                }
+                Err(reason) => {
+                    log::warn!(
+                        "Could not consult rate limiter for {} ({}); {} the request",
+                        client_ip,
+                        reason.as_deref().unwrap_or("timed out waiting for the lock"),
+                        if self.fail_open { "allowing" } else { "rejecting" }
+                    );
+                    return if self.fail_open {
+                        Decision::Allow
+                    } else {
+                        Decision::Reject { retry_after: None }
+                    };
+                }
+            }
+        }
+    }
+
+    /// 返回当前窗口内每个 IP（IPv6 客户端是网络前缀）的请求数（按 IP 排序）。锁被占用或已经中毒时返回 None，
+    /// 而不是等待。
+    pub fn counts(&self) -> Option<Vec<(String, usize)>> {
+        let window = self.window.try_lock().ok()?;
+        let mut counts: Vec<_> = window
+            .counts
+            .iter()
+            .map(|(ip, count)| (ip.clone(), *count))
+            .collect();
+        counts.sort();
+        Some(counts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_window_counts_per_ip_and_resets() {
+        let start = Instant::now();
+        let mut window = Window {
+            start,
+            counts: HashMap::new(),
+        };
+        assert_eq!(window.record("1.1.1.1", start, 2), Decision::Allow);
+        assert_eq!(window.record("1.1.1.1", start, 2), Decision::Allow);
+        assert_eq!(
+            window.record("1.1.1.1", start, 2),
+            Decision::Reject {
+                retry_after: Some(WINDOW)
+            }
+        );
+        // 其他 IP 有自己的计数
+        assert_eq!(window.record("2.2.2.2", start, 2), Decision::Allow);
+        // 被拒绝的请求不计入
+        assert_eq!(window.counts["1.1.1.1"], 2);
+
+        // 下一个窗口开始时计数清零
+        assert_eq!(
+            window.record("1.1.1.1", start + Duration::from_secs(59), 2),
+            Decision::Reject {
+                retry_after: Some(Duration::from_secs(1))
+            }
+        );
+        assert_eq!(window.record("1.1.1.1", start + WINDOW, 2), Decision::Allow);
+        assert_eq!(window.counts.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_allow_and_counts() {
+        let limiter = RateLimiter::new(1, 64, false);
+        assert_eq!(limiter.allow("1.1.1.1").await, Decision::Allow);
+        match limiter.allow("1.1.1.1").await {
+            Decision::Reject {
+                retry_after: Some(retry_after),
+            } => assert!(retry_after > Duration::from_secs(59) && retry_after <= WINDOW),
+            decision => panic!("Expected a rejection with retry_after, got {:?}", decision),
+        }
+        assert_eq!(limiter.allow("2.2.2.2").await, Decision::Allow);
+        assert_eq!(
+            limiter.counts(),
+            Some(vec![(String::from("1.1.1.1"), 1), (String::from("2.2.2.2"), 1)])
+        );
+    }
+
+    #[test]
+    fn test_bucket_key() {
+        assert_eq!(bucket_key("203.0.113.7", 64), "203.0.113.7");
+        assert_eq!(bucket_key("2001:db8:1:2:aaaa::1", 64), "2001:db8:1:2::/64");
+        assert_eq!(bucket_key("2001:db8:1:2:aaaa::1", 48), "2001:db8:1::/48");
+        assert_eq!(bucket_key("2001:db8:1:2:aaaa::1", 128), "2001:db8:1:2:aaaa::1");
+        assert_eq!(bucket_key("2001:db8::1", 0), "::/0");
+        assert_eq!(bucket_key("not an ip", 64), "not an ip");
+    }
+
+    #[tokio::test]
+    async fn test_ipv6_clients_share_prefix_bucket() {
+        let limiter = RateLimiter::new(2, 64, false);
+        assert_eq!(limiter.allow("2001:db8::1").await, Decision::Allow);
+        assert_eq!(limiter.allow("2001:db8::2").await, Decision::Allow);
+        // 同一个 /64 中的第三个地址共享前两个地址的计数
+        assert!(matches!(
+            limiter.allow("2001:db8::ffff:3").await,
+            Decision::Reject { .. }
+        ));
+        // 其他 /64 有自己的计数
+        assert_eq!(limiter.allow("2001:db8:0:1::1").await, Decision::Allow);
+        assert_eq!(
+            limiter.counts(),
+            Some(vec![(String::from("2001:db8:0:1::/64"), 1), (String::from("2001:db8::/64"), 2)])
+        );
+
+        // 前缀长度为 128 时每个地址单独计数
+        let limiter = RateLimiter::new(1, 128, false);
+        assert_eq!(limiter.allow("2001:db8::1").await, Decision::Allow);
+        assert_eq!(limiter.allow("2001:db8::2").await, Decision::Allow);
+    }
+
+    /// 无法查询速率限制器时的结果：不知道窗口什么时候结束，所以拒绝时没有 retry_after
+    fn fallback(fail_open: bool) -> Decision {
+        if fail_open {
+            Decision::Allow
+        } else {
+            Decision::Reject { retry_after: None }
+        }
+    }
+
+    #[test]
+    fn test_contended_lock() {
+        // 这个测试需要在持有锁的同时调用 allow，所以不使用 #[tokio::test]（不能跨越 .await 持有 MutexGuard）
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        for fail_open in [true, false] {
+            let limiter = RateLimiter::new(100, 64, fail_open);
+            let guard = limiter.window.lock().unwrap();
+            let started = Instant::now();
+            assert_eq!(runtime.block_on(limiter.allow("1.1.1.1")), fallback(fail_open));
+            // 只等待 LOCK_BUDGET，而不是等到锁被释放
+            assert!(started.elapsed() < Duration::from_secs(1));
+            assert_eq!(limiter.counts(), None);
+            drop(guard);
+            // 锁被释放之后正常计数；等待锁时放行的请求不计入
+            assert_eq!(runtime.block_on(limiter.allow("1.1.1.1")), Decision::Allow);
+            assert_eq!(limiter.counts(), Some(vec![(String::from("1.1.1.1"), 1)]));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_poisoned_lock() {
+        for fail_open in [true, false] {
+            let limiter = RateLimiter::new(100, 64, fail_open);
+            let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                let _guard = limiter.window.lock().unwrap();
+                panic!("poison the rate limiter lock");
+            }));
+            assert!(limiter.window.is_poisoned());
+            assert_eq!(limiter.allow("1.1.1.1").await, fallback(fail_open));
+        }
+    }
+}
@@ -1,41 +1,27 @@
 use std::cmp::min;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
-
-const MAX_HEADERS_SIZE: usize = 8000;
-const MAX_BODY_SIZE: usize = 10000000;
-const MAX_NUM_HEADERS: usize = 32;
-
-#[derive(Debug)]
-pub enum Error {
-    /// 客户端在发送完整请求之前挂断。IncompleteRequest 包含客户端挂断前成功读取的字节数
-    IncompleteRequest(usize),
-    /// 客户端发送了无效的 HTTP 请求。httparse::Error 包含更多详细信息
-    MalformedRequest(httparse::Error),
-    /// Content-Length 头存在，但不包含有效的数字值
-    InvalidContentLength,
-    /// Content-Length 头与发送的请求体大小不匹配
-    ContentLengthMismatch,
-    /// 请求体大于 MAX_BODY_SIZE
-    RequestBodyTooLarge,
-    /// 读取/写入 TcpStream 时遇到 I/O 错误
-    ConnectionError(std::io::Error),
-}
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::error::{httparse_error_from, ProxyError};
+use crate::headers;
+use crate::limits::ParseLimits;
+
+/// 请求方法最长允许的字节数。httparse 不限制方法的长度，但没有真实的方法会这么长。
+const MAX_METHOD_LENGTH: usize = 32;
 
 /// 从提供的请求中提取 Content-Length 头值。如果 Content-Length 存在且有效则返回 Ok(Some(usize))，
-/// 如果 Content-Length 不存在则返回 Ok(None)，如果 Content-Length 存在但无效则返回 Err(Error)。
+/// 如果 Content-Length 不存在则返回 Ok(None)，如果 Content-Length 存在但无效则返回 Err(ProxyError)。
 ///
 /// 您不需要修改此函数。
-fn get_content_length(request: &http::Request<Vec<u8>>) -> Result<Option<usize>, Error> {
+fn get_content_length(request: &http::Request<Vec<u8>>) -> Result<Option<usize>, ProxyError> {
     // 查找 content-length 头
     if let Some(header_value) = request.headers().get("content-length") {
         // 如果存在，将其解析为 usize（如果无法解析则返回 InvalidContentLength）
         Ok(Some(
             header_value
                 .to_str()
-                .or(Err(Error::InvalidContentLength))?
+                .or(Err(ProxyError::InvalidContentLength))?
                 .parse::<usize>()
-                .or(Err(Error::InvalidContentLength))?,
+                .or(Err(ProxyError::InvalidContentLength))?,
         ))
     } else {
         // 如果不存在，返回 None
@@ -43,47 +29,116 @@ fn get_content_length(request: &http::Request<Vec<u8>>) -> Result<Option<usize>,
     }
 }
 
+/// 与 get_content_length 相同，但如果 Content-Length 超过了 limits 中的请求体限制，
+/// 返回 Err(ProxyError::RequestBodyTooLarge)。
+fn get_checked_content_length(
+    request: &http::Request<Vec<u8>>,
+    limits: &ParseLimits,
+) -> Result<Option<usize>, ProxyError> {
+    match get_content_length(request)? {
+        Some(content_length) if content_length > limits.max_request_body => {
+            Err(ProxyError::RequestBodyTooLarge)
+        }
+        content_length => Ok(content_length),
+    }
+}
+
+/// 如果请求声明了请求体（非零的 Content-Length，或者 chunked 的 Transfer-Encoding），返回 true。
+fn declares_body(request: &http::Request<Vec<u8>>) -> Result<bool, ProxyError> {
+    let chunked = request
+        .headers()
+        .get_all("transfer-encoding")
+        .iter()
+        .any(|value| {
+            value
+                .to_str()
+                .map(|value| value.to_ascii_lowercase().contains("chunked"))
+                .unwrap_or(true)
+        });
+    Ok(chunked || get_content_length(request)?.unwrap_or(0) > 0)
+}
+
 /// 此函数追加到头值（如果头尚不存在则添加新头）。这用于将客户端的 IP 地址添加到 
 /// X-Forwarded-For 列表的末尾，或者如果尚不存在则添加新的 X-Forwarded-For 头。
 ///
-/// 您不需要修改此函数。
+/// 头不存在时直接插入 extend_value 的克隆（与原来的值共享内存），不需要拼接新的字节。
 pub fn extend_header_value(
     request: &mut http::Request<Vec<u8>>,
     name: &'static str,
-    extend_value: &str,
+    extend_value: &http::HeaderValue,
 ) {
     let new_value = match request.headers().get(name) {
         Some(existing_value) => {
-            [existing_value.as_bytes(), b", ", extend_value.as_bytes()].concat()
+            let joined = [existing_value.as_bytes(), b", ", extend_value.as_bytes()].concat();
+            // 两个合法的头值用 ", " 连接起来仍然是合法的头值
+            http::HeaderValue::from_bytes(&joined).unwrap()
         }
-        None => extend_value.as_bytes().to_owned(),
+        None => extend_value.clone(),
     };
-    request
-        .headers_mut()
-        .insert(name, http::HeaderValue::from_bytes(&new_value).unwrap());
+    request.headers_mut().insert(name, new_value);
+}
+
+/// 转发请求之前如何处理 Host 头（--preserve-host 和 --set-host）
+#[derive(Debug, Clone)]
+pub enum HostRewrite {
+    /// 保留客户端发送的 Host 头
+    Preserve,
+    /// 将 Host 头改写为选中的上游服务器地址（虚拟主机）
+    Upstream,
+    /// 将 Host 头改写为固定的值
+    Fixed(http::HeaderValue),
+}
+
+impl HostRewrite {
+    /// 按照配置改写请求的 Host 头。每次转发到某个上游服务器之前调用，因为重试时可能换了一个上游服务器。
+    pub fn apply(&self, request: &mut http::Request<Vec<u8>>, upstream_address: &str) {
+        let host = match self {
+            HostRewrite::Preserve => return,
+            // 上游服务器地址来自命令行或 --upstream-file，不是合法头值的地址也不可能连接成功
+            HostRewrite::Upstream => match http::HeaderValue::from_str(upstream_address) {
+                Ok(host) => host,
+                Err(_) => return,
+            },
+            HostRewrite::Fixed(host) => host.clone(),
+        };
+        request.headers_mut().insert(http::header::HOST, host);
+    }
 }
 
 /// 尝试将提供的缓冲区中的数据解析为 HTTP 请求。返回以下之一：
 ///
 /// * 如果缓冲区中有完整且有效的请求，返回 Ok(Some(http::Request))
 /// * 如果缓冲区中有不完整但到目前为止有效的请求，返回 Ok(None)
-/// * 如果缓冲区中的数据绝对不是有效的 HTTP 请求，返回 Err(Error)
+/// * 如果缓冲区中的数据绝对不是有效的 HTTP 请求，返回 Err(ProxyError)
+///
+/// 最多解析 max_headers 个头；超过时返回 ProxyError::RequestHeadersTooLarge。
 ///
 /// 您不需要修改此函数。
-fn parse_request(buffer: &[u8]) -> Result<Option<(http::Request<Vec<u8>>, usize)>, Error> {
-    let mut headers = [httparse::EMPTY_HEADER; MAX_NUM_HEADERS];
+fn parse_request(
+    buffer: &[u8],
+    max_headers: usize,
+) -> Result<Option<(http::Request<Vec<u8>>, usize)>, ProxyError> {
+    let mut headers = vec![httparse::EMPTY_HEADER; max_headers];
     let mut req = httparse::Request::new(&mut headers);
-    let res = req.parse(buffer).or_else(|err| Err(Error::MalformedRequest(err)))?;
+    let res = req.parse(buffer).map_err(|err| match err {
+        httparse::Error::TooManyHeaders => ProxyError::RequestHeadersTooLarge,
+        err => ProxyError::MalformedRequest(err),
+    })?;
 
     if let httparse::Status::Complete(len) = res {
-        let mut request = http::Request::builder()
-            .method(req.method.unwrap())
-            .uri(req.path.unwrap())
-            .version(http::Version::HTTP_11);
-        for header in req.headers {
-            request = request.header(header.name, header.value);
+        let method = req.method.ok_or(ProxyError::MalformedRequest(httparse::Error::Token))?;
+        let path = req.path.ok_or(ProxyError::MalformedRequest(httparse::Error::Token))?;
+        if method.len() > MAX_METHOD_LENGTH {
+            return Err(ProxyError::MalformedRequest(httparse::Error::Token));
         }
-        let request = request.body(Vec::new()).unwrap();
+        let mut request = http::Request::builder()
+            .method(method)
+            .uri(path)
+            .version(http::Version::HTTP_11)
+            .body(Vec::new())
+            .map_err(|err| ProxyError::MalformedRequest(httparse_error_from(&err)))?;
+        headers::copy_parsed_headers(req.headers, request.headers_mut())
+            .map_err(ProxyError::MalformedRequest)?;
         Ok(Some((request, len)))
     } else {
         Ok(None)
@@ -93,29 +148,35 @@ fn parse_request(buffer: &[u8]) -> Result<Option<(http::Request<Vec<u8>>, usize)
 /// 从提供的流中读取 HTTP 请求，等待直到发送完整的头集合。
 /// 此函数只读取请求行和头；随后可以调用 read_body 函数来读取请求体（对于 POST 请求）。
 ///
-/// 如果收到有效请求则返回 Ok(http::Request)，否则返回 Error。
+/// 如果收到有效请求则返回 Ok(http::Request)，否则返回 ProxyError。如果头的数量或字节数超过
+/// limits 中的限制，返回 ProxyError::RequestHeadersTooLarge。
 ///
 /// 您需要在里程碑 2 中修改此函数。
-async fn read_headers(stream: &mut TcpStream) -> Result<http::Request<Vec<u8>>, Error> {
+async fn read_headers(
+    stream: &mut (impl AsyncRead + Unpin),
+    limits: &ParseLimits,
+) -> Result<http::Request<Vec<u8>>, ProxyError> {
     // 尝试从请求中读取头。我们可能不会一次收到所有头
     // （例如，我们可能先收到请求的前几个字节，然后其余部分稍后到达）。
     // 反复尝试解析，直到我们读取到有效的 HTTP 请求
-    let mut request_buffer = [0_u8; MAX_HEADERS_SIZE];
+    let mut request_buffer = vec![0_u8; limits.max_header_bytes];
     let mut bytes_read = 0;
     loop {
         // 从连接中读取字节到缓冲区，从 bytes_read 位置开始
         let new_bytes = stream
             .read(&mut request_buffer[bytes_read..])
             .await
-            .or_else(|err| Err(Error::ConnectionError(err)))?;
+            .or_else(|err| Err(ProxyError::ConnectionError(err)))?;
         if new_bytes == 0 {
             // 我们没能读取到完整的请求
-            return Err(Error::IncompleteRequest(bytes_read));
+            return Err(ProxyError::IncompleteRequest(bytes_read));
         }
         bytes_read += new_bytes;
 
         // 查看我们到目前为止是否已读取到有效请求
-        if let Some((mut request, headers_len)) = parse_request(&request_buffer[..bytes_read])? {
+        if let Some((mut request, headers_len)) =
+            parse_request(&request_buffer[..bytes_read], limits.max_headers)?
+        {
             // 我们已读取了完整的头集合。但是，如果这是 POST 请求，可能还包含了请求体，
             // 并且我们可能已经从流中将部分请求体读取到了 header_buffer 中。我们需要将这些字节
             // 添加到 Request body 中，以免丢失它们
@@ -124,23 +185,28 @@ async fn read_headers(stream: &mut TcpStream) -> Result<http::Request<Vec<u8>>,
                 .extend_from_slice(&request_buffer[headers_len..bytes_read]);
             return Ok(request);
         }
+
+        // 缓冲区已满但头仍然不完整
+        if bytes_read == request_buffer.len() {
+            return Err(ProxyError::RequestHeadersTooLarge);
+        }
     }
 }
 
 /// 此函数从流中读取请求的请求体。只有当 Content-Length 头存在时，客户端才会发送请求体；
-/// 此函数从流中读取相应字节数。如果成功则返回 Ok(())，如果无法读取 Content-Length 字节数则返回 Err(Error)。
+/// 此函数从流中读取相应字节数。如果成功则返回 Ok(())，如果无法读取 Content-Length 字节数则返回 Err(ProxyError)。
 ///
 /// 您需要在里程碑 2 中修改此函数。
 async fn read_body(
-    stream: &mut TcpStream,
+    stream: &mut (impl AsyncRead + Unpin),
     request: &mut http::Request<Vec<u8>>,
     content_length: usize,
-) -> Result<(), Error> {
+) -> Result<(), ProxyError> {
     // 持续读取数据，直到我们读取了完整的请求体长度，或者遇到错误。
     while request.body().len() < content_length {
         // 一次最多读取 512 字节。（如果客户端只发送了小的请求体，则只分配读取该请求体所需的空间。）
         let mut buffer = vec![0_u8; min(512, content_length)];
-        let bytes_read = stream.read(&mut buffer).await.or_else(|err| Err(Error::ConnectionError(err)))?;
+        let bytes_read = stream.read(&mut buffer).await.or_else(|err| Err(ProxyError::ConnectionError(err)))?;
 
         // 确保客户端仍在向我们发送字节
         if bytes_read == 0 {
@@ -150,7 +216,7 @@ async fn read_body(
                 request.body().len(),
                 content_length
             );
-            return Err(Error::ContentLengthMismatch);
+            return Err(ProxyError::ContentLengthMismatch);
         }
 
         // 确保客户端没有发送*过多*的字节
@@ -158,7 +224,7 @@ async fn read_body(
             log::debug!(
                 "Client sent more bytes than we expected based on the given content length!"
             );
-            return Err(Error::ContentLengthMismatch);
+            return Err(ProxyError::ContentLengthMismatch);
         }
 
         // 将接收到的字节存储到请求体中
@@ -167,44 +233,403 @@ async fn read_body(
     Ok(())
 }
 
-/// 此函数从流中读取并返回 HTTP 请求，如果客户端过早关闭连接或发送无效请求则返回 Error。
+/// 此函数从流中读取并返回 HTTP 请求，如果客户端过早关闭连接或发送无效请求则返回 ProxyError。
 ///
 /// 您需要在里程碑 2 中修改此函数。
-pub async fn read_from_stream(stream: &mut TcpStream) -> Result<http::Request<Vec<u8>>, Error> {
+pub async fn read_from_stream(
+    stream: &mut (impl AsyncRead + Unpin),
+    limits: &ParseLimits,
+) -> Result<http::Request<Vec<u8>>, ProxyError> {
     // 读取头
-    let mut request = read_headers(stream).await?;
-    // 如果客户端提供了 Content-Length 头（对于 POST 请求会提供），则读取请求体
-    if let Some(content_length) = get_content_length(&request)? {
-        if content_length > MAX_BODY_SIZE {
-            return Err(Error::RequestBodyTooLarge);
-        } else {
-            read_body(stream, &mut request, content_length).await?;
-        }
+    let mut request = read_headers(stream, limits).await?;
+    // 在读取请求体之前拒绝带有请求体的 GET/HEAD 请求（这往往是请求走私的迹象）
+    if limits.reject_body_on_get
+        && (request.method() == http::Method::GET || request.method() == http::Method::HEAD)
+        && declares_body(&request)?
+    {
+        return Err(ProxyError::UnexpectedRequestBody(request.method().clone()));
+    }
+    // 如果客户端提供了 Content-Length 头，则读取请求体。与方法无关：POST、PUT、PATCH、DELETE 等都一样
+    if let Some(content_length) = get_checked_content_length(&request, limits)? {
+        read_body(stream, &mut request, content_length).await?;
     }
     Ok(request)
 }
 
-/// 此函数将请求序列化为字节并将这些字节写入提供的流。
-///
-/// 您需要在里程碑 2 中修改此函数。
+/// 将请求序列化为字节：请求行，按 headers() 的顺序每个头一行，一个空行，然后是请求体（原样写入，
+/// 所以请求体的长度必须与 Content-Length 头一致）。
+pub fn serialize(request: &http::Request<Vec<u8>>) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(headers::head_capacity(request.headers()) + request.body().len());
+    headers::write_head(
+        &mut bytes,
+        // 与 format_request_line 相同
+        format_args!("{} {} {:?}", request.method(), request.uri(), request.version()),
+        request.headers(),
+    );
+    bytes.extend_from_slice(request.body());
+    bytes
+}
+
+/// 此函数将请求序列化为字节并将这些字节写入提供的流。使用 write_all 而不是 write：一次 write 可能只写入
+/// 一部分字节（例如套接字的发送缓冲区已满时），write_all 会继续写剩下的部分，并重试被 EINTR 打断的写入，
+/// 所以请求不会被截断。
 pub async fn write_to_stream(
     request: &http::Request<Vec<u8>>,
-    stream: &mut TcpStream,
+    stream: &mut (impl AsyncWrite + Unpin),
 ) -> Result<(), std::io::Error> {
-    stream.write_all(&format_request_line(request).into_bytes()).await?;
-    stream.write_all(&['\r' as u8, '\n' as u8]).await?; // \r\n
-    for (header_name, header_value) in request.headers() {
-        stream.write_all(&format!("{}: ", header_name).as_bytes()).await?;
-        stream.write_all(header_value.as_bytes()).await?;
-        stream.write_all(&['\r' as u8, '\n' as u8]).await?; // \r\n
-    }
-    stream.write_all(&['\r' as u8, '\n' as u8]).await?;
-    if request.body().len() > 0 {
-        stream.write_all(request.body()).await?;
-    }
-    Ok(())
+    stream.write_all(&serialize(request)).await
 }
 
 pub fn format_request_line(request: &http::Request<Vec<u8>>) -> String {
     format!("{} {} {:?}", request.method(), request.uri(), request.version())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 解析一段输入，期望得到 MalformedRequest 错误
+    fn assert_malformed(input: &[u8]) {
+        let result = parse_request(input, 32);
+        assert!(
+            matches!(result, Err(ProxyError::MalformedRequest(_))),
+            "expected MalformedRequest for {:?}, got {:?}",
+            String::from_utf8_lossy(input),
+            result
+        );
+    }
+
+    /// 解析一段完整且合法的请求头
+    fn parse_complete(input: &[u8]) -> http::Request<Vec<u8>> {
+        match parse_request(input, 32) {
+            Ok(Some((request, _))) => request,
+            other => panic!("expected a complete request, got {:?}", other),
+        }
+    }
+
+    /// 减少内存分配之前的 parse_request（通过 Builder 逐个添加头）和 serialize（先用 format! 生成请求行），
+    /// 用来确认优化之后的结果不变，并比较两者的性能
+    fn parse_unoptimized(input: &[u8]) -> http::Request<Vec<u8>> {
+        let mut headers = vec![httparse::EMPTY_HEADER; 32];
+        let mut req = httparse::Request::new(&mut headers);
+        req.parse(input).unwrap();
+        let mut request = http::Request::builder()
+            .method(req.method.unwrap())
+            .uri(req.path.unwrap())
+            .version(http::Version::HTTP_11);
+        for header in req.headers {
+            request = request.header(header.name, header.value);
+        }
+        request.body(Vec::new()).unwrap()
+    }
+
+    fn serialize_unoptimized(request: &http::Request<Vec<u8>>) -> Vec<u8> {
+        let mut bytes = format_request_line(request).into_bytes();
+        bytes.extend_from_slice(b"\r\n");
+        for (header_name, header_value) in request.headers() {
+            bytes.extend_from_slice(header_name.as_str().as_bytes());
+            bytes.extend_from_slice(b": ");
+            bytes.extend_from_slice(header_value.as_bytes());
+            bytes.extend_from_slice(b"\r\n");
+        }
+        bytes.extend_from_slice(b"\r\n");
+        bytes.extend_from_slice(request.body());
+        bytes
+    }
+
+    /// 测试和性能测试共用的请求：重复的头、自定义的头、很长的 URI
+    fn sample_requests() -> Vec<Vec<u8>> {
+        let long_uri = format!("/{}?{}", "a".repeat(200), "q=1&".repeat(20));
+        vec![
+            b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n".to_vec(),
+            b"POST /submit?x=1 HTTP/1.1\r\nHost: example.com\r\nContent-Length: 11\r\n\
+              Cookie: a=1\r\nCookie: b=2\r\nX-Custom-Header: \xe9t\xe9\r\n\r\n"
+                .to_vec(),
+            format!(
+                "GET {} HTTP/1.1\r\nHost: example.com\r\nUser-Agent: curl/8.0\r\nAccept: */*\r\n\
+                 Accept-Encoding: gzip, deflate\r\nX-Forwarded-For: 1.1.1.1, 2.2.2.2\r\n\r\n",
+                long_uri
+            )
+            .into_bytes(),
+        ]
+    }
+
+    #[test]
+    fn test_parse_and_serialize_match_unoptimized() {
+        for input in sample_requests() {
+            let mut request = parse_complete(&input);
+            let mut unoptimized = parse_unoptimized(&input);
+            assert_eq!(request.method(), unoptimized.method());
+            assert_eq!(request.uri(), unoptimized.uri());
+            assert_eq!(request.headers(), unoptimized.headers());
+            assert_eq!(serialize(&request), serialize_unoptimized(&unoptimized));
+
+            *request.body_mut() = b"hello world".to_vec();
+            *unoptimized.body_mut() = b"hello world".to_vec();
+            assert_eq!(serialize(&request), serialize_unoptimized(&unoptimized));
+        }
+    }
+
+    /// 比较优化前后解析和序列化请求的耗时。用 cargo test --release -- --ignored --nocapture 运行
+    #[test]
+    #[ignore]
+    fn bench_parse_and_serialize() {
+        let inputs = sample_requests();
+        let iterations = 200_000;
+        let time = |label: &str, round_trip: &dyn Fn(&[u8]) -> Vec<u8>| {
+            let start = std::time::Instant::now();
+            for _ in 0..iterations {
+                for input in &inputs {
+                    std::hint::black_box(round_trip(std::hint::black_box(input)));
+                }
+            }
+            println!("{}: {:?} per request", label, start.elapsed() / (iterations * inputs.len() as u32));
+        };
+        time("before", &|input| serialize_unoptimized(&parse_unoptimized(input)));
+        time("after", &|input| serialize(&parse_complete(input)));
+    }
+
+    #[test]
+    fn test_host_rewrite() {
+        let input = b"GET / HTTP/1.1\r\nHost: proxy.example.com\r\n\r\n";
+
+        let mut preserved = parse_complete(input);
+        HostRewrite::Preserve.apply(&mut preserved, "10.0.0.1:8080");
+        assert_eq!(preserved.headers()["host"], "proxy.example.com");
+
+        let mut rewritten = parse_complete(input);
+        HostRewrite::Upstream.apply(&mut rewritten, "10.0.0.1:8080");
+        assert_eq!(rewritten.headers()["host"], "10.0.0.1:8080");
+        assert_eq!(rewritten.headers().get_all("host").iter().count(), 1);
+
+        let mut fixed = parse_complete(input);
+        HostRewrite::Fixed(http::HeaderValue::from_static("backend.internal"))
+            .apply(&mut fixed, "10.0.0.1:8080");
+        assert_eq!(fixed.headers()["host"], "backend.internal");
+    }
+
+    #[test]
+    fn test_valid_request() {
+        let request = parse_complete(b"GET /index.html HTTP/1.1\r\nHost: example.com\r\n\r\n");
+        assert_eq!(request.method(), http::Method::GET);
+        assert_eq!(request.uri(), "/index.html");
+        assert_eq!(request.headers()["host"], "example.com");
+    }
+
+    #[test]
+    fn test_incomplete_request() {
+        assert!(matches!(parse_request(b"GET /index.html HTTP/1.1\r\nHost: exa", 32), Ok(None)));
+    }
+
+    #[test]
+    fn test_embedded_nul() {
+        assert_malformed(b"G\0T / HTTP/1.1\r\n\r\n");
+        assert_malformed(b"GET /a\0b HTTP/1.1\r\n\r\n");
+        assert_malformed(b"GET / HTTP/1.1\r\nHost: a\0b\r\n\r\n");
+    }
+
+    #[test]
+    fn test_cr_without_lf() {
+        assert_malformed(b"GET / HTTP/1.1\rHost: example.com\r\n\r\n");
+        assert_malformed(b"GET / HTTP/1.1\r\nHost: example.com\rAccept: */*\r\n\r\n");
+    }
+
+    #[test]
+    fn test_header_without_colon() {
+        assert_malformed(b"GET / HTTP/1.1\r\nNoColonHere\r\n\r\n");
+    }
+
+    #[test]
+    fn test_overlong_method() {
+        let mut input = "A".repeat(10000).into_bytes();
+        input.extend_from_slice(b" / HTTP/1.1\r\n\r\n");
+        assert_malformed(&input);
+    }
+
+    #[test]
+    fn test_negative_content_length() {
+        let request = parse_complete(b"POST / HTTP/1.1\r\nContent-Length: -5\r\n\r\n");
+        assert!(matches!(
+            get_content_length(&request),
+            Err(ProxyError::InvalidContentLength)
+        ));
+    }
+
+    #[test]
+    fn test_gigantic_content_length() {
+        // 超出 usize 范围
+        let request = parse_complete(
+            b"POST / HTTP/1.1\r\nContent-Length: 99999999999999999999999999\r\n\r\n",
+        );
+        assert!(matches!(
+            get_content_length(&request),
+            Err(ProxyError::InvalidContentLength)
+        ));
+
+        // 在 usize 范围内，但超过了请求体限制
+        let request =
+            parse_complete(b"POST / HTTP/1.1\r\nContent-Length: 1000000000000\r\n\r\n");
+        assert!(matches!(
+            get_checked_content_length(&request, &ParseLimits::default()),
+            Err(ProxyError::RequestBodyTooLarge)
+        ));
+    }
+
+    #[test]
+    fn test_uri_rejected_by_http_crate() {
+        // httparse 接受 '<' 和 '>'，但 http::Uri 不接受
+        assert_malformed(b"GET /a<b>c HTTP/1.1\r\n\r\n");
+        assert_malformed(b"GET /a`b HTTP/1.1\r\n\r\n");
+    }
+
+    #[test]
+    fn test_invalid_header_value() {
+        assert_malformed(b"GET / HTTP/1.1\r\nX-Delete: a\x7fb\r\n\r\n");
+        assert_malformed(b"GET / HTTP/1.1\r\nX-Bell: a\x07b\r\n\r\n");
+    }
+
+    #[test]
+    fn test_mutated_requests_never_panic() {
+        // 用一个简单的线性同余生成器对合法请求做确定性的随机变异，确保解析器只返回错误而不会 panic
+        let valid = b"POST /path?query=1 HTTP/1.1\r\nHost: example.com\r\nContent-Length: 5\r\n\r\nhello";
+        let mut seed: u64 = 0x5eed;
+        let mut next = || {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (seed >> 33) as usize
+        };
+        for _ in 0..5000 {
+            let mut input = valid.to_vec();
+            for _ in 0..(next() % 4 + 1) {
+                let idx = next() % input.len();
+                input[idx] = next() as u8;
+            }
+            input.truncate(next() % (input.len() + 1));
+            let _ = parse_request(&input, 32);
+        }
+    }
+
+    #[test]
+    fn test_too_many_headers() {
+        let input = b"GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n";
+        assert!(matches!(
+            parse_request(input, 2),
+            Err(ProxyError::RequestHeadersTooLarge)
+        ));
+        assert!(matches!(parse_request(input, 3), Ok(Some(_))));
+    }
+
+    #[test]
+    fn test_serialize_request_with_body() {
+        let request = http::Request::builder()
+            .method("POST")
+            .uri("/submit?x=1")
+            .header("Host", "example.com")
+            .header("Content-Length", "11")
+            .body(b"hello world".to_vec())
+            .unwrap();
+        assert_eq!(
+            serialize(&request),
+            b"POST /submit?x=1 HTTP/1.1\r\nhost: example.com\r\ncontent-length: 11\r\n\r\nhello world"
+        );
+        // 没有请求体时以空行结束
+        let request = parse_complete(b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n");
+        assert_eq!(serialize(&request), b"GET / HTTP/1.1\r\nhost: example.com\r\n\r\n");
+    }
+
+    #[test]
+    fn test_extend_header_value() {
+        let client_ip = http::HeaderValue::from_static("10.0.0.2");
+        let mut request = parse_complete(b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n");
+        extend_header_value(&mut request, "x-forwarded-for", &client_ip);
+        assert_eq!(request.headers()["x-forwarded-for"], "10.0.0.2");
+
+        let mut request = parse_complete(
+            b"GET / HTTP/1.1\r\nHost: example.com\r\nX-Forwarded-For: 1.1.1.1, 2.2.2.2\r\n\r\n",
+        );
+        extend_header_value(&mut request, "x-forwarded-for", &client_ip);
+        assert_eq!(request.headers()["x-forwarded-for"], "1.1.1.1, 2.2.2.2, 10.0.0.2");
+        assert_eq!(request.headers().get_all("x-forwarded-for").iter().count(), 1);
+    }
+
+    /// 测量添加 X-Forwarded-For 头的开销。用 cargo test --release -- --ignored --nocapture 运行
+    #[test]
+    #[ignore]
+    fn bench_extend_header_value() {
+        let client_ip = http::HeaderValue::from_static("10.0.0.2");
+        let without_header = parse_complete(b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n");
+        let with_header = parse_complete(
+            b"GET / HTTP/1.1\r\nHost: example.com\r\nX-Forwarded-For: 1.1.1.1\r\n\r\n",
+        );
+        for (label, mut request) in [("absent", without_header), ("present", with_header)] {
+            let original = request.headers().get("x-forwarded-for").cloned();
+            let iterations = 1_000_000;
+            let start = std::time::Instant::now();
+            for _ in 0..iterations {
+                extend_header_value(&mut request, "x-forwarded-for", &client_ip);
+                std::hint::black_box(&request);
+                // 恢复原来的头，这样每次迭代测量的都是同一种情况
+                match &original {
+                    Some(value) => request.headers_mut().insert("x-forwarded-for", value.clone()),
+                    None => request.headers_mut().remove("x-forwarded-for"),
+                };
+            }
+            println!("{}: {:?} per request", label, start.elapsed() / iterations);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bodies_forwarded_for_every_method() {
+        let body = b"line one\r\n\r\nline two \xc3\xa9\x00";
+        for method in ["POST", "PUT", "PATCH", "DELETE"] {
+            let input = [
+                format!("{} /item/1 HTTP/1.1\r\nHost: example.com\r\nContent-Length: {}\r\n\r\n", method, body.len())
+                    .as_bytes(),
+                body,
+            ]
+            .concat();
+            let request = read_from_stream(&mut &input[..], &ParseLimits::default())
+                .await
+                .unwrap();
+            assert_eq!(request.method().as_str(), method);
+            assert_eq!(request.body(), body);
+            // 转发给上游服务器的字节与客户端发送的完全相同（除了头名称变成小写）
+            let forwarded = serialize(&request);
+            assert!(forwarded.ends_with(&[&b"\r\n\r\n"[..], body].concat()));
+            assert_eq!(forwarded.len(), input.len());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_write_to_small_socket_buffer() {
+        // 发送缓冲区比请求小得多，每次 write 都只能写入一部分，必须写很多次才能写完
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let socket = tokio::net::TcpSocket::new_v4().unwrap();
+        socket.set_send_buffer_size(4096).unwrap();
+        let mut stream = socket.connect(listener.local_addr().unwrap()).await.unwrap();
+        let (mut peer, _) = listener.accept().await.unwrap();
+
+        let body: Vec<u8> = (0..1_000_000_u32).map(|i| (i % 251) as u8).collect();
+        let request = http::Request::builder()
+            .method("POST")
+            .uri("/upload")
+            .header("Content-Length", body.len())
+            .body(body)
+            .unwrap();
+        let expected = serialize(&request);
+        let reader = tokio::spawn(async move {
+            let mut received = Vec::new();
+            let mut buffer = [0_u8; 1024];
+            loop {
+                // 读得很慢，让发送缓冲区一直是满的
+                tokio::task::yield_now().await;
+                match peer.read(&mut buffer).await.unwrap() {
+                    0 => return received,
+                    n => received.extend_from_slice(&buffer[..n]),
+                }
+            }
+        });
+        write_to_stream(&request, &mut stream).await.unwrap();
+        drop(stream);
+        assert!(reader.await.unwrap() == expected, "the request arrived truncated or corrupted");
+    }
+}
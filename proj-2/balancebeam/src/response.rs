@@ -1,40 +1,23 @@
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
-
-const MAX_HEADERS_SIZE: usize = 8000;
-const MAX_BODY_SIZE: usize = 10000000;
-const MAX_NUM_HEADERS: usize = 32;
-
-#[derive(Debug)]
-pub enum Error {
-    /// 客户端在发送完整请求之前挂断
-    IncompleteResponse,
-    /// 客户端发送了无效的 HTTP 请求。httparse::Error 包含更多详细信息
-    MalformedResponse(httparse::Error),
-    /// Content-Length 头存在，但不包含有效的数字值
-    InvalidContentLength,
-    /// Content-Length 头与发送的请求体大小不匹配
-    ContentLengthMismatch,
-    /// 请求体大于 MAX_BODY_SIZE
-    ResponseBodyTooLarge,
-    /// 读取/写入 TcpStream 时遇到 I/O 错误
-    ConnectionError(std::io::Error),
-}
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::error::{httparse_error_from, ProxyError};
+use crate::headers;
+use crate::limits::ParseLimits;
 
 /// 从提供的响应中提取 Content-Length 头值。如果 Content-Length 存在且有效则返回 Ok(Some(usize))，
-/// 如果 Content-Length 不存在则返回 Ok(None)，如果 Content-Length 存在但无效则返回 Err(Error)。
+/// 如果 Content-Length 不存在则返回 Ok(None)，如果 Content-Length 存在但无效则返回 Err(ProxyError)。
 ///
 /// 您不需要修改此函数。
-fn get_content_length(response: &http::Response<Vec<u8>>) -> Result<Option<usize>, Error> {
+fn get_content_length(response: &http::Response<Vec<u8>>) -> Result<Option<usize>, ProxyError> {
     // 查找 content-length 头
     if let Some(header_value) = response.headers().get("content-length") {
         // 如果存在，将其解析为 usize（如果无法解析则返回 InvalidResponseFormat）
         Ok(Some(
             header_value
                 .to_str()
-                .or(Err(Error::InvalidContentLength))?
+                .or(Err(ProxyError::InvalidContentLength))?
                 .parse::<usize>()
-                .or(Err(Error::InvalidContentLength))?,
+                .or(Err(ProxyError::InvalidContentLength))?,
         ))
     } else {
         // 如果不存在，返回 None
@@ -46,24 +29,33 @@ fn get_content_length(response: &http::Response<Vec<u8>>) -> Result<Option<usize
 ///
 /// * 如果缓冲区中有完整且有效的响应，返回 Ok(Some(http::Request))
 /// * 如果缓冲区中有不完整但到目前为止有效的响应，返回 Ok(None)
-/// * 如果缓冲区中的数据绝对不是有效的 HTTP 响应，返回 Err(Error)
+/// * 如果缓冲区中的数据绝对不是有效的 HTTP 响应，返回 Err(ProxyError)
+///
+/// 最多解析 max_headers 个头；超过时返回 ProxyError::ResponseHeadersTooLarge。
 ///
 /// 您不需要修改此函数。
-fn parse_response(buffer: &[u8]) -> Result<Option<(http::Response<Vec<u8>>, usize)>, Error> {
-    let mut headers = [httparse::EMPTY_HEADER; MAX_NUM_HEADERS];
+fn parse_response(
+    buffer: &[u8],
+    max_headers: usize,
+) -> Result<Option<(http::Response<Vec<u8>>, usize)>, ProxyError> {
+    let mut headers = vec![httparse::EMPTY_HEADER; max_headers];
     let mut resp = httparse::Response::new(&mut headers);
-    let res = resp
-        .parse(buffer)
-        .or_else(|err| Err(Error::MalformedResponse(err)))?;
+    let res = resp.parse(buffer).map_err(|err| match err {
+        httparse::Error::TooManyHeaders => ProxyError::ResponseHeadersTooLarge,
+        err => ProxyError::MalformedResponse(err),
+    })?;
 
     if let httparse::Status::Complete(len) = res {
+        let code = resp
+            .code
+            .ok_or(ProxyError::MalformedResponse(httparse::Error::Status))?;
         let mut response = http::Response::builder()
-            .status(resp.code.unwrap())
-            .version(http::Version::HTTP_11);
-        for header in resp.headers {
-            response = response.header(header.name, header.value);
-        }
-        let response = response.body(Vec::new()).unwrap();
+            .status(code)
+            .version(http::Version::HTTP_11)
+            .body(Vec::new())
+            .map_err(|err| ProxyError::MalformedResponse(httparse_error_from(&err)))?;
+        headers::copy_parsed_headers(resp.headers, response.headers_mut())
+            .map_err(ProxyError::MalformedResponse)?;
         Ok(Some((response, len)))
     } else {
         Ok(None)
@@ -73,29 +65,35 @@ fn parse_response(buffer: &[u8]) -> Result<Option<(http::Response<Vec<u8>>, usiz
 /// 从提供的流中读取 HTTP 响应，等待直到发送完整的头集合。
 /// 此函数只读取响应行和头；随后可以调用 read_body 函数来读取响应体。
 ///
-/// 如果收到有效响应则返回 Ok(http::Response)，否则返回 Error。
+/// 如果收到有效响应则返回 Ok(http::Response)，否则返回 ProxyError。如果头的数量或字节数超过
+/// limits 中的限制，返回 ProxyError::ResponseHeadersTooLarge。
 ///
 /// 您需要在里程碑 2 中修改此函数。
-async fn read_headers(stream: &mut TcpStream) -> Result<http::Response<Vec<u8>>, Error> {
+async fn read_headers(
+    stream: &mut (impl AsyncRead + Unpin),
+    limits: &ParseLimits,
+) -> Result<http::Response<Vec<u8>>, ProxyError> {
     // 尝试从响应中读取头。我们可能不会一次收到所有头
     // （例如，我们可能先收到响应的前几个字节，然后其余部分稍后到达）。
     // 反复尝试解析，直到我们读取到有效的 HTTP 响应
-    let mut response_buffer = [0_u8; MAX_HEADERS_SIZE];
+    let mut response_buffer = vec![0_u8; limits.max_header_bytes];
     let mut bytes_read = 0;
     loop {
         // 从连接中读取字节到缓冲区，从 bytes_read 位置开始
         let new_bytes = stream
             .read(&mut response_buffer[bytes_read..])
             .await
-            .or_else(|err| Err(Error::ConnectionError(err)))?;
+            .or_else(|err| Err(ProxyError::ConnectionError(err)))?;
         if new_bytes == 0 {
             // 我们没能读取到完整的响应
-            return Err(Error::IncompleteResponse);
+            return Err(ProxyError::IncompleteResponse);
         }
         bytes_read += new_bytes;
 
         // 查看我们到目前为止是否已读取到有效响应
-        if let Some((mut response, headers_len)) = parse_response(&response_buffer[..bytes_read])? {
+        if let Some((mut response, headers_len)) =
+            parse_response(&response_buffer[..bytes_read], limits.max_headers)?
+        {
             // 我们已读取了完整的头集合。我们可能还读取了响应体的第一部分；
             // 取出响应缓冲区中剩余的内容，并将其保存为响应体的开始。
             response
@@ -103,6 +101,11 @@ async fn read_headers(stream: &mut TcpStream) -> Result<http::Response<Vec<u8>>,
                 .extend_from_slice(&response_buffer[headers_len..bytes_read]);
             return Ok(response);
         }
+
+        // 缓冲区已满但头仍然不完整
+        if bytes_read == response_buffer.len() {
+            return Err(ProxyError::ResponseHeadersTooLarge);
+        }
     }
 }
 
@@ -110,17 +113,39 @@ async fn read_headers(stream: &mut TcpStream) -> Result<http::Response<Vec<u8>>,
 /// 否则，读取字节直到连接关闭。
 ///
 /// 您需要在里程碑 2 中修改此函数。
-async fn read_body(stream: &mut TcpStream, response: &mut http::Response<Vec<u8>>) -> Result<(), Error> {
+async fn read_body(
+    stream: &mut (impl AsyncRead + Unpin),
+    response: &mut http::Response<Vec<u8>>,
+    max_body_size: usize,
+    buffer_size: usize,
+) -> Result<(), ProxyError> {
     // 响应可能提供也可能不提供 Content-Length 头。如果提供了该头，则我们
     // 要读取相应字节数；如果没有提供，我们要持续读取字节直到连接关闭。
+    // 206（部分内容）的 Content-Length 是这一段的长度而不是整个资源的长度，所以不需要特殊处理。
     let content_length = get_content_length(response)?;
+    // 如果服务器事先声明的长度就超过了限制，不需要读取响应体
+    if matches!(content_length, Some(len) if len > max_body_size) {
+        return Err(ProxyError::ResponseBodyTooLarge);
+    }
+    // read_headers 顺便读入的字节可能已经超过了 Content-Length（例如服务器回复 206 时声明的是这一段的
+    // 长度，发送的却是整个资源），与下面的循环中多读到字节一样处理
+    if matches!(content_length, Some(len) if response.body().len() > len) {
+        return Err(ProxyError::ContentLengthMismatch);
+    }
 
+    // 长度已知时一次分配好响应体需要的空间，避免追加时反复扩容
+    if let Some(len) = content_length {
+        let additional = len - response.body().len();
+        response.body_mut().reserve(additional);
+    }
+    // 缓冲区越大，读取大的响应体需要的 read 系统调用越少。每次 read 都不限制在剩余的长度以内，
+    // 这样服务器多发送的字节也能被读到，从而检查出 Content-Length 不一致
+    let mut buffer = vec![0_u8; buffer_size];
     while content_length.is_none() || response.body().len() < content_length.unwrap() {
-        let mut buffer = [0_u8; 512];
         let bytes_read = stream
             .read(&mut buffer)
             .await
-            .or_else(|err| Err(Error::ConnectionError(err)))?;
+            .or_else(|err| Err(ProxyError::ConnectionError(err)))?;
         if bytes_read == 0 {
             // 服务器已挂断！
             if content_length.is_none() {
@@ -128,19 +153,19 @@ async fn read_body(stream: &mut TcpStream, response: &mut http::Response<Vec<u8>
                 break;
             } else {
                 // Content-Length 已设置，但服务器在我们读取相应字节数之前挂断了
-                return Err(Error::ContentLengthMismatch);
+                return Err(ProxyError::ContentLengthMismatch);
             }
         }
 
         // 确保服务器发送的字节数不超过它承诺发送的字节数
         if content_length.is_some() && response.body().len() + bytes_read > content_length.unwrap()
         {
-            return Err(Error::ContentLengthMismatch);
+            return Err(ProxyError::ContentLengthMismatch);
         }
 
         // 确保服务器发送的字节数不超过我们允许的字节数
-        if response.body().len() + bytes_read > MAX_BODY_SIZE {
-            return Err(Error::ResponseBodyTooLarge);
+        if response.body().len() + bytes_read > max_body_size {
+            return Err(ProxyError::ResponseBodyTooLarge);
         }
 
         // 将接收到的字节追加到响应体
@@ -149,45 +174,164 @@ async fn read_body(stream: &mut TcpStream, response: &mut http::Response<Vec<u8>
     Ok(())
 }
 
-/// 此函数从流中读取并返回 HTTP 响应，如果服务器过早关闭连接或发送无效响应则返回 Error。
+/// 只要响应不是对 HEAD 请求的响应，并且响应状态码不是 1xx、204（无内容）或 304（未修改），
+/// 响应就可能有响应体。
+fn may_have_body(response: &http::Response<Vec<u8>>, request_method: &http::Method) -> bool {
+    !(request_method == http::Method::HEAD
+        || response.status().as_u16() < 200
+        || response.status() == http::StatusCode::NO_CONTENT
+        || response.status() == http::StatusCode::NOT_MODIFIED)
+}
+
+/// 如果响应体使用分块编码（Transfer-Encoding 的最后一个编码是 chunked），返回 true
+fn is_chunked(response: &http::Response<Vec<u8>>) -> bool {
+    response
+        .headers()
+        .get_all("transfer-encoding")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .last()
+        .is_some_and(|coding| coding.trim().eq_ignore_ascii_case("chunked"))
+}
+
+/// 响应体的结尾不依赖于服务器关闭连接（没有响应体、分块编码或者有 Content-Length），读完这个响应之后
+/// 连接还可以继续使用
+pub fn is_delimited(response: &http::Response<Vec<u8>>, request_method: &http::Method) -> bool {
+    !may_have_body(response, request_method)
+        || is_chunked(response)
+        || response.headers().contains_key("content-length")
+}
+
+/// 此函数从流中读取并返回 HTTP 响应，如果服务器过早关闭连接或发送无效响应则返回 ProxyError。
 ///
 /// 您需要在里程碑 2 中修改此函数。
 pub async fn read_from_stream(
-    stream: &mut TcpStream,
+    stream: &mut (impl AsyncRead + Unpin),
     request_method: &http::Method,
-) -> Result<http::Response<Vec<u8>>, Error> {
-    let mut response = read_headers(stream).await?;
-    // 只要响应不是对 HEAD 请求的响应，并且响应状态码不是 1xx、204（无内容）或 304（未修改），
-    // 响应就可能有响应体。
-    if !(request_method == http::Method::HEAD
-        || response.status().as_u16() < 200
-        || response.status() == http::StatusCode::NO_CONTENT
-        || response.status() == http::StatusCode::NOT_MODIFIED)
-    {
-        read_body(stream, &mut response).await?;
+    limits: &ParseLimits,
+) -> Result<http::Response<Vec<u8>>, ProxyError> {
+    let response = read_headers(stream, limits).await?;
+    read_remaining(stream, response, request_method, limits).await
+}
+
+/// read_head_from_stream 返回时响应体的状态。除了 Buffered 以外，响应体中只有读取响应头时顺便读入的
+/// 原始数据，剩下的响应体应该边读边转发给客户端
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodyKind {
+    /// 响应体已经完整读入
+    Buffered,
+    /// 分块编码，用 chunked::relay 转发
+    Chunked,
+    /// Content-Length 不小于 stream_threshold，用 stream::relay_length 转发这么多字节
+    Length(usize),
+    /// 没有 Content-Length，响应体一直到连接关闭为止，用 stream::relay_until_close 转发
+    UntilClose,
+}
+
+/// 与 read_from_stream 相同，但是不读取分块编码的响应体。分块编码优先于 Content-Length，所以这时会删除
+/// Content-Length 头。
+///
+/// 如果设置了 stream_threshold，Content-Length 不小于它的响应体和长度未知的响应体也不读取。
+/// 声明的 Content-Length 超过 max_response_body 时仍然返回 ResponseBodyTooLarge。
+pub async fn read_head_from_stream(
+    stream: &mut (impl AsyncRead + Unpin),
+    request_method: &http::Method,
+    limits: &ParseLimits,
+    stream_threshold: Option<usize>,
+) -> Result<(http::Response<Vec<u8>>, BodyKind), ProxyError> {
+    let mut response = read_headers(stream, limits).await?;
+    if may_have_body(&response, request_method) {
+        if is_chunked(&response) {
+            response.headers_mut().remove("content-length");
+            return Ok((response, BodyKind::Chunked));
+        }
+        if let Some(threshold) = stream_threshold {
+            match get_content_length(&response)? {
+                Some(len) if len > limits.max_response_body => {
+                    return Err(ProxyError::ResponseBodyTooLarge)
+                }
+                Some(len) if len >= threshold => return Ok((response, BodyKind::Length(len))),
+                Some(_) => {}
+                None => return Ok((response, BodyKind::UntilClose)),
+            }
+        }
+    }
+    Ok((
+        read_remaining(stream, response, request_method, limits).await?,
+        BodyKind::Buffered,
+    ))
+}
+
+/// 读取响应头之后的响应体（如果这个响应可能有响应体的话）
+async fn read_remaining(
+    stream: &mut (impl AsyncRead + Unpin),
+    mut response: http::Response<Vec<u8>>,
+    request_method: &http::Method,
+    limits: &ParseLimits,
+) -> Result<http::Response<Vec<u8>>, ProxyError> {
+    if may_have_body(&response, request_method) {
+        read_body(
+            stream,
+            &mut response,
+            limits.max_response_body,
+            limits.read_buffer_size,
+        )
+        .await?;
+    } else {
+        // read_headers 可能已经把头后面的字节当作响应体的开始读了进来。这种响应不应该有响应体，
+        // 所以服务器（错误地）发送的任何字节都要丢弃
+        response.body_mut().clear();
     }
     Ok(response)
 }
 
+/// 将响应行和头序列化为字节：响应行，按 headers() 的顺序每个头一行，然后是结束的空行
+pub fn serialize_head(response: &http::Response<Vec<u8>>) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(headers::head_capacity(response.headers()));
+    write_head(&mut bytes, response);
+    bytes
+}
+
+/// 将整个响应序列化为字节：serialize_head 的结果后面跟着原样的响应体
+pub fn serialize(response: &http::Response<Vec<u8>>) -> Vec<u8> {
+    // 一开始就为响应体预留空间，避免追加响应体时再复制一次响应头
+    let mut bytes = Vec::with_capacity(headers::head_capacity(response.headers()) + response.body().len());
+    write_head(&mut bytes, response);
+    bytes.extend_from_slice(response.body());
+    bytes
+}
+
+/// 写入状态行（格式与 format_response_line 相同）和所有头
+fn write_head(bytes: &mut Vec<u8>, response: &http::Response<Vec<u8>>) {
+    headers::write_head(
+        bytes,
+        format_args!(
+            "{:?} {} {}",
+            response.version(),
+            response.status().as_str(),
+            response.status().canonical_reason().unwrap_or("")
+        ),
+        response.headers(),
+    );
+}
+
 /// 此函数将响应序列化为字节并将这些字节写入提供的流。
 ///
 /// 您需要在里程碑 2 中修改此函数。
 pub async fn write_to_stream(
     response: &http::Response<Vec<u8>>,
-    stream: &mut TcpStream,
+    stream: &mut (impl AsyncWrite + Unpin),
 ) -> Result<(), std::io::Error> {
-    stream.write_all(&format_response_line(response).into_bytes()).await?;
-    stream.write_all(&['\r' as u8, '\n' as u8]).await?; // \r\n
-    for (header_name, header_value) in response.headers() {
-        stream.write_all(&format!("{}: ", header_name).as_bytes()).await?;
-        stream.write_all(header_value.as_bytes()).await?;
-        stream.write_all(&['\r' as u8, '\n' as u8]).await?; // \r\n
-    }
-    stream.write_all(&['\r' as u8, '\n' as u8]).await?;
-    if response.body().len() > 0 {
-        stream.write_all(response.body()).await?;
-    }
-    Ok(())
+    stream.write_all(&serialize(response)).await
+}
+
+/// 只写入响应行和头（包括结束的空行），不写入响应体
+pub async fn write_head_to_stream(
+    response: &http::Response<Vec<u8>>,
+    stream: &mut (impl AsyncWrite + Unpin),
+) -> Result<(), std::io::Error> {
+    stream.write_all(&serialize_head(response)).await
 }
 
 pub fn format_response_line(response: &http::Response<Vec<u8>>) -> String {
@@ -199,14 +343,48 @@ pub fn format_response_line(response: &http::Response<Vec<u8>>) -> String {
     )
 }
 
+/// 告诉客户端发送这个响应之后我们会关闭连接
+pub fn set_connection_close(response: &mut http::Response<Vec<u8>>) {
+    response
+        .headers_mut()
+        .insert(http::header::CONNECTION, http::HeaderValue::from_static("close"));
+}
+
+/// 对 HEAD 请求的响应绝不能包含响应体，但要保留 Content-Length 头（它描述的是对应 GET 请求的
+/// 响应体大小）。在将响应发送给客户端之前调用此函数。
+pub fn strip_body_for_head(response: &mut http::Response<Vec<u8>>, request_method: &http::Method) {
+    if request_method == http::Method::HEAD {
+        response.body_mut().clear();
+    }
+}
+
 /// 这是一个辅助函数，创建包含可以发送给客户端的 HTTP 错误的 http::Response。
 pub fn make_http_error(status: http::StatusCode) -> http::Response<Vec<u8>> {
-    let body = format!(
-        "HTTP {} {}",
-        status.as_u16(),
-        status.canonical_reason().unwrap_or("")
-    )
-    .into_bytes();
+    make_http_error_with_headers(status, Vec::new())
+}
+
+/// 与 make_http_error 相同，但是额外加上 headers 中的头（例如 429 响应的 Retry-After）
+pub fn make_http_error_with_headers(
+    status: http::StatusCode,
+    headers: Vec<(http::HeaderName, http::HeaderValue)>,
+) -> http::Response<Vec<u8>> {
+    let mut response = make_text_response(
+        status,
+        format!(
+            "HTTP {} {}",
+            status.as_u16(),
+            status.canonical_reason().unwrap_or("")
+        ),
+    );
+    for (name, value) in headers {
+        response.headers_mut().insert(name, value);
+    }
+    response
+}
+
+/// 这是一个辅助函数，创建一个带有纯文本响应体的 http::Response。
+pub fn make_text_response(status: http::StatusCode, body: String) -> http::Response<Vec<u8>> {
+    let body = body.into_bytes();
     http::Response::builder()
         .status(status)
         .header("Content-Type", "text/plain")
@@ -215,3 +393,431 @@ pub fn make_http_error(status: http::StatusCode) -> http::Response<Vec<u8>> {
         .body(body)
         .unwrap()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limits::{DEFAULT_MAX_HEADER_BYTES, DEFAULT_READ_BUFFER_SIZE};
+
+    /// 解析一段输入，期望得到 MalformedResponse 错误
+    fn assert_malformed(input: &[u8]) {
+        let result = parse_response(input, 32);
+        assert!(
+            matches!(result, Err(ProxyError::MalformedResponse(_))),
+            "expected MalformedResponse for {:?}, got {:?}",
+            String::from_utf8_lossy(input),
+            result
+        );
+    }
+
+    /// 解析一段完整且合法的响应头
+    fn parse_complete(input: &[u8]) -> http::Response<Vec<u8>> {
+        match parse_response(input, 32) {
+            Ok(Some((response, _))) => response,
+            other => panic!("expected a complete response, got {:?}", other),
+        }
+    }
+
+    /// 减少内存分配之前的 parse_response（通过 Builder 逐个添加头）和 serialize（先用 format! 生成状态行，
+    /// 追加响应体时再扩容），用来确认优化之后的结果不变，并比较两者的性能
+    fn parse_unoptimized(input: &[u8]) -> http::Response<Vec<u8>> {
+        let mut headers = vec![httparse::EMPTY_HEADER; 32];
+        let mut resp = httparse::Response::new(&mut headers);
+        resp.parse(input).unwrap();
+        let mut response = http::Response::builder()
+            .status(resp.code.unwrap())
+            .version(http::Version::HTTP_11);
+        for header in resp.headers {
+            response = response.header(header.name, header.value);
+        }
+        response.body(Vec::new()).unwrap()
+    }
+
+    fn serialize_unoptimized(response: &http::Response<Vec<u8>>) -> Vec<u8> {
+        let mut bytes = format_response_line(response).into_bytes();
+        bytes.extend_from_slice(b"\r\n");
+        for (header_name, header_value) in response.headers() {
+            bytes.extend_from_slice(header_name.as_str().as_bytes());
+            bytes.extend_from_slice(b": ");
+            bytes.extend_from_slice(header_value.as_bytes());
+            bytes.extend_from_slice(b"\r\n");
+        }
+        bytes.extend_from_slice(b"\r\n");
+        bytes.extend_from_slice(response.body());
+        bytes
+    }
+
+    /// 测试和性能测试共用的响应头：重复的头、自定义的头、没有标准原因短语的状态码
+    fn sample_responses() -> Vec<&'static [u8]> {
+        vec![
+            b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n",
+            b"HTTP/1.1 404 Not Found\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: 9\r\n\
+              Set-Cookie: a=1; Path=/\r\nSet-Cookie: b=2; HttpOnly\r\nServer: nginx/1.25\r\n\
+              Cache-Control: no-cache\r\nX-Request-Id: 0123456789abcdef\r\n\r\n",
+            b"HTTP/1.1 599 Whatever\r\nX-Custom: \xe9t\xe9\r\n\r\n",
+        ]
+    }
+
+    #[test]
+    fn test_parse_and_serialize_match_unoptimized() {
+        for input in sample_responses() {
+            let mut response = parse_complete(input);
+            let mut unoptimized = parse_unoptimized(input);
+            assert_eq!(response.status(), unoptimized.status());
+            assert_eq!(response.headers(), unoptimized.headers());
+            assert_eq!(serialize(&response), serialize_unoptimized(&unoptimized));
+
+            *response.body_mut() = b"not found".to_vec();
+            *unoptimized.body_mut() = b"not found".to_vec();
+            assert_eq!(serialize(&response), serialize_unoptimized(&unoptimized));
+            assert!(serialize(&response).starts_with(&serialize_head(&response)));
+        }
+    }
+
+    /// 比较优化前后解析和序列化响应的耗时。用 cargo test --release -- --ignored --nocapture 运行
+    #[test]
+    #[ignore]
+    fn bench_parse_and_serialize() {
+        let inputs = sample_responses();
+        let body = vec![b'x'; 4096];
+        let iterations = 200_000;
+        let time = |label: &str, round_trip: &dyn Fn(&[u8]) -> Vec<u8>| {
+            let start = std::time::Instant::now();
+            for _ in 0..iterations {
+                for input in &inputs {
+                    std::hint::black_box(round_trip(std::hint::black_box(input)));
+                }
+            }
+            println!("{}: {:?} per response", label, start.elapsed() / (iterations * inputs.len() as u32));
+        };
+        time("before", &|input| {
+            let mut response = parse_unoptimized(input);
+            *response.body_mut() = body.clone();
+            serialize_unoptimized(&response)
+        });
+        time("after", &|input| {
+            let mut response = parse_complete(input);
+            *response.body_mut() = body.clone();
+            serialize(&response)
+        });
+    }
+
+    #[test]
+    fn test_valid_response() {
+        let response = parse_complete(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n");
+        assert_eq!(response.status(), http::StatusCode::NOT_FOUND);
+        assert_eq!(response.headers()["content-length"], "0");
+    }
+
+    #[test]
+    fn test_embedded_nul() {
+        assert_malformed(b"HTTP/1.1 200 O\0K\r\n\r\n");
+        assert_malformed(b"HTTP/1.1 200 OK\r\nServer: a\0b\r\n\r\n");
+    }
+
+    #[test]
+    fn test_cr_without_lf() {
+        assert_malformed(b"HTTP/1.1 200 OK\rServer: x\r\n\r\n");
+    }
+
+    #[test]
+    fn test_header_without_colon() {
+        assert_malformed(b"HTTP/1.1 200 OK\r\nNoColonHere\r\n\r\n");
+    }
+
+    #[test]
+    fn test_bad_status_code() {
+        assert_malformed(b"HTTP/1.1 2x0 OK\r\n\r\n");
+        assert_malformed(b"HTTP/1.1 2000 OK\r\n\r\n");
+    }
+
+    #[test]
+    fn test_status_code_rejected_by_http_crate() {
+        // httparse 接受任意三位数字，但 http::StatusCode 要求 100-999
+        assert_malformed(b"HTTP/1.1 000 Zero\r\n\r\n");
+        assert_malformed(b"HTTP/1.1 099 Low\r\n\r\n");
+    }
+
+    #[test]
+    fn test_invalid_header_value() {
+        assert_malformed(b"HTTP/1.1 200 OK\r\nX-Delete: a\x7fb\r\n\r\n");
+    }
+
+    #[test]
+    fn test_mutated_responses_never_panic() {
+        // 与 request.rs 中的测试相同：对合法响应做确定性的随机变异，确保解析器不会 panic
+        let valid = b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 5\r\n\r\nhello";
+        let mut seed: u64 = 0x5eed;
+        let mut next = || {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (seed >> 33) as usize
+        };
+        for _ in 0..5000 {
+            let mut input = valid.to_vec();
+            for _ in 0..(next() % 4 + 1) {
+                let idx = next() % input.len();
+                input[idx] = next() as u8;
+            }
+            input.truncate(next() % (input.len() + 1));
+            let _ = parse_response(&input, 32);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_read_head_stops_at_chunked_body() {
+        let input = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: gzip, chunked\r\nContent-Length: 4\r\n\r\n4\r\nab";
+        let (response, kind) =
+            read_head_from_stream(&mut &input[..], &http::Method::GET, &ParseLimits::default(), None)
+                .await
+                .unwrap();
+        assert_eq!(kind, BodyKind::Chunked);
+        assert_eq!(response.body(), b"4\r\nab");
+        assert!(response.headers().get("content-length").is_none());
+
+        // 对 HEAD 请求的响应没有响应体；chunked 不是最后一个编码时按原来的方式读到连接关闭
+        let input = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n";
+        let (_, kind) =
+            read_head_from_stream(&mut &input[..], &http::Method::HEAD, &ParseLimits::default(), None)
+                .await
+                .unwrap();
+        assert_eq!(kind, BodyKind::Buffered);
+        let input = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked, gzip\r\n\r\nabc";
+        let (response, kind) =
+            read_head_from_stream(&mut &input[..], &http::Method::GET, &ParseLimits::default(), None)
+                .await
+                .unwrap();
+        assert_eq!(kind, BodyKind::Buffered);
+        assert_eq!(response.body(), b"abc");
+    }
+
+    #[tokio::test]
+    async fn test_read_partial_content() {
+        // 206 的响应体按它自己的 Content-Length 读取，而不是 Content-Range 中整个资源的长度
+        let input = b"HTTP/1.1 206 Partial Content\r\nContent-Range: bytes 0-4/11\r\n\
+            Accept-Ranges: bytes\r\nContent-Length: 5\r\n\r\nhello";
+        let response = read_from_stream(&mut &input[..], &http::Method::GET, &ParseLimits::default())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.headers()["content-range"], "bytes 0-4/11");
+        assert_eq!(response.headers()["accept-ranges"], "bytes");
+        assert_eq!(response.body(), b"hello");
+
+        // 和响应头一起读入的字节比 Content-Length 多
+        let input = b"HTTP/1.1 206 Partial Content\r\nContent-Range: bytes 0-4/11\r\n\
+            Content-Length: 5\r\n\r\nhello world";
+        let result = read_from_stream(&mut &input[..], &http::Method::GET, &ParseLimits::default()).await;
+        assert!(matches!(result, Err(ProxyError::ContentLengthMismatch)));
+    }
+
+    /// 统计 read 被调用了多少次的流
+    struct CountingReader<R> {
+        inner: R,
+        reads: usize,
+    }
+
+    impl<R: AsyncRead + Unpin> AsyncRead for CountingReader<R> {
+        fn poll_read(
+            mut self: std::pin::Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+            buf: &mut tokio::io::ReadBuf<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            self.reads += 1;
+            std::pin::Pin::new(&mut self.inner).poll_read(cx, buf)
+        }
+    }
+
+    /// 一个带 Content-Length（或者不带，响应体一直到连接关闭为止）的响应，响应体是 len 个不重复的字节
+    fn large_response(len: usize, content_length: bool) -> (Vec<u8>, Vec<u8>) {
+        let body: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+        let mut input = b"HTTP/1.1 200 OK\r\n".to_vec();
+        if content_length {
+            input.extend_from_slice(format!("Content-Length: {}\r\n", len).as_bytes());
+        }
+        input.extend_from_slice(b"\r\n");
+        input.extend_from_slice(&body);
+        (input, body)
+    }
+
+    /// 用 buffer_size 大小的缓冲区读取响应，返回响应体和 read 的调用次数
+    async fn read_with_buffer(input: &[u8], buffer_size: usize) -> Result<(Vec<u8>, usize), ProxyError> {
+        let limits = ParseLimits {
+            read_buffer_size: buffer_size,
+            ..ParseLimits::default()
+        };
+        let mut stream = CountingReader { inner: input, reads: 0 };
+        let response = read_from_stream(&mut stream, &http::Method::GET, &limits).await?;
+        Ok((response.into_body(), stream.reads))
+    }
+
+    #[tokio::test]
+    async fn test_read_buffer_size() {
+        let len = 1 << 20;
+        for content_length in [true, false] {
+            let (input, body) = large_response(len, content_length);
+            for buffer_size in [1, 7, 512, DEFAULT_READ_BUFFER_SIZE] {
+                if buffer_size == 1 && !content_length {
+                    continue;
+                }
+                let (read, _) = read_with_buffer(&input, buffer_size).await.unwrap();
+                assert!(read == body, "buffer size {} changed the body", buffer_size);
+            }
+        }
+
+        // 缓冲区越大，read 调用越少：读取响应头的那一次之后，每次最多读取 read_buffer_size 字节
+        let (input, _) = large_response(len, true);
+        let (_, small_reads) = read_with_buffer(&input, 512).await.unwrap();
+        let (_, large_reads) = read_with_buffer(&input, DEFAULT_READ_BUFFER_SIZE).await.unwrap();
+        assert!(small_reads > (len - DEFAULT_MAX_HEADER_BYTES) / 512, "{}", small_reads);
+        assert!(large_reads <= 1 + len.div_ceil(DEFAULT_READ_BUFFER_SIZE), "{}", large_reads);
+
+        // 大缓冲区一次读到的字节可能超过 Content-Length，仍然要检查出来
+        let mut longer = input.clone();
+        longer.extend_from_slice(b"extra");
+        let result = read_with_buffer(&longer, DEFAULT_READ_BUFFER_SIZE).await;
+        assert!(matches!(result, Err(ProxyError::ContentLengthMismatch)));
+        let result = read_with_buffer(&input[..input.len() - 1], DEFAULT_READ_BUFFER_SIZE).await;
+        assert!(matches!(result, Err(ProxyError::ContentLengthMismatch)));
+    }
+
+    /// 比较用 512 字节和默认大小的缓冲区通过 TCP 连接读取 8MB 响应体的 read 次数和耗时。
+    /// 用 cargo test --release -- --ignored --nocapture 运行
+    #[tokio::test]
+    #[ignore]
+    async fn bench_read_buffer_size() {
+        let (input, body) = large_response(8 << 20, true);
+        let input = std::sync::Arc::new(input);
+        for buffer_size in [512, DEFAULT_READ_BUFFER_SIZE] {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let address = listener.local_addr().unwrap();
+            let writer_input = std::sync::Arc::clone(&input);
+            let writer = tokio::spawn(async move {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                stream.write_all(&writer_input).await.unwrap();
+            });
+            let limits = ParseLimits {
+                max_response_body: body.len(),
+                read_buffer_size: buffer_size,
+                ..ParseLimits::default()
+            };
+            let mut stream = CountingReader {
+                inner: tokio::net::TcpStream::connect(address).await.unwrap(),
+                reads: 0,
+            };
+            let start = std::time::Instant::now();
+            let response = read_from_stream(&mut stream, &http::Method::GET, &limits).await.unwrap();
+            let elapsed = start.elapsed();
+            writer.await.unwrap();
+            assert!(response.body() == &body);
+            println!(
+                "buffer {} bytes: {} reads, {:?} ({:.0} MB/s)",
+                buffer_size,
+                stream.reads,
+                elapsed,
+                body.len() as f64 / elapsed.as_secs_f64() / 1e6
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_read_head_stream_threshold() {
+        async fn read_kind(input: &[u8]) -> Result<(Vec<u8>, BodyKind), ProxyError> {
+            let limits = ParseLimits {
+                max_response_body: 100,
+                ..ParseLimits::default()
+            };
+            let (response, kind) =
+                read_head_from_stream(&mut &input[..], &http::Method::GET, &limits, Some(10)).await?;
+            Ok((response.into_body(), kind))
+        }
+        // 小于阈值的响应体照常读入
+        let (body, kind) = read_kind(b"HTTP/1.1 200 OK\r\nContent-Length: 9\r\n\r\n123456789")
+            .await
+            .unwrap();
+        assert_eq!(kind, BodyKind::Buffered);
+        assert_eq!(body, b"123456789");
+        // 达到阈值时只读取响应头
+        let (body, kind) = read_kind(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n1234567890")
+            .await
+            .unwrap();
+        assert_eq!(kind, BodyKind::Length(10));
+        assert_eq!(body, b"1234567890");
+        let (_, kind) = read_kind(b"HTTP/1.1 200 OK\r\n\r\nabc").await.unwrap();
+        assert_eq!(kind, BodyKind::UntilClose);
+        // 流式转发也不允许超过 max_response_body 的响应体
+        assert!(matches!(
+            read_kind(b"HTTP/1.1 200 OK\r\nContent-Length: 101\r\n\r\n").await,
+            Err(ProxyError::ResponseBodyTooLarge)
+        ));
+    }
+
+    #[test]
+    fn test_is_delimited() {
+        let response = |status: u16, header: Option<(&str, &str)>| {
+            let mut builder = http::Response::builder().status(status);
+            if let Some((name, value)) = header {
+                builder = builder.header(name, value);
+            }
+            builder.body(Vec::new()).unwrap()
+        };
+        let get = &http::Method::GET;
+        assert!(is_delimited(&response(200, Some(("Content-Length", "3"))), get));
+        assert!(is_delimited(&response(200, Some(("Transfer-Encoding", "chunked"))), get));
+        assert!(is_delimited(&response(204, None), get));
+        assert!(is_delimited(&response(200, None), &http::Method::HEAD));
+        // 响应体一直到连接关闭为止
+        assert!(!is_delimited(&response(200, None), get));
+    }
+
+    #[test]
+    fn test_serialize_response_with_multiple_headers() {
+        let response = http::Response::builder()
+            .status(404)
+            .header("Content-Type", "text/plain")
+            .header("Set-Cookie", "a=1")
+            .header("Set-Cookie", "b=2")
+            .header("Content-Length", "9")
+            .body(b"not found".to_vec())
+            .unwrap();
+        let head = b"HTTP/1.1 404 Not Found\r\ncontent-type: text/plain\r\nset-cookie: a=1\r\n\
+            set-cookie: b=2\r\ncontent-length: 9\r\n\r\n";
+        assert_eq!(serialize_head(&response), head);
+        assert_eq!(serialize(&response), [&head[..], b"not found"].concat());
+        // 序列化的结果可以被原样解析回来
+        let (parsed, len) = parse_response(&serialize(&response), 32).unwrap().unwrap();
+        assert_eq!(len, head.len());
+        assert_eq!(parsed.headers(), response.headers());
+    }
+
+    #[test]
+    fn test_make_http_error_with_headers() {
+        let response = make_http_error_with_headers(
+            http::StatusCode::TOO_MANY_REQUESTS,
+            vec![(http::header::RETRY_AFTER, http::HeaderValue::from(42))],
+        );
+        assert_eq!(response.status(), http::StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()["retry-after"], "42");
+        assert_eq!(response.body(), b"HTTP 429 Too Many Requests");
+        assert_eq!(response.headers()["content-length"], "26");
+    }
+
+    #[test]
+    fn test_negative_content_length() {
+        let response = parse_complete(b"HTTP/1.1 200 OK\r\nContent-Length: -5\r\n\r\n");
+        assert!(matches!(
+            get_content_length(&response),
+            Err(ProxyError::InvalidContentLength)
+        ));
+    }
+
+    #[test]
+    fn test_gigantic_content_length() {
+        let response = parse_complete(
+            b"HTTP/1.1 200 OK\r\nContent-Length: 99999999999999999999999999\r\n\r\n",
+        );
+        assert!(matches!(
+            get_content_length(&response),
+            Err(ProxyError::InvalidContentLength)
+        ));
+    }
+}
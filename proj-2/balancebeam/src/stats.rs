@@ -0,0 +1,194 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// 某个上游服务器返回的响应状态码分布，按 2xx/3xx/4xx/5xx 分类统计。
+/// 使用原子计数器，这样多个连接任务可以同时更新而不需要加锁。
+#[derive(Debug, Default)]
+pub struct StatusCounts {
+    /// classes[0] 是 2xx 的数量，classes[1] 是 3xx，依此类推
+    classes: [AtomicUsize; 4],
+    /// 连续收到 5xx 响应的次数，收到其他响应时清零
+    consecutive_server_errors: AtomicUsize,
+}
+
+impl StatusCounts {
+    /// 记录一个从上游服务器收到的响应状态码。不在 2xx-5xx 范围内的状态码不计入分布。
+    /// 返回该上游服务器当前连续返回 5xx 的次数（收到非 5xx 响应后为 0）。
+    pub fn record(&self, status: http::StatusCode) -> usize {
+        let class = status.as_u16() / 100;
+        if let Some(counter) = self.counter_for_class(class) {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+        if class == 5 {
+            self.consecutive_server_errors.fetch_add(1, Ordering::Relaxed) + 1
+        } else {
+            self.consecutive_server_errors.store(0, Ordering::Relaxed);
+            0
+        }
+    }
+
+    /// 将连续 5xx 次数清零（例如上游服务器被摘除之后）
+    pub fn reset_consecutive_server_errors(&self) {
+        self.consecutive_server_errors.store(0, Ordering::Relaxed);
+    }
+
+    /// 返回某一类状态码（2 表示 2xx，5 表示 5xx，等等）的响应数量
+    pub fn count(&self, class: u16) -> usize {
+        self.counter_for_class(class)
+            .map_or(0, |counter| counter.load(Ordering::Relaxed))
+    }
+
+    fn counter_for_class(&self, class: u16) -> Option<&AtomicUsize> {
+        match class {
+            2..=5 => Some(&self.classes[(class - 2) as usize]),
+            _ => None,
+        }
+    }
+}
+
+/// 某个上游服务器的连接失败次数、读取响应超时次数和最近一次错误，用来查看上游服务器为什么被标记为失败
+#[derive(Debug, Default)]
+pub struct UpstreamErrors {
+    connect_failures: AtomicUsize,
+    read_timeouts: AtomicUsize,
+    /// 最近一次错误（包括发送请求和读取响应时的其他错误）的描述，还没有出过错时为 None
+    last_error: Mutex<Option<String>>,
+}
+
+impl UpstreamErrors {
+    /// 记录一次连接失败（包括连接超时）
+    pub fn record_connect_failure(&self, error: String) {
+        self.connect_failures.fetch_add(1, Ordering::Relaxed);
+        self.record_error(error);
+    }
+
+    /// 记录一次读取响应超时
+    pub fn record_read_timeout(&self, error: String) {
+        self.read_timeouts.fetch_add(1, Ordering::Relaxed);
+        self.record_error(error);
+    }
+
+    /// 只更新最近一次错误，不计数
+    pub fn record_error(&self, error: String) {
+        *self.last_error.lock().unwrap() = Some(error);
+    }
+
+    pub fn connect_failures(&self) -> usize {
+        self.connect_failures.load(Ordering::Relaxed)
+    }
+
+    pub fn read_timeouts(&self) -> usize {
+        self.read_timeouts.load(Ordering::Relaxed)
+    }
+
+    pub fn last_error(&self) -> Option<String> {
+        self.last_error.lock().unwrap().clone()
+    }
+
+    /// 格式化为 `connect_failures=1 read_timeouts=0 last_error="..."`（没有出过错时 last_error=-）
+    pub fn summary(&self) -> String {
+        format!(
+            "connect_failures={} read_timeouts={} last_error={}",
+            self.connect_failures(),
+            self.read_timeouts(),
+            self.last_error().map_or(String::from("-"), |error| format!("{:?}", error))
+        )
+    }
+}
+
+/// 将每个上游服务器的错误统计格式化为纯文本，每行一个上游服务器
+pub fn render_upstream_errors(upstream_addresses: &[String], errors: &[Arc<UpstreamErrors>]) -> String {
+    let mut output = String::new();
+    for (address, errors) in upstream_addresses.iter().zip(errors) {
+        output += &format!("{} {}\n", address, errors.summary());
+    }
+    output
+}
+
+/// 将每个上游服务器的状态码分布格式化为纯文本，每行一个上游服务器
+pub fn render_status_counts(upstream_addresses: &[String], counts: &[Arc<StatusCounts>]) -> String {
+    let mut output = String::new();
+    for (address, counts) in upstream_addresses.iter().zip(counts) {
+        output += &format!(
+            "{} 2xx={} 3xx={} 4xx={} 5xx={}\n",
+            address,
+            counts.count(2),
+            counts.count(3),
+            counts.count(4),
+            counts.count(5)
+        );
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_status_classes() {
+        let counts = StatusCounts::default();
+        counts.record(http::StatusCode::OK);
+        counts.record(http::StatusCode::CREATED);
+        counts.record(http::StatusCode::MOVED_PERMANENTLY);
+        counts.record(http::StatusCode::NOT_FOUND);
+        counts.record(http::StatusCode::INTERNAL_SERVER_ERROR);
+        counts.record(http::StatusCode::BAD_GATEWAY);
+        counts.record(http::StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(counts.count(2), 2);
+        assert_eq!(counts.count(3), 1);
+        assert_eq!(counts.count(4), 1);
+        assert_eq!(counts.count(5), 3);
+    }
+
+    #[test]
+    fn test_informational_statuses_ignored() {
+        let counts = StatusCounts::default();
+        counts.record(http::StatusCode::CONTINUE);
+        assert_eq!(counts.count(1), 0);
+        for class in 2..=5 {
+            assert_eq!(counts.count(class), 0);
+        }
+    }
+
+    #[test]
+    fn test_consecutive_server_errors() {
+        let counts = StatusCounts::default();
+        assert_eq!(counts.record(http::StatusCode::INTERNAL_SERVER_ERROR), 1);
+        assert_eq!(counts.record(http::StatusCode::BAD_GATEWAY), 2);
+        assert_eq!(counts.record(http::StatusCode::OK), 0);
+        assert_eq!(counts.record(http::StatusCode::INTERNAL_SERVER_ERROR), 1);
+        counts.reset_consecutive_server_errors();
+        assert_eq!(counts.record(http::StatusCode::INTERNAL_SERVER_ERROR), 1);
+        assert_eq!(counts.count(5), 4);
+    }
+
+    #[test]
+    fn test_render_status_counts() {
+        let addresses = vec![String::from("a:1"), String::from("b:2")];
+        let counts = vec![Arc::new(StatusCounts::default()), Arc::new(StatusCounts::default())];
+        counts[0].record(http::StatusCode::OK);
+        counts[1].record(http::StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(
+            render_status_counts(&addresses, &counts),
+            "a:1 2xx=1 3xx=0 4xx=0 5xx=0\nb:2 2xx=0 3xx=0 4xx=0 5xx=1\n"
+        );
+    }
+
+    #[test]
+    fn test_upstream_errors() {
+        let errors = UpstreamErrors::default();
+        assert_eq!(errors.summary(), "connect_failures=0 read_timeouts=0 last_error=-");
+        errors.record_connect_failure(String::from("connect: Connection refused"));
+        errors.record_connect_failure(String::from("connect: timed out"));
+        errors.record_read_timeout(String::from("read response: timed out"));
+        assert_eq!(errors.connect_failures(), 2);
+        assert_eq!(errors.read_timeouts(), 1);
+        errors.record_error(String::from("send request: Broken pipe"));
+        assert_eq!(errors.last_error().as_deref(), Some("send request: Broken pipe"));
+        assert_eq!(
+            render_upstream_errors(&[String::from("a:1")], &[Arc::new(errors)]),
+            "a:1 connect_failures=2 read_timeouts=1 last_error=\"send request: Broken pipe\"\n"
+        );
+    }
+}
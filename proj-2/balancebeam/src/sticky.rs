@@ -0,0 +1,149 @@
+/// 会话保持配置。设置了 --sticky-cookie 时，balancebeam 根据请求中这个 cookie 的值选择上游服务器，
+/// 同一个会话总是被转发到同一个上游服务器（只要它还存活）。
+///
+/// 服务器端不保存任何会话状态：上游服务器完全由 cookie 值和上游服务器地址列表决定（rendezvous 哈希）。
+/// 因为哈希的是地址而不是索引，重新加载上游服务器列表后，只有原来分配到被移除服务器的会话才会换到别的服务器。
+pub struct StickyCookie {
+    name: String,
+}
+
+impl StickyCookie {
+    /// 从 --sticky-cookie 参数构建配置。cookie 名称必须是 RFC 6265 中的 token，否则返回 Err。
+    pub fn new(name: &str) -> Result<StickyCookie, String> {
+        let is_token_char = |c: char| c.is_ascii_graphic() && !"()<>@,;:\\\"/[]?={}".contains(c);
+        if name.is_empty() || !name.chars().all(is_token_char) {
+            return Err(format!("Invalid cookie name for --sticky-cookie: {:?}", name));
+        }
+        Ok(StickyCookie {
+            name: name.to_string(),
+        })
+    }
+
+    /// 返回请求中会话 cookie 的值。请求可能有多个 Cookie 头，每个头可能包含多个以 "; " 分隔的 cookie。
+    pub fn session_id(&self, request: &http::Request<Vec<u8>>) -> Option<String> {
+        request
+            .headers()
+            .get_all("cookie")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(';'))
+            .filter_map(|cookie| cookie.trim().split_once('='))
+            .find(|(name, value)| *name == self.name && !value.is_empty())
+            .map(|(_, value)| value.to_string())
+    }
+
+    /// 为还没有会话 cookie 的客户端生成一个新的会话 ID
+    pub fn new_session_id() -> String {
+        format!("{:016x}", rand::random::<u64>())
+    }
+
+    /// 在响应中添加 Set-Cookie 头，让客户端之后的请求带上这个会话 ID。不会覆盖上游服务器自己设置的 cookie。
+    pub fn set_cookie(&self, response: &mut http::Response<Vec<u8>>, session_id: &str) {
+        let cookie = format!("{}={}; Path=/", self.name, session_id);
+        // cookie 名称在 new() 中检查过，会话 ID 来自 new_session_id()，所以一定是合法的头值
+        response
+            .headers_mut()
+            .append("set-cookie", http::HeaderValue::from_str(&cookie).unwrap());
+    }
+}
+
+/// 返回会话应该被转发到的上游服务器索引：对每个地址计算 hash(会话 ID, 地址)，选择哈希值最大的那个。
+/// 列表为空时返回 None。
+pub fn preferred_upstream(session_id: &str, addresses: &[String]) -> Option<usize> {
+    addresses
+        .iter()
+        .enumerate()
+        .max_by_key(|(_, address)| session_hash(session_id, address))
+        .map(|(idx, _)| idx)
+}
+
+/// 稳定的 64 位哈希（FNV-1a 加上 splitmix64 的最终混合）。不能使用 std 的 DefaultHasher，因为它的算法
+/// 在不同的 Rust 版本之间可能会变化，那样升级 balancebeam 之后所有会话都会换到别的上游服务器。
+fn session_hash(session_id: &str, address: &str) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    // 用 0 字节分隔两部分，避免 ("ab", "c") 和 ("a", "bc") 得到同样的哈希值
+    for byte in session_id.bytes().chain([0]).chain(address.bytes()) {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash ^= hash >> 30;
+    hash = hash.wrapping_mul(0xbf58476d1ce4e5b9);
+    hash ^= hash >> 27;
+    hash = hash.wrapping_mul(0x94d049bb133111eb);
+    hash ^ (hash >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request_with_cookies(cookie_headers: &[&str]) -> http::Request<Vec<u8>> {
+        let mut builder = http::Request::builder().uri("/");
+        for value in cookie_headers {
+            builder = builder.header("cookie", *value);
+        }
+        builder.body(Vec::new()).unwrap()
+    }
+
+    #[test]
+    fn test_session_id_from_cookie_headers() {
+        let sticky = StickyCookie::new("SESSIONID").unwrap();
+        let request = request_with_cookies(&["theme=dark", "lang=en; SESSIONID=abc123; x=y"]);
+        assert_eq!(sticky.session_id(&request), Some(String::from("abc123")));
+
+        // 名称必须完全匹配，空值被视为没有 cookie
+        let request = request_with_cookies(&["MYSESSIONID=abc; SESSIONID="]);
+        assert_eq!(sticky.session_id(&request), None);
+        assert_eq!(sticky.session_id(&request_with_cookies(&[])), None);
+    }
+
+    #[test]
+    fn test_invalid_cookie_names_rejected() {
+        assert!(StickyCookie::new("").is_err());
+        assert!(StickyCookie::new("SESSION ID").is_err());
+        assert!(StickyCookie::new("SESSION=ID").is_err());
+        assert!(StickyCookie::new("SESSION_ID").is_ok());
+    }
+
+    #[test]
+    fn test_preferred_upstream_is_stable_across_reordering() {
+        let addresses: Vec<String> = (0..5).map(|i| format!("127.0.0.1:80{:02}", i)).collect();
+        assert_eq!(preferred_upstream("abc", &[]), None);
+        for i in 0..100 {
+            let session_id = format!("session-{}", i);
+            let idx = preferred_upstream(&session_id, &addresses).unwrap();
+            let mut reversed = addresses.clone();
+            reversed.reverse();
+            let reversed_idx = preferred_upstream(&session_id, &reversed).unwrap();
+            assert_eq!(addresses[idx], reversed[reversed_idx]);
+
+            // 移除一个别的上游服务器不会影响这个会话
+            let mut removed = addresses.clone();
+            removed.remove((idx + 1) % addresses.len());
+            let removed_idx = preferred_upstream(&session_id, &removed).unwrap();
+            assert_eq!(addresses[idx], removed[removed_idx]);
+        }
+    }
+
+    #[test]
+    fn test_sessions_spread_across_upstreams() {
+        let addresses: Vec<String> = (0..3).map(|i| format!("127.0.0.1:80{:02}", i)).collect();
+        let mut counts = [0; 3];
+        for i in 0..300 {
+            counts[preferred_upstream(&format!("session-{}", i), &addresses).unwrap()] += 1;
+        }
+        assert!(counts.iter().all(|&count| count > 50), "{:?}", counts);
+    }
+
+    #[test]
+    fn test_set_cookie_appends() {
+        let sticky = StickyCookie::new("SESSIONID").unwrap();
+        let mut response = http::Response::builder()
+            .header("set-cookie", "theme=dark")
+            .body(Vec::new())
+            .unwrap();
+        sticky.set_cookie(&mut response, "0123456789abcdef");
+        let cookies: Vec<_> = response.headers().get_all("set-cookie").iter().collect();
+        assert_eq!(cookies, ["theme=dark", "SESSIONID=0123456789abcdef; Path=/"]);
+    }
+}
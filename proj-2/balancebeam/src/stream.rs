@@ -0,0 +1,275 @@
+use std::io;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::net::TcpStream;
+
+use crate::chunked::RelayError;
+use crate::counting::CountingStream;
+use crate::error::ProxyError;
+
+/// 每次从上游服务器读取的最大字节数
+const READ_BUFFER_SIZE: usize = 8192;
+
+/// 转发响应体时写入的客户端连接。除了写入之外，还要能在等待上游服务器的数据时发现客户端已经断开：
+/// 否则要等到下一次写入失败才会停止，而上游服务器发送得很慢时这可能要很久
+pub trait ClientStream: AsyncWrite + Unpin {
+    /// 客户端的连接已经出错（例如被重置）时返回 Ready，否则返回 Pending
+    fn poll_disconnected(&self, cx: &mut Context<'_>) -> Poll<()>;
+}
+
+impl ClientStream for TcpStream {
+    fn poll_disconnected(&self, cx: &mut Context<'_>) -> Poll<()> {
+        // 只 peek 不读取，这样客户端已经发送的下一个请求留给之后的 read_from_stream
+        let mut byte = [0_u8; 1];
+        match self.poll_peek(cx, &mut ReadBuf::new(&mut byte)) {
+            Poll::Ready(Err(_)) => Poll::Ready(()),
+            // 读到 EOF 不一定是断开：客户端可以发送完请求之后只关闭写入的一半，仍然等待响应。
+            // 真正关闭了连接的客户端会在我们下一次写入时回复 RST，之后 peek 出错或者写入失败。
+            // 客户端已经发送了更多数据时说明它还在。这两种情况都不注册唤醒，否则会一直被立即唤醒
+            Poll::Ready(Ok(_)) | Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<S: ClientStream> ClientStream for CountingStream<S> {
+    fn poll_disconnected(&self, cx: &mut Context<'_>) -> Poll<()> {
+        self.get_ref().poll_disconnected(cx)
+    }
+}
+
+/// 写入内存的客户端永远不会断开
+#[cfg(test)]
+impl ClientStream for Vec<u8> {
+    fn poll_disconnected(&self, _cx: &mut Context<'_>) -> Poll<()> {
+        Poll::Pending
+    }
+}
+
+/// 从上游服务器读取一段数据。读取时出错返回 RelayError::Upstream；客户端的连接在等待期间出错时立即返回
+/// RelayError::Client，调用者随后丢弃上游连接，不再读取剩下的响应体
+pub async fn read_upstream(
+    upstream: &mut (impl AsyncRead + Unpin),
+    buffer: &mut [u8],
+    client: &impl ClientStream,
+) -> Result<usize, RelayError> {
+    tokio::select! {
+        result = upstream.read(buffer) => {
+            result.map_err(|err| RelayError::Upstream(ProxyError::ConnectionError(err)))
+        }
+        () = std::future::poll_fn(|cx| client.poll_disconnected(cx)) => Err(RelayError::Client(
+            io::Error::new(io::ErrorKind::ConnectionAborted, "client disconnected"),
+        )),
+    }
+}
+
+/// 将有 Content-Length 的响应体边读边转发给客户端，直到一共转发了 content_length 字节。
+///
+/// initial 是读取响应头时已经读入的响应体开头部分。上游服务器发送的字节数与 content_length
+/// 不一致时返回 ContentLengthMismatch（这时客户端已经收到了一部分响应体）。返回转发的字节数。
+pub async fn relay_length(
+    upstream: &mut (impl AsyncRead + Unpin),
+    initial: Vec<u8>,
+    client: &mut impl ClientStream,
+    content_length: usize,
+) -> Result<u64, RelayError> {
+    if initial.len() > content_length {
+        return Err(RelayError::Upstream(ProxyError::ContentLengthMismatch));
+    }
+    client
+        .write_all(&initial)
+        .await
+        .map_err(RelayError::Client)?;
+    let mut remaining = content_length - initial.len();
+    let mut buffer = [0_u8; READ_BUFFER_SIZE];
+    while remaining > 0 {
+        // 最多只读取剩下的字节数，上游服务器多发送的字节留在连接中（这个连接之后不会再使用）
+        let len = remaining.min(buffer.len());
+        let bytes_read = read_upstream(upstream, &mut buffer[..len], client).await?;
+        if bytes_read == 0 {
+            return Err(RelayError::Upstream(ProxyError::ContentLengthMismatch));
+        }
+        client
+            .write_all(&buffer[..bytes_read])
+            .await
+            .map_err(RelayError::Client)?;
+        remaining -= bytes_read;
+    }
+    client.flush().await.map_err(RelayError::Client)?;
+    Ok(content_length as u64)
+}
+
+/// 将没有 Content-Length 的响应体边读边转发给客户端，直到上游服务器关闭连接。客户端同样只能通过连接
+/// 关闭知道响应体结束，所以转发之后必须关闭客户端连接。
+///
+/// initial 与 relay_length 相同。响应体超过 max_body_size 时返回 ResponseBodyTooLarge。返回转发的字节数。
+pub async fn relay_until_close(
+    upstream: &mut (impl AsyncRead + Unpin),
+    initial: Vec<u8>,
+    client: &mut impl ClientStream,
+    max_body_size: usize,
+) -> Result<u64, RelayError> {
+    let mut body_len = initial.len();
+    if body_len > max_body_size {
+        return Err(RelayError::Upstream(ProxyError::ResponseBodyTooLarge));
+    }
+    client
+        .write_all(&initial)
+        .await
+        .map_err(RelayError::Client)?;
+    let mut buffer = [0_u8; READ_BUFFER_SIZE];
+    loop {
+        let bytes_read = read_upstream(upstream, &mut buffer, client).await?;
+        if bytes_read == 0 {
+            break;
+        }
+        body_len += bytes_read;
+        if body_len > max_body_size {
+            return Err(RelayError::Upstream(ProxyError::ResponseBodyTooLarge));
+        }
+        client
+            .write_all(&buffer[..bytes_read])
+            .await
+            .map_err(RelayError::Client)?;
+    }
+    client.flush().await.map_err(RelayError::Client)?;
+    Ok(body_len as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_relay_length() {
+        let mut upstream = &b"llo, world!extra"[..];
+        let mut client = Vec::new();
+        let result = relay_length(&mut upstream, b"he".to_vec(), &mut client, 13).await;
+        assert_eq!(result.unwrap(), 13);
+        assert_eq!(client, b"hello, world!");
+        // 多余的字节不读取
+        assert_eq!(upstream, b"extra");
+    }
+
+    #[tokio::test]
+    async fn test_relay_length_mismatch() {
+        // 上游服务器在发送完响应体之前挂断
+        let mut client = Vec::new();
+        let result = relay_length(&mut &b"lo"[..], b"hel".to_vec(), &mut client, 10).await;
+        assert!(matches!(
+            result,
+            Err(RelayError::Upstream(ProxyError::ContentLengthMismatch))
+        ));
+        assert_eq!(client, b"hello");
+        // 读取响应头时就已经读入了超过 Content-Length 的字节
+        let result = relay_length(&mut &b""[..], b"hello".to_vec(), &mut Vec::new(), 3).await;
+        assert!(matches!(
+            result,
+            Err(RelayError::Upstream(ProxyError::ContentLengthMismatch))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_relay_until_close() {
+        let mut client = Vec::new();
+        let result = relay_until_close(&mut &b"llo"[..], b"he".to_vec(), &mut client, 5).await;
+        assert_eq!(result.unwrap(), 5);
+        assert_eq!(client, b"hello");
+
+        let mut client = Vec::new();
+        let result = relay_until_close(&mut &b"llo!"[..], b"he".to_vec(), &mut client, 5).await;
+        assert!(matches!(
+            result,
+            Err(RelayError::Upstream(ProxyError::ResponseBodyTooLarge))
+        ));
+        assert_eq!(client, b"he");
+    }
+
+    /// 建立一对 TCP 连接，返回 (balancebeam 一侧, 客户端一侧)
+    async fn tcp_pair() -> (TcpStream, TcpStream) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        (server, client)
+    }
+
+    #[tokio::test]
+    async fn test_relay_stops_when_client_disconnects() {
+        let (mut server, client) = tcp_pair().await;
+        // 关闭时发送 RST 而不是 FIN
+        #[allow(deprecated)]
+        client.set_linger(Some(std::time::Duration::ZERO)).unwrap();
+        // 上游服务器发送了响应头之后就不再发送数据
+        let (mut upstream, _upstream_peer) = tokio::io::duplex(64);
+        let relay = tokio::spawn(async move {
+            relay_length(&mut upstream, b"x".to_vec(), &mut server, 1000).await
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        drop(client);
+        let result = tokio::time::timeout(std::time::Duration::from_secs(1), relay)
+            .await
+            .expect("relay kept waiting for the upstream after the client disconnected")
+            .unwrap();
+        match result {
+            Err(RelayError::Client(err)) => assert_eq!(err.kind(), io::ErrorKind::ConnectionAborted),
+            other => panic!("Expected a client error, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_relay_stops_on_write_failure_after_client_close() {
+        let (mut server, client) = tcp_pair().await;
+        let (mut upstream, mut upstream_peer) = tokio::io::duplex(64);
+        // 客户端正常关闭连接（FIN）之后上游服务器仍在不断发送数据，写入失败时停止转发
+        drop(client);
+        tokio::spawn(async move {
+            while upstream_peer.write_all(&[b'x'; 64]).await.is_ok() {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        });
+        let relay = tokio::spawn(async move {
+            relay_until_close(&mut upstream, Vec::new(), &mut server, usize::MAX).await
+        });
+        let result = tokio::time::timeout(std::time::Duration::from_secs(2), relay)
+            .await
+            .expect("relay kept reading from the upstream after the client closed the connection")
+            .unwrap();
+        assert!(matches!(result, Err(RelayError::Client(_))), "{:?}", result);
+    }
+
+    #[tokio::test]
+    async fn test_half_closed_client_is_not_a_disconnect() {
+        let (mut server, mut client) = tcp_pair().await;
+        let (mut upstream, mut upstream_peer) = tokio::io::duplex(64);
+        // 客户端发送完请求之后关闭了写入的一半，但仍在等待响应
+        client.shutdown().await.unwrap();
+        let relay = tokio::spawn(async move {
+            relay_length(&mut upstream, b"he".to_vec(), &mut server, 5).await
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        upstream_peer.write_all(b"llo").await.unwrap();
+        assert_eq!(relay.await.unwrap().unwrap(), 5);
+        let mut received = Vec::new();
+        client.read_to_end(&mut received).await.unwrap();
+        assert_eq!(received, b"hello");
+    }
+
+    #[tokio::test]
+    async fn test_pipelined_request_is_not_a_disconnect() {
+        let (mut server, mut client) = tcp_pair().await;
+        let (mut upstream, mut upstream_peer) = tokio::io::duplex(64);
+        // 客户端在响应结束之前就发送了下一个请求
+        client.write_all(b"GET /next").await.unwrap();
+        let relay = tokio::spawn(async move {
+            let result = relay_length(&mut upstream, Vec::new(), &mut server, 5).await;
+            (result, server)
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        upstream_peer.write_all(b"hello").await.unwrap();
+        let (result, mut server) = relay.await.unwrap();
+        assert_eq!(result.unwrap(), 5);
+        // 下一个请求还没有被读取
+        let mut next = [0_u8; 9];
+        server.read_exact(&mut next).await.unwrap();
+        assert_eq!(&next, b"GET /next");
+    }
+}
@@ -0,0 +1,282 @@
+use rand::Rng;
+use std::collections::HashSet;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+use crate::drain::InFlight;
+use crate::stats::{StatusCounts, UpstreamErrors};
+
+/// 如何在可用的上游服务器中选择一个（--load-balance-algorithm）。random 随机选择；least-connections
+/// 选择正在处理的请求最少的服务器，有多个时在它们之中随机选择。请求处理时间差别很大时，least-connections
+/// 不会让请求堆积在慢的服务器上。两种算法都考虑服务器的权重
+#[derive(clap::ValueEnum, serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum LoadBalanceAlgorithm {
+    Random,
+    LeastConnections,
+}
+
+/// 某一时刻的上游服务器列表，以及与之一一对应（按索引）的健康状态和统计信息。
+///
+/// 重新加载上游服务器列表时不会修改这个结构体，而是构建一个新的 UpstreamList 整体替换掉旧的。
+/// 正在处理的请求持有旧列表的 Arc，因此即使某个上游服务器已被移除，发往它的请求也能正常完成，
+/// 而且索引始终指向同一个列表里的服务器。
+#[derive(Debug)]
+pub struct UpstreamList {
+    /// 我们正在代理到的服务器地址
+    pub addresses: Vec<String>,
+    /// 每个上游服务器的权重（至少为 1；只能在 --config 的 upstreams 中设置，其他方式指定的服务器为 1）。
+    /// random 选中一个服务器的概率与它的权重成正比；least-connections 比较正在处理的请求数除以权重
+    pub weights: Vec<u32>,
+    /// 存储已失败的上游服务器索引（里程碑 3）
+    /// 使用 RwLock 允许多个任务同时读取，只有在标记服务器失败时才需要写锁
+    pub dead: RwLock<HashSet<usize>>,
+    /// dead 中被主动健康检查判定失败、或者连续返回太多 5xx 而被摘除的服务器。它们仍然能接受连接，
+    /// 所以连接成功不能说明它们恢复了：所有服务器都失败时不再给它们机会，只有通过主动健康检查才会恢复。
+    /// 同时需要两个锁时先获取 dead 的锁
+    pub ejected: RwLock<HashSet<usize>>,
+    /// 每个上游服务器的响应状态码分布。使用 Arc，这样重新加载后仍然存在的服务器可以保留原来的计数
+    pub status_counts: Vec<Arc<StatusCounts>>,
+    /// 每个上游服务器的连接失败、读取超时次数和最近一次错误，与 status_counts 一样在重新加载后保留
+    pub errors: Vec<Arc<UpstreamErrors>>,
+    /// 每个上游服务器正在处理的请求数，重新加载后保留。被移除的服务器在 --drain-timeout 之后强制关闭剩下的请求
+    pub in_flight: Vec<Arc<InFlight>>,
+}
+
+impl UpstreamList {
+    /// 所有服务器的权重都为 1
+    #[cfg(test)]
+    pub fn new(addresses: Vec<String>) -> UpstreamList {
+        UpstreamList::weighted(addresses.into_iter().map(|address| (address, 1)).collect())
+    }
+
+    /// 由（地址，权重）构建列表
+    pub fn weighted(upstreams: Vec<(String, u32)>) -> UpstreamList {
+        let (addresses, weights): (Vec<String>, Vec<u32>) = upstreams.into_iter().unzip();
+        let status_counts = addresses.iter().map(|_| Arc::default()).collect();
+        let errors = addresses.iter().map(|_| Arc::default()).collect();
+        let in_flight = addresses.iter().map(|_| Arc::default()).collect();
+        UpstreamList {
+            addresses,
+            weights,
+            dead: RwLock::new(HashSet::new()),
+            ejected: RwLock::new(HashSet::new()),
+            status_counts,
+            errors,
+            in_flight,
+        }
+    }
+
+    /// 构建一个包含新地址列表的 UpstreamList。同时出现在新旧列表中的服务器保留原来的权重、失败状态和
+    /// 统计信息；新加入的服务器被视为存活，权重为 1。
+    pub async fn reloaded(&self, addresses: Vec<String>) -> UpstreamList {
+        let old_dead = self.dead.read().await;
+        let old_ejected = self.ejected.read().await;
+        let mut dead = HashSet::new();
+        let mut ejected = HashSet::new();
+        let mut weights = Vec::with_capacity(addresses.len());
+        let mut status_counts = Vec::with_capacity(addresses.len());
+        let mut errors = Vec::with_capacity(addresses.len());
+        let mut in_flight = Vec::with_capacity(addresses.len());
+        for (new_idx, address) in addresses.iter().enumerate() {
+            match self.addresses.iter().position(|old| old == address) {
+                Some(old_idx) => {
+                    weights.push(self.weights[old_idx]);
+                    if old_dead.contains(&old_idx) {
+                        dead.insert(new_idx);
+                    }
+                    if old_ejected.contains(&old_idx) {
+                        ejected.insert(new_idx);
+                    }
+                    status_counts.push(Arc::clone(&self.status_counts[old_idx]));
+                    errors.push(Arc::clone(&self.errors[old_idx]));
+                    in_flight.push(Arc::clone(&self.in_flight[old_idx]));
+                }
+                None => {
+                    weights.push(1);
+                    status_counts.push(Arc::default());
+                    errors.push(Arc::default());
+                    in_flight.push(Arc::default());
+                }
+            }
+        }
+        UpstreamList {
+            addresses,
+            weights,
+            dead: RwLock::new(dead),
+            ejected: RwLock::new(ejected),
+            status_counts,
+            errors,
+            in_flight,
+        }
+    }
+
+    /// 摘除 upstream_idx（标记为失败，并且只有主动健康检查能恢复它）。返回它之前是否存活
+    pub async fn eject(&self, upstream_idx: usize) -> bool {
+        let mut dead = self.dead.write().await;
+        self.ejected.write().await.insert(upstream_idx);
+        dead.insert(upstream_idx)
+    }
+
+    /// 按 algorithm 从 candidates（不能为空）中选择一个服务器，返回它的索引
+    pub fn choose(&self, algorithm: LoadBalanceAlgorithm, candidates: &[usize], rng: &mut impl Rng) -> usize {
+        match algorithm {
+            LoadBalanceAlgorithm::Random => {
+                let total: u64 = candidates.iter().map(|&idx| u64::from(self.weights[idx])).sum();
+                let mut pick = rng.gen_range(0..total);
+                for &idx in candidates {
+                    let weight = u64::from(self.weights[idx]);
+                    if pick < weight {
+                        return idx;
+                    }
+                    pick -= weight;
+                }
+                unreachable!("pick is less than the total weight")
+            }
+            LoadBalanceAlgorithm::LeastConnections => {
+                // 每个服务器的计数只读取一次，这样即使其他任务同时在修改计数，最少的那些服务器也不会是空的。
+                // 比较 count / weight 时交叉相乘，避免浮点数
+                let loads: Vec<(u64, u64)> = candidates
+                    .iter()
+                    .map(|&idx| (self.in_flight[idx].count() as u64, u64::from(self.weights[idx])))
+                    .collect();
+                let (fewest, weight) = *loads
+                    .iter()
+                    .min_by(|(a, a_weight), (b, b_weight)| (a * b_weight).cmp(&(b * a_weight)))
+                    .expect("candidates must not be empty");
+                let least_loaded: Vec<usize> = candidates
+                    .iter()
+                    .zip(&loads)
+                    .filter(|(_, &(count, count_weight))| count * weight == fewest * count_weight)
+                    .map(|(&idx, _)| idx)
+                    .collect();
+                least_loaded[rng.gen_range(0..least_loaded.len())]
+            }
+        }
+    }
+
+    /// 在这个列表中、但不在 new 中的服务器（地址以及它们正在处理的请求数），用于重新加载后排空这些服务器
+    pub fn removed_in(&self, new: &UpstreamList) -> Vec<(String, Arc<InFlight>)> {
+        self.addresses
+            .iter()
+            .zip(&self.in_flight)
+            .filter(|(address, _)| !new.addresses.contains(address))
+            .map(|(address, in_flight)| (address.clone(), Arc::clone(in_flight)))
+            .collect()
+    }
+}
+
+/// 读取 --upstream-file 指定的文件。每行一个上游服务器地址；忽略空行和以 '#' 开头的注释行。
+pub fn read_upstream_file(path: &str) -> std::io::Result<Vec<String>> {
+    let contents = std::fs::read_to_string(path)?;
+    Ok(contents
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addresses(list: &[&str]) -> Vec<String> {
+        list.iter().map(|addr| addr.to_string()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_reload_carries_over_state() {
+        let old = UpstreamList::weighted(vec![
+            (String::from("a:1"), 1),
+            (String::from("b:2"), 2),
+            (String::from("c:3"), 1),
+        ]);
+        old.eject(1).await;
+        old.status_counts[2].record(http::StatusCode::OK);
+        old.errors[1].record_connect_failure(String::from("connect: Connection refused"));
+
+        let new = old.reloaded(addresses(&["c:3", "d:4", "b:2"])).await;
+        assert_eq!(new.addresses, addresses(&["c:3", "d:4", "b:2"]));
+        // b:2 仍然是被摘除的状态，只是索引变了
+        assert_eq!(*new.dead.read().await, HashSet::from([2]));
+        assert_eq!(*new.ejected.read().await, HashSet::from([2]));
+        // b:2 保留原来的权重，新加入的 d:4 权重为 1
+        assert_eq!(new.weights, [1, 1, 2]);
+        // c:3 的统计信息被保留，d:4 从零开始
+        assert_eq!(new.status_counts[0].count(2), 1);
+        assert_eq!(new.status_counts[1].count(2), 0);
+        assert_eq!(new.errors[2].connect_failures(), 1);
+        assert_eq!(new.errors[1].connect_failures(), 0);
+        assert!(Arc::ptr_eq(&new.in_flight[0], &old.in_flight[2]));
+        // 旧列表不受影响
+        assert_eq!(old.addresses.len(), 3);
+
+        // 只有 a:1 被移除
+        let removed = old.removed_in(&new);
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].0, "a:1");
+        assert!(Arc::ptr_eq(&removed[0].1, &old.in_flight[0]));
+    }
+
+    #[test]
+    fn test_choose_least_connections() {
+        let upstreams = UpstreamList::new(addresses(&["a:1", "b:2", "c:3"]));
+        let (a, _a_peer) = tokio::io::duplex(64);
+        let (b1, _b1_peer) = tokio::io::duplex(64);
+        let (b2, _b2_peer) = tokio::io::duplex(64);
+        let _a = upstreams.in_flight[0].track(a);
+        let _b1 = upstreams.in_flight[1].track(b1);
+        let _b2 = upstreams.in_flight[1].track(b2);
+        let mut rng = rand::thread_rng();
+        for _ in 0..20 {
+            // c:3 没有正在处理的请求
+            assert_eq!(upstreams.choose(LoadBalanceAlgorithm::LeastConnections, &[0, 1, 2], &mut rng), 2);
+            // 只在候选服务器中选择
+            assert_eq!(upstreams.choose(LoadBalanceAlgorithm::LeastConnections, &[0, 1], &mut rng), 0);
+        }
+        // 计数相同时随机选择，两个服务器都会被选中
+        drop(_a);
+        let chosen: HashSet<usize> = (0..100)
+            .map(|_| upstreams.choose(LoadBalanceAlgorithm::LeastConnections, &[0, 2], &mut rng))
+            .collect();
+        assert_eq!(chosen, HashSet::from([0, 2]));
+    }
+
+    #[test]
+    fn test_choose_weighted() {
+        let upstreams = UpstreamList::weighted(vec![(String::from("a:1"), 1), (String::from("b:2"), 3)]);
+        let mut rng = rand::thread_rng();
+        let mut counts = [0; 2];
+        for _ in 0..4000 {
+            counts[upstreams.choose(LoadBalanceAlgorithm::Random, &[0, 1], &mut rng)] += 1;
+        }
+        // b:2 被选中的次数大约是 a:1 的三倍
+        assert!((800..1200).contains(&counts[0]), "{:?}", counts);
+        // 只有一个候选服务器时总是选择它
+        assert_eq!(upstreams.choose(LoadBalanceAlgorithm::Random, &[0], &mut rng), 0);
+
+        // b:2 有两个正在处理的请求，但权重是 a:1 的三倍，所以它的负载（2/3）比 a:1 的（1/1）低
+        let (a, _a_peer) = tokio::io::duplex(64);
+        let (b1, _b1_peer) = tokio::io::duplex(64);
+        let (b2, _b2_peer) = tokio::io::duplex(64);
+        let _a = upstreams.in_flight[0].track(a);
+        let _b1 = upstreams.in_flight[1].track(b1);
+        let _b2 = upstreams.in_flight[1].track(b2);
+        for _ in 0..20 {
+            assert_eq!(upstreams.choose(LoadBalanceAlgorithm::LeastConnections, &[0, 1], &mut rng), 1);
+        }
+    }
+
+    #[test]
+    fn test_read_upstream_file() {
+        let path = std::env::temp_dir().join(format!(
+            "balancebeam-upstream-file-test-{}",
+            std::process::id()
+        ));
+        std::fs::write(&path, "# upstreams\n127.0.0.1:8000\n\n  127.0.0.1:8001  \n").unwrap();
+        let upstreams = read_upstream_file(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(upstreams, addresses(&["127.0.0.1:8000", "127.0.0.1:8001"]));
+    }
+}
@@ -1,7 +1,12 @@
 mod common;
 
-use common::{init_logging, BalanceBeam, EchoServer, Server};
+use common::flaky_server::FlakyServer;
+use common::{init_logging, BalanceBeam, EchoServer, RawServer, Server};
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::{sleep, timeout};
 
 async fn setup() -> (BalanceBeam, EchoServer) {
     init_logging();
@@ -99,3 +104,1341 @@ async fn test_multiple_requests_per_connection() {
 
     log::info!("All done :)");
 }
+
+/// Make sure --max-retries lets us retry against a single upstream. The upstream hangs up on the
+/// first connection, so the request only succeeds if balancebeam reconnects to the same server.
+#[tokio::test]
+async fn test_retry_single_flaky_upstream() {
+    init_logging();
+    let upstream = FlakyServer::new(1).await;
+    let balancebeam = BalanceBeam::new_with_args(&[&upstream.address], &["--max-retries", "3"]).await;
+
+    log::info!("Sending a GET request to a flaky upstream");
+    let response_text = balancebeam
+        .get("/flaky")
+        .await
+        .expect("Error sending request to balancebeam");
+    assert!(
+        response_text.contains("GET /flaky HTTP/1.1"),
+        "balancebeam did not retry against the flaky upstream"
+    );
+
+    log::info!("Checking that the upstream served exactly one request");
+    let num_requests_received = Box::new(upstream).stop().await;
+    assert_eq!(
+        num_requests_received, 1,
+        "Upstream server did not receive the expected number of requests"
+    );
+
+    log::info!("All done :)");
+}
+
+/// Make sure --keepalive-timeout closes idle client connections. Send one request on a raw
+/// connection, go quiet for longer than the timeout, and check that balancebeam hangs up.
+#[tokio::test]
+async fn test_keepalive_timeout_closes_idle_connection() {
+    init_logging();
+    let upstream = EchoServer::new().await;
+    let balancebeam =
+        BalanceBeam::new_with_args(&[&upstream.address], &["--keepalive-timeout", "1"]).await;
+
+    log::info!("Sending a single request over a raw connection");
+    let mut conn = TcpStream::connect(&balancebeam.address)
+        .await
+        .expect("Could not connect to balancebeam");
+    conn.write_all(b"GET /idle HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .expect("Could not send request to balancebeam");
+
+    log::info!("Idling past the keepalive timeout");
+    sleep(Duration::from_secs(2)).await;
+
+    log::info!("Checking that balancebeam closed the connection");
+    let mut received = Vec::new();
+    timeout(Duration::from_secs(5), conn.read_to_end(&mut received))
+        .await
+        .expect("balancebeam did not close the idle connection")
+        .expect("Error reading from balancebeam");
+    let received = String::from_utf8_lossy(&received);
+    assert!(received.starts_with("HTTP/1.1 200"));
+    assert!(received.contains("GET /idle HTTP/1.1"));
+
+    Box::new(upstream).stop().await;
+    log::info!("All done :)");
+}
+
+/// Builds a GET request carrying `n_headers` headers in total (including Host), each padded with
+/// `padding` bytes of value.
+fn request_with_headers(n_headers: usize, padding: usize) -> Vec<u8> {
+    let mut request = String::from("GET /headers HTTP/1.1\r\nHost: localhost\r\n");
+    for i in 1..n_headers {
+        request += &format!("x-header-{}: {}\r\n", i, "a".repeat(padding));
+    }
+    request += "\r\n";
+    request.into_bytes()
+}
+
+/// Make sure --max-headers accepts requests right at the limit and rejects requests above it with
+/// a 431.
+#[tokio::test]
+async fn test_max_headers_limit() {
+    init_logging();
+    let upstream = EchoServer::new().await;
+    let balancebeam = BalanceBeam::new_with_args(&[&upstream.address], &["--max-headers", "8"]).await;
+
+    log::info!("Sending a request with exactly the maximum number of headers");
+    let response_text = balancebeam
+        .send_raw(&request_with_headers(8, 1))
+        .await
+        .expect("Error sending request to balancebeam");
+    assert!(response_text.starts_with("HTTP/1.1 200"), "{}", response_text);
+
+    log::info!("Sending a request with one header too many");
+    let response_text = balancebeam
+        .send_raw(&request_with_headers(9, 1))
+        .await
+        .expect("Error sending request to balancebeam");
+    assert!(response_text.starts_with("HTTP/1.1 431"), "{}", response_text);
+
+    let num_requests_received = Box::new(upstream).stop().await;
+    assert_eq!(num_requests_received, 1);
+    log::info!("All done :)");
+}
+
+/// Make sure --max-header-bytes accepts header blocks right at the limit and rejects larger ones
+/// with a 431.
+#[tokio::test]
+async fn test_max_header_bytes_limit() {
+    init_logging();
+    let upstream = EchoServer::new().await;
+    let at_limit = request_with_headers(4, 50);
+    let limit = at_limit.len().to_string();
+    let balancebeam =
+        BalanceBeam::new_with_args(&[&upstream.address], &["--max-header-bytes", &limit]).await;
+
+    log::info!("Sending a request whose headers are exactly {} bytes", limit);
+    let response_text = balancebeam
+        .send_raw(&at_limit)
+        .await
+        .expect("Error sending request to balancebeam");
+    assert!(response_text.starts_with("HTTP/1.1 200"), "{}", response_text);
+
+    log::info!("Sending a request whose headers are over the limit");
+    let response_text = balancebeam
+        .send_raw(&request_with_headers(4, 51))
+        .await
+        .expect("Error sending request to balancebeam");
+    assert!(response_text.starts_with("HTTP/1.1 431"), "{}", response_text);
+
+    let num_requests_received = Box::new(upstream).stop().await;
+    assert_eq!(num_requests_received, 1);
+    log::info!("All done :)");
+}
+
+/// Make sure --cors-allow-origin answers preflight requests itself (without bothering the
+/// upstream) and adds Access-Control-Allow-Origin to normal forwarded responses.
+#[tokio::test]
+async fn test_cors_preflight_and_header_injection() {
+    init_logging();
+    let upstream = EchoServer::new().await;
+    let balancebeam = BalanceBeam::new_with_args(
+        &[&upstream.address],
+        &["--cors-allow-origin", "https://example.com"],
+    )
+    .await;
+    let client = reqwest::Client::new();
+
+    log::info!("Sending a CORS preflight request");
+    let response = client
+        .request(
+            reqwest::Method::OPTIONS,
+            &format!("http://{}/preflight", balancebeam.address),
+        )
+        .header("Origin", "https://example.com")
+        .header("Access-Control-Request-Method", "POST")
+        .send()
+        .await
+        .expect("Error sending preflight request to balancebeam");
+    assert_eq!(response.status().as_u16(), 204);
+    let headers = response.headers();
+    assert_eq!(headers["access-control-allow-origin"], "https://example.com");
+    assert_eq!(
+        headers["access-control-allow-methods"],
+        "GET, POST, PUT, DELETE, OPTIONS"
+    );
+    assert_eq!(
+        headers["access-control-allow-headers"],
+        "Content-Type, Authorization"
+    );
+
+    log::info!("Sending a normal GET request");
+    let response = client
+        .get(&format!("http://{}/cors", balancebeam.address))
+        .header("Origin", "https://example.com")
+        .send()
+        .await
+        .expect("Error sending request to balancebeam");
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()["access-control-allow-origin"],
+        "https://example.com"
+    );
+    let response_text = response.text().await.expect("Error reading response body");
+    assert!(response_text.contains("GET /cors HTTP/1.1"));
+
+    log::info!("Checking that only the GET request reached the upstream");
+    let num_requests_received = Box::new(upstream).stop().await;
+    assert_eq!(num_requests_received, 1);
+    log::info!("All done :)");
+}
+
+/// Bodies are framed by Content-Length whatever the method, so PUT, PATCH and DELETE bodies should
+/// reach the upstream byte-for-byte just like POST bodies, with Content-Length intact
+#[tokio::test]
+async fn test_bodies_for_other_methods() {
+    let (balancebeam, upstream) = setup().await;
+    let body = "line one\r\n\r\nline two: caf\u{e9}";
+    for method in [reqwest::Method::PUT, reqwest::Method::PATCH, reqwest::Method::DELETE] {
+        log::info!("Sending a {} request with a body", method);
+        let response_text = balancebeam
+            .send_with_body(method.clone(), "/item/1", body)
+            .await
+            .expect("Error sending request to balancebeam");
+        assert!(
+            response_text.starts_with(&format!("{} /item/1 HTTP/1.1\n", method)),
+            "{}",
+            response_text
+        );
+        assert!(response_text.contains(&format!("content-length: {}\n", body.len())));
+        assert!(response_text.contains("x-forwarded-for: 127.0.0.1"));
+        // The echo server prints the headers, a blank line, then exactly the body it received
+        assert!(response_text.ends_with(&format!("\n\n{}", body)), "{}", response_text);
+    }
+
+    let num_requests_received = Box::new(upstream).stop().await;
+    assert_eq!(num_requests_received, 3);
+}
+
+/// Make sure --max-request-body rejects oversized request bodies with a 413 without forwarding
+/// them, while bodies at the limit go through.
+#[tokio::test]
+async fn test_max_request_body_limit() {
+    init_logging();
+    let upstream = EchoServer::new().await;
+    let balancebeam =
+        BalanceBeam::new_with_args(&[&upstream.address], &["--max-request-body", "10"]).await;
+    let client = reqwest::Client::new();
+
+    log::info!("Sending a POST request with a body right at the limit");
+    let response = client
+        .post(&format!("http://{}/small", balancebeam.address))
+        .body("0123456789")
+        .send()
+        .await
+        .expect("Error sending request to balancebeam");
+    assert_eq!(response.status().as_u16(), 200);
+
+    log::info!("Sending a POST request with a body over the limit");
+    let response = client
+        .post(&format!("http://{}/large", balancebeam.address))
+        .body("0123456789a")
+        .send()
+        .await
+        .expect("Error sending request to balancebeam");
+    assert_eq!(response.status().as_u16(), 413);
+
+    let num_requests_received = Box::new(upstream).stop().await;
+    assert_eq!(num_requests_received, 1);
+    log::info!("All done :)");
+}
+
+/// Make sure --max-response-body turns oversized upstream responses into a 502 for the client.
+#[tokio::test]
+async fn test_max_response_body_limit() {
+    init_logging();
+    let upstream = EchoServer::new().await;
+    let balancebeam =
+        BalanceBeam::new_with_args(&[&upstream.address], &["--max-response-body", "50"]).await;
+    let client = reqwest::Client::new();
+
+    log::info!("Sending a request whose echoed response is larger than the limit");
+    let response = client
+        .post(&format!("http://{}/echo", balancebeam.address))
+        .body("x".repeat(100))
+        .send()
+        .await
+        .expect("Error sending request to balancebeam");
+    assert_eq!(response.status().as_u16(), 502);
+
+    log::info!("Checking that balancebeam did not retry the oversized response");
+    let num_requests_received = Box::new(upstream).stop().await;
+    assert_eq!(num_requests_received, 1);
+    log::info!("All done :)");
+}
+
+/// Send a HEAD request to an upstream that (wrongly) sends a body along with its headers, and make
+/// sure balancebeam forwards the Content-Length header but not the body.
+#[tokio::test]
+async fn test_head_response_from_upstream_has_no_body() {
+    init_logging();
+    let upstream =
+        RawServer::new(b"HTTP/1.1 200 OK\r\nContent-Length: 11\r\n\r\nhello world").await;
+    let balancebeam = BalanceBeam::new(&[&upstream.address], None, None).await;
+
+    let response_text = balancebeam
+        .send_raw(b"HEAD / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .expect("Error sending request to balancebeam");
+    log::info!("Response: {:?}", response_text);
+    assert!(response_text.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response_text.to_lowercase().contains("content-length: 11\r\n"));
+    assert!(
+        response_text.ends_with("\r\n\r\n"),
+        "HEAD response should end right after the headers"
+    );
+
+    log::info!("Making sure GET still gets the body");
+    let response_text = balancebeam
+        .send_raw(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .expect("Error sending request to balancebeam");
+    assert!(response_text.ends_with("\r\n\r\nhello world"));
+
+    assert_eq!(Box::new(upstream).stop().await, 2);
+    log::info!("All done :)");
+}
+
+/// Send a HEAD request when the only upstream is down, and make sure the 502 that balancebeam
+/// generates has a Content-Length but no body.
+#[tokio::test]
+async fn test_head_error_response_has_no_body() {
+    init_logging();
+    let upstream = EchoServer::new().await;
+    let balancebeam = BalanceBeam::new(&[&upstream.address], None, None).await;
+    Box::new(upstream).stop().await;
+
+    let response_text = balancebeam
+        .send_raw(b"HEAD / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .expect("Error sending request to balancebeam");
+    log::info!("Response: {:?}", response_text);
+    assert!(response_text.starts_with("HTTP/1.1 502 Bad Gateway\r\n"));
+    assert!(response_text.to_lowercase().contains("content-length: "));
+    assert!(response_text.ends_with("\r\n\r\n"));
+    log::info!("All done :)");
+}
+
+/// Make sure a GET request with a body is forwarded normally by default, body included.
+#[tokio::test]
+async fn test_get_with_body_forwarded_by_default() {
+    let (balancebeam, upstream) = setup().await;
+
+    let response_text = balancebeam
+        .send_raw(b"GET /with-body HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhello")
+        .await
+        .expect("Error sending request to balancebeam");
+    assert!(response_text.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response_text.contains("GET /with-body HTTP/1.1"));
+    assert!(response_text.ends_with("\n\nhello"));
+
+    assert_eq!(Box::new(upstream).stop().await, 1);
+    log::info!("All done :)");
+}
+
+/// Make sure --reject-body-on-get answers GET/HEAD requests that carry a body (by Content-Length
+/// or chunked encoding) with a 400 instead of forwarding them, while bodiless GETs still go
+/// through.
+#[tokio::test]
+async fn test_reject_body_on_get() {
+    init_logging();
+    let upstream = EchoServer::new().await;
+    let balancebeam =
+        BalanceBeam::new_with_args(&[&upstream.address], &["--reject-body-on-get"]).await;
+
+    let rejected_requests: [&[u8]; 3] = [
+        b"GET / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhello",
+        b"HEAD / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhello",
+        b"GET / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n",
+    ];
+    for request in rejected_requests.iter() {
+        let response_text = balancebeam
+            .send_raw(request)
+            .await
+            .expect("Error sending request to balancebeam");
+        assert!(
+            response_text.starts_with("HTTP/1.1 400 Bad Request\r\n"),
+            "Unexpected response: {:?}",
+            response_text
+        );
+        // The body was left unread, so balancebeam closes the connection and has to say so
+        assert!(
+            response_text.to_lowercase().contains("\r\nconnection: close\r\n"),
+            "Unexpected response: {:?}",
+            response_text
+        );
+        // A response to HEAD never has a body
+        if request.starts_with(b"HEAD ") {
+            assert!(response_text.ends_with("\r\n\r\n"), "Unexpected response: {:?}", response_text);
+        }
+    }
+
+    log::info!("Checking that requests without a body are still forwarded");
+    let response_text = balancebeam
+        .send_raw(b"GET /no-body HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\n\r\n")
+        .await
+        .expect("Error sending request to balancebeam");
+    assert!(response_text.starts_with("HTTP/1.1 200 OK\r\n"));
+    let response_text = balancebeam
+        .post("/post-body", "hello")
+        .await
+        .expect("Error sending request to balancebeam");
+    assert!(response_text.contains("\n\nhello"));
+
+    assert_eq!(Box::new(upstream).stop().await, 2);
+    log::info!("All done :)");
+}
+
+/// Drive a known number of requests with --stats-interval set, and make sure the periodic
+/// throughput log lines add up to that number
+#[tokio::test]
+async fn test_request_rate_logging() {
+    init_logging();
+    let upstream = EchoServer::new().await;
+    let balancebeam =
+        BalanceBeam::new_with_args(&[&upstream.address], &["--stats-interval", "1"]).await;
+
+    let n_requests = 10;
+    for i in 0..n_requests {
+        let path = format!("/request-{}", i);
+        let response_text = balancebeam
+            .get(&path)
+            .await
+            .expect("Error sending request to balancebeam");
+        assert!(response_text.contains(&format!("GET {} HTTP/1.1", path)));
+    }
+
+    log::info!("Waiting for the next throughput log line");
+    sleep(Duration::from_millis(2500)).await;
+    // The requests may straddle two intervals, so add up every interval's count
+    let logged_requests: usize = balancebeam
+        .output_lines()
+        .iter()
+        .filter_map(|line| {
+            let rest = &line[line.find("Handled ")? + "Handled ".len()..];
+            rest.split(' ').next()?.parse::<usize>().ok()
+        })
+        .sum();
+    assert_eq!(logged_requests, n_requests);
+
+    assert_eq!(Box::new(upstream).stop().await, n_requests);
+    log::info!("All done :)");
+}
+
+/// Send a request with a known-size body over one connection, then make sure the byte totals on
+/// the stats endpoint match what actually crossed the wire
+#[tokio::test]
+async fn test_byte_accounting() {
+    init_logging();
+    let upstream = EchoServer::new().await;
+    let balancebeam =
+        BalanceBeam::new_with_args(&[&upstream.address], &["--stats-path", "/__stats"]).await;
+
+    let body = "x".repeat(1000);
+    let request = format!(
+        "POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}",
+        body.len(),
+        body
+    );
+    let response_text = balancebeam
+        .send_raw(request.as_bytes())
+        .await
+        .expect("Error sending request to balancebeam");
+    assert!(response_text.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response_text.ends_with(&body));
+
+    // The totals only cover closed connections, so they don't include the stats request itself
+    let stats = balancebeam
+        .send_raw(b"GET /__stats HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .expect("Error fetching stats from balancebeam");
+    log::info!("Stats: {}", stats);
+    let totals: Vec<u64> = stats
+        .lines()
+        .find(|line| line.starts_with("bytes "))
+        .expect("No byte totals in stats")
+        .split(' ')
+        .skip(1)
+        .map(|field| field.split('=').nth(1).unwrap().parse().unwrap())
+        .collect();
+    let (client_read, client_written, upstream_read, upstream_written) =
+        (totals[0], totals[1], totals[2], totals[3]);
+    assert_eq!(client_read, request.len() as u64);
+    assert_eq!(client_written, response_text.len() as u64);
+    // The forwarded request gains an X-Forwarded-For header, and the echoed response contains the
+    // whole request, so both directions carry more than the body
+    assert!(upstream_written > request.len() as u64);
+    assert!(upstream_read > body.len() as u64);
+
+    assert_eq!(Box::new(upstream).stop().await, 1);
+    log::info!("All done :)");
+}
+
+/// Make sure the Host header reaching the upstream follows --preserve-host and --set-host
+#[tokio::test]
+async fn test_host_header_modes() {
+    init_logging();
+    let upstream = EchoServer::new().await;
+
+    log::info!("By default the client's Host header is forwarded unchanged");
+    let balancebeam = BalanceBeam::new_with_args(&[&upstream.address], &[]).await;
+    let response_text = balancebeam.get("/").await.expect("Error sending request to balancebeam");
+    assert!(
+        response_text.contains(&format!("host: {}\n", balancebeam.address)),
+        "{}",
+        response_text
+    );
+    drop(balancebeam);
+
+    log::info!("With --preserve-host false, Host is rewritten to the upstream's address");
+    let balancebeam =
+        BalanceBeam::new_with_args(&[&upstream.address], &["--preserve-host", "false"]).await;
+    let response_text = balancebeam.get("/").await.expect("Error sending request to balancebeam");
+    assert!(
+        response_text.contains(&format!("host: {}\n", upstream.address)),
+        "{}",
+        response_text
+    );
+    drop(balancebeam);
+
+    log::info!("--set-host forces a specific Host");
+    let balancebeam = BalanceBeam::new_with_args(
+        &[&upstream.address],
+        &["--preserve-host", "false", "--set-host", "backend.example.com"],
+    )
+    .await;
+    let response_text = balancebeam.get("/").await.expect("Error sending request to balancebeam");
+    assert!(response_text.contains("host: backend.example.com\n"), "{}", response_text);
+    assert_eq!(response_text.matches("host: ").count(), 1);
+
+    assert_eq!(Box::new(upstream).stop().await, 3);
+    log::info!("All done :)");
+}
+
+/// Push request bodies through one keep-alive connection until --max-connection-bytes is exceeded,
+/// and make sure the next request gets a 413 while a fresh connection still works
+#[tokio::test]
+async fn test_max_connection_bytes() {
+    init_logging();
+    let upstream = EchoServer::new().await;
+    let balancebeam =
+        BalanceBeam::new_with_args(&[&upstream.address], &["--max-connection-bytes", "1000"])
+            .await;
+
+    // reqwest reuses one pooled connection for sequential requests
+    let client = reqwest::Client::new();
+    let body = "x".repeat(300);
+    let send = |client: &reqwest::Client| {
+        client
+            .post(&format!("http://{}/upload", balancebeam.address))
+            .body(body.clone())
+            .send()
+    };
+    // The echo server sends each body back along with the request headers, so each exchange
+    // counts for well over 600 bytes: the first is under the limit, the second goes over it
+    for _ in 0..2 {
+        let response = send(&client).await.expect("Error sending request to balancebeam");
+        assert_eq!(response.status().as_u16(), 200);
+        response.text().await.unwrap();
+    }
+
+    log::info!("Sending a request after the limit was exceeded");
+    let response = send(&client).await.expect("Error sending request to balancebeam");
+    assert_eq!(response.status().as_u16(), 413);
+    // balancebeam closes the connection after this response, so it has to say so
+    assert_eq!(
+        response.headers().get("connection").map(|value| value.as_bytes()),
+        Some(&b"close"[..])
+    );
+
+    log::info!("Checking that a new connection starts from zero");
+    let response = send(&reqwest::Client::new())
+        .await
+        .expect("Error sending request to balancebeam");
+    assert_eq!(response.status().as_u16(), 200);
+
+    assert_eq!(Box::new(upstream).stop().await, 3);
+    log::info!("All done :)");
+}
+
+/// Reads from the stream into `received` until it contains `pattern`
+async fn read_until(stream: &mut TcpStream, received: &mut Vec<u8>, pattern: &[u8]) {
+    let mut buffer = [0_u8; 512];
+    while !received.windows(pattern.len()).any(|window| window == pattern) {
+        let bytes_read = stream.read(&mut buffer).await.expect("Error reading from stream");
+        assert!(bytes_read > 0, "Stream closed before {:?} arrived", pattern);
+        received.extend_from_slice(&buffer[..bytes_read]);
+    }
+}
+
+/// Have the upstream stream a chunked, SSE-style response one event at a time while keeping its
+/// connection open, and make sure the client receives each event before the upstream sends the
+/// next one, followed by the final chunk and the upstream's trailer
+#[tokio::test]
+async fn test_chunked_response_streamed() {
+    init_logging();
+    let upstream = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_address = upstream.local_addr().unwrap().to_string();
+    let balancebeam = BalanceBeam::new(&[&upstream_address], None, None).await;
+
+    let mut client = TcpStream::connect(&balancebeam.address).await.unwrap();
+    client
+        .write_all(b"GET /events HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+    let (mut upstream_conn, _) = timeout(Duration::from_secs(2), upstream.accept())
+        .await
+        .expect("balancebeam never connected to the upstream")
+        .unwrap();
+    read_until(&mut upstream_conn, &mut Vec::new(), b"\r\n\r\n").await;
+    upstream_conn
+        .write_all(
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nTransfer-Encoding: chunked\r\n\
+            Trailer: x-checksum\r\n\r\n",
+        )
+        .await
+        .unwrap();
+
+    let mut received = Vec::new();
+    for event in ["data: first\n\n", "data: second\n\n"] {
+        log::info!("Upstream sending {:?}", event);
+        let chunk = format!("{:x}\r\n{}\r\n", event.len(), event);
+        upstream_conn.write_all(chunk.as_bytes()).await.unwrap();
+        timeout(
+            Duration::from_secs(2),
+            read_until(&mut client, &mut received, event.as_bytes()),
+        )
+        .await
+        .expect("balancebeam buffered the event instead of forwarding it");
+    }
+    upstream_conn
+        .write_all(b"0\r\nx-checksum: abc123\r\n\r\n")
+        .await
+        .unwrap();
+    timeout(
+        Duration::from_secs(2),
+        read_until(&mut client, &mut received, b"0\r\nx-checksum: abc123\r\n\r\n"),
+    )
+    .await
+    .expect("balancebeam did not forward the end of the response");
+
+    let response = String::from_utf8(received).unwrap();
+    log::info!("Client received {:?}", response);
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("transfer-encoding: chunked\r\n"));
+    // balancebeam re-chunks the body as it arrives, so chunk boundaries may differ from the
+    // upstream's, but the events arrive in order
+    let first = response.find("data: first").unwrap();
+    assert!(response[first..].contains("data: second"));
+    assert!(response.ends_with("0\r\nx-checksum: abc123\r\n\r\n"));
+    log::info!("All done :)");
+}
+
+/// Start an upstream that answers every request with a chunked body it trickles out one byte at a
+/// time, forever. Each byte arrives long before the per-read timeout, so only --request-timeout can
+/// stop it.
+async fn start_trickling_upstream() -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    tokio::spawn(async move {
+        while let Ok((mut conn, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                read_until(&mut conn, &mut Vec::new(), b"\r\n\r\n").await;
+                let headers = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n";
+                if conn.write_all(headers).await.is_err() {
+                    return;
+                }
+                while conn.write_all(b"1\r\nx\r\n").await.is_ok() {
+                    sleep(Duration::from_millis(200)).await;
+                }
+            });
+        }
+    });
+    address
+}
+
+/// Make sure --request-timeout cuts off a response that an upstream trickles out indefinitely. The
+/// response has already started, so balancebeam can't send a 504 and closes the connection instead
+#[tokio::test]
+async fn test_request_timeout_cuts_off_trickling_body() {
+    init_logging();
+    let upstream_address = start_trickling_upstream().await;
+    let balancebeam =
+        BalanceBeam::new_with_args(&[&upstream_address], &["--request-timeout", "2"]).await;
+
+    let mut client = TcpStream::connect(&balancebeam.address).await.unwrap();
+    client
+        .write_all(b"GET /huge HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+    let mut received = Vec::new();
+    timeout(Duration::from_secs(5), client.read_to_end(&mut received))
+        .await
+        .expect("balancebeam kept relaying the body past --request-timeout")
+        .unwrap();
+
+    let response = String::from_utf8(received).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("1\r\nx\r\n"));
+    assert!(!response.ends_with("0\r\n\r\n"));
+    log::info!("All done :)");
+}
+
+/// Point balancebeam at an upstream that accepts connections but never answers. Each attempt
+/// times out after a second, so three attempts would take three seconds; --request-timeout should
+/// give up after two and reply 504
+#[tokio::test]
+async fn test_request_timeout_returns_504() {
+    init_logging();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_address = listener.local_addr().unwrap().to_string();
+    tokio::spawn(async move {
+        let mut connections = Vec::new();
+        while let Ok((conn, _)) = listener.accept().await {
+            // Hold the connection open without ever responding
+            connections.push(conn);
+        }
+    });
+    let balancebeam = BalanceBeam::new_with_args(
+        &[&upstream_address],
+        &["--request-timeout", "2", "--max-retries", "3"],
+    )
+    .await;
+
+    let started = std::time::Instant::now();
+    let response = reqwest::Client::new()
+        .get(&format!("http://{}/slow", balancebeam.address))
+        .send()
+        .await
+        .expect("Error sending request to balancebeam");
+    assert_eq!(response.status().as_u16(), 504);
+    assert!(started.elapsed() < Duration::from_millis(2900), "{:?}", started.elapsed());
+    log::info!("All done :)");
+}
+
+/// Have the upstream send a response with the given Content-Length, but hold back the second half
+/// of the body until the client has had a chance to receive the first half. Returns whether the
+/// first half reached the client early, after checking that the full response arrives intact
+async fn body_arrives_before_complete(content_length: usize, stream_threshold: usize) -> bool {
+    let upstream = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_address = upstream.local_addr().unwrap().to_string();
+    let balancebeam = BalanceBeam::new_with_args(
+        &[&upstream_address],
+        &["--stream-threshold", &stream_threshold.to_string()],
+    )
+    .await;
+
+    let mut client = TcpStream::connect(&balancebeam.address).await.unwrap();
+    client
+        .write_all(b"GET /download HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+    let (mut upstream_conn, _) = timeout(Duration::from_secs(2), upstream.accept())
+        .await
+        .expect("balancebeam never connected to the upstream")
+        .unwrap();
+    read_until(&mut upstream_conn, &mut Vec::new(), b"\r\n\r\n").await;
+    let first_half = "a".repeat(content_length / 2);
+    let second_half = "b".repeat(content_length - first_half.len());
+    let head = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", content_length);
+    upstream_conn.write_all(head.as_bytes()).await.unwrap();
+    upstream_conn.write_all(first_half.as_bytes()).await.unwrap();
+
+    // Stay well below the 1 second balancebeam waits for a buffered response
+    let mut received = Vec::new();
+    let arrived_early = timeout(
+        Duration::from_millis(300),
+        read_until(&mut client, &mut received, first_half.as_bytes()),
+    )
+    .await
+    .is_ok();
+
+    upstream_conn.write_all(second_half.as_bytes()).await.unwrap();
+    let body = first_half + &second_half;
+    timeout(
+        Duration::from_secs(2),
+        read_until(&mut client, &mut received, body.as_bytes()),
+    )
+    .await
+    .expect("Client never received the whole body");
+    let response = String::from_utf8(received).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(
+        response
+            .to_lowercase()
+            .contains(&format!("content-length: {}\r\n", content_length)),
+        "{}",
+        response
+    );
+    assert!(response.ends_with(&body), "{}", response);
+    arrived_early
+}
+
+/// Responses at or above --stream-threshold should be relayed as they arrive, while smaller ones
+/// are still read completely before anything is sent to the client
+#[tokio::test]
+async fn test_stream_threshold() {
+    init_logging();
+    assert!(body_arrives_before_complete(100, 64).await);
+    assert!(body_arrives_before_complete(64, 64).await);
+    assert!(!body_arrives_before_complete(63, 64).await);
+}
+
+/// A response without a Content-Length can only end when the upstream closes the connection, so
+/// when streaming it balancebeam has to close the client connection afterwards too
+#[tokio::test]
+async fn test_stream_threshold_unknown_length() {
+    init_logging();
+    let upstream = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_address = upstream.local_addr().unwrap().to_string();
+    let balancebeam =
+        BalanceBeam::new_with_args(&[&upstream_address], &["--stream-threshold", "1000"]).await;
+
+    let mut client = TcpStream::connect(&balancebeam.address).await.unwrap();
+    client
+        .write_all(b"GET /download HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+    let (mut upstream_conn, _) = timeout(Duration::from_secs(2), upstream.accept())
+        .await
+        .expect("balancebeam never connected to the upstream")
+        .unwrap();
+    read_until(&mut upstream_conn, &mut Vec::new(), b"\r\n\r\n").await;
+    upstream_conn
+        .write_all(b"HTTP/1.1 200 OK\r\n\r\nfirst part, ")
+        .await
+        .unwrap();
+    let mut received = Vec::new();
+    timeout(
+        Duration::from_millis(500),
+        read_until(&mut client, &mut received, b"first part, "),
+    )
+    .await
+    .expect("Body of unknown length was not streamed");
+    upstream_conn.write_all(b"second part").await.unwrap();
+    drop(upstream_conn);
+
+    timeout(Duration::from_secs(2), client.read_to_end(&mut received))
+        .await
+        .expect("balancebeam did not close the client connection")
+        .unwrap();
+    let response = String::from_utf8(received).unwrap().to_lowercase();
+    assert!(response.contains("connection: close\r\n"), "{}", response);
+    assert!(response.ends_with("\r\n\r\nfirst part, second part"), "{}", response);
+}
+
+/// With --log-format=json, balancebeam should print one JSON object per request, with the fields
+/// log pipelines expect.
+#[tokio::test]
+async fn test_json_log_format() {
+    init_logging();
+    let upstream = EchoServer::new().await;
+    let balancebeam =
+        BalanceBeam::new_with_args(&[&upstream.address], &["--log-format", "json"]).await;
+
+    log::info!("Sending a GET request");
+    let response_text = balancebeam
+        .get("/logged?x=1")
+        .await
+        .expect("Error sending request to balancebeam");
+    sleep(Duration::from_millis(200)).await;
+
+    let output = balancebeam.output_lines();
+    let entries: Vec<serde_json::Value> = output
+        .iter()
+        .filter(|line| line.starts_with('{'))
+        .map(|line| serde_json::from_str(line).expect("Log line is not valid JSON"))
+        .collect();
+    assert_eq!(entries.len(), 1, "{:#?}", output);
+    let entry = &entries[0];
+    for key in ["ts", "client_ip", "method", "uri", "status", "upstream", "latency_ms", "bytes"] {
+        assert!(entry.get(key).is_some(), "Missing {} in {}", key, entry);
+    }
+    assert_eq!(entry["client_ip"], "127.0.0.1");
+    assert_eq!(entry["method"], "GET");
+    assert_eq!(entry["uri"], "/logged?x=1");
+    assert_eq!(entry["status"], 200);
+    assert_eq!(entry["upstream"], upstream.address.as_str());
+    assert_eq!(entry["bytes"], response_text.len() as u64);
+    assert!(entry["latency_ms"].as_f64().unwrap() >= 0.0);
+    assert!(entry["ts"].as_str().unwrap().ends_with('Z'));
+    // The text request/response lines are replaced, not duplicated
+    assert!(!output.iter().any(|line| line.contains("127.0.0.1 -> GET")), "{:#?}", output);
+
+    assert_eq!(Box::new(upstream).stop().await, 1);
+    log::info!("All done :)");
+}
+
+/// Range requests should pass through untouched: the Range header reaches the upstream, and a 206
+/// response comes back with its Content-Range/Accept-Ranges headers and only the requested bytes.
+#[tokio::test]
+async fn test_range_requests() {
+    init_logging();
+    let upstream = EchoServer::new().await;
+    let balancebeam = BalanceBeam::new(&[&upstream.address], None, None).await;
+
+    log::info!("Making sure the Range header is forwarded");
+    let response_text = balancebeam
+        .send_raw(b"GET /video HTTP/1.1\r\nHost: localhost\r\nRange: bytes=0-1023\r\n\r\n")
+        .await
+        .expect("Error sending request to balancebeam");
+    assert!(response_text.to_lowercase().contains("range: bytes=0-1023"), "{}", response_text);
+    Box::new(upstream).stop().await;
+
+    log::info!("Making sure a 206 response is relayed with its own Content-Length");
+    let upstream = RawServer::new(
+        b"HTTP/1.1 206 Partial Content\r\nAccept-Ranges: bytes\r\nContent-Range: bytes 0-4/11\r\n\
+          Content-Length: 5\r\n\r\nhello",
+    )
+    .await;
+    let balancebeam = BalanceBeam::new(&[&upstream.address], None, None).await;
+    let response_text = balancebeam
+        .send_raw(b"GET / HTTP/1.1\r\nHost: localhost\r\nRange: bytes=0-4\r\n\r\n")
+        .await
+        .expect("Error sending request to balancebeam");
+    log::info!("Response: {:?}", response_text);
+    assert!(response_text.starts_with("HTTP/1.1 206 Partial Content\r\n"));
+    let lowercase = response_text.to_lowercase();
+    assert!(lowercase.contains("content-range: bytes 0-4/11\r\n"));
+    assert!(lowercase.contains("accept-ranges: bytes\r\n"));
+    assert!(lowercase.contains("content-length: 5\r\n"));
+    assert!(response_text.ends_with("\r\n\r\nhello"));
+
+    assert_eq!(Box::new(upstream).stop().await, 1);
+    log::info!("All done :)");
+}
+
+/// With --error-page-dir, the 502 balancebeam generates when the upstream is down should be the
+/// custom page from that directory, while statuses without a page keep the plain-text body.
+#[tokio::test]
+async fn test_custom_error_pages() {
+    init_logging();
+    let dir = std::env::temp_dir()
+        .join(format!("balancebeam-test-error-pages-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let page = "<html><body><h1>We'll be right back</h1></body></html>";
+    std::fs::write(dir.join("502.html"), page).unwrap();
+
+    let upstream = EchoServer::new().await;
+    let balancebeam = BalanceBeam::new_with_args(
+        &[&upstream.address],
+        &["--error-page-dir", dir.to_str().unwrap(), "--max-headers", "8"],
+    )
+    .await;
+    Box::new(upstream).stop().await;
+
+    log::info!("Sending a request while the upstream is down");
+    let response = reqwest::get(&format!("http://{}/", balancebeam.address))
+        .await
+        .expect("Error sending request to balancebeam");
+    assert_eq!(response.status().as_u16(), 502);
+    assert_eq!(response.headers()["content-type"], "text/html; charset=utf-8");
+    assert_eq!(response.text().await.unwrap(), page);
+
+    log::info!("Making sure statuses without a page still get the plain-text body");
+    let response_text = balancebeam
+        .send_raw(&request_with_headers(9, 1))
+        .await
+        .expect("Error sending request to balancebeam");
+    log::info!("Response: {:?}", response_text);
+    assert!(response_text.starts_with("HTTP/1.1 431"), "{}", response_text);
+    assert!(response_text.to_lowercase().contains("content-type: text/plain\r\n"));
+    assert!(!response_text.contains(page));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+    log::info!("All done :)");
+}
+
+/// --strip-request-header should remove the named headers (in any case) from requests before they
+/// reach the upstream, and other headers should still be forwarded
+#[tokio::test]
+async fn test_strip_request_headers() {
+    init_logging();
+    let upstream = EchoServer::new().await;
+    let balancebeam = BalanceBeam::new_with_args(
+        &[&upstream.address],
+        &[
+            "--strip-request-header",
+            "X-Internal",
+            "--strip-request-header",
+            "x-debug-token",
+        ],
+    )
+    .await;
+
+    let response_text = balancebeam
+        .send_raw(
+            b"GET /strip HTTP/1.1\r\nHost: localhost\r\nx-internal: 1\r\nX-INTERNAL: 2\r\n\
+              X-Debug-Token: secret\r\nX-Tag: kept\r\n\r\n",
+        )
+        .await
+        .expect("Error sending request to balancebeam");
+    log::info!("Response: {:?}", response_text);
+    assert!(response_text.contains("GET /strip HTTP/1.1"));
+    assert!(!response_text.contains("x-internal"));
+    assert!(!response_text.contains("x-debug-token"));
+    assert!(!response_text.contains("secret"));
+    assert!(response_text.contains("x-tag: kept"));
+    assert!(response_text.contains("x-forwarded-for: 127.0.0.1"));
+
+    assert_eq!(Box::new(upstream).stop().await, 1);
+    log::info!("All done :)");
+}
+
+/// --strip-response-header should remove the named headers (in any case) from the upstream's
+/// response, and other headers should still reach the client
+#[tokio::test]
+async fn test_strip_response_headers() {
+    init_logging();
+    let upstream = RawServer::new(
+        b"HTTP/1.1 200 OK\r\nServer: nginx/1.2.3\r\nX-Powered-By: PHP\r\nX-Tag: kept\r\n\
+          Content-Length: 5\r\n\r\nhello",
+    )
+    .await;
+    let balancebeam = BalanceBeam::new_with_args(
+        &[&upstream.address],
+        &[
+            "--strip-response-header",
+            "server",
+            "--strip-response-header",
+            "X-POWERED-BY",
+        ],
+    )
+    .await;
+
+    let response_text = balancebeam
+        .send_raw(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .expect("Error sending request to balancebeam");
+    log::info!("Response: {:?}", response_text);
+    assert!(response_text.starts_with("HTTP/1.1 200 OK\r\n"));
+    let lowercase = response_text.to_lowercase();
+    assert!(!lowercase.contains("server:"));
+    assert!(!lowercase.contains("x-powered-by"));
+    assert!(lowercase.contains("x-tag: kept\r\n"));
+    assert!(response_text.ends_with("\r\n\r\nhello"));
+
+    assert_eq!(Box::new(upstream).stop().await, 1);
+    log::info!("All done :)");
+}
+
+/// With --max-requests-per-connection 3, balancebeam should answer three requests on one
+/// keep-alive connection, mark the third response with Connection: close and then hang up, so a
+/// fourth request on that connection never reaches the upstream
+#[tokio::test]
+async fn test_max_requests_per_connection() {
+    init_logging();
+    let upstream = EchoServer::new().await;
+    let balancebeam = BalanceBeam::new_with_args(
+        &[&upstream.address],
+        &["--max-requests-per-connection", "3"],
+    )
+    .await;
+
+    let mut conn = TcpStream::connect(&balancebeam.address)
+        .await
+        .expect("Could not connect to balancebeam");
+    let mut received = Vec::new();
+    for i in 1..=3 {
+        let request = format!("GET /request-{} HTTP/1.1\r\nHost: localhost\r\n\r\n", i);
+        conn.write_all(request.as_bytes())
+            .await
+            .expect("Could not send request to balancebeam");
+        read_until(&mut conn, &mut received, format!("GET /request-{} HTTP/1.1", i).as_bytes()).await;
+    }
+
+    log::info!("Sending a fourth request on the same connection");
+    // balancebeam may already have closed the connection, in which case the write or the read
+    // fails; either way no response should arrive
+    let _ = conn
+        .write_all(b"GET /request-4 HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await;
+    let _ = timeout(Duration::from_secs(5), conn.read_to_end(&mut received))
+        .await
+        .expect("balancebeam did not close the connection after 3 requests");
+
+    let received = String::from_utf8_lossy(&received);
+    log::info!("Received: {:?}", received);
+    assert_eq!(received.matches("HTTP/1.1 200 OK\r\n").count(), 3);
+    assert!(!received.contains("/request-4"));
+    // Only the last response tells the client the connection is closing
+    let responses: Vec<&str> = received.split("HTTP/1.1 200 OK\r\n").skip(1).collect();
+    assert!(!responses[0].to_lowercase().contains("connection: close\r\n"));
+    assert!(!responses[1].to_lowercase().contains("connection: close\r\n"));
+    assert!(responses[2].to_lowercase().contains("connection: close\r\n"));
+
+    assert_eq!(Box::new(upstream).stop().await, 3);
+    log::info!("All done :)");
+}
+
+/// If the client hangs up partway through a streamed response, balancebeam should stop waiting for
+/// the rest of the body and close its upstream connection right away, rather than only noticing
+/// when it next has something to write
+#[tokio::test]
+async fn test_client_disconnect_closes_upstream() {
+    init_logging();
+    let upstream = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_address = upstream.local_addr().unwrap().to_string();
+    let balancebeam =
+        BalanceBeam::new_with_args(&[&upstream_address], &["--stream-threshold", "1000"]).await;
+
+    let mut client = TcpStream::connect(&balancebeam.address).await.unwrap();
+    client
+        .write_all(b"GET /download HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+    let (mut upstream_conn, _) = timeout(Duration::from_secs(2), upstream.accept())
+        .await
+        .expect("balancebeam never connected to the upstream")
+        .unwrap();
+    read_until(&mut upstream_conn, &mut Vec::new(), b"\r\n\r\n").await;
+    // Start a large body, then stall without sending the rest
+    upstream_conn
+        .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 1000000\r\n\r\nfirst part")
+        .await
+        .unwrap();
+    read_until(&mut client, &mut Vec::new(), b"first part").await;
+
+    log::info!("Resetting the client connection mid-response");
+    // Close with an RST: a plain close looks the same as a client that only shut down its write
+    // side, which balancebeam can't tell apart until it next writes
+    #[allow(deprecated)]
+    client.set_linger(Some(Duration::ZERO)).unwrap();
+    drop(client);
+    let mut buffer = [0_u8; 1024];
+    let read_result = timeout(Duration::from_secs(1), upstream_conn.read(&mut buffer))
+        .await
+        .expect("balancebeam kept the upstream connection open after the client disconnected");
+    assert!(matches!(read_result, Ok(0) | Err(_)), "{:?}", read_result);
+    log::info!("All done :)");
+}
+
+/// A client that shuts down its write side after sending the request is still waiting for the
+/// response, so a streamed body must be relayed in full rather than treated as a disconnect
+#[tokio::test]
+async fn test_half_closed_client_gets_streamed_response() {
+    init_logging();
+    let upstream = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_address = upstream.local_addr().unwrap().to_string();
+    let balancebeam =
+        BalanceBeam::new_with_args(&[&upstream_address], &["--stream-threshold", "10"]).await;
+
+    let mut client = TcpStream::connect(&balancebeam.address).await.unwrap();
+    client
+        .write_all(b"GET /download HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+    client.shutdown().await.unwrap();
+    let (mut upstream_conn, _) = timeout(Duration::from_secs(2), upstream.accept())
+        .await
+        .expect("balancebeam never connected to the upstream")
+        .unwrap();
+    read_until(&mut upstream_conn, &mut Vec::new(), b"\r\n\r\n").await;
+    upstream_conn
+        .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 21\r\n\r\nfirst part, ")
+        .await
+        .unwrap();
+    sleep(Duration::from_millis(200)).await;
+    upstream_conn.write_all(b"last part").await.unwrap();
+
+    let mut received = Vec::new();
+    timeout(Duration::from_secs(2), client.read_to_end(&mut received))
+        .await
+        .expect("balancebeam did not finish the response")
+        .unwrap();
+    let received = String::from_utf8_lossy(&received);
+    assert!(received.ends_with("\r\n\r\nfirst part, last part"), "{:?}", received);
+    log::info!("All done :)");
+}
+
+/// Settings can come from a --config file, with options given on the command line taking
+/// precedence. The test harness always passes --bind, so the bind address in the file is ignored
+#[tokio::test]
+async fn test_config_file() {
+    init_logging();
+    let upstream = EchoServer::new().await;
+    let config_file = std::env::temp_dir().join(format!(
+        "balancebeam-config-{}.toml",
+        rand::random::<u32>()
+    ));
+    std::fs::write(
+        &config_file,
+        format!(
+            "# balancebeam settings\nbind = \"127.0.0.1:1\"\nupstream = [\"{}\"]\n\
+             set-host = \"from-config.example.com\"\n",
+            upstream.address
+        ),
+    )
+    .expect("Could not write config file");
+    let balancebeam =
+        BalanceBeam::new_with_args(&[], &["--config", config_file.to_str().unwrap()]).await;
+
+    let response_text = balancebeam.get("/").await.expect("Error sending request to balancebeam");
+    assert!(
+        response_text.contains("host: from-config.example.com\n"),
+        "{}",
+        response_text
+    );
+    std::fs::remove_file(&config_file).unwrap();
+    assert_eq!(Box::new(upstream).stop().await, 1);
+    log::info!("All done :)");
+}
+
+/// Accept a connection on the fake upstream (unless one is given) and read one request head from it
+async fn accept_request(
+    upstream: &tokio::net::TcpListener,
+    upstream_conn: Option<TcpStream>,
+) -> (TcpStream, String) {
+    let mut upstream_conn = match upstream_conn {
+        Some(conn) => conn,
+        None => {
+            timeout(Duration::from_secs(2), upstream.accept())
+                .await
+                .expect("balancebeam never connected to the upstream")
+                .unwrap()
+                .0
+        }
+    };
+    let mut received = Vec::new();
+    timeout(
+        Duration::from_secs(2),
+        read_until(&mut upstream_conn, &mut received, b"\r\n\r\n"),
+    )
+    .await
+    .expect("balancebeam never forwarded the request");
+    (upstream_conn, String::from_utf8(received).unwrap().to_lowercase())
+}
+
+/// Without --upstream-keepalive, every forwarded request should tell the upstream to close the
+/// connection, whatever the client sent, and the upstream's Connection header should not leak
+/// into the response to the client
+#[tokio::test]
+async fn test_upstream_connection_close_by_default() {
+    init_logging();
+    let upstream = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_address = upstream.local_addr().unwrap().to_string();
+    let balancebeam = BalanceBeam::new(&[&upstream_address], None, None).await;
+
+    let mut client = TcpStream::connect(&balancebeam.address).await.unwrap();
+    for _ in 0..2 {
+        client
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: keep-alive\r\n\r\n")
+            .await
+            .unwrap();
+        // Each request arrives on a new upstream connection
+        let (mut upstream_conn, request) = accept_request(&upstream, None).await;
+        assert!(request.contains("connection: close\r\n"), "{}", request);
+        assert!(!request.contains("keep-alive"), "{}", request);
+        upstream_conn
+            .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok")
+            .await
+            .unwrap();
+        drop(upstream_conn);
+
+        let mut response = Vec::new();
+        timeout(Duration::from_secs(2), read_until(&mut client, &mut response, b"\r\n\r\nok"))
+            .await
+            .expect("No response from balancebeam");
+        let response = String::from_utf8(response).unwrap().to_lowercase();
+        assert!(!response.contains("connection:"), "{}", response);
+    }
+}
+
+/// With --upstream-keepalive, forwarded requests should ask the upstream to keep the connection
+/// open, and later requests (even from other clients) should reuse it. A client's own
+/// Connection: close should still close the client connection, but not the upstream one.
+#[tokio::test]
+async fn test_upstream_keepalive() {
+    init_logging();
+    let upstream = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_address = upstream.local_addr().unwrap().to_string();
+    let balancebeam =
+        BalanceBeam::new_with_args(&[&upstream_address], &["--upstream-keepalive"]).await;
+
+    let mut upstream_conn = None;
+    for i in 0..2 {
+        let mut client = TcpStream::connect(&balancebeam.address).await.unwrap();
+        client
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        // The second request must arrive on the connection the first one used
+        let (mut conn, request) = accept_request(&upstream, upstream_conn.take()).await;
+        assert!(request.contains("connection: keep-alive\r\n"), "request {}: {}", i, request);
+        assert!(!request.contains("close"), "request {}: {}", i, request);
+        conn.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok")
+            .await
+            .unwrap();
+        upstream_conn = Some(conn);
+
+        let mut response = Vec::new();
+        timeout(Duration::from_secs(2), client.read_to_end(&mut response))
+            .await
+            .expect("balancebeam did not close the client connection")
+            .unwrap();
+        let response = String::from_utf8(response).unwrap().to_lowercase();
+        assert!(response.contains("connection: close\r\n"), "{}", response);
+        assert!(response.ends_with("\r\n\r\nok"), "{}", response);
+    }
+}
+
+/// With --wait-for-upstream, balancebeam shouldn't accept connections until its upstream passes a
+/// health check, so the first clients don't get 502s from an upstream that is still starting
+#[tokio::test]
+async fn test_wait_for_upstream() {
+    init_logging();
+    // Pick an address for the upstream, but don't start it yet
+    let upstream_address = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .to_string();
+    let balancebeam =
+        BalanceBeam::new_with_args(&[&upstream_address], &["--wait-for-upstream"]).await;
+    assert!(
+        TcpStream::connect(&balancebeam.address).await.is_err(),
+        "balancebeam accepted a connection before any upstream was up"
+    );
+
+    log::info!("Starting the upstream");
+    let _upstream = EchoServer::new_at_address(upstream_address).await;
+    // Give balancebeam time for its next health check
+    sleep(Duration::from_secs(2)).await;
+    let response_text = balancebeam
+        .get("/first_url")
+        .await
+        .expect("Error sending request to balancebeam");
+    assert!(response_text.contains("GET /first_url HTTP/1.1"));
+}
+
+/// If no upstream comes up within --wait-for-upstream-timeout, balancebeam should start accepting
+/// connections anyway
+#[tokio::test]
+async fn test_wait_for_upstream_timeout() {
+    init_logging();
+    let upstream_address = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .to_string();
+    let balancebeam = BalanceBeam::new_with_args(
+        &[&upstream_address],
+        &["--wait-for-upstream", "--wait-for-upstream-timeout", "1"],
+    )
+    .await;
+    sleep(Duration::from_secs(1)).await;
+    let response = balancebeam
+        .send_raw(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .expect("balancebeam did not start accepting connections after the timeout");
+    assert!(response.starts_with("HTTP/1.1 502"), "{}", response);
+}
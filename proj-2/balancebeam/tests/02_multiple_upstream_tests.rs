@@ -1,9 +1,14 @@
 mod common;
 
-use common::{init_logging, BalanceBeam, EchoServer, ErrorServer, Server};
+use common::{init_logging, BalanceBeam, EchoServer, ErrorServer, RawServer, Server};
 
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
-use tokio::time::sleep;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::watch;
+use tokio::time::{sleep, timeout};
 
 async fn setup_with_params(
     n_upstreams: usize,
@@ -283,6 +288,16 @@ async fn test_rate_limiting() {
         log::info!("{:?}", response);
         log::info!("Checking to make sure the server responded with HTTP 429");
         assert_eq!(response.status().as_u16(), 429);
+        // The limit resets when the current one-minute window ends
+        let retry_after: u64 = response
+            .headers()
+            .get("retry-after")
+            .expect("429 response is missing Retry-After")
+            .to_str()
+            .unwrap()
+            .parse()
+            .expect("Retry-After should be a whole number of seconds");
+        assert!((1..=60).contains(&retry_after), "Retry-After: {}", retry_after);
     }
 
     log::info!("Ensuring the extra requests didn't go through to the upstream servers");
@@ -294,3 +309,710 @@ async fn test_rate_limiting() {
 
     log::info!("All done :)");
 }
+
+/// Send requests to a mix of a healthy upstream and one that only returns 500s, and make sure the
+/// stats endpoint reports the right status-code distribution for each
+#[tokio::test]
+async fn test_status_code_stats() {
+    init_logging();
+    let echo_server = EchoServer::new().await;
+    let error_server = ErrorServer::new().await;
+    let balancebeam = BalanceBeam::new_with_args(
+        &[&echo_server.address, &error_server.address],
+        &["--stats-path", "/__stats"],
+    )
+    .await;
+
+    let n_requests = 20;
+    let mut n_errors = 0;
+    let client = reqwest::Client::new();
+    for i in 0..n_requests {
+        let response = client
+            .get(&format!("http://{}/request-{}", balancebeam.address, i))
+            .send()
+            .await
+            .expect("Error sending request to balancebeam");
+        match response.status().as_u16() {
+            200 => {}
+            500 => n_errors += 1,
+            other => panic!("Unexpected status {} from balancebeam", other),
+        }
+    }
+
+    log::info!("Fetching stats from balancebeam");
+    let stats = balancebeam
+        .get("/__stats")
+        .await
+        .expect("Error fetching stats from balancebeam");
+    log::info!("Stats: {}", stats);
+    assert!(stats.contains(&format!(
+        "{} 2xx={} 3xx=0 4xx=0 5xx=0",
+        echo_server.address,
+        n_requests - n_errors
+    )));
+    assert!(stats.contains(&format!(
+        "{} 2xx=0 3xx=0 4xx=0 5xx={}",
+        error_server.address, n_errors
+    )));
+
+    log::info!("Checking that the stats request was not forwarded");
+    let total_request_count =
+        Box::new(echo_server).stop().await + Box::new(error_server).stop().await;
+    assert_eq!(total_request_count, n_requests);
+
+    log::info!("All done :)");
+}
+
+/// Make sure --max-5xx-before-eject takes an upstream that only returns 500s out of rotation once
+/// it hits the threshold, even though it still accepts connections
+#[tokio::test]
+async fn test_eject_upstream_after_consecutive_5xx() {
+    init_logging();
+    let eject_threshold = 2;
+    let echo_server = EchoServer::new().await;
+    let error_server = ErrorServer::new().await;
+    let balancebeam = BalanceBeam::new_with_args(
+        &[&echo_server.address, &error_server.address],
+        &["--max-5xx-before-eject", &eject_threshold.to_string()],
+    )
+    .await;
+
+    let n_requests = 20;
+    let mut n_errors = 0;
+    let client = reqwest::Client::new();
+    for i in 0..n_requests {
+        let response = client
+            .get(&format!("http://{}/request-{}", balancebeam.address, i))
+            .send()
+            .await
+            .expect("Error sending request to balancebeam");
+        if response.status().as_u16() == 500 {
+            n_errors += 1;
+        }
+    }
+
+    log::info!("Checking that the error server stopped getting requests after the threshold");
+    assert_eq!(
+        n_errors, eject_threshold,
+        "Expected exactly {} error responses before the upstream was ejected",
+        eject_threshold
+    );
+    let error_server_count = Box::new(error_server).stop().await;
+    assert_eq!(error_server_count, eject_threshold);
+    let echo_server_count = Box::new(echo_server).stop().await;
+    assert_eq!(echo_server_count, n_requests - eject_threshold);
+
+    log::info!("All done :)");
+}
+
+/// With a single upstream that only returns 500s, make sure an ejected upstream stays out of
+/// rotation even though it is the last one left and still accepts connections
+#[tokio::test]
+async fn test_eject_only_upstream_after_consecutive_5xx() {
+    init_logging();
+    let eject_threshold = 2;
+    let error_server = ErrorServer::new().await;
+    let balancebeam = BalanceBeam::new_with_args(
+        &[&error_server.address],
+        &["--max-5xx-before-eject", &eject_threshold.to_string()],
+    )
+    .await;
+
+    let n_requests = 6;
+    let client = reqwest::Client::new();
+    let mut statuses = Vec::new();
+    for i in 0..n_requests {
+        let response = client
+            .get(&format!("http://{}/request-{}", balancebeam.address, i))
+            .send()
+            .await
+            .expect("Error sending request to balancebeam");
+        if response.status().as_u16() == 502 {
+            // balancebeam closes the connection after the 502, so the client must not reuse it
+            assert_eq!(
+                response.headers().get("connection").map(|value| value.as_bytes()),
+                Some(&b"close"[..])
+            );
+        }
+        statuses.push(response.status().as_u16());
+    }
+
+    log::info!("Checking that balancebeam stopped forwarding after the threshold");
+    let mut expected = vec![500; eject_threshold];
+    expected.resize(n_requests, 502);
+    assert_eq!(statuses, expected);
+    let error_server_count = Box::new(error_server).stop().await;
+    assert_eq!(error_server_count, eject_threshold);
+
+    log::info!("All done :)");
+}
+
+/// Start with one upstream listed in --upstream-file, rewrite the file to list two different
+/// upstreams, send SIGHUP, and make sure requests go only to the new set
+#[tokio::test]
+async fn test_reload_upstream_file() {
+    init_logging();
+    let old_server = EchoServer::new().await;
+    let new_servers = vec![EchoServer::new().await, EchoServer::new().await];
+    let upstream_file = std::env::temp_dir().join(format!(
+        "balancebeam-upstreams-{}.txt",
+        rand::random::<u32>()
+    ));
+    std::fs::write(&upstream_file, format!("{}\n", old_server.address))
+        .expect("Could not write upstream file");
+    let balancebeam = BalanceBeam::new_with_args(
+        &[],
+        &["--upstream-file", upstream_file.to_str().unwrap()],
+    )
+    .await;
+
+    let n_requests = 10;
+    for i in 0..n_requests {
+        let path = format!("/before-reload-{}", i);
+        let response_text = balancebeam
+            .get(&path)
+            .await
+            .expect("Error sending request to balancebeam");
+        assert!(response_text.contains(&format!("GET {} HTTP/1.1", path)));
+    }
+
+    log::info!("Rewriting upstream file and sending SIGHUP");
+    let new_addresses: Vec<String> = new_servers.iter().map(|s| s.address.clone()).collect();
+    std::fs::write(&upstream_file, new_addresses.join("\n") + "\n")
+        .expect("Could not rewrite upstream file");
+    balancebeam.send_sighup();
+    sleep(Duration::from_millis(500)).await;
+
+    for i in 0..n_requests {
+        let path = format!("/after-reload-{}", i);
+        let response_text = balancebeam
+            .get(&path)
+            .await
+            .expect("Error sending request to balancebeam");
+        assert!(response_text.contains(&format!("GET {} HTTP/1.1", path)));
+    }
+    let _ = std::fs::remove_file(&upstream_file);
+
+    log::info!("Checking that the removed upstream got no requests after the reload");
+    assert_eq!(Box::new(old_server).stop().await, n_requests);
+    let mut new_request_count = 0;
+    for server in new_servers {
+        new_request_count += Box::new(server).stop().await;
+    }
+    assert_eq!(new_request_count, n_requests);
+
+    log::info!("All done :)");
+}
+
+/// With --sticky-cookie, requests carrying the same session cookie should all go to one upstream,
+/// and a client without the cookie should be given one
+#[tokio::test]
+async fn test_sticky_sessions() {
+    init_logging();
+    let n_requests = 20;
+    let mut upstreams: Vec<Box<dyn Server>> = Vec::new();
+    for _ in 0..3 {
+        upstreams.push(Box::new(EchoServer::new().await));
+    }
+    let upstream_addresses: Vec<String> = upstreams.iter().map(|u| u.address()).collect();
+    let upstream_addresses: Vec<&str> = upstream_addresses.iter().map(|a| a.as_str()).collect();
+    let balancebeam =
+        BalanceBeam::new_with_args(&upstream_addresses, &["--sticky-cookie", "SESSIONID"]).await;
+
+    log::info!("Checking that a client without a session gets a cookie");
+    let response = balancebeam
+        .send_raw(b"GET /first HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .await
+        .expect("Error sending request to balancebeam");
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert!(
+        response.to_ascii_lowercase().contains("set-cookie: sessionid="),
+        "No session cookie in response: {}",
+        response
+    );
+
+    log::info!("Sending requests with the same session cookie");
+    let client = reqwest::Client::new();
+    for i in 0..n_requests {
+        let response = client
+            .get(&format!("http://{}/request-{}", balancebeam.address, i))
+            .header("cookie", "theme=dark; SESSIONID=test-session")
+            .send()
+            .await
+            .expect("Error sending request to balancebeam");
+        assert_eq!(response.status().as_u16(), 200);
+        assert!(
+            response.headers().get("set-cookie").is_none(),
+            "Session cookie should not be set again"
+        );
+    }
+
+    let mut request_counters = Vec::new();
+    while let Some(upstream) = upstreams.pop() {
+        request_counters.insert(0, upstream.stop().await);
+    }
+    log::info!("Number of requests received by each upstream: {:?}", request_counters);
+    // The first request went to some upstream; every request with the cookie went to the same one
+    assert_eq!(request_counters.iter().sum::<usize>(), n_requests + 1);
+    assert!(
+        request_counters.iter().any(|&count| count >= n_requests),
+        "Requests with the same session cookie were spread across upstreams: {:?}",
+        request_counters
+    );
+
+    log::info!("All done :)");
+}
+
+/// Put an upstream whose health endpoint answers 204 next to one that answers 200, and make sure
+/// --health-check-expect-status decides which of them the active health checks keep in rotation
+#[tokio::test]
+async fn test_health_check_expect_status() {
+    init_logging();
+    let cases: [(&[&str], u16); 2] = [
+        (&[], 200),
+        (&["--health-check-expect-status", "204"], 204),
+    ];
+    for (extra_args, expected_status) in cases {
+        let echo_server = EchoServer::new().await;
+        let no_content_server = RawServer::new(b"HTTP/1.1 204 No Content\r\n\r\n").await;
+        let mut args = vec!["--active-health-check-interval", "1"];
+        args.extend_from_slice(extra_args);
+        let balancebeam = BalanceBeam::new_with_args(
+            &[&echo_server.address, &no_content_server.address],
+            &args,
+        )
+        .await;
+
+        log::info!("Waiting for health checks to run with {:?}", extra_args);
+        sleep(Duration::from_secs(3)).await;
+
+        log::info!("Checking that every request goes to the upstream returning {}", expected_status);
+        let client = reqwest::Client::new();
+        for i in 0..10 {
+            let response = client
+                .get(&format!("http://{}/request-{}", balancebeam.address, i))
+                .send()
+                .await
+                .expect("Error sending request to balancebeam");
+            assert_eq!(response.status().as_u16(), expected_status);
+        }
+
+        Box::new(echo_server).stop().await;
+        Box::new(no_content_server).stop().await;
+    }
+
+    log::info!("All done :)");
+}
+
+/// Put an upstream whose health endpoint reports it is degraded next to a healthy one, both
+/// answering 200, and make sure --health-check-expect-body keeps the degraded one out of rotation
+#[tokio::test]
+async fn test_health_check_expect_body() {
+    init_logging();
+    let degraded_server =
+        RawServer::new(b"HTTP/1.1 200 OK\r\nContent-Length: 16\r\n\r\nstatus: degraded").await;
+    let healthy_server =
+        RawServer::new(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nstatus: ok").await;
+    let balancebeam = BalanceBeam::new_with_args(
+        &[&degraded_server.address, &healthy_server.address],
+        &[
+            "--active-health-check-interval",
+            "1",
+            "--health-check-expect-body",
+            "status: ok",
+        ],
+    )
+    .await;
+
+    log::info!("Waiting for several rounds of health checks");
+    sleep(Duration::from_secs(3)).await;
+
+    log::info!("Checking that the degraded upstream stays out of rotation");
+    let client = reqwest::Client::new();
+    for i in 0..10 {
+        let body = client
+            .get(&format!("http://{}/request-{}", balancebeam.address, i))
+            .send()
+            .await
+            .expect("Error sending request to balancebeam")
+            .text()
+            .await
+            .expect("Balancebeam replied with a malformed response");
+        assert_eq!(body, "status: ok");
+    }
+    let output = balancebeam.output_lines();
+    assert!(
+        output.iter().any(|line| line.contains("response body does not contain \"status: ok\"")),
+        "{:#?}",
+        output
+    );
+
+    Box::new(degraded_server).stop().await;
+    Box::new(healthy_server).stop().await;
+    log::info!("All done :)");
+}
+
+/// Let the active health checks mark an unreachable upstream dead, then send SIGUSR2 and make sure
+/// the logged state snapshot shows which upstream is dead
+#[tokio::test]
+async fn test_state_dump_on_sigusr2() {
+    init_logging();
+    let echo_server = EchoServer::new().await;
+    // Nothing listens on this address once the listener is dropped
+    let dead_address = {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().to_string()
+    };
+    let balancebeam = BalanceBeam::new_with_args(
+        &[&echo_server.address, &dead_address],
+        &["--active-health-check-interval", "1"],
+    )
+    .await;
+
+    log::info!("Waiting for health checks to mark {} dead", dead_address);
+    sleep(Duration::from_secs(2)).await;
+    balancebeam.send_sigusr2();
+    sleep(Duration::from_millis(500)).await;
+
+    let output = balancebeam.output_lines();
+    let dump_line = |needle: &str| output.iter().any(|line| line.contains(needle));
+    assert!(dump_line("upstreams (2 total, 1 dead)"), "{:#?}", output);
+    assert!(dump_line(&format!("[0] {} alive", echo_server.address)));
+    assert!(dump_line(&format!("[1] {} dead", dead_address)));
+    assert!(dump_line("active connections: 0"));
+
+    Box::new(echo_server).stop().await;
+    log::info!("All done :)");
+}
+
+/// Send a request when the only upstream refuses connections, and make sure the connect failure
+/// and its error message show up in the stats endpoint and in the SIGUSR2 snapshot
+#[tokio::test]
+async fn test_connect_failures_recorded() {
+    init_logging();
+    let dead_address = {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().to_string()
+    };
+    let balancebeam =
+        BalanceBeam::new_with_args(&[&dead_address], &["--stats-path", "/__stats"]).await;
+
+    log::info!("Sending a request that can't be forwarded");
+    balancebeam
+        .get("/unreachable")
+        .await
+        .expect("Error sending request to balancebeam");
+
+    let stats = balancebeam
+        .get("/__stats")
+        .await
+        .expect("Error fetching stats from balancebeam");
+    log::info!("Stats: {}", stats);
+    let errors_line = stats
+        .lines()
+        .find(|line| line.starts_with(&format!("{} connect_failures=", dead_address)))
+        .expect("No error stats for the upstream");
+    let failures: usize = errors_line
+        .split_whitespace()
+        .find_map(|field| field.strip_prefix("connect_failures="))
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(failures >= 1, "{}", errors_line);
+    assert!(errors_line.contains("read_timeouts=0"), "{}", errors_line);
+    assert!(errors_line.contains("last_error=\"connect: "), "{}", errors_line);
+
+    balancebeam.send_sigusr2();
+    sleep(Duration::from_millis(500)).await;
+    let output = balancebeam.output_lines();
+    assert!(
+        output.iter().any(|line| line.contains(&format!("[0] {} dead connect_failures=", dead_address))),
+        "{:#?}",
+        output
+    );
+
+    log::info!("All done :)");
+}
+
+/// Start an upstream that takes `delay` to answer each request, always with a 500
+async fn start_slow_failing_upstream(delay: Duration) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    tokio::spawn(async move {
+        while let Ok((mut conn, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let mut buffer = [0_u8; 1024];
+                let _ = conn.read(&mut buffer).await;
+                sleep(delay).await;
+                let _ = conn
+                    .write_all(b"HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\n\r\n")
+                    .await;
+            });
+        }
+    });
+    address
+}
+
+/// With 16 upstreams that each take 400ms to answer, probing them one at a time would take 6.4s
+/// per cycle. With --health-check-concurrency 8 the first cycle should mark all of them dead in
+/// under a second, well within the 1 second health check interval
+#[tokio::test]
+async fn test_health_checks_run_concurrently() {
+    init_logging();
+    let mut addresses = Vec::new();
+    for _ in 0..16 {
+        addresses.push(start_slow_failing_upstream(Duration::from_millis(400)).await);
+    }
+    let upstreams: Vec<&str> = addresses.iter().map(String::as_str).collect();
+    let balancebeam = BalanceBeam::new_with_args(
+        &upstreams,
+        &[
+            "--active-health-check-interval",
+            "1",
+            "--health-check-concurrency",
+            "8",
+        ],
+    )
+    .await;
+
+    // BalanceBeam::new_with_args waits a second after starting balancebeam, so the first cycle
+    // starts about now and should take about 800ms
+    sleep(Duration::from_millis(1500)).await;
+    let output = balancebeam.output_lines();
+    let marked_dead = output
+        .iter()
+        .filter(|line| line.contains("failed a health check"))
+        .count();
+    assert_eq!(marked_dead, 16, "{:#?}", output);
+
+    log::info!("All done :)");
+}
+
+/// Start an upstream that answers every request with a chunked body, one single-byte chunk every
+/// 200ms. With `chunks` set it finishes the body after that many chunks, otherwise it never does
+async fn start_trickling_upstream(chunks: Option<usize>) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    tokio::spawn(async move {
+        while let Ok((mut conn, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let mut buffer = [0_u8; 1024];
+                let _ = conn.read(&mut buffer).await;
+                let headers = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n";
+                if conn.write_all(headers).await.is_err() {
+                    return;
+                }
+                let mut sent = 0;
+                while chunks.map_or(true, |chunks| sent < chunks) {
+                    if conn.write_all(b"1\r\nx\r\n").await.is_err() {
+                        return;
+                    }
+                    sent += 1;
+                    sleep(Duration::from_millis(200)).await;
+                }
+                let _ = conn.write_all(b"0\r\n\r\n").await;
+            });
+        }
+    });
+    address
+}
+
+/// Read from `client` until the body of a chunked response ends or the connection closes
+async fn read_chunked_response(client: &mut TcpStream) -> String {
+    let mut received = Vec::new();
+    let mut buffer = [0_u8; 1024];
+    while !received.ends_with(b"0\r\n\r\n") {
+        match client.read(&mut buffer).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => received.extend_from_slice(&buffer[..n]),
+        }
+    }
+    String::from_utf8(received).unwrap()
+}
+
+/// Start a slow request to the only upstream in --upstream-file, then reload the file so that
+/// upstream is removed. Returns the balancebeam instance, the client connection for the slow
+/// request and the address of the upstream that replaced it
+async fn start_request_and_remove_upstream(
+    chunks: Option<usize>,
+    drain_timeout: &str,
+) -> (BalanceBeam, TcpStream, EchoServer) {
+    let slow_address = start_trickling_upstream(chunks).await;
+    let new_server = EchoServer::new().await;
+    let upstream_file = std::env::temp_dir().join(format!(
+        "balancebeam-upstreams-{}.txt",
+        rand::random::<u32>()
+    ));
+    std::fs::write(&upstream_file, format!("{}\n", slow_address))
+        .expect("Could not write upstream file");
+    let balancebeam = BalanceBeam::new_with_args(
+        &[],
+        &[
+            "--upstream-file",
+            upstream_file.to_str().unwrap(),
+            "--drain-timeout",
+            drain_timeout,
+        ],
+    )
+    .await;
+
+    let mut client = TcpStream::connect(&balancebeam.address).await.unwrap();
+    client
+        .write_all(b"GET /slow HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+    sleep(Duration::from_millis(500)).await;
+
+    log::info!("Removing the slow upstream while its request is in flight");
+    std::fs::write(&upstream_file, format!("{}\n", new_server.address))
+        .expect("Could not rewrite upstream file");
+    balancebeam.send_sighup();
+    sleep(Duration::from_millis(200)).await;
+    let _ = std::fs::remove_file(&upstream_file);
+    (balancebeam, client, new_server)
+}
+
+/// A request that is in flight when its upstream is removed should still complete if it finishes
+/// within --drain-timeout, while new requests go to the remaining upstream
+#[tokio::test]
+async fn test_drain_timeout_lets_request_finish() {
+    init_logging();
+    // The slow upstream takes about 2 seconds to send its body
+    let (balancebeam, mut client, new_server) =
+        start_request_and_remove_upstream(Some(10), "5").await;
+
+    let response_text = balancebeam
+        .get("/after-reload")
+        .await
+        .expect("Error sending request to balancebeam");
+    assert!(response_text.contains("GET /after-reload HTTP/1.1"));
+
+    let response = timeout(Duration::from_secs(4), read_chunked_response(&mut client))
+        .await
+        .expect("The in-flight request did not finish within the drain window");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert_eq!(response.matches("1\r\nx\r\n").count(), 10);
+    assert!(response.ends_with("0\r\n\r\n"));
+    assert!(!balancebeam
+        .output_lines()
+        .iter()
+        .any(|line| line.contains("after the drain timeout")));
+
+    assert_eq!(Box::new(new_server).stop().await, 1);
+    log::info!("All done :)");
+}
+
+/// A request that is still in flight when --drain-timeout expires should be cut off
+#[tokio::test]
+async fn test_drain_timeout_closes_slow_request() {
+    init_logging();
+    let (balancebeam, mut client, _new_server) = start_request_and_remove_upstream(None, "1").await;
+
+    let response = timeout(Duration::from_secs(3), read_chunked_response(&mut client))
+        .await
+        .expect("balancebeam kept relaying the body past --drain-timeout");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("1\r\nx\r\n"));
+    assert!(!response.ends_with("0\r\n\r\n"));
+    let output = balancebeam.output_lines();
+    assert!(
+        output
+            .iter()
+            .any(|line| line.contains("Closing 1 in-flight request(s)")),
+        "{:#?}",
+        output
+    );
+    log::info!("All done :)");
+}
+
+/// Start an upstream that answers each request with an empty 200, but only once `true` has been
+/// sent on the returned channel. Returns its address, a counter of the requests it received and
+/// the sender that releases them
+async fn start_held_upstream() -> (String, Arc<AtomicUsize>, watch::Sender<bool>) {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let requests = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&requests);
+    let (release, released) = watch::channel(false);
+    tokio::spawn(async move {
+        while let Ok((mut conn, _)) = listener.accept().await {
+            let counter = Arc::clone(&counter);
+            let mut released = released.clone();
+            tokio::spawn(async move {
+                let mut buffer = [0_u8; 1024];
+                let _ = conn.read(&mut buffer).await;
+                counter.fetch_add(1, Ordering::SeqCst);
+                let _ = released.wait_for(|released| *released).await;
+                let _ = conn
+                    .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                    .await;
+            });
+        }
+    });
+    (address, requests, release)
+}
+
+/// With --load-balance-algorithm least-connections, requests sent while the slow upstream is
+/// holding earlier requests open should go to the fast one, which is idle again as soon as it has
+/// answered. Random selection would split them roughly evenly
+#[tokio::test]
+async fn test_least_connections() {
+    init_logging();
+    let (fast_address, fast_requests, release_fast) = start_held_upstream().await;
+    release_fast.send(true).unwrap();
+    let (slow_address, slow_requests, release_slow) = start_held_upstream().await;
+    let balancebeam = BalanceBeam::new_with_args(
+        &[&fast_address, &slow_address],
+        &["--load-balance-algorithm", "least-connections"],
+    )
+    .await;
+
+    let n_requests = 10;
+    let mut held_requests = Vec::new();
+    for i in 0..n_requests {
+        let url = format!("http://{}/request-{}", balancebeam.address, i);
+        let slow_before = slow_requests.load(Ordering::SeqCst);
+        let request = tokio::spawn(async move { reqwest::get(&url).await });
+        // Wait for the request to reach an upstream. If it went to the fast one, let it finish, so
+        // that only the slow upstream's requests are in flight when the next one is sent
+        timeout(Duration::from_secs(5), async {
+            while fast_requests.load(Ordering::SeqCst) + slow_requests.load(Ordering::SeqCst) <= i {
+                sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("Request never reached an upstream");
+        if slow_requests.load(Ordering::SeqCst) == slow_before {
+            let response = request
+                .await
+                .unwrap()
+                .expect("Error sending request to balancebeam");
+            assert_eq!(response.status().as_u16(), 200);
+        } else {
+            held_requests.push(request);
+        }
+    }
+    release_slow.send(true).unwrap();
+    for request in held_requests {
+        let response = request
+            .await
+            .unwrap()
+            .expect("Error sending request to balancebeam");
+        assert_eq!(response.status().as_u16(), 200);
+    }
+
+    // The slow upstream is only chosen on a tie, which can't happen once it holds more requests
+    // than the fast one ever has in flight
+    let slow_count = slow_requests.load(Ordering::SeqCst);
+    let fast_count = fast_requests.load(Ordering::SeqCst);
+    log::info!("Fast upstream: {} requests, slow upstream: {}", fast_count, slow_count);
+    assert_eq!(fast_count + slow_count, n_requests);
+    assert!(
+        fast_count > slow_count,
+        "fast upstream got {} of {} requests",
+        fast_count,
+        n_requests
+    );
+
+    log::info!("All done :)");
+}
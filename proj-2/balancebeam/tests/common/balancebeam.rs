@@ -1,6 +1,8 @@
 use rand::Rng;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
-use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
 use tokio::process::{Child, Command};
 use tokio::time::sleep;
 
@@ -8,6 +10,7 @@ pub struct BalanceBeam {
     #[allow(dead_code)]
     child: Child, // process is killed when dropped (Command::kill_on_drop)
     pub address: String,
+    output: Arc<Mutex<Vec<String>>>,
 }
 
 impl BalanceBeam {
@@ -24,6 +27,22 @@ impl BalanceBeam {
         active_health_check_interval: Option<usize>,
         max_requests_per_minute: Option<usize>,
     ) -> BalanceBeam {
+        let mut extra_args = Vec::new();
+        if let Some(active_health_check_interval) = active_health_check_interval {
+            extra_args.push("--active-health-check-interval".to_string());
+            extra_args.push(active_health_check_interval.to_string());
+        }
+        if let Some(max_requests_per_minute) = max_requests_per_minute {
+            extra_args.push("--max-requests-per-minute".to_string());
+            extra_args.push(max_requests_per_minute.to_string());
+        }
+        let extra_args: Vec<&str> = extra_args.iter().map(|arg| arg.as_str()).collect();
+        BalanceBeam::new_with_args(upstreams, &extra_args).await
+    }
+
+    /// Starts balancebeam with the given upstreams, passing any additional command-line flags
+    /// through verbatim.
+    pub async fn new_with_args(upstreams: &[&str], extra_args: &[&str]) -> BalanceBeam {
         let mut rng = rand::thread_rng();
         let address = format!("127.0.0.1:{}", rng.gen_range(1024..65535));
         let mut cmd = Command::new(BalanceBeam::target_bin_path());
@@ -31,14 +50,7 @@ impl BalanceBeam {
         for upstream in upstreams {
             cmd.arg("--upstream").arg(upstream);
         }
-        if let Some(active_health_check_interval) = active_health_check_interval {
-            cmd.arg("--active-health-check-interval")
-                .arg(active_health_check_interval.to_string());
-        }
-        if let Some(max_requests_per_minute) = max_requests_per_minute {
-            cmd.arg("--max-requests-per-minute")
-                .arg(max_requests_per_minute.to_string());
-        }
+        cmd.args(extra_args);
         cmd.kill_on_drop(true);
         cmd.stdout(std::process::Stdio::piped());
         cmd.stderr(std::process::Stdio::piped());
@@ -50,10 +62,12 @@ impl BalanceBeam {
         // Print output from the child. We want to intercept and log this output (instead of letting
         // the child inherit stderr and print directly to the terminal) so that the output can be
         // suppressed if the test passes and displayed if it fails.
+        let output = Arc::new(Mutex::new(Vec::new()));
         let stdout = child
             .stdout
             .take()
             .expect("Child process somehow missing stdout pipe!");
+        let stdout_output = output.clone();
         tokio::spawn(async move {
             let mut stdout_reader = BufReader::new(stdout).lines();
             while let Some(line) = stdout_reader
@@ -62,12 +76,14 @@ impl BalanceBeam {
                 .expect("I/O error reading from child stdout")
             {
                 println!("Balancebeam output: {}", line);
+                stdout_output.lock().unwrap().push(line);
             }
         });
         let stderr = child
             .stderr
             .take()
             .expect("Child process somehow missing stderr pipe!");
+        let stderr_output = output.clone();
         tokio::spawn(async move {
             let mut stderr_reader = BufReader::new(stderr).lines();
             while let Some(line) = stderr_reader
@@ -76,12 +92,17 @@ impl BalanceBeam {
                 .expect("I/O error reading from child stderr")
             {
                 println!("Balancebeam output: {}", line);
+                stderr_output.lock().unwrap().push(line);
             }
         });
 
         // Hack: wait for executable to start running
         sleep(Duration::from_secs(1)).await;
-        BalanceBeam { child, address }
+        BalanceBeam {
+            child,
+            address,
+            output,
+        }
     }
 
     #[allow(dead_code)]
@@ -98,9 +119,20 @@ impl BalanceBeam {
 
     #[allow(dead_code)]
     pub async fn post(&self, path: &str, body: &str) -> Result<String, reqwest::Error> {
+        self.send_with_body(reqwest::Method::POST, path, body).await
+    }
+
+    /// Sends a request with the given method and body, returning the response body.
+    #[allow(dead_code)]
+    pub async fn send_with_body(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+        body: &str,
+    ) -> Result<String, reqwest::Error> {
         let client = reqwest::Client::new();
         client
-            .post(&format!("http://{}{}", self.address, path))
+            .request(method, &format!("http://{}{}", self.address, path))
             .header("x-sent-by", "balancebeam-tests")
             .body(body.to_string())
             .send()
@@ -108,4 +140,40 @@ impl BalanceBeam {
             .text()
             .await
     }
+
+    /// Returns every line balancebeam has printed so far (stdout and stderr interleaved).
+    #[allow(dead_code)]
+    pub fn output_lines(&self) -> Vec<String> {
+        self.output.lock().unwrap().clone()
+    }
+
+    /// Sends SIGHUP to balancebeam, asking it to reload its --upstream-file.
+    #[allow(dead_code)]
+    pub fn send_sighup(&self) {
+        self.send_signal(nix::sys::signal::Signal::SIGHUP);
+    }
+
+    /// Sends SIGUSR2 to balancebeam, asking it to log a snapshot of its state.
+    #[allow(dead_code)]
+    pub fn send_sigusr2(&self) {
+        self.send_signal(nix::sys::signal::Signal::SIGUSR2);
+    }
+
+    fn send_signal(&self, signal: nix::sys::signal::Signal) {
+        let pid = self.child.id().expect("balancebeam has already exited");
+        nix::sys::signal::kill(nix::unistd::Pid::from_raw(pid as i32), signal)
+            .unwrap_or_else(|err| panic!("Could not send {} to balancebeam: {}", signal, err));
+    }
+
+    /// Sends raw bytes to balancebeam over a fresh connection, then closes our side of the
+    /// connection and returns everything balancebeam sent back before hanging up.
+    #[allow(dead_code)]
+    pub async fn send_raw(&self, request: &[u8]) -> std::io::Result<String> {
+        let mut conn = TcpStream::connect(&self.address).await?;
+        conn.write_all(request).await?;
+        conn.shutdown().await?;
+        let mut received = Vec::new();
+        conn.read_to_end(&mut received).await?;
+        Ok(String::from_utf8_lossy(&received).to_string())
+    }
 }
@@ -1,6 +1,8 @@
 mod balancebeam;
 mod echo_server;
 mod error_server;
+pub mod flaky_server;
+mod raw_server;
 mod server;
 
 use std::sync;
@@ -8,6 +10,7 @@ use std::sync;
 pub use balancebeam::BalanceBeam;
 pub use echo_server::EchoServer;
 pub use error_server::ErrorServer;
+pub use raw_server::RawServer;
 pub use server::Server;
 
 static INIT_TESTS: sync::Once = sync::Once::new();
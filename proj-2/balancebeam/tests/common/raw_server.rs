@@ -0,0 +1,89 @@
+use crate::common::server::Server;
+use async_trait::async_trait;
+use rand::Rng;
+use std::sync::{atomic, Arc};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::oneshot;
+
+/// A server that answers every request with the exact same canned bytes and then hangs up. Unlike
+/// the hyper-based servers, it doesn't care whether the response is valid for the request, which
+/// makes it useful for simulating misbehaving upstreams.
+pub struct RawServer {
+    shutdown_signal_sender: oneshot::Sender<()>,
+    server_task: tokio::task::JoinHandle<()>,
+    pub address: String,
+    requests_received: Arc<atomic::AtomicUsize>,
+}
+
+impl RawServer {
+    #[allow(dead_code)]
+    pub async fn new(response: &[u8]) -> RawServer {
+        let mut rng = rand::thread_rng();
+        let bind_addr_string = format!("127.0.0.1:{}", rng.gen_range(1024..65535));
+        // Create a one-shot channel that can be used to tell the server to shut down
+        let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+        let requests_received = Arc::new(atomic::AtomicUsize::new(0));
+        let server_task_requests_received = requests_received.clone();
+        let response = Arc::new(response.to_vec());
+
+        let listener = TcpListener::bind(&bind_addr_string).await.unwrap();
+
+        let server_task = tokio::spawn(async move {
+            let mut shutdown_rx = shutdown_rx;
+            loop {
+                tokio::select! {
+                    accept_result = listener.accept() => {
+                        match accept_result {
+                            Ok((mut stream, _)) => {
+                                let requests_received = server_task_requests_received.clone();
+                                let response = response.clone();
+                                tokio::spawn(async move {
+                                    // Read until the end of the request headers, then reply
+                                    let mut request = Vec::new();
+                                    let mut buffer = [0_u8; 512];
+                                    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+                                        match stream.read(&mut buffer).await {
+                                            Ok(0) | Err(_) => return,
+                                            Ok(n) => request.extend_from_slice(&buffer[..n]),
+                                        }
+                                    }
+                                    requests_received.fetch_add(1, atomic::Ordering::SeqCst);
+                                    let _ = stream.write_all(&response).await;
+                                });
+                            }
+                            Err(e) => {
+                                log::error!("Error accepting connection: {}", e);
+                            }
+                        }
+                    }
+                    _ = &mut shutdown_rx => {
+                        break;
+                    }
+                }
+            }
+        });
+
+        RawServer {
+            shutdown_signal_sender: shutdown_tx,
+            server_task,
+            address: bind_addr_string,
+            requests_received,
+        }
+    }
+}
+
+#[async_trait]
+impl Server for RawServer {
+    async fn stop(self: Box<Self>) -> usize {
+        let _ = self.shutdown_signal_sender.send(());
+        self.server_task
+            .await
+            .expect("RawServer server task panicked");
+        self.requests_received.load(atomic::Ordering::SeqCst)
+    }
+
+    fn address(&self) -> String {
+        self.address.clone()
+    }
+}
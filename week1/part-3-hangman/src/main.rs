@@ -14,29 +14,146 @@
 // more in depth in the coming lectures.
 extern crate rand;
 use rand::Rng;
+use std::env;
 use std::fs;
 use std::io;
+use std::io::IsTerminal;
 use std::io::Write;
 
 const NUM_INCORRECT_GUESSES: u32 = 5;
+// --score mode: points lost for a letter that isn't in the word, and for
+// guessing a letter that was already guessed this round
+const WRONG_GUESS_PENALTY: i32 = 2;
+const REPEAT_GUESS_PENALTY: i32 = 3;
 const WORDS_PATH: &str = "words.txt";
+// ANSI escape codes for the word display (same 256-color codes as inspect-fds)
+const REVEALED_COLOR: &str = "\x1B[38;5;10m"; // green
+const HIDDEN_COLOR: &str = "\x1B[38;5;8m"; // gray
+const CLEAR_COLOR: &str = "\x1B[0m";
 
-fn pick_a_random_word() -> String {
+// Read every word from words.txt.
+fn load_words() -> Vec<String> {
     let file_string = fs::read_to_string(WORDS_PATH).expect("Unable to read file.");
-    let words: Vec<&str> = file_string.split('\n').collect();
-    String::from(words[rand::thread_rng().gen_range(0, words.len())].trim())
+    parse_words(&file_string)
+}
+
+// Split a word list into words, one per line. Blank and whitespace-only lines
+// (such as the one after a trailing newline) are skipped so they can never
+// become a zero-length secret word.
+fn parse_words(contents: &str) -> Vec<String> {
+    contents
+        .split('\n')
+        .map(|word| word.trim())
+        .filter(|word| !word.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+// Keep only the words whose length falls within [min_length, max_length].
+// A bound of None means that side is unconstrained.
+fn filter_by_length(
+    words: &[String],
+    min_length: Option<usize>,
+    max_length: Option<usize>,
+) -> Vec<String> {
+    words
+        .iter()
+        .filter(|word| {
+            let len = word.chars().count();
+            len >= min_length.unwrap_or(0) && len <= max_length.unwrap_or(usize::MAX)
+        })
+        .cloned()
+        .collect()
+}
+
+// Command-line options. A length bound of None means that side is unconstrained.
+#[derive(Debug, Default, PartialEq)]
+struct Options {
+    min_length: Option<usize>,
+    max_length: Option<usize>,
+    score: bool,
+}
+
+// Parse the optional --min-length / --max-length / --score flags from the
+// command line.
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut i = 0;
+    while i < args.len() {
+        let flag = args[i].as_str();
+        if flag == "--score" {
+            options.score = true;
+            i += 1;
+            continue;
+        }
+        if flag != "--min-length" && flag != "--max-length" {
+            return Err(format!("Unrecognized argument: {}", flag));
+        }
+        let value = args
+            .get(i + 1)
+            .ok_or(format!("{} requires a value", flag))?
+            .parse::<usize>()
+            .map_err(|_| format!("{} must be a non-negative integer", flag))?;
+        if flag == "--min-length" {
+            options.min_length = Some(value);
+        } else {
+            options.max_length = Some(value);
+        }
+        i += 2;
+    }
+    Ok(options)
+}
+
+// Pick up to n distinct words from candidates, in random order. Returns all of
+// the candidates (shuffled) if there are fewer than n.
+fn pick_n_random_words(candidates: &[String], n: usize) -> Vec<String> {
+    let mut pool: Vec<&String> = candidates.iter().collect();
+    let n = n.min(pool.len());
+    let mut rng = rand::thread_rng();
+    // Partial Fisher-Yates shuffle: only the first n slots need to be random
+    for i in 0..n {
+        let j = rng.gen_range(i, pool.len());
+        pool.swap(i, j);
+    }
+    pool.into_iter().take(n).cloned().collect()
+}
+
+// Pick a random word out of the (already filtered) candidate list, or None if
+// the list is empty.
+fn pick_a_random_word(candidates: &[String]) -> Option<String> {
+    pick_n_random_words(candidates, 1).pop()
+}
+
+// Like pick_a_random_word, but never picks a word in exclude (e.g. the previous
+// round's word) unless every candidate is excluded.
+fn pick_a_random_word_excluding(candidates: &[String], exclude: &[String]) -> Option<String> {
+    let allowed: Vec<String> = candidates
+        .iter()
+        .filter(|word| !exclude.contains(word))
+        .cloned()
+        .collect();
+    if allowed.is_empty() {
+        pick_a_random_word(candidates)
+    } else {
+        pick_a_random_word(&allowed)
+    }
 }
 
 // Read a single valid letter from stdin. Re-prompts until the user
-// enters exactly one alphabetic character. Returns the lowercase char.
-fn read_guess() -> char {
+// enters exactly one alphabetic character. Returns the lowercase char,
+// or None if stdin has been closed.
+fn read_guess() -> Option<char> {
     loop {
         print!("Please guess a letter: ");
         io::stdout().flush().expect("Error flushing stdout.");
         let mut guess = String::new();
-        io::stdin()
+        let bytes_read = io::stdin()
             .read_line(&mut guess)
             .expect("Error reading line.");
+        if bytes_read == 0 {
+            println!();
+            return None;
+        }
         let guess = guess.trim();
 
         if guess.len() != 1 {
@@ -50,33 +167,164 @@ fn read_guess() -> char {
             continue;
         }
         println!();
-        return ch.to_ascii_lowercase();
+        return Some(ch.to_ascii_lowercase());
     }
 }
 
-fn main() {
-    let secret_word = pick_a_random_word();
+// Ask whether the player wants another round. Re-prompts until the user
+// answers y or n. Treats a closed stdin as "no".
+fn ask_play_again() -> bool {
+    loop {
+        print!("Play again? (y/n) ");
+        io::stdout().flush().expect("Error flushing stdout.");
+        let mut answer = String::new();
+        let bytes_read = io::stdin()
+            .read_line(&mut answer)
+            .expect("Error reading line.");
+        if bytes_read == 0 {
+            println!();
+            return false;
+        }
+        match answer.trim().to_ascii_lowercase().as_str() {
+            "y" | "yes" => return true,
+            "n" | "no" => return false,
+            _ => println!("Please answer y or n."),
+        }
+    }
+}
+
+// Cumulative win/loss record across the rounds of one session.
+#[derive(Debug, Default, PartialEq)]
+struct Stats {
+    wins: u32,
+    losses: u32,
+}
+
+impl Stats {
+    fn record(&mut self, won: bool) {
+        if won {
+            self.wins += 1;
+        } else {
+            self.losses += 1;
+        }
+    }
+
+    fn rounds_played(&self) -> u32 {
+        self.wins + self.losses
+    }
+}
+
+// How many points a correct guess of letter is worth: its Scrabble tile value,
+// so rarer letters are worth more. Anything without a tile scores 1.
+fn letter_points(letter: char) -> i32 {
+    match letter {
+        'd' | 'g' => 2,
+        'b' | 'c' | 'm' | 'p' => 3,
+        'f' | 'h' | 'v' | 'w' | 'y' => 4,
+        'k' => 5,
+        'j' | 'x' => 8,
+        'q' | 'z' => 10,
+        _ => 1,
+    }
+}
+
+// The score change for guessing letter in --score mode. A letter in the word
+// earns its points once, however many times it appears; a letter that isn't
+// costs WRONG_GUESS_PENALTY, and repeating any earlier guess (already_guessed)
+// costs REPEAT_GUESS_PENALTY.
+fn score_guess(letter: char, word: &str, already_guessed: &[char]) -> i32 {
+    if already_guessed.contains(&letter) {
+        -REPEAT_GUESS_PENALTY
+    } else if word.contains(letter) {
+        letter_points(letter)
+    } else {
+        -WRONG_GUESS_PENALTY
+    }
+}
+
+// Render the word so far as space-separated letters, e.g. "_ a _ _". When
+// is_tty is set, revealed letters are shown in green and blanks in gray;
+// otherwise the output is plain text so it stays readable when piped.
+fn render_progress(guessed: &[char], is_tty: bool) -> String {
+    guessed
+        .iter()
+        .map(|&ch| {
+            if !is_tty {
+                ch.to_string()
+            } else if ch == '_' {
+                format!("{}{}{}", HIDDEN_COLOR, ch, CLEAR_COLOR)
+            } else {
+                format!("{}{}{}", REVEALED_COLOR, ch, CLEAR_COLOR)
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+// What a guess did to the round in progress.
+#[derive(Debug, PartialEq)]
+enum GuessOutcome {
+    // The letter was guessed before; nothing changes and no guess is used up
+    AlreadyGuessed,
+    Correct,
+    Incorrect,
+}
+
+// Apply one guess to the round: record it in have_guessed and reveal every
+// position of guess in guessed_word. A letter that is already in have_guessed
+// has been fully resolved by the earlier guess, so it is left alone.
+fn apply_guess(
+    guess: char,
+    secret_word_chars: &[char],
+    guessed_word: &mut [char],
+    have_guessed: &mut Vec<char>,
+) -> GuessOutcome {
+    if have_guessed.contains(&guess) {
+        return GuessOutcome::AlreadyGuessed;
+    }
+    have_guessed.push(guess);
+    let mut found = false;
+    for (i, &ch) in secret_word_chars.iter().enumerate() {
+        if ch == guess {
+            guessed_word[i] = guess;
+            found = true;
+        }
+    }
+    if found {
+        GuessOutcome::Correct
+    } else {
+        GuessOutcome::Incorrect
+    }
+}
+
+// Play a single game of hangman with the given secret word.
+// Returns true if the player guessed the word. Running out of input
+// mid-round counts as a loss. In --score mode, each guess's score_guess is
+// added to score.
+fn play_round(secret_word: &str, mut score: Option<&mut i32>) -> bool {
     let secret_word_chars: Vec<char> = secret_word.chars().collect();
     let mut have_guessed: Vec<char> = vec![];
-    println!("Welcome to CS110L Hangman!");
-    let mut guessed_word: Vec<char> = vec!['_'; secret_word.len()];
+    println!("The secret word has {} letters.", secret_word_chars.len());
+    let mut guessed_word: Vec<char> = vec!['_'; secret_word_chars.len()];
     let mut can_guesses = NUM_INCORRECT_GUESSES;
+    let is_tty = io::stdout().is_terminal();
     while guessed_word != secret_word_chars && can_guesses > 0 {
-        println!("The word so far is {:?}", guessed_word);
+        println!("The word so far is {}", render_progress(&guessed_word, is_tty));
         println!("You have guessed the following letters: {:?}", have_guessed);
         println!("You have {} guesses left", can_guesses);
-        let guess_char = read_guess();
-        have_guessed.push(guess_char);
-        let mut flag = false;
-        for i in 0..secret_word.len() {
-            if secret_word_chars[i] == guess_char && guessed_word[i] == '_' {
-                guessed_word[i] = guess_char;
-                flag = true;
-                break;
-            }
+        let guess_char = match read_guess() {
+            Some(ch) => ch,
+            None => return false,
+        };
+        if let Some(score) = score.as_deref_mut() {
+            let points = score_guess(guess_char, secret_word, &have_guessed);
+            *score += points;
+            println!("{:+} points (score: {})", points, score);
         }
-        if !flag {
-            can_guesses -= 1;
+        match apply_guess(guess_char, &secret_word_chars, &mut guessed_word, &mut have_guessed) {
+            GuessOutcome::AlreadyGuessed => println!("You already guessed '{}'.", guess_char),
+            GuessOutcome::Correct => {}
+            GuessOutcome::Incorrect => can_guesses -= 1,
         }
     }
 
@@ -85,7 +333,261 @@ fn main() {
             "Congratulations you guessed the secret word: {:?}!",
             guessed_word
         );
+        true
     } else {
         println!("Sorry, you ran out of guesses!");
+        false
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(err) => {
+            println!("{}", err);
+            println!("Usage: hangman [--min-length N] [--max-length N] [--score]");
+            std::process::exit(1);
+        }
+    };
+    let words = load_words();
+    if words.is_empty() {
+        println!("{} does not contain any words.", WORDS_PATH);
+        std::process::exit(1);
+    }
+    let candidates = filter_by_length(&words, options.min_length, options.max_length);
+    if candidates.is_empty() {
+        println!("No words in {} match the requested length constraints.", WORDS_PATH);
+        std::process::exit(1);
+    }
+
+    println!("Welcome to CS110L Hangman!");
+    let mut stats = Stats::default();
+    let mut score = 0;
+    let mut previous_word: Vec<String> = vec![];
+    loop {
+        // Don't give the player the same word twice in a row
+        let secret_word = pick_a_random_word_excluding(&candidates, &previous_word)
+            .expect("candidates is not empty");
+        let round_score = if options.score { Some(&mut score) } else { None };
+        stats.record(play_round(&secret_word, round_score));
+        previous_word = vec![secret_word];
+        if !ask_play_again() {
+            break;
+        }
+    }
+    println!(
+        "Thanks for playing! You won {} and lost {} of {} rounds.",
+        stats.wins,
+        stats.losses,
+        stats.rounds_played()
+    );
+    if options.score {
+        println!("Final score: {}", score);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn word_list() -> Vec<String> {
+        vec!["ox", "cat", "fish", "horse", "lobster"]
+            .into_iter()
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn test_filter_by_length_range() {
+        assert_eq!(
+            filter_by_length(&word_list(), Some(3), Some(4)),
+            vec!["cat", "fish"]
+        );
+    }
+
+    #[test]
+    fn test_filter_by_length_open_bounds() {
+        assert_eq!(filter_by_length(&word_list(), None, None), word_list());
+        assert_eq!(filter_by_length(&word_list(), Some(5), None), vec!["horse", "lobster"]);
+        assert_eq!(filter_by_length(&word_list(), None, Some(2)), vec!["ox"]);
+    }
+
+    #[test]
+    fn test_filter_by_length_no_match() {
+        assert!(filter_by_length(&word_list(), Some(8), None).is_empty());
+        assert!(filter_by_length(&word_list(), Some(4), Some(3)).is_empty());
+    }
+
+    #[test]
+    fn test_pick_a_random_word_from_candidates() {
+        let candidates = filter_by_length(&word_list(), Some(3), Some(4));
+        for _ in 0..20 {
+            let word = pick_a_random_word(&candidates).unwrap();
+            assert!(candidates.contains(&word));
+        }
+    }
+
+    #[test]
+    fn test_parse_words_skips_blank_lines() {
+        let words = parse_words("cat\n\n  \nfish\r\n\t\nox\n");
+        assert_eq!(words, vec!["cat", "fish", "ox"]);
+        assert!(parse_words("\n\n").is_empty());
+        for _ in 0..20 {
+            assert!(!pick_a_random_word(&words).unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_pick_from_empty_list() {
+        assert_eq!(pick_a_random_word(&[]), None);
+        assert_eq!(pick_a_random_word_excluding(&[], &word_list()), None);
+        assert!(pick_n_random_words(&[], 3).is_empty());
+        assert!(parse_words("").is_empty());
+    }
+
+    #[test]
+    fn test_pick_a_random_word_excluding() {
+        let candidates = parse_words("cat\n\nfish\n");
+        let previous = vec![String::from("cat")];
+        for _ in 0..20 {
+            assert_eq!(
+                pick_a_random_word_excluding(&candidates, &previous),
+                Some(String::from("fish"))
+            );
+        }
+        // With only one word there is nothing else to pick
+        let candidates = parse_words("cat\n");
+        assert_eq!(
+            pick_a_random_word_excluding(&candidates, &previous),
+            Some(String::from("cat"))
+        );
+    }
+
+    #[test]
+    fn test_pick_n_random_words() {
+        let mut picked = pick_n_random_words(&word_list(), 3);
+        assert_eq!(picked.len(), 3);
+        picked.sort();
+        picked.dedup();
+        assert_eq!(picked.len(), 3);
+        assert!(picked.iter().all(|word| word_list().contains(word)));
+
+        let mut all = pick_n_random_words(&word_list(), 10);
+        all.sort();
+        let mut expected = word_list();
+        expected.sort();
+        assert_eq!(all, expected);
+    }
+
+    #[test]
+    fn test_render_progress_plain() {
+        let rendered = render_progress(&['_', 'a', '_', '_'], false);
+        assert_eq!(rendered, "_ a _ _");
+        assert!(!rendered.contains('\x1B'));
+        assert_eq!(render_progress(&[], false), "");
+    }
+
+    #[test]
+    fn test_render_progress_colored() {
+        assert_eq!(
+            render_progress(&['_', 'a'], true),
+            format!("{}_{} {}a{}", HIDDEN_COLOR, CLEAR_COLOR, REVEALED_COLOR, CLEAR_COLOR)
+        );
+    }
+
+    #[test]
+    fn test_apply_guess() {
+        let secret: Vec<char> = "hello".chars().collect();
+        let mut guessed_word = vec!['_'; secret.len()];
+        let mut have_guessed = vec![];
+        // Every occurrence is revealed at once
+        assert_eq!(
+            apply_guess('l', &secret, &mut guessed_word, &mut have_guessed),
+            GuessOutcome::Correct
+        );
+        assert_eq!(guessed_word, vec!['_', '_', 'l', 'l', '_']);
+        assert_eq!(
+            apply_guess('z', &secret, &mut guessed_word, &mut have_guessed),
+            GuessOutcome::Incorrect
+        );
+        assert_eq!(have_guessed, vec!['l', 'z']);
+    }
+
+    #[test]
+    fn test_apply_guess_repeat() {
+        let secret: Vec<char> = "hello".chars().collect();
+        let mut guessed_word = vec!['_'; secret.len()];
+        let mut have_guessed = vec![];
+        apply_guess('l', &secret, &mut guessed_word, &mut have_guessed);
+        apply_guess('z', &secret, &mut guessed_word, &mut have_guessed);
+        // Repeating either a hit or a miss changes nothing
+        for &letter in ['l', 'z'].iter() {
+            assert_eq!(
+                apply_guess(letter, &secret, &mut guessed_word, &mut have_guessed),
+                GuessOutcome::AlreadyGuessed
+            );
+        }
+        assert_eq!(guessed_word, vec!['_', '_', 'l', 'l', '_']);
+        assert_eq!(have_guessed, vec!['l', 'z']);
+    }
+
+    #[test]
+    fn test_stats_accounting() {
+        let mut stats = Stats::default();
+        assert_eq!(stats.rounds_played(), 0);
+        for &won in [true, false, false, true, true].iter() {
+            stats.record(won);
+        }
+        assert_eq!(stats, Stats { wins: 3, losses: 2 });
+        assert_eq!(stats.rounds_played(), 5);
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args: Vec<String> = vec!["--min-length", "3", "--max-length", "6"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        assert_eq!(
+            parse_args(&args),
+            Ok(Options { min_length: Some(3), max_length: Some(6), score: false })
+        );
+        assert_eq!(parse_args(&[]), Ok(Options::default()));
+        assert!(parse_args(&[String::from("--min-length")]).is_err());
+        assert!(parse_args(&[String::from("--min-length"), String::from("x")]).is_err());
+    }
+
+    #[test]
+    fn test_parse_args_score() {
+        let args: Vec<String> = vec!["--score", "--max-length", "6"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        assert_eq!(
+            parse_args(&args),
+            Ok(Options { min_length: None, max_length: Some(6), score: true })
+        );
+    }
+
+    #[test]
+    fn test_score_correct_guess() {
+        assert_eq!(score_guess('e', "cheese", &[]), 1);
+        // Rarer letters are worth more
+        assert_eq!(score_guess('z', "pizza", &['p']), 10);
+        // A letter appearing several times is only counted once
+        assert_eq!(score_guess('s', "grass", &[]), letter_points('s'));
+    }
+
+    #[test]
+    fn test_score_incorrect_guess() {
+        assert_eq!(score_guess('q', "cheese", &['c']), -WRONG_GUESS_PENALTY);
+    }
+
+    #[test]
+    fn test_score_repeat_guess() {
+        // Repeating a letter costs points whether or not it was in the word
+        assert_eq!(score_guess('c', "cheese", &['c', 'x']), -REPEAT_GUESS_PENALTY);
+        assert_eq!(score_guess('x', "cheese", &['c', 'x']), -REPEAT_GUESS_PENALTY);
     }
 }
@@ -1,39 +1,299 @@
 use std::env;
-use std::fs::File; // For read_file_lines()
-use std::io::{self, BufRead}; // For read_file_lines()
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead};
+use std::ops::{Add, AddAssign};
 use std::process;
 
-fn read_file_lines(filename: &String) -> Result<Vec<String>, io::Error> {
-    let file = File::open(filename)?;
-    let mut l = Vec::new();
-    for line in io::BufReader::new(file).lines() {
-        let line_str = line?;
-        l.push(line_str);
+/// 一个输入的统计结果
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct Counts {
+    /// 换行符的个数（与 wc 相同，最后一行没有换行符时不计入）
+    lines: usize,
+    /// 以空白字符分隔的单词个数
+    words: usize,
+    /// UTF-8 字符个数（无效的 UTF-8 序列按替换字符计数）
+    chars: usize,
+    /// 字节数
+    bytes: usize,
+}
+
+impl AddAssign for Counts {
+    fn add_assign(&mut self, other: Counts) {
+        self.lines += other.lines;
+        self.words += other.words;
+        self.chars += other.chars;
+        self.bytes += other.bytes;
+    }
+}
+
+impl Add for Counts {
+    type Output = Counts;
+
+    fn add(mut self, other: Counts) -> Counts {
+        self += other;
+        self
+    }
+}
+
+impl fmt::Display for Counts {
+    /// 格式类似 wc 命令：行数 字数 字符数 字节数
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {} {}", self.lines, self.words, self.chars, self.bytes)
+    }
+}
+
+/// 边读边统计：输入按块送进来，只保留跨越两块的那个字符的几个字节，所以内存占用与输入（包括其中最长的一行）
+/// 的大小无关
+#[derive(Debug, Default)]
+struct Counter {
+    counts: Counts,
+    /// 上一个字符是否属于一个单词（单词可能跨越两块）
+    in_word: bool,
+    /// 上一块末尾一个还不完整的 UTF-8 字符的字节（最多 3 个）
+    pending: Vec<u8>,
+}
+
+impl Counter {
+    fn add_char(&mut self, c: char) {
+        self.counts.chars += 1;
+        // 与 split_whitespace 相同：一个单词从空白字符之后的第一个非空白字符开始
+        let whitespace = c.is_whitespace();
+        if !whitespace && !self.in_word {
+            self.counts.words += 1;
+        }
+        self.in_word = !whitespace;
+    }
+
+    fn add_text(&mut self, text: &str) {
+        for c in text.chars() {
+            self.add_char(c);
+        }
+    }
+
+    /// 统计下一块输入。无效的 UTF-8 序列与 String::from_utf8_lossy 一样按替换字符计数
+    fn add_bytes(&mut self, mut bytes: &[u8]) {
+        self.counts.bytes += bytes.len();
+        self.counts.lines += bytes.iter().filter(|&&byte| byte == b'\n').count();
+
+        // 先用这一块开头的字节补全上一块末尾被截断的字符
+        while !self.pending.is_empty() {
+            let byte = match bytes.first() {
+                Some(&byte) => byte,
+                None => return,
+            };
+            self.pending.push(byte);
+            match std::str::from_utf8(&self.pending) {
+                Ok(text) => {
+                    let c = text.chars().next().unwrap();
+                    self.pending.clear();
+                    self.add_char(c);
+                    bytes = &bytes[1..];
+                }
+                // 还不完整，继续补
+                Err(err) if err.error_len().is_none() => bytes = &bytes[1..],
+                // 被截断的字节本身就是无效序列；刚加入的这个字节不属于它，留给下面正常处理
+                Err(_) => {
+                    self.pending.clear();
+                    self.add_char(char::REPLACEMENT_CHARACTER);
+                }
+            }
+        }
+
+        loop {
+            match std::str::from_utf8(bytes) {
+                Ok(text) => return self.add_text(text),
+                Err(err) => {
+                    let (valid, rest) = bytes.split_at(err.valid_up_to());
+                    self.add_text(std::str::from_utf8(valid).unwrap());
+                    match err.error_len() {
+                        Some(len) => {
+                            self.add_char(char::REPLACEMENT_CHARACTER);
+                            bytes = &rest[len..];
+                        }
+                        // 这一块在一个字符中间结束
+                        None => return self.pending.extend_from_slice(rest),
+                    }
+                }
+            }
+        }
+    }
+
+    fn finish(mut self) -> Counts {
+        // 输入在一个字符中间结束
+        if !self.pending.is_empty() {
+            self.add_char(char::REPLACEMENT_CHARACTER);
+        }
+        self.counts
+    }
+}
+
+/// 读取 reader 中的所有内容，一次遍历统计所有字段。按 reader 缓冲区大小的块读取而不是按行读取，所以即使是
+/// 没有换行符的巨大文件也只占用固定大小的内存；不是有效 UTF-8 的输入也可以统计。
+fn count<R: BufRead>(mut reader: R) -> io::Result<Counts> {
+    let mut counter = Counter::default();
+    loop {
+        let chunk = match reader.fill_buf() {
+            Ok(chunk) => chunk,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        if chunk.is_empty() {
+            return Ok(counter.finish());
+        }
+        counter.add_bytes(chunk);
+        let len = chunk.len();
+        reader.consume(len);
     }
-    Ok(l)
+}
+
+fn count_file(filename: &str) -> io::Result<Counts> {
+    count(io::BufReader::new(File::open(filename)?))
 }
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
+    let filenames: Vec<String> = env::args().skip(1).collect();
+    if filenames.is_empty() {
         println!("Too few arguments.");
         process::exit(1);
     }
-    let filename = &args[1];
-    let lines = read_file_lines(filename).unwrap();
 
-    let mut line_count = 0;
-    let mut word_count = 0;
-    let mut char_count = 0;
+    let mut total = Counts::default();
+    let mut failed = false;
+    for filename in &filenames {
+        match count_file(filename) {
+            Ok(counts) => {
+                println!("{} {}", counts, filename);
+                total += counts;
+            }
+            Err(err) => {
+                println!("{}: {}", filename, err);
+                failed = true;
+            }
+        }
+    }
+    // 与 wc 相同，统计多个文件时最后输出总数
+    if filenames.len() > 1 {
+        println!("{} total", total);
+    }
+    if failed {
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn count_str(input: &str) -> Counts {
+        count(input.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_empty_input() {
+        assert_eq!(count_str(""), Counts::default());
+    }
+
+    #[test]
+    fn test_lines() {
+        assert_eq!(count_str("a\nb\nc\n").lines, 3);
+        // 最后一行没有换行符
+        assert_eq!(count_str("a\nb").lines, 1);
+        assert_eq!(count_str("\n\n").lines, 2);
+    }
 
-    for line in lines {
-        line_count += 1;
-        // 统计字符数（包括换行符）
-        char_count += line.len() + 1; // +1 for newline character
-                                      // 统计字数（按空白字符分割）
-        word_count += line.split_whitespace().count();
+    #[test]
+    fn test_words() {
+        assert_eq!(count_str("hello world\n").words, 2);
+        assert_eq!(count_str("  leading\tand   trailing  \n\nnext line").words, 5);
+        assert_eq!(count_str(" \n\t\n").words, 0);
     }
 
-    // 输出统计结果，格式类似 wc 命令：行数 字数 字符数 文件名
-    println!("{} {} {} {}", line_count, word_count, char_count, filename);
+    #[test]
+    fn test_chars_and_bytes() {
+        let counts = count_str("héllo 世界\n");
+        assert_eq!(counts.chars, 9);
+        assert_eq!(counts.bytes, "héllo 世界\n".len());
+        assert_eq!(counts.bytes, 14);
+
+        let counts = count(&b"ab\xff\n"[..]).unwrap();
+        assert_eq!(counts.chars, 4);
+        assert_eq!(counts.bytes, 4);
+    }
+
+    #[test]
+    fn test_sum() {
+        let first = count_str("one two\nthree\n");
+        let second = count_str("four\n");
+        assert_eq!(
+            first + second,
+            Counts {
+                lines: 3,
+                words: 4,
+                chars: 19,
+                bytes: 19,
+            }
+        );
+        let mut total = Counts::default();
+        total += first;
+        total += second;
+        assert_eq!(total, first + second);
+        assert_eq!(total.to_string(), "3 4 19 19");
+    }
+
+    #[test]
+    fn test_characters_split_across_chunks() {
+        let input = ["héllo 世界 😀\nab".as_bytes(), b"\xff\xe4\xb8 next \xe4"].concat();
+        let expected = count(&input[..]).unwrap();
+        assert_eq!(
+            expected,
+            Counts {
+                lines: 1,
+                words: 6,
+                chars: 22,
+                bytes: input.len(),
+            }
+        );
+        // 块的边界落在多字节字符、无效序列和单词中间时结果不变
+        for capacity in 1..=5 {
+            let reader = io::BufReader::with_capacity(capacity, &input[..]);
+            assert_eq!(count(reader).unwrap(), expected, "capacity {}", capacity);
+        }
+    }
+
+    /// 不停产生 "word " 的 reader，总共 len 个字节，没有换行符。数据是现生成的，所以整个输入从来不在内存中
+    struct RepeatedWords {
+        remaining: usize,
+        offset: usize,
+    }
+
+    impl io::Read for RepeatedWords {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(self.remaining);
+            for byte in &mut buf[..len] {
+                *byte = b"word "[self.offset];
+                self.offset = (self.offset + 1) % 5;
+            }
+            self.remaining -= len;
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn test_huge_single_line() {
+        let len = 50 * 1024 * 1024;
+        let reader = io::BufReader::new(RepeatedWords {
+            remaining: len,
+            offset: 0,
+        });
+        assert_eq!(
+            count(reader).unwrap(),
+            Counts {
+                lines: 0,
+                words: len / 5,
+                chars: len,
+                bytes: len,
+            }
+        );
+    }
 }
@@ -0,0 +1,25 @@
+#include <unistd.h>
+#include <sys/wait.h>
+
+int main() {
+    int fds[2];
+    pipe(fds);
+    pid_t pid = fork();
+    if (pid == 0) {
+        pid_t grandchild = fork();
+        if (grandchild == 0) {
+            dup2(fds[0], STDIN_FILENO);
+            close(fds[0]);
+            close(fds[1]);
+            sleep(2);
+            return 0;
+        }
+        close(fds[0]);
+        close(fds[1]);
+        waitpid(grandchild, NULL, 0);
+        return 0;
+    }
+    close(fds[0]);
+    waitpid(pid, NULL, 0);
+    return 0;
+}
@@ -6,11 +6,16 @@ mod ps_utils;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        println!("Usage: {} <name or pid of target>", args[0]);
-        std::process::exit(1);
-    }
-    let target = &args[1];
+    // --recursive：除了直接的子进程，还打印所有后代进程打开的文件。不同进程中指向同一个管道的 fd
+    // 颜色相同，所以可以看出整个进程树中的进程是怎样通过管道连接的
+    let (recursive, target) = match &args[1..] {
+        [target] => (false, target),
+        [flag, target] if flag == "--recursive" => (true, target),
+        _ => {
+            println!("Usage: {} [--recursive] <name or pid of target>", args[0]);
+            std::process::exit(1);
+        }
+    };
 
     // Milestone 1: 使用 ps_utils::get_target() 获取目标进程
     match ps_utils::get_target(target) {
@@ -19,8 +24,12 @@ fn main() {
             process.print();
             
             // Milestone 5: 获取并打印子进程
-            let child_processes = ps_utils::get_child_processes(process.pid)
-                .expect("Failed to get child processes");
+            let child_processes = if recursive {
+                ps_utils::get_descendant_processes(process.pid)
+            } else {
+                ps_utils::get_child_processes(process.pid)
+            }
+            .expect("Failed to get child processes");
             
             for child in child_processes {
                 child.print();
@@ -62,6 +71,23 @@ mod test {
             "We expected the program to exit normally, but it didn't."
         );
         let _ = subprocess.kill();
+        let _ = subprocess.wait();
+    }
+
+    #[test]
+    fn test_recursive_lists_descendants() {
+        let mut subprocess = start_c_program("./pipe_tree_test");
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        let output = Command::new("./target/debug/inspect-fds")
+            .args(["--recursive", &subprocess.id().to_string()])
+            .output()
+            .expect("Could not find target/debug/inspect-fds. Is the binary compiled?");
+        let _ = subprocess.kill();
+        let _ = subprocess.wait();
+        assert!(output.status.success());
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let headers = stdout.lines().filter(|line| line.starts_with("==========")).count();
+        assert_eq!(headers, 3, "{}", stdout);
     }
 
     #[test]
@@ -140,6 +140,22 @@ impl OpenFile {
         })
     }
 
+    /// 读取 /proc/{pid}/fd 目录，对其中的每个 fd 调用 from_fd，返回按 fd 编号排序的
+    /// (fd 编号, OpenFile) 列表。无法获取信息的 fd 会被跳过（例如在我们读取目录之后刚好被关闭的 fd）；
+    /// 如果无法读取这个目录（例如进程已经退出），则返回空列表。
+    pub fn all_for_pid(pid: usize) -> Vec<(usize, OpenFile)> {
+        let entries = match fs::read_dir(format!("/proc/{}/fd", pid)) {
+            Ok(entries) => entries,
+            Err(_) => return Vec::new(),
+        };
+        let mut open_files: Vec<(usize, OpenFile)> = entries
+            .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<usize>().ok())
+            .filter_map(|fd| Some((fd, OpenFile::from_fd(pid, fd)?)))
+            .collect();
+        open_files.sort_by_key(|(fd, _)| *fd);
+        open_files
+    }
+
     /// 这个函数返回带有 ANSI 转义码的 OpenFile 名称，用于对管道名称进行着色。
     /// 它对管道名称进行哈希处理，使得相同的管道名称总是产生相同的颜色。
     /// 这对于使程序输出更易读很有用，因为用户可以快速看到指向特定管道的所有 fd。
@@ -179,6 +195,19 @@ mod test {
         assert_eq!(open_file.cursor, 0);
         assert_eq!(open_file.access_mode, AccessMode::ReadWrite);
         let _ = test_subprocess.kill();
+        let _ = test_subprocess.wait();
+    }
+
+    #[test]
+    fn test_openfile_all_for_pid() {
+        // 查看测试进程自己，这样不会留下 multi_pipe_test 的子进程干扰其他测试按名字查找进程
+        let open_files = OpenFile::all_for_pid(std::process::id() as usize);
+        let fds: Vec<usize> = open_files.iter().map(|(fd, _)| *fd).collect();
+        // 至少有标准输入、标准输出和标准错误，并且按 fd 编号排序
+        assert_eq!(&fds[..3], &[0, 1, 2]);
+        assert!(fds.windows(2).all(|pair| pair[0] < pair[1]));
+        // 不存在的进程没有打开的文件
+        assert!(OpenFile::all_for_pid(usize::MAX).is_empty());
     }
 
     #[test]
@@ -191,5 +220,6 @@ mod test {
             "Expected None because file descriptor 30 is invalid"
         );
         let _ = test_subprocess.kill();
+        let _ = test_subprocess.wait();
     }
 }
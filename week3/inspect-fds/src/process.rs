@@ -1,5 +1,5 @@
 use crate::open_file::OpenFile;
-use std::fs;
+use std::{fmt, fs};
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Process {
@@ -33,45 +33,80 @@ impl Process {
 
         Some(fds)
     }
-    /// This function returns a list of (fdnumber, OpenFile) tuples, if file descriptor
-    /// information is available (it returns None otherwise). The information is commonly
-    /// unavailable if the process has already exited.
+    /// This function returns a list of (fdnumber, OpenFile) tuples sorted by fd, if file
+    /// descriptor information is available (it returns None otherwise). The information is
+    /// commonly unavailable if the process has already exited. Fds that close while we're
+    /// looking at them are left out.
     pub fn list_open_files(&self) -> Option<Vec<(usize, OpenFile)>> {
-        let mut open_files = vec![];
-        for fd in self.list_fds()? {
-            open_files.push((fd, OpenFile::from_fd(self.pid, fd)?));
-        }
-        Some(open_files)
+        // list_fds tells us whether the fd table is available at all
+        self.list_fds()?;
+        Some(OpenFile::all_for_pid(self.pid))
+    }
+
+    pub fn print(&self) {
+        print!("{}", self);
     }
+}
 
-    pub fn print(&self){
-        println!("========== {} (pid {}, ppid {}) ==========",self.command,self.pid,self.ppid);
+/// Renders the process header followed by one line per open fd (fd, access mode, cursor and
+/// colorized name), with the columns padded to line up across all of the process's fds.
+impl fmt::Display for Process {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "========== {} (pid {}, ppid {}) ==========",
+            self.command, self.pid, self.ppid
+        )?;
 
-        match self.list_open_files() {
-            None => println!(
-                "Warning: could not inspect file descriptors for this process! \
-                It might have exited just as we were about to look at its fd table, \
-                or it might have exited a while ago and is waiting for the parent \
-                to reap it."
-            ),
-            Some(open_files) => {
-                for (fd, file) in open_files {
-                    println!(
-                        "{:<4} {:<15} cursor: {:<4} {}",
-                        fd,
-                        format!("({})", file.access_mode),
-                        file.cursor,
-                        file.colorized_name(),
-                    );
-                }
+        let open_files = match self.list_open_files() {
+            Some(open_files) => open_files,
+            None => {
+                return writeln!(
+                    f,
+                    "Warning: could not inspect file descriptors for this process! \
+                    It might have exited just as we were about to look at its fd table, \
+                    or it might have exited a while ago and is waiting for the parent \
+                    to reap it."
+                )
             }
+        };
+        let rows: Vec<(String, String, String, String)> = open_files
+            .iter()
+            .map(|(fd, file)| {
+                (
+                    fd.to_string(),
+                    format!("({})", file.access_mode),
+                    file.cursor.to_string(),
+                    file.colorized_name(),
+                )
+            })
+            .collect();
+        let fd_width = rows.iter().map(|row| row.0.len()).max().unwrap_or(0);
+        let mode_width = rows.iter().map(|row| row.1.len()).max().unwrap_or(0);
+        let cursor_width = rows.iter().map(|row| row.2.len()).max().unwrap_or(0);
+        for (fd, mode, cursor, name) in rows {
+            writeln!(
+                f,
+                "{:<fd_width$} {:<mode_width$} cursor: {:<cursor_width$} {}",
+                fd,
+                mode,
+                cursor,
+                name,
+                fd_width = fd_width,
+                mode_width = mode_width,
+                cursor_width = cursor_width,
+            )?;
         }
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod test {
+    use super::*;
     use crate::ps_utils;
+    use nix::sys::signal::{self, Signal};
+    use nix::unistd::Pid;
     use std::process::{Child, Command};
 
     fn start_c_program(program: &str) -> Child {
@@ -91,6 +126,70 @@ mod test {
             vec![0, 1, 2, 4, 5]
         );
         let _ = test_subprocess.kill();
+        let _ = test_subprocess.wait();
+    }
+
+    #[test]
+    fn test_display() {
+        let mut test_subprocess = start_c_program("./multi_pipe_test");
+        // Give it time to set up its pipes
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        let process = ps_utils::get_target(&test_subprocess.id().to_string()).unwrap().unwrap();
+        let output = process.to_string();
+        // Kill the forked child first so multi_pipe_test reaps it, instead of leaving an orphan
+        // that other tests would find when they look up multi_pipe_test by name
+        for child in ps_utils::get_child_processes(process.pid).unwrap() {
+            let _ = signal::kill(Pid::from_raw(child.pid as i32), Signal::SIGKILL);
+        }
+        let _ = test_subprocess.kill();
+        let _ = test_subprocess.wait();
+        let mut lines = output.lines();
+        let header = format!(
+            "========== {} (pid {}, ppid {}) ==========",
+            process.command, process.pid, process.ppid
+        );
+        assert_eq!(lines.next(), Some(header.as_str()));
+        // One line per fd, in fd order: 4 is the write end of the first pipe, 5 the read end of
+        // the second
+        let fd_lines: Vec<&str> = lines.collect();
+        let columns: Vec<Vec<&str>> =
+            fd_lines.iter().map(|line| line.split_whitespace().collect()).collect();
+        let fds: Vec<&str> = columns.iter().map(|columns| columns[0]).collect();
+        assert_eq!(fds, vec!["0", "1", "2", "4", "5"]);
+        assert_eq!(&columns[3][1..4], &["(write)", "cursor:", "0"]);
+        assert!(fd_lines[3].contains("<pipe #"), "{:?}", fd_lines[3]);
+        assert_eq!(&columns[4][1..4], &["(read)", "cursor:", "0"]);
+        assert!(fd_lines[4].contains("<pipe #"), "{:?}", fd_lines[4]);
+        // The columns line up
+        let cursor_column: Vec<Option<usize>> =
+            fd_lines.iter().map(|line| line.find("cursor:")).collect();
+        assert!(cursor_column.iter().all(|column| *column == cursor_column[0]), "{:?}", fd_lines);
+    }
+
+    #[test]
+    fn test_shared_pipe_same_color() {
+        let mut test_subprocess = start_c_program("./pipe_tree_test");
+        // Give the child and grandchild time to be forked and set up their fds
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        let parent = ps_utils::get_target(&test_subprocess.id().to_string()).unwrap().unwrap();
+        let descendants = ps_utils::get_descendant_processes(parent.pid).unwrap();
+        let grandchild = &descendants[1];
+        let pipe_name = |process: &Process, fd: usize| -> String {
+            let open_files = process.list_open_files().unwrap();
+            let (_, file) = open_files.iter().find(|(open_fd, _)| *open_fd == fd).unwrap();
+            file.colorized_name()
+        };
+        // The parent writes to the pipe on fd 4 and the grandchild reads it on stdin
+        let parent_pipe = pipe_name(&parent, 4);
+        let grandchild_pipe = pipe_name(grandchild, 0);
+        let parent_output = parent.to_string();
+        let grandchild_output = grandchild.to_string();
+        let _ = test_subprocess.kill();
+        let _ = test_subprocess.wait();
+        assert!(parent_pipe.contains("<pipe #"), "{:?}", parent_pipe);
+        assert_eq!(parent_pipe, grandchild_pipe);
+        assert!(parent_output.contains(&parent_pipe), "{}", parent_output);
+        assert!(grandchild_output.contains(&parent_pipe), "{}", grandchild_output);
     }
 
     #[test]
@@ -102,5 +201,6 @@ mod test {
             "Expected list_fds to return None for a zombie process"
         );
         let _ = test_subprocess.kill();
+        let _ = test_subprocess.wait();
     }
 }
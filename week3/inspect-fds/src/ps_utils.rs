@@ -1,7 +1,17 @@
 use crate::process::Process;
 use nix::unistd::getuid;
 use std::fmt;
-use std::process::Command;
+use std::io::{self, Read};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// 默认 ps/pgrep 最多运行多长时间，超过时把它杀死
+const DEFAULT_COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+/// 默认 ps/pgrep 超时或者无法执行时最多尝试几次
+const DEFAULT_COMMAND_ATTEMPTS: usize = 3;
+/// 等待命令结束时检查它是否已经退出的间隔
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
 
 /// 这个枚举表示可能发生错误的原因。它很有用，因为它允许 API 的调用者根据出错的具体情况
 /// 对错误处理进行细粒度控制。你可以在 Rust 库中找到类似的想法，例如 std::io:
@@ -44,6 +54,70 @@ impl From<std::num::ParseIntError> for Error {
     }
 }
 
+/// 运行 ps/pgrep 时的超时时间和最多尝试次数。系统负载很高时 ps/pgrep 可能卡住，
+/// 有了这些限制，inspect-fds 不会跟着一起卡住。
+#[derive(Debug, Clone)]
+pub struct CommandLimits {
+    /// 每次运行最多等待多长时间
+    pub timeout: Duration,
+    /// 超时或者无法执行时最多尝试几次（至少一次）
+    pub attempts: usize,
+}
+
+impl Default for CommandLimits {
+    fn default() -> CommandLimits {
+        CommandLimits {
+            timeout: DEFAULT_COMMAND_TIMEOUT,
+            attempts: DEFAULT_COMMAND_ATTEMPTS,
+        }
+    }
+}
+
+/// 运行一次命令并返回它的标准输出。命令在 timeout 之内没有结束时把它杀死，并返回 TimedOut 错误。
+fn run_once(program: &str, args: &[&str], timeout: Duration) -> io::Result<Vec<u8>> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    // 在另一个线程中读取输出，这样即使输出很多，命令也不会因为管道写满而阻塞
+    let mut stdout = child.stdout.take().unwrap();
+    let reader = thread::spawn(move || {
+        let mut output = Vec::new();
+        stdout.read_to_end(&mut output).map(|_| output)
+    });
+    let start = Instant::now();
+    while child.try_wait()?.is_none() {
+        if start.elapsed() >= timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("{} did not finish within {:?}", program, timeout),
+            ));
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+    reader
+        .join()
+        .unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::Other, "Error reading output")))
+}
+
+/// 运行命令并返回它的标准输出（不检查退出状态：例如 pgrep 没有找到进程时返回 1）。
+/// 命令超时或者无法执行时重试，直到尝试了 limits.attempts 次；仍然失败时返回最后一次的
+/// Error::ExecutableError。
+fn run_command(program: &str, args: &[&str], limits: &CommandLimits) -> Result<Vec<u8>, Error> {
+    let mut last_error = None;
+    for _ in 0..limits.attempts.max(1) {
+        match run_once(program, args, limits.timeout) {
+            Ok(output) => return Ok(output),
+            Err(err) => last_error = Some(err),
+        }
+    }
+    Err(Error::ExecutableError(last_error.unwrap()))
+}
+
 /// 这个函数接收一行用 -o "pid= ppid= command=" 格式化的 ps 输出，
 /// 并返回一个从解析的输出初始化的 Process 结构体。
 ///
@@ -72,12 +146,11 @@ fn get_process(pid: usize) -> Result<Option<Process>, Error> {
     // 运行 ps 来查找指定的 pid。我们使用 ? 运算符在执行 ps 失败或返回非 utf-8 输出时返回 Error。
     // (上面的额外 Error trait 用于自动将像 std::io::Error 或 std::string::FromUtf8Error 
     // 这样的错误转换为我们的自定义错误类型。)
-    let output = String::from_utf8(
-        Command::new("ps")
-            .args(&["--pid", &pid.to_string(), "-o", "pid= ppid= command="])
-            .output()?
-            .stdout,
-    )?;
+    let output = String::from_utf8(run_command(
+        "ps",
+        &["--pid", &pid.to_string(), "-o", "pid= ppid= command="],
+        &CommandLimits::default(),
+    )?)?;
     // 如果找到了进程并且输出解析成功，则返回 Some；如果 ps 没有产生输出（表示没有匹配的进程），
     // 则返回 None。注意使用 ? 来传播在解析输出时发生的错误。
     if output.trim().len() > 0 {
@@ -91,25 +164,39 @@ fn get_process(pid: usize) -> Result<Option<Process>, Error> {
 /// 列表中包含所有以指定 pid 为父进程的进程。
 /// 如果 ps 无法执行或产生意外的输出格式，则返回 Error。
 pub fn get_child_processes(pid: usize) -> Result<Vec<Process>, Error> {
-    let ps_output = Command::new("ps")
-        .args(&["--ppid", &pid.to_string(), "-o", "pid= ppid= command="])
-        .output()?;
+    let ps_output = run_command(
+        "ps",
+        &["--ppid", &pid.to_string(), "-o", "pid= ppid= command="],
+        &CommandLimits::default(),
+    )?;
     let mut output = Vec::new();
-    for line in String::from_utf8(ps_output.stdout)?.lines() {
+    for line in String::from_utf8(ps_output)?.lines() {
         output.push(parse_ps_line(line)?);
     }
     Ok(output)
 }
 
+/// 这个函数接收一个 pid 并返回它的所有后代进程（子进程、子进程的子进程……）。列表按深度优先的先序排列，
+/// 所以每个进程都排在它的父进程之后，并且一个子进程的所有后代都排在下一个子进程之前。
+/// 如果 ps 无法执行或产生意外的输出格式，则返回 Error。
+pub fn get_descendant_processes(pid: usize) -> Result<Vec<Process>, Error> {
+    let mut descendants = Vec::new();
+    for child in get_child_processes(pid)? {
+        let child_pid = child.pid;
+        descendants.push(child);
+        descendants.extend(get_descendant_processes(child_pid)?);
+    }
+    Ok(descendants)
+}
+
 /// 这个函数接收一个命令名（例如 "sort" 或 "./multi_pipe_test"）并返回第一个匹配进程的 pid，
 /// 如果没有找到匹配的进程则返回 None。如果运行 pgrep 或解析 pgrep 的输出时出错，则返回 Error。
 fn get_pid_by_command_name(name: &str) -> Result<Option<usize>, Error> {
-    let output = String::from_utf8(
-        Command::new("pgrep")
-            .args(&["-xU", getuid().to_string().as_str(), name])
-            .output()?
-            .stdout,
-    )?;
+    let output = String::from_utf8(run_command(
+        "pgrep",
+        &["-xU", getuid().to_string().as_str(), name],
+        &CommandLimits::default(),
+    )?)?;
     Ok(match output.lines().next() {
         Some(line) => Some(line.parse::<usize>()?),
         None => None,
@@ -142,6 +229,29 @@ mod test {
             .expect(&format!("Could not find {}. Have you run make?", program))
     }
 
+    #[test]
+    fn test_command_timeout() {
+        let limits = CommandLimits {
+            timeout: Duration::from_millis(100),
+            attempts: 2,
+        };
+        // 用一个很慢的命令代替 ps：每次尝试都在超时之后被杀死，而不是等它结束
+        let start = Instant::now();
+        match run_command("sleep", &["10"], &limits) {
+            Err(Error::ExecutableError(err)) => assert_eq!(err.kind(), io::ErrorKind::TimedOut),
+            other => panic!("Expected a timeout error, got {:?}", other),
+        }
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        // 按时结束的命令返回它的输出
+        assert_eq!(run_command("echo", &["hello"], &limits).unwrap(), b"hello\n");
+        // 无法执行的命令重试之后仍然返回错误
+        assert!(matches!(
+            run_command("./no-such-ps", &[], &limits),
+            Err(Error::ExecutableError(_))
+        ));
+    }
+
     #[test]
     fn test_get_target_success() {
         let mut subprocess = start_c_program("./multi_pipe_test");
@@ -150,6 +260,25 @@ mod test {
             .expect("Passed valid \"multi_pipe_test\" to get_target, but it returned None");
         assert_eq!(found.command, "./multi_pipe_test");
         let _ = subprocess.kill();
+        let _ = subprocess.wait();
+    }
+
+    #[test]
+    fn test_get_descendant_processes() {
+        let mut subprocess = start_c_program("./pipe_tree_test");
+        // 等待子进程和孙进程都创建好
+        thread::sleep(Duration::from_millis(100));
+        let pid = subprocess.id() as usize;
+        let descendants = get_descendant_processes(pid).expect("ps should be working");
+        let children = get_child_processes(pid).unwrap();
+        let _ = subprocess.kill();
+        let _ = subprocess.wait();
+        // 子进程在前，孙进程紧跟在它后面
+        assert_eq!(descendants.len(), 2, "{:?}", descendants);
+        assert_eq!(descendants[0].ppid, pid);
+        assert_eq!(descendants[1].ppid, descendants[0].pid);
+        // 直接的子进程只有一个
+        assert_eq!(children, &descendants[..1]);
     }
 
     #[test]
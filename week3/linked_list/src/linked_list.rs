@@ -1,9 +1,18 @@
+use std::collections::{HashMap, HashSet};
 use std::fmt;
+use std::hash::Hash;
+use std::iter::{FromIterator, Product, Sum};
+use std::mem::MaybeUninit;
 use std::option::Option;
 
 pub struct LinkedList<T> {
     head: Option<Box<Node<T>>>,
     size: usize,
+    /// Allocations of popped nodes, kept for reuse by later pushes. None unless the list was
+    /// created with `with_pool`.
+    pool: Option<Vec<Box<MaybeUninit<Node<T>>>>>,
+    /// Most elements the list holds. None unless the list was created with `with_capacity`.
+    capacity: Option<usize>,
 }
 
 struct Node<T> {
@@ -13,7 +22,7 @@ struct Node<T> {
 
 impl<T: Clone + PartialEq> Node<T> {
     pub fn new(value: T, next: Option<Box<Node<T>>>) -> Node<T> {
-        Node {value: value, next: next}
+        Node {value, next}
     }
 }
 
@@ -28,7 +37,76 @@ impl<T: Clone + PartialEq> Clone for Node<T> {
 
 impl<T: Clone + PartialEq> LinkedList<T> {
     pub fn new() -> LinkedList<T> {
-        LinkedList {head: None, size: 0}
+        LinkedList {head: None, size: 0, pool: None, capacity: None}
+    }
+    
+    /// Creates an empty list that recycles nodes: `pop_front` and `pop_back` keep the popped
+    /// node's allocation, and `push_front` and `push_back` reuse one before allocating a new
+    /// Box. This saves allocator work when the list is pushed and popped many times. The pool
+    /// only ever grows (up to the largest size the list reached); call `shrink` to free it.
+    pub fn with_pool() -> LinkedList<T> {
+        LinkedList {head: None, size: 0, pool: Some(Vec::new()), capacity: None}
+    }
+    
+    /// Creates an empty list that holds at most `capacity` elements, like a ring buffer with the
+    /// newest element at the front: once the list is full, `push_front` drops the last (oldest)
+    /// element and returns it. The list never grows past `capacity`; `push_back` and `prepend`
+    /// drop whatever ends up past it at the tail. Panics if `capacity` is 0.
+    pub fn with_capacity(capacity: usize) -> LinkedList<T> {
+        assert!(capacity > 0, "LinkedList capacity must be at least 1");
+        LinkedList {head: None, size: 0, pool: None, capacity: Some(capacity)}
+    }
+    
+    /// The most elements the list can hold, or None if it is unbounded
+    pub fn capacity(&self) -> Option<usize> {
+        self.capacity
+    }
+    
+    /// Drops the elements past the capacity (if any) at the tail
+    fn truncate_to_capacity(&mut self) {
+        if let Some(capacity) = self.capacity {
+            self.truncate(capacity);
+        }
+    }
+    
+    /// Frees the nodes kept for reuse by a list created with `with_pool`. The list keeps
+    /// recycling nodes popped after this.
+    pub fn shrink(&mut self) {
+        if let Some(pool) = &mut self.pool {
+            *pool = Vec::new();
+        }
+    }
+    
+    /// Number of recycled nodes waiting to be reused (always 0 without a pool)
+    pub fn pool_size(&self) -> usize {
+        self.pool.as_ref().map_or(0, Vec::len)
+    }
+    
+    /// Boxes a new node, reusing a pooled allocation if there is one
+    fn alloc_node(&mut self, value: T, next: Option<Box<Node<T>>>) -> Box<Node<T>> {
+        match self.pool.as_mut().and_then(Vec::pop) {
+            Some(slot) => Box::write(slot, Node::new(value, next)),
+            None => Box::new(Node::new(value, next)),
+        }
+    }
+    
+    /// Moves the value and `next` link out of a node that has been unlinked from the list, and
+    /// keeps its allocation in the pool (or frees it if there is no pool).
+    fn free_node(&mut self, node: Box<Node<T>>) -> (T, Option<Box<Node<T>>>) {
+        let pool = match self.pool.as_mut() {
+            Some(pool) => pool,
+            None => {
+                let node = *node;
+                return (node.value, node.next);
+            }
+        };
+        let raw = Box::into_raw(node);
+        // SAFETY: raw came from Box::into_raw, so it is valid and the node is initialized. After
+        // reading the fields out we only ever treat the allocation as uninitialized memory (a
+        // MaybeUninit<Node<T>> has the same layout as a Node<T>), so nothing is dropped twice.
+        let Node { value, next } = unsafe { raw.read() };
+        pool.push(unsafe { Box::from_raw(raw.cast::<MaybeUninit<Node<T>>>()) });
+        (value, next)
     }
     
     pub fn get_size(&self) -> usize {
@@ -39,17 +117,92 @@ impl<T: Clone + PartialEq> LinkedList<T> {
         self.get_size() == 0
     }
     
-    pub fn push_front(&mut self, value: T) {
-        let new_node: Box<Node<T>> = Box::new(Node::new(value, self.head.take()));
+    /// Adds an element at the front of the list. If the list was created with `with_capacity`
+    /// and is full, the last element is removed to make room and returned (an O(n) walk, like
+    /// `pop_back`); otherwise returns None.
+    pub fn push_front(&mut self, value: T) -> Option<T> {
+        let evicted = match self.capacity {
+            Some(capacity) if self.size == capacity => self.pop_back(),
+            _ => None,
+        };
+        let next = self.head.take();
+        let new_node: Box<Node<T>> = self.alloc_node(value, next);
         self.head = Some(new_node);
         self.size += 1;
+        evicted
+    }
+    
+    /// Appends an element at the end of the list.
+    ///
+    /// Like `peek_back`, this is an O(n) walk to the last node.
+    pub fn push_back(&mut self, value: T) {
+        // Allocate first: walking to the tail borrows the list
+        let new_node = self.alloc_node(value, None);
+        let mut tail = &mut self.head;
+        while let Some(node) = tail {
+            tail = &mut node.next;
+        }
+        *tail = Some(new_node);
+        self.size += 1;
+        self.truncate_to_capacity();
+    }
+    
+    /// Moves all of `other`'s elements to the front of this list, in their order, leaving
+    /// `other` empty.
+    ///
+    /// The nodes are relinked rather than reallocated, so nothing is cloned, but like
+    /// `push_back` this walks to the last node of `other`: O(n) in the length of `other`.
+    pub fn prepend(&mut self, other: &mut LinkedList<T>) {
+        let mut tail = &mut other.head;
+        while let Some(node) = tail {
+            tail = &mut node.next;
+        }
+        *tail = self.head.take();
+        self.head = other.head.take();
+        self.size += other.size;
+        other.size = 0;
+        self.truncate_to_capacity();
     }
     
     pub fn pop_front(&mut self) -> Option<T> {
         let node: Box<Node<T>> = self.head.take()?;
-        self.head = node.next;
+        let (value, next) = self.free_node(node);
+        self.head = next;
         self.size -= 1;
-        Some(node.value)
+        Some(value)
+    }
+    
+    /// Removes the last element and returns it, or None if empty.
+    ///
+    /// Like `peek_back`, this is an O(n) walk to the last node.
+    pub fn pop_back(&mut self) -> Option<T> {
+        let mut current = &mut self.head;
+        while current.as_ref()?.next.is_some() {
+            current = &mut current.as_mut().unwrap().next;
+        }
+        let node = current.take()?;
+        self.size -= 1;
+        Some(self.free_node(node).0)
+    }
+    
+    /// Moves the first element for which `pred` returns true to the front of the list. Returns
+    /// false (and leaves the list unchanged) if no element matches.
+    ///
+    /// Finding the element is O(n), but the node itself is relinked rather than reallocated, so
+    /// nothing is cloned.
+    pub fn move_to_front<P: FnMut(&T) -> bool>(&mut self, mut pred: P) -> bool {
+        let mut current = &mut self.head;
+        while current.as_ref().is_some_and(|node| !pred(&node.value)) {
+            current = &mut current.as_mut().unwrap().next;
+        }
+        let mut node = match current.take() {
+            Some(node) => node,
+            None => return false,
+        };
+        *current = node.next.take();
+        node.next = self.head.take();
+        self.head = Some(node);
+        true
     }
     
     /// Returns a reference to the first element, or None if empty
@@ -62,15 +215,75 @@ impl<T: Clone + PartialEq> LinkedList<T> {
         self.head.as_mut().map(|node| &mut node.value)
     }
     
+    /// Returns a reference to the last element, or None if empty.
+    ///
+    /// The list does not keep a tail pointer, so this walks every node: O(n).
+    pub fn peek_back(&self) -> Option<&T> {
+        let mut current = self.head.as_ref()?;
+        while let Some(next) = current.next.as_ref() {
+            current = next;
+        }
+        Some(&current.value)
+    }
+    
+    /// Returns a mutable reference to the last element, or None if empty.
+    ///
+    /// Like `peek_back`, this is an O(n) walk to the last node.
+    pub fn peek_back_mut(&mut self) -> Option<&mut T> {
+        let mut current = self.head.as_mut()?;
+        while current.next.is_some() {
+            current = current.next.as_mut().unwrap();
+        }
+        Some(&mut current.value)
+    }
+    
+    /// Returns a reference to the first element, or None if empty. Same as `peek`.
+    pub fn first(&self) -> Option<&T> {
+        self.peek()
+    }
+    
+    /// Returns a reference to the last element, or None if empty. Same as `peek_back`, so O(n).
+    pub fn last(&self) -> Option<&T> {
+        self.peek_back()
+    }
+    
+    /// Returns a reference to the element at index `n` (0 is the front), or None if the list is
+    /// shorter than that
+    pub fn nth(&self, n: usize) -> Option<&T> {
+        let mut current = self.head.as_ref()?;
+        for _ in 0..n {
+            current = current.next.as_ref()?;
+        }
+        Some(&current.value)
+    }
+    
     /// Clears the list, removing all elements
     pub fn clear(&mut self) {
         self.head = None;
         self.size = 0;
     }
     
+    /// Keeps the first `len` elements and drops the rest, like `Vec::truncate`. Does nothing if
+    /// the list is already no longer than `len`.
+    pub fn truncate(&mut self, len: usize) {
+        if len >= self.size {
+            return;
+        }
+        let mut current = &mut self.head;
+        for _ in 0..len {
+            current = &mut current.as_mut().unwrap().next;
+        }
+        // Unlink the rest one node at a time, like Drop, so a long tail doesn't recurse
+        let mut rest = current.take();
+        while let Some(mut node) = rest {
+            rest = node.next.take();
+        }
+        self.size = len;
+    }
+    
     /// Converts the list to a Vec
     pub fn to_vec(&self) -> Vec<T> {
-        let mut vec = Vec::new();
+        let mut vec = Vec::with_capacity(self.size);
         let mut current = &self.head;
         while let Some(node) = current {
             vec.push(node.value.clone());
@@ -87,6 +300,267 @@ impl<T: Clone + PartialEq> LinkedList<T> {
         }
         list
     }
+    
+    /// Creates a list of `n` elements by calling `f` `n` times. The first call's result is at
+    /// the front.
+    pub fn from_fn<F: FnMut() -> T>(n: usize, mut f: F) -> LinkedList<T> {
+        let mut list = LinkedList::new();
+        // Append at the tail as we go so the order matches the call order
+        let mut tail = &mut list.head;
+        for _ in 0..n {
+            tail = &mut tail.insert(Box::new(Node::new(f(), None))).next;
+        }
+        list.size = n;
+        list
+    }
+    
+    /// Creates a list by pushing each element of `iter` onto the front, so the list holds them
+    /// in *reverse* iteration order: `from_iter_front([1, 2, 3])` is `[3, 2, 1]`. To keep the
+    /// iteration order, `collect()` into a list instead (see the `FromIterator` impl).
+    pub fn from_iter_front<I: IntoIterator<Item = T>>(iter: I) -> LinkedList<T> {
+        let mut list = LinkedList::new();
+        for item in iter {
+            list.push_front(item);
+        }
+        list
+    }
+    
+    /// Creates a list of `n` copies of `value`
+    pub fn repeat(value: T, n: usize) -> LinkedList<T> {
+        LinkedList::from_fn(n, || value.clone())
+    }
+    
+    /// Removes consecutive repeated elements, like `Vec::dedup`.
+    ///
+    /// Removed nodes are unlinked and dropped; nothing is cloned.
+    pub fn dedup(&mut self) {
+        let mut current = match self.head.as_mut() {
+            Some(node) => node,
+            None => return,
+        };
+        while let Some(mut next) = current.next.take() {
+            if next.value == current.value {
+                current.next = next.next.take();
+                self.size -= 1;
+            } else {
+                current.next = Some(next);
+                current = current.next.as_mut().unwrap();
+            }
+        }
+    }
+}
+
+impl<T> LinkedList<T> {
+    /// Returns an iterator over references to the elements, front to back
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter {next: self.head.as_deref()}
+    }
+    
+    /// Returns an iterator over mutable references to the elements, front to back
+    pub fn iter_mut(&mut self) -> IterMut<'_, T> {
+        IterMut {next: self.head.as_deref_mut()}
+    }
+}
+
+/// Borrowing iterator returned by `LinkedList::iter`
+pub struct Iter<'a, T> {
+    next: Option<&'a Node<T>>,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+    
+    fn next(&mut self) -> Option<&'a T> {
+        let node = self.next?;
+        self.next = node.next.as_deref();
+        Some(&node.value)
+    }
+}
+
+/// Mutably borrowing iterator returned by `LinkedList::iter_mut`
+pub struct IterMut<'a, T> {
+    next: Option<&'a mut Node<T>>,
+}
+
+impl<'a, T> Iterator for IterMut<'a, T> {
+    type Item = &'a mut T;
+    
+    fn next(&mut self) -> Option<&'a mut T> {
+        // Take the node out so the returned &mut to its value doesn't alias the iterator's
+        let node = self.next.take()?;
+        self.next = node.next.as_deref_mut();
+        Some(&mut node.value)
+    }
+}
+
+/// Consuming iterator returned by `LinkedList::into_iter`; pops elements off the front
+pub struct IntoIter<T: Clone + PartialEq>(LinkedList<T>);
+
+impl<T: Clone + PartialEq> Iterator for IntoIter<T> {
+    type Item = T;
+    
+    fn next(&mut self) -> Option<T> {
+        self.0.pop_front()
+    }
+    
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.0.size, Some(self.0.size))
+    }
+}
+
+impl<T: Clone + PartialEq> IntoIterator for LinkedList<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+    
+    fn into_iter(self) -> IntoIter<T> {
+        IntoIter(self)
+    }
+}
+
+impl<'a, T> IntoIterator for &'a LinkedList<T> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+    
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a mut LinkedList<T> {
+    type Item = &'a mut T;
+    type IntoIter = IterMut<'a, T>;
+    
+    fn into_iter(self) -> IterMut<'a, T> {
+        self.iter_mut()
+    }
+}
+
+impl<T: Clone + Eq + Hash> LinkedList<T> {
+    /// Removes every element that is equal to an earlier one, keeping first occurrences in their
+    /// original order.
+    ///
+    /// The nodes are detached first so the `HashSet` can borrow their values, then the survivors
+    /// are relinked; nothing is cloned.
+    pub fn dedup_all(&mut self) {
+        let mut nodes = Vec::with_capacity(self.size);
+        let mut current = self.head.take();
+        while let Some(mut node) = current {
+            current = node.next.take();
+            nodes.push(node);
+        }
+        let keep: Vec<bool> = {
+            let mut seen = HashSet::new();
+            nodes.iter().map(|node| seen.insert(&node.value)).collect()
+        };
+        self.size = 0;
+        for (mut node, keep) in nodes.into_iter().zip(keep).rev() {
+            if keep {
+                node.next = self.head.take();
+                self.head = Some(node);
+                self.size += 1;
+            }
+        }
+    }
+    
+    /// Returns true if both lists hold the same elements the same number of times, in any order.
+    /// Unlike `==`, `[1, 2, 2]` and `[2, 1, 2]` compare equal.
+    pub fn eq_unordered(&self, other: &Self) -> bool {
+        self.size == other.size && self.counts() == other.counts()
+    }
+    
+    /// Counts how many times each element appears, borrowing the values rather than cloning them
+    fn counts(&self) -> HashMap<&T, usize> {
+        let mut counts = HashMap::new();
+        let mut current = &self.head;
+        while let Some(node) = current {
+            *counts.entry(&node.value).or_insert(0) += 1;
+            current = &node.next;
+        }
+        counts
+    }
+}
+
+impl<T> LinkedList<T> {
+    /// Adds up all the elements; an empty list sums to zero. Works for every type that can be
+    /// summed by reference, which includes all the built-in numeric types.
+    pub fn sum(&self) -> T
+    where
+        T: for<'a> Sum<&'a T>,
+    {
+        self.iter().sum()
+    }
+
+    /// Multiplies all the elements together; the product of an empty list is one
+    pub fn product(&self) -> T
+    where
+        T: for<'a> Product<&'a T>,
+    {
+        self.iter().product()
+    }
+
+    /// The smallest element, or None if the list is empty. If several elements are equally
+    /// small, the first one is returned.
+    pub fn min(&self) -> Option<&T>
+    where
+        T: Ord,
+    {
+        let mut min = None;
+        for value in self.iter() {
+            if min.is_none_or(|min| value < min) {
+                min = Some(value);
+            }
+        }
+        min
+    }
+
+    /// The largest element, or None if the list is empty. If several elements are equally large,
+    /// the first one is returned.
+    pub fn max(&self) -> Option<&T>
+    where
+        T: Ord,
+    {
+        let mut max = None;
+        for value in self.iter() {
+            if max.is_none_or(|max| value > max) {
+                max = Some(value);
+            }
+        }
+        max
+    }
+}
+
+/// Collects into a list in iteration order, appending each element at the tail as it goes
+/// (`from_iter_front` is the reverse-order alternative)
+impl<T: Clone + PartialEq> FromIterator<T> for LinkedList<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut list = LinkedList::new();
+        let mut tail = &mut list.head;
+        for item in iter {
+            tail = &mut tail.insert(Box::new(Node::new(item, None))).next;
+            list.size += 1;
+        }
+        list
+    }
+}
+
+/// Converts from the standard library's list, keeping the order
+impl<T: Clone + PartialEq> From<std::collections::LinkedList<T>> for LinkedList<T> {
+    fn from(list: std::collections::LinkedList<T>) -> Self {
+        list.into_iter().collect()
+    }
+}
+
+/// Converts into the standard library's list, keeping the order, for std APIs that expect it
+impl<T: Clone + PartialEq> From<LinkedList<T>> for std::collections::LinkedList<T> {
+    fn from(list: LinkedList<T>) -> Self {
+        list.into_iter().collect()
+    }
+}
+
+impl<T: Clone + PartialEq> Default for LinkedList<T> {
+    fn default() -> Self {
+        LinkedList::new()
+    }
 }
 
 impl<T: Clone + PartialEq> Clone for LinkedList<T> {
@@ -94,6 +568,9 @@ impl<T: Clone + PartialEq> Clone for LinkedList<T> {
         let mut new_list = LinkedList::new();
         new_list.head = self.head.clone();
         new_list.size = self.size;
+        // A clone recycles nodes too, but starts with an empty pool
+        new_list.pool = self.pool.as_ref().map(|_| Vec::new());
+        new_list.capacity = self.capacity;
         new_list
     }
 }
@@ -130,18 +607,12 @@ impl<T: Clone + PartialEq + fmt::Debug> fmt::Debug for LinkedList<T> {
 
 impl<T: fmt::Display> fmt::Display for LinkedList<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let mut current: &Option<Box<Node<T>>> = &self.head;
-        let mut result = String::new();
-        loop {
-            match current {
-                Some(node) => {
-                    result = format!("{} {}", result, node.value);
-                    current = &node.next;
-                },
-                None => break,
-            }
+        // Write each element straight into the formatter instead of rebuilding a String per
+        // element, which made printing a list O(n²)
+        for value in self.iter() {
+            write!(f, " {}", value)?;
         }
-        write!(f, "{}", result)
+        Ok(())
     }
 }
 
@@ -157,6 +628,8 @@ impl<T> Drop for LinkedList<T> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::rc::Rc;
+    use std::time::Instant;
 
     #[test]
     fn test_new_list() {
@@ -295,6 +768,208 @@ mod tests {
         assert_eq!(list.peek(), Some(&10));
     }
 
+    #[test]
+    fn test_peek_back_empty() {
+        let mut list: LinkedList<i32> = LinkedList::new();
+        assert_eq!(list.peek_back(), None);
+        assert_eq!(list.peek_back_mut(), None);
+    }
+
+    #[test]
+    fn test_peek_back_single() {
+        let mut list: LinkedList<i32> = LinkedList::new();
+        list.push_front(1);
+        
+        assert_eq!(list.peek_back(), Some(&1));
+        assert_eq!(list.peek_back(), list.peek());
+    }
+
+    #[test]
+    fn test_peek_back_multiple() {
+        let list = LinkedList::from_vec(vec![1, 2, 3]);
+        
+        assert_eq!(list.peek_back(), Some(&3));
+        assert_eq!(list.peek(), Some(&1));
+        assert_eq!(list.get_size(), 3); // peek_back 不应该改变大小
+    }
+
+    #[test]
+    fn test_peek_back_mut() {
+        let mut list = LinkedList::from_vec(vec![1, 2, 3]);
+        
+        if let Some(value) = list.peek_back_mut() {
+            *value = 30;
+        }
+        
+        assert_eq!(list.peek_back(), Some(&30));
+        assert_eq!(list.to_vec(), vec![1, 2, 30]);
+    }
+
+    #[test]
+    fn test_pop_back() {
+        let mut list = LinkedList::from_vec(vec![1, 2, 3]);
+        assert_eq!(list.pop_back(), Some(3));
+        assert_eq!(list.pop_back(), Some(2));
+        assert_eq!(list.get_size(), 1);
+        assert_eq!(list.pop_back(), Some(1));
+        assert_eq!(list.pop_back(), None);
+        assert!(list.is_empty());
+        assert_eq!(list.peek(), None);
+    }
+
+    #[test]
+    fn test_move_to_front() {
+        let mut list = LinkedList::from_vec(vec![1, 2, 3, 2]);
+        
+        // 只移动第一个匹配的元素
+        assert!(list.move_to_front(|&x| x == 2));
+        assert_eq!(list.to_vec(), vec![2, 1, 3, 2]);
+        assert!(list.move_to_front(|&x| x == 2));
+        assert_eq!(list.to_vec(), vec![2, 1, 3, 2]);
+        assert!(list.move_to_front(|&x| x == 3));
+        assert_eq!(list.to_vec(), vec![3, 2, 1, 2]);
+        
+        assert!(!list.move_to_front(|&x| x == 4));
+        assert_eq!(list.to_vec(), vec![3, 2, 1, 2]);
+        assert_eq!(list.get_size(), 4);
+        
+        let mut empty: LinkedList<i32> = LinkedList::new();
+        assert!(!empty.move_to_front(|_| true));
+    }
+
+    #[test]
+    fn test_prepend() {
+        let mut list = LinkedList::from_vec(vec![4, 5]);
+        let mut other = LinkedList::from_vec(vec![1, 2, 3]);
+        list.prepend(&mut other);
+        assert_eq!(list.to_vec(), vec![1, 2, 3, 4, 5]);
+        assert_eq!(list.get_size(), 5);
+        assert!(other.is_empty());
+        assert_eq!(other.to_vec(), Vec::<i32>::new());
+        
+        // 原来的最后一个节点仍然是最后一个，other 清空之后还能继续使用
+        assert_eq!(list.pop_back(), Some(5));
+        other.push_back(6);
+        assert_eq!(other.to_vec(), vec![6]);
+    }
+
+    #[test]
+    fn test_prepend_empty_lists() {
+        // 在空链表前面插入
+        let mut list: LinkedList<i32> = LinkedList::new();
+        let mut other = LinkedList::from_vec(vec![1, 2]);
+        list.prepend(&mut other);
+        assert_eq!(list.to_vec(), vec![1, 2]);
+        assert_eq!(list.get_size(), 2);
+        assert_eq!(other.get_size(), 0);
+        
+        // 插入空链表不改变原链表
+        let mut empty = LinkedList::new();
+        list.prepend(&mut empty);
+        assert_eq!(list.to_vec(), vec![1, 2]);
+        assert_eq!(list.get_size(), 2);
+        
+        let mut both: LinkedList<i32> = LinkedList::new();
+        both.prepend(&mut LinkedList::new());
+        assert!(both.is_empty());
+    }
+
+    #[test]
+    fn test_push_back() {
+        let mut list: LinkedList<i32> = LinkedList::new();
+        list.push_back(1);
+        list.push_back(2);
+        list.push_front(0);
+        list.push_back(3);
+        assert_eq!(list.to_vec(), vec![0, 1, 2, 3]);
+        assert_eq!(list.get_size(), 4);
+    }
+
+    #[test]
+    fn test_with_capacity_evicts_oldest() {
+        const N: usize = 5;
+        let mut list: LinkedList<usize> = LinkedList::with_capacity(N);
+        assert_eq!(list.capacity(), Some(N));
+        // 前 N 个元素不会淘汰任何元素，之后每次淘汰最旧的一个，按放入的顺序
+        let evicted: Vec<Option<usize>> = (0..N + 2).map(|i| list.push_front(i)).collect();
+        assert_eq!(&evicted[..N], &[None; N]);
+        assert_eq!(&evicted[N..], &[Some(0), Some(1)]);
+        assert_eq!(list.get_size(), N);
+        assert_eq!(list.to_vec(), vec![6, 5, 4, 3, 2]);
+
+        // 弹出之后又有空间了
+        assert_eq!(list.pop_back(), Some(2));
+        assert_eq!(list.push_front(7), None);
+        assert_eq!(list.push_front(8), Some(3));
+        // 克隆保留容量
+        let mut clone = list.clone();
+        assert_eq!(clone.push_front(9), Some(4));
+        assert_eq!(clone.get_size(), N);
+
+        // 没有容量的链表不会淘汰元素
+        let mut unbounded: LinkedList<usize> = LinkedList::new();
+        assert_eq!(unbounded.capacity(), None);
+        assert!((0..N + 2).all(|i| unbounded.push_front(i).is_none()));
+        assert_eq!(unbounded.get_size(), N + 2);
+    }
+
+    #[test]
+    fn test_with_capacity_other_insertions() {
+        // push_back 和 prepend 也不会让链表超过容量，超出的部分从尾部丢弃
+        let mut list: LinkedList<i32> = LinkedList::with_capacity(3);
+        list.push_back(1);
+        list.push_back(2);
+        list.push_back(3);
+        list.push_back(4);
+        assert_eq!(list.to_vec(), vec![1, 2, 3]);
+        list.prepend(&mut LinkedList::from_vec(vec![-1, 0]));
+        assert_eq!(list.to_vec(), vec![-1, 0, 1]);
+        assert_eq!(list.get_size(), 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "capacity must be at least 1")]
+    fn test_with_zero_capacity_panics() {
+        let _list: LinkedList<i32> = LinkedList::with_capacity(0);
+    }
+
+    #[test]
+    fn test_first_last_nth_empty() {
+        let list: LinkedList<i32> = LinkedList::new();
+        assert_eq!(list.first(), None);
+        assert_eq!(list.last(), None);
+        assert_eq!(list.nth(0), None);
+    }
+
+    #[test]
+    fn test_first_last_nth() {
+        let list = LinkedList::from_vec(vec![10, 20, 30]);
+        assert_eq!(list.first(), Some(&10));
+        assert_eq!(list.last(), Some(&30));
+        assert_eq!(list.nth(0), Some(&10));
+        assert_eq!(list.nth(1), Some(&20));
+        assert_eq!(list.nth(2), Some(&30));
+        assert_eq!(list.nth(3), None);
+        assert_eq!(list.nth(usize::MAX), None);
+    }
+
+    #[test]
+    fn test_last_after_interleaved_pushes() {
+        let mut list: LinkedList<i32> = LinkedList::new();
+        list.push_front(2);
+        assert_eq!(list.last(), Some(&2));
+        list.push_front(1);
+        // push_front 不会改变最后一个元素
+        assert_eq!(list.last(), Some(&2));
+        list.push_back(3);
+        assert_eq!(list.last(), Some(&3));
+        list.push_front(0);
+        list.push_back(4);
+        assert_eq!(list.first(), Some(&0));
+        assert_eq!(list.last(), Some(&4));
+        assert_eq!(list.nth(2), Some(&2));
+    }
+
     #[test]
     fn test_clear() {
         let mut list: LinkedList<i32> = LinkedList::new();
@@ -309,6 +984,43 @@ mod tests {
         assert_eq!(list.peek(), None);
     }
 
+    #[test]
+    fn test_truncate_to_zero() {
+        let mut list = LinkedList::from_vec(vec![1, 2, 3]);
+        list.truncate(0);
+        assert!(list.is_empty());
+        assert_eq!(list.peek(), None);
+        // 截断之后链表仍然可以正常使用
+        list.push_back(4);
+        assert_eq!(list.to_vec(), vec![4]);
+    }
+
+    #[test]
+    fn test_truncate_middle() {
+        let mut list = LinkedList::from_vec(vec![1, 2, 3, 4, 5]);
+        list.truncate(2);
+        assert_eq!(list.get_size(), 2);
+        assert_eq!(list.to_vec(), vec![1, 2]);
+        assert_eq!(list.last(), Some(&2));
+        // 被截掉的元素都被释放了
+        let value = Rc::new(0);
+        let mut list = LinkedList::repeat(Rc::clone(&value), 4);
+        list.truncate(1);
+        assert_eq!(Rc::strong_count(&value), 2);
+    }
+
+    #[test]
+    fn test_truncate_longer_than_list() {
+        let mut list = LinkedList::from_vec(vec![1, 2, 3]);
+        list.truncate(3);
+        list.truncate(10);
+        assert_eq!(list.get_size(), 3);
+        assert_eq!(list.to_vec(), vec![1, 2, 3]);
+        let mut empty: LinkedList<i32> = LinkedList::new();
+        empty.truncate(1);
+        assert!(empty.is_empty());
+    }
+
     #[test]
     fn test_to_vec() {
         let mut list: LinkedList<i32> = LinkedList::new();
@@ -342,6 +1054,350 @@ mod tests {
         assert_eq!(list1, list2);
     }
 
+    #[test]
+    fn test_dedup_consecutive_only() {
+        let mut list = LinkedList::from_vec(vec![1, 1, 2, 3, 3, 3, 1, 2, 2]);
+        list.dedup();
+        
+        // 只删除相邻的重复元素，后面的 1 和 2 保留
+        assert_eq!(list.to_vec(), vec![1, 2, 3, 1, 2]);
+        assert_eq!(list.get_size(), 5);
+    }
+
+    #[test]
+    fn test_dedup_all() {
+        let mut list = LinkedList::from_vec(vec![1, 1, 2, 3, 3, 3, 1, 2, 2]);
+        list.dedup_all();
+        
+        // 删除所有重复元素，保留第一次出现的位置
+        assert_eq!(list.to_vec(), vec![1, 2, 3]);
+        assert_eq!(list.get_size(), 3);
+    }
+
+    #[test]
+    fn test_dedup_no_duplicates_and_empty() {
+        let mut list = LinkedList::from_vec(vec![3, 1, 2]);
+        list.dedup();
+        list.dedup_all();
+        assert_eq!(list.to_vec(), vec![3, 1, 2]);
+        assert_eq!(list.get_size(), 3);
+        
+        let mut empty: LinkedList<i32> = LinkedList::new();
+        empty.dedup();
+        empty.dedup_all();
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn test_dedup_all_same() {
+        let mut list = LinkedList::from_vec(vec![String::from("a"); 4]);
+        let mut list2 = list.clone();
+        list.dedup();
+        list2.dedup_all();
+        
+        assert_eq!(list.to_vec(), vec![String::from("a")]);
+        assert_eq!(list, list2);
+    }
+
+    #[test]
+    fn test_eq_unordered() {
+        let list1 = LinkedList::from_vec(vec![1, 2, 2]);
+        let list2 = LinkedList::from_vec(vec![2, 1, 2]);
+        assert!(list1.eq_unordered(&list2));
+        assert_ne!(list1, list2);
+        
+        // 元素相同但出现次数不同
+        let list3 = LinkedList::from_vec(vec![1, 2]);
+        assert!(!list3.eq_unordered(&list1));
+        assert!(!list1.eq_unordered(&list3));
+        assert_ne!(list3, list1);
+        
+        let list4 = LinkedList::from_vec(vec![1, 1, 2]);
+        assert!(!list1.eq_unordered(&list4));
+        
+        let empty: LinkedList<i32> = LinkedList::new();
+        assert!(empty.eq_unordered(&LinkedList::new()));
+    }
+
+    #[test]
+    fn test_from_fn() {
+        let mut counter = 0;
+        let list = LinkedList::from_fn(4, || {
+            counter += 1;
+            counter * 10
+        });
+        assert_eq!(list.get_size(), 4);
+        assert_eq!(list.to_vec(), vec![10, 20, 30, 40]);
+        
+        let empty: LinkedList<i32> = LinkedList::from_fn(0, || panic!("f should not be called"));
+        assert!(empty.is_empty());
+        assert_eq!(empty.peek(), None);
+    }
+
+    #[test]
+    fn test_collect_and_from_iter_front() {
+        // 同一个迭代器：collect 保持顺序，from_iter_front 得到相反的顺序
+        let source = || (1..=4).map(|x| x * 10);
+        let collected: LinkedList<i32> = source().collect();
+        let reversed = LinkedList::from_iter_front(source());
+        assert_eq!(collected.to_vec(), vec![10, 20, 30, 40]);
+        assert_eq!(reversed.to_vec(), vec![40, 30, 20, 10]);
+        assert_eq!(collected.get_size(), 4);
+        assert_eq!(reversed.get_size(), 4);
+        assert_eq!(collected, LinkedList::from_vec(vec![10, 20, 30, 40]));
+        
+        let empty: LinkedList<i32> = std::iter::empty().collect();
+        assert!(empty.is_empty());
+        assert!(LinkedList::<i32>::from_iter_front(Vec::new()).is_empty());
+    }
+
+    #[test]
+    fn test_repeat() {
+        let list = LinkedList::repeat(String::from("ab"), 3);
+        assert_eq!(list.get_size(), 3);
+        assert_eq!(list.to_vec(), vec![String::from("ab"); 3]);
+        
+        let empty = LinkedList::repeat(7, 0);
+        assert!(empty.is_empty());
+        assert_eq!(empty, LinkedList::new());
+    }
+
+    #[test]
+    fn test_pool_matches_plain_list() {
+        let mut plain: LinkedList<String> = LinkedList::new();
+        let mut pooled: LinkedList<String> = LinkedList::with_pool();
+        for round in 0..3 {
+            for i in 0..5 {
+                let value = format!("{}-{}", round, i);
+                if i % 2 == 0 {
+                    plain.push_front(value.clone());
+                    pooled.push_front(value);
+                } else {
+                    plain.push_back(value.clone());
+                    pooled.push_back(value);
+                }
+            }
+            assert_eq!(plain.pop_front(), pooled.pop_front());
+            assert_eq!(plain.pop_back(), pooled.pop_back());
+            assert_eq!(plain, pooled);
+            assert_eq!(plain.to_vec(), pooled.to_vec());
+        }
+        // 每轮弹出的两个节点都被下一轮的 push 重用
+        assert_eq!(pooled.pool_size(), 2);
+        assert_eq!(plain.pool_size(), 0);
+        
+        while pooled.pop_front().is_some() {}
+        assert_eq!(pooled.pool_size(), 11);
+        pooled.shrink();
+        assert_eq!(pooled.pool_size(), 0);
+        pooled.push_front(String::from("again"));
+        assert_eq!(pooled.pop_back(), Some(String::from("again")));
+        assert_eq!(pooled.pool_size(), 1);
+    }
+
+    #[test]
+    fn test_pool_drops_each_value_once() {
+        let value = Rc::new(5);
+        let mut list = LinkedList::with_pool();
+        for _ in 0..4 {
+            list.push_front(Rc::clone(&value));
+        }
+        assert_eq!(Rc::strong_count(&value), 5);
+        // 回收的节点不再持有值
+        drop(list.pop_front());
+        drop(list.pop_back());
+        assert_eq!(Rc::strong_count(&value), 3);
+        list.push_back(Rc::clone(&value));
+        assert_eq!(Rc::strong_count(&value), 4);
+        drop(list);
+        assert_eq!(Rc::strong_count(&value), 1);
+    }
+
+    /// 比较使用和不使用节点池时反复 push_front/pop_front 的耗时。
+    /// 用 cargo test --release -- --ignored --nocapture 运行
+    #[test]
+    #[ignore]
+    fn bench_pool_churn() {
+        const ROUNDS: usize = 20_000;
+        const BATCH: usize = 100;
+        let churn = |mut list: LinkedList<u64>| {
+            let start = Instant::now();
+            for round in 0..ROUNDS {
+                for i in 0..BATCH {
+                    list.push_front((round * BATCH + i) as u64);
+                }
+                for _ in 0..BATCH {
+                    list.pop_front();
+                }
+            }
+            start.elapsed()
+        };
+        let plain = churn(LinkedList::new());
+        let pooled = churn(LinkedList::with_pool());
+        println!("{} push/pop 轮次（每轮 {} 个节点）：", ROUNDS, BATCH);
+        println!("  不使用节点池: {:?}", plain);
+        println!("  使用节点池:   {:?}", pooled);
+    }
+
+    #[test]
+    fn test_iter_composes_with_adaptors() {
+        let list: LinkedList<i32> = (1..=10).collect();
+        let sum: i32 = list.iter().map(|x| x * x).filter(|x| x % 2 == 0).sum();
+        assert_eq!(sum, 4 + 16 + 36 + 64 + 100);
+        assert_eq!(list.iter().count(), 10);
+        // iter() 只是借用，链表不变
+        assert_eq!(list.to_vec(), (1..=10).collect::<Vec<i32>>());
+        assert_eq!(LinkedList::<i32>::new().iter().next(), None);
+    }
+
+    #[test]
+    fn test_iter_mut() {
+        let mut list: LinkedList<i32> = (1..=5).collect();
+        for value in list.iter_mut() {
+            *value *= 10;
+        }
+        assert_eq!(list.to_vec(), vec![10, 20, 30, 40, 50]);
+        for value in &mut list {
+            *value += 1;
+        }
+        assert_eq!(list.to_vec(), vec![11, 21, 31, 41, 51]);
+        assert_eq!(list.get_size(), 5);
+    }
+
+    #[test]
+    fn test_into_iter() {
+        let list = LinkedList::from_vec(vec![String::from("a"), String::from("b"), String::from("c")]);
+        let mut iter = list.into_iter();
+        assert_eq!(iter.size_hint(), (3, Some(3)));
+        assert_eq!(iter.next().as_deref(), Some("a"));
+        assert_eq!(iter.collect::<Vec<String>>(), vec!["b", "c"]);
+
+        let mut seen = Vec::new();
+        for value in LinkedList::from_vec(vec![1, 2, 3]) {
+            seen.push(value);
+        }
+        assert_eq!(seen, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_std_linked_list_round_trip() {
+        let std_list: std::collections::LinkedList<String> =
+            ["a", "b", "c"].iter().map(|s| s.to_string()).collect();
+        let list = LinkedList::from(std_list.clone());
+        assert_eq!(list.get_size(), 3);
+        assert_eq!(list.to_vec(), vec!["a", "b", "c"]);
+        let back: std::collections::LinkedList<String> = list.into();
+        assert_eq!(back, std_list);
+
+        // 空链表
+        let empty = LinkedList::from(std::collections::LinkedList::<i32>::new());
+        assert!(empty.is_empty());
+        assert!(std::collections::LinkedList::from(empty).is_empty());
+    }
+
+    /// 比较遍历 1M 个元素的 LinkedList 和 Vec 的耗时，也用来发现迭代器意外的 O(n²) 行为。
+    /// 用 cargo test --release -- --ignored --nocapture 运行
+    #[test]
+    #[ignore]
+    fn bench_iter_vs_vec() {
+        const N: i32 = 1_000_000;
+        let mut list: LinkedList<i32> = (0..N).collect();
+        let mut vec: Vec<i32> = (0..N).collect();
+        let time = |f: &mut dyn FnMut() -> i64| {
+            let start = Instant::now();
+            let result = f();
+            (start.elapsed(), result)
+        };
+        let (list_iter, list_sum) = time(&mut || list.iter().map(|&x| x as i64).sum());
+        let (vec_iter, vec_sum) = time(&mut || vec.iter().map(|&x| x as i64).sum());
+        assert_eq!(list_sum, vec_sum);
+        let (list_iter_mut, _) = time(&mut || {
+            list.iter_mut().for_each(|x| *x += 1);
+            0
+        });
+        let (vec_iter_mut, _) = time(&mut || {
+            vec.iter_mut().for_each(|x| *x += 1);
+            0
+        });
+        assert_eq!(list.peek_back(), vec.last());
+        println!("{} 个元素：", N);
+        println!("  iter:     LinkedList {:?}, Vec {:?}", list_iter, vec_iter);
+        println!("  iter_mut: LinkedList {:?}, Vec {:?}", list_iter_mut, vec_iter_mut);
+    }
+
+    /// 以前的 Display 实现：每个元素都用 format! 重新构建整个字符串
+    fn display_by_repeated_format<T: fmt::Display>(list: &LinkedList<T>) -> String {
+        let mut result = String::new();
+        for value in list.iter() {
+            result = format!("{} {}", result, value);
+        }
+        result
+    }
+
+    #[test]
+    fn test_display_matches_previous_output() {
+        let list: LinkedList<i32> = (1..=5).collect();
+        assert_eq!(list.to_string(), " 1 2 3 4 5");
+        assert_eq!(list.to_string(), display_by_repeated_format(&list));
+
+        let mut words: LinkedList<String> = LinkedList::new();
+        words.push_back(String::from("hello"));
+        words.push_back(String::from("big world"));
+        assert_eq!(words.to_string(), display_by_repeated_format(&words));
+        assert_eq!(format!("{}", words), " hello big world");
+
+        let empty: LinkedList<i32> = LinkedList::new();
+        assert_eq!(empty.to_string(), "");
+    }
+
+    /// 比较新旧 Display 实现打印 100k 个元素的链表的耗时。
+    /// 用 cargo test --release -- --ignored --nocapture 运行
+    #[test]
+    #[ignore]
+    fn bench_display() {
+        const N: i32 = 100_000;
+        let list: LinkedList<i32> = (0..N).collect();
+        let start = Instant::now();
+        let before = display_by_repeated_format(&list);
+        let before_time = start.elapsed();
+        let start = Instant::now();
+        let after = list.to_string();
+        let after_time = start.elapsed();
+        assert_eq!(before, after);
+        println!("打印 {} 个元素：", N);
+        println!("  每个元素 format! 一次: {:?}", before_time);
+        println!("  直接写入 Formatter:   {:?}", after_time);
+    }
+
+    #[test]
+    fn test_sum_product_min_max() {
+        let list: LinkedList<i32> = vec![3, -1, 4, 1, 5].into_iter().collect();
+        assert_eq!(list.sum(), 12);
+        assert_eq!(list.product(), -60);
+        assert_eq!(list.min(), Some(&-1));
+        assert_eq!(list.max(), Some(&5));
+
+        let floats: LinkedList<f64> = vec![0.5, 2.0, 4.0].into_iter().collect();
+        assert_eq!(floats.sum(), 6.5);
+        assert_eq!(floats.product(), 4.0);
+
+        // 相等的元素中返回第一个
+        let pairs: LinkedList<(i32, &str)> = vec![(1, "a"), (2, "b"), (1, "c"), (2, "d")].into_iter().collect();
+        assert_eq!(pairs.min(), Some(&(1, "a")));
+        assert_eq!(pairs.max(), Some(&(2, "d")));
+        let words: LinkedList<String> = vec![String::from("pear"), String::from("apple")].into_iter().collect();
+        assert_eq!(words.min().map(String::as_str), Some("apple"));
+    }
+
+    #[test]
+    fn test_sum_product_min_max_empty() {
+        let empty: LinkedList<i32> = LinkedList::new();
+        assert_eq!(empty.sum(), 0);
+        assert_eq!(empty.product(), 1);
+        assert_eq!(empty.min(), None);
+        assert_eq!(empty.max(), None);
+    }
+
     #[test]
     fn test_with_strings() {
         let mut list1: LinkedList<String> = LinkedList::new();
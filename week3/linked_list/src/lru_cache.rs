@@ -0,0 +1,126 @@
+use crate::linked_list::LinkedList;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// A fixed-capacity cache that evicts the least recently used entry when it is full.
+///
+/// Values live in a `HashMap`; a `LinkedList` of keys keeps the recency order, most recently used
+/// at the front. The list is singly linked with no tail pointer, so touching or evicting an entry
+/// is O(capacity) rather than O(1). That is fine for small caches and for showing off the list.
+pub struct LruCache<K, V> {
+    capacity: usize,
+    entries: HashMap<K, V>,
+    recency: LinkedList<K>,
+}
+
+impl<K: Clone + Eq + Hash, V> LruCache<K, V> {
+    /// Creates an empty cache that holds at most `capacity` entries. Panics if `capacity` is 0.
+    pub fn new(capacity: usize) -> LruCache<K, V> {
+        assert!(capacity > 0, "LruCache capacity must be at least 1");
+        LruCache {
+            capacity,
+            entries: HashMap::with_capacity(capacity),
+            recency: LinkedList::new(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the value for `key` and marks it as the most recently used entry, or None on a miss
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        if self.entries.contains_key(key) {
+            self.recency.move_to_front(|k| k == key);
+        }
+        self.entries.get(key)
+    }
+
+    /// Inserts or updates `key`, making it the most recently used entry. If this adds a new key
+    /// to a full cache, the least recently used entry is removed and returned.
+    pub fn put(&mut self, key: K, value: V) -> Option<(K, V)> {
+        if let Some(existing) = self.entries.get_mut(&key) {
+            *existing = value;
+            self.recency.move_to_front(|k| *k == key);
+            return None;
+        }
+        let evicted = if self.entries.len() == self.capacity {
+            // The back of the list is the least recently used key
+            let lru_key = self.recency.pop_back().unwrap();
+            let lru_value = self.entries.remove(&lru_key).unwrap();
+            Some((lru_key, lru_value))
+        } else {
+            None
+        };
+        self.recency.push_front(key.clone());
+        self.entries.insert(key, value);
+        evicted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hit_and_miss() {
+        let mut cache = LruCache::new(2);
+        assert!(cache.is_empty());
+        assert_eq!(cache.get(&"a"), None);
+
+        assert_eq!(cache.put("a", 1), None);
+        assert_eq!(cache.get(&"a"), Some(&1));
+        assert_eq!(cache.get(&"b"), None);
+
+        // 更新已有的键不会淘汰任何条目
+        assert_eq!(cache.put("b", 2), None);
+        assert_eq!(cache.put("a", 10), None);
+        assert_eq!(cache.get(&"a"), Some(&10));
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let mut cache = LruCache::new(3);
+        cache.put(1, "one");
+        cache.put(2, "two");
+        cache.put(3, "three");
+
+        // get 和 put 都会更新最近使用的顺序：现在 2 是最久没有使用的
+        cache.get(&1);
+        cache.put(3, "THREE");
+        assert_eq!(cache.put(4, "four"), Some((2, "two")));
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.put(5, "five"), Some((1, "one")));
+        assert_eq!(cache.put(6, "six"), Some((3, "THREE")));
+
+        assert_eq!(cache.len(), 3);
+        assert_eq!(cache.get(&4), Some(&"four"));
+        assert_eq!(cache.get(&5), Some(&"five"));
+        assert_eq!(cache.get(&6), Some(&"six"));
+    }
+
+    #[test]
+    fn test_capacity_one() {
+        let mut cache = LruCache::new(1);
+        assert_eq!(cache.capacity(), 1);
+        cache.put(String::from("a"), 1);
+        assert_eq!(cache.put(String::from("b"), 2), Some((String::from("a"), 1)));
+        assert_eq!(cache.get(&String::from("a")), None);
+        assert_eq!(cache.get(&String::from("b")), Some(&2));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_zero_capacity_panics() {
+        let _cache: LruCache<i32, i32> = LruCache::new(0);
+    }
+}
@@ -1,5 +1,11 @@
 use linked_list::LinkedList;
+use lru_cache::LruCache;
+use queue::Queue;
+use stack::Stack;
 pub mod linked_list;
+pub mod lru_cache;
+pub mod queue;
+pub mod stack;
 
 fn main() {
     let mut list: LinkedList<i32> = LinkedList::new();
@@ -13,7 +19,8 @@ fn main() {
     println!("top element: {}", list.pop_front().unwrap());
     println!("{}", list);
     println!("size: {}", list.get_size());
-    println!("{}", list.to_string()); // ToString impl for anything impl Display
+    let list_string: String = list.to_string(); // ToString impl for anything impl Display
+    println!("{}", list_string);
 
     // 测试 == 运算符（通过 PartialEq trait 实现）
     println!("\n--- 测试链表相等性判断 (== 运算符) ---");
@@ -47,6 +54,27 @@ fn main() {
     // 测试 != 运算符（也由 PartialEq 提供）
     println!("\nlist1 != list2: {}", list1 != list2); // 使用 != 运算符
 
+    // 使用链表实现的 LRU 缓存
+    println!("\n--- 测试 LRU 缓存 ---");
+    let mut cache: LruCache<&str, i32> = LruCache::new(2);
+    cache.put("a", 1);
+    cache.put("b", 2);
+    println!("get a: {:?}", cache.get(&"a")); // a 成为最近使用的
+    println!("put c, evicted: {:?}", cache.put("c", 3)); // 淘汰 b
+    println!("get b: {:?}", cache.get(&"b"));
+    println!("cache size: {}", cache.len());
+
+    // 基于链表的栈和队列
+    println!("\n--- 测试栈和队列 ---");
+    let mut stack = Stack::new();
+    let mut queue = Queue::new();
+    for i in 1..4 {
+        stack.push(i);
+        queue.enqueue(i);
+    }
+    println!("stack pops: {:?} {:?} {:?}", stack.pop(), stack.pop(), stack.pop()); // 后进先出
+    println!("queue dequeues: {:?} {:?} {:?}", queue.dequeue(), queue.dequeue(), queue.dequeue()); // 先进先出
+
     // If you implement iterator trait:
     //for val in &list {
     //    println!("{}", val);
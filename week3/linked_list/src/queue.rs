@@ -0,0 +1,77 @@
+use crate::linked_list::LinkedList;
+
+/// A first-in, first-out queue. Values are enqueued at the back of the underlying `LinkedList` and
+/// dequeued from the front. The list has no tail pointer, so `enqueue` is O(n); `dequeue` and
+/// `peek` are O(1).
+pub struct Queue<T> {
+    list: LinkedList<T>,
+}
+
+impl<T: Clone + PartialEq> Queue<T> {
+    pub fn new() -> Queue<T> {
+        Queue {
+            list: LinkedList::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.list.get_size()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    /// Adds `value` to the back of the queue
+    pub fn enqueue(&mut self, value: T) {
+        self.list.push_back(value);
+    }
+
+    /// Removes and returns the value that has been in the queue longest, or None if it is empty
+    pub fn dequeue(&mut self) -> Option<T> {
+        self.list.pop_front()
+    }
+
+    /// Returns the value `dequeue` would return, without removing it
+    pub fn peek(&self) -> Option<&T> {
+        self.list.peek()
+    }
+}
+
+impl<T: Clone + PartialEq> Default for Queue<T> {
+    fn default() -> Self {
+        Queue::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fifo_order() {
+        let mut queue = Queue::new();
+        for i in 1..=3 {
+            queue.enqueue(i);
+        }
+        assert_eq!(queue.len(), 3);
+        assert_eq!(queue.peek(), Some(&1));
+        assert_eq!(queue.dequeue(), Some(1));
+        queue.enqueue(4);
+        assert_eq!(queue.dequeue(), Some(2));
+        assert_eq!(queue.dequeue(), Some(3));
+        assert_eq!(queue.dequeue(), Some(4));
+    }
+
+    #[test]
+    fn test_underflow() {
+        let mut queue: Queue<i32> = Queue::new();
+        assert!(queue.is_empty());
+        assert_eq!(queue.peek(), None);
+        assert_eq!(queue.dequeue(), None);
+        queue.enqueue(1);
+        queue.dequeue();
+        assert_eq!(queue.dequeue(), None);
+        assert!(queue.is_empty());
+    }
+}
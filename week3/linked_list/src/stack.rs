@@ -0,0 +1,76 @@
+use crate::linked_list::LinkedList;
+
+/// A last-in, first-out stack. The top of the stack is the front of the underlying `LinkedList`,
+/// so every operation is O(1).
+pub struct Stack<T> {
+    list: LinkedList<T>,
+}
+
+impl<T: Clone + PartialEq> Stack<T> {
+    pub fn new() -> Stack<T> {
+        Stack {
+            list: LinkedList::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.list.get_size()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    /// Puts `value` on top of the stack
+    pub fn push(&mut self, value: T) {
+        self.list.push_front(value);
+    }
+
+    /// Removes and returns the most recently pushed value, or None if the stack is empty
+    pub fn pop(&mut self) -> Option<T> {
+        self.list.pop_front()
+    }
+
+    /// Returns the value `pop` would return, without removing it
+    pub fn peek(&self) -> Option<&T> {
+        self.list.peek()
+    }
+}
+
+impl<T: Clone + PartialEq> Default for Stack<T> {
+    fn default() -> Self {
+        Stack::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lifo_order() {
+        let mut stack = Stack::new();
+        for i in 1..=3 {
+            stack.push(i);
+        }
+        assert_eq!(stack.len(), 3);
+        assert_eq!(stack.peek(), Some(&3));
+        assert_eq!(stack.pop(), Some(3));
+        stack.push(4);
+        assert_eq!(stack.pop(), Some(4));
+        assert_eq!(stack.pop(), Some(2));
+        assert_eq!(stack.pop(), Some(1));
+    }
+
+    #[test]
+    fn test_underflow() {
+        let mut stack: Stack<i32> = Stack::new();
+        assert!(stack.is_empty());
+        assert_eq!(stack.peek(), None);
+        assert_eq!(stack.pop(), None);
+        stack.push(1);
+        stack.pop();
+        assert_eq!(stack.pop(), None);
+        assert!(stack.is_empty());
+    }
+}
@@ -58,6 +58,108 @@ where
     output_vec
 }
 
+/// parallel_map_unordered 返回的迭代器。结果按照工作线程完成的顺序产生。
+struct UnorderedResults<U> {
+    // 在 drop 时先丢弃接收端，这样工作线程的下一次 send 会失败并退出
+    result_receiver: Option<crossbeam_channel::Receiver<U>>,
+    handles: Vec<thread::JoinHandle<()>>,
+}
+
+impl<U> Iterator for UnorderedResults<U> {
+    type Item = U;
+
+    fn next(&mut self) -> Option<U> {
+        // 所有工作线程退出后 result_sender 全部被丢弃，recv 返回 Err，迭代结束
+        self.result_receiver.as_ref()?.recv().ok()
+    }
+}
+
+impl<U> Drop for UnorderedResults<U> {
+    fn drop(&mut self) {
+        // 如果迭代器被提前丢弃，不需要再计算剩下的任务：关闭结果通道，
+        // 工作线程完成手头的任务后就会退出
+        drop(self.result_receiver.take());
+        for handle in self.handles.drain(..) {
+            handle.join().unwrap();
+        }
+    }
+}
+
+/// 与 parallel_map 相同，但不保证顺序：返回一个迭代器，每个结果一计算完就可以取到，
+/// 不需要等待所有输入都处理完毕。迭代器被丢弃时会等待所有工作线程退出。
+fn parallel_map_unordered<T, U, F>(
+    input_vec: Vec<T>,
+    num_threads: usize,
+    f: F,
+) -> impl Iterator<Item = U>
+where
+    F: FnOnce(T) -> U + Send + Copy + 'static,
+    T: Send + 'static,
+    U: Send + 'static,
+{
+    let (task_sender, task_receiver) = crossbeam_channel::unbounded::<T>();
+    let (result_sender, result_receiver) = crossbeam_channel::unbounded::<U>();
+
+    let mut handles = Vec::new();
+    for _ in 0..num_threads {
+        let task_rx = task_receiver.clone();
+        let result_tx = result_sender.clone();
+
+        let handle = thread::spawn(move || {
+            while let Ok(item) = task_rx.recv() {
+                // 迭代器已被丢弃，没有人需要结果了
+                if result_tx.send(f(item)).is_err() {
+                    break;
+                }
+            }
+        });
+
+        handles.push(handle);
+    }
+
+    // 丢弃当前线程持有的 result_sender，这样当所有工作线程完成后通道会关闭
+    drop(result_sender);
+
+    // 任务通道是无界的，所以可以一次把所有输入都发送出去，然后关闭任务通道
+    for item in input_vec {
+        task_sender.send(item).unwrap();
+    }
+    drop(task_sender);
+
+    UnorderedResults {
+        result_receiver: Some(result_receiver),
+        handles,
+    }
+}
+
+/// 与 parallel_map 相同，但是处理的是切片：把输入分成 num_threads 个连续的段，每个作用域线程处理一段，
+/// 把结果写入它自己的输出段，最后按顺序拼接起来。不需要为每个元素发送通道消息，所以 f 很便宜时快得多。
+fn parallel_map_slice<T, U, F>(input: &[T], num_threads: usize, f: F) -> Vec<U>
+where
+    F: Fn(&T) -> U + Sync,
+    T: Sync,
+    U: Send,
+{
+    if input.is_empty() {
+        return Vec::new();
+    }
+    // 向上取整，保证最多分成 num_threads 段
+    let chunk_size = input.len().div_ceil(num_threads.max(1));
+    let f = &f;
+    thread::scope(|scope| {
+        let handles: Vec<_> = input
+            .chunks(chunk_size)
+            .map(|chunk| scope.spawn(move || chunk.iter().map(f).collect::<Vec<U>>()))
+            .collect();
+        // 按段的顺序拼接，所以输出顺序与输入相同
+        let mut output = Vec::with_capacity(input.len());
+        for handle in handles {
+            output.extend(handle.join().unwrap());
+        }
+        output
+    })
+}
+
 fn main() {
     let v = vec![6, 7, 8, 9, 10, 1, 2, 3, 4, 5, 12, 18, 11, 5, 20];
     let squares = parallel_map(v, 10, |num| {
@@ -66,4 +168,83 @@ fn main() {
         num * num
     });
     println!("squares: {:?}", squares);
+
+    let v = vec![6, 7, 8, 9, 10, 1, 2, 3, 4, 5, 12, 18, 11, 5, 20];
+    for square in parallel_map_unordered(v, 10, |num| {
+        thread::sleep(time::Duration::from_millis(50 * num as u64));
+        num * num
+    }) {
+        println!("finished: {}", square);
+    }
+
+    let v: Vec<u64> = (1..=20).collect();
+    let cubes = parallel_map_slice(&v, 4, |num| num * num * num);
+    println!("cubes: {:?}", cubes);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unordered_yields_every_item_once() {
+        let input: Vec<u32> = (0..100).collect();
+        let mut results: Vec<u32> = parallel_map_unordered(input, 4, |num| num * 2).collect();
+        // 顺序不固定，排序之后再比较
+        results.sort();
+        let expected: Vec<u32> = (0..100).map(|num| num * 2).collect();
+        assert_eq!(results, expected);
+    }
+
+    #[test]
+    fn test_unordered_empty_input() {
+        let input: Vec<u32> = Vec::new();
+        assert_eq!(parallel_map_unordered(input, 4, |num| num).count(), 0);
+    }
+
+    #[test]
+    fn test_unordered_drop_early() {
+        let input: Vec<u32> = (0..1000).collect();
+        let mut results = parallel_map_unordered(input, 4, |num| {
+            thread::sleep(time::Duration::from_millis(1));
+            num
+        });
+        assert!(results.next().is_some());
+        // 提前丢弃迭代器时，工作线程应该很快退出，而不是把剩下的 1000 个任务都做完
+        let start = time::Instant::now();
+        drop(results);
+        assert!(start.elapsed() < time::Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_slice_matches_parallel_map() {
+        let input: Vec<u64> = (0..1000).collect();
+        let expected = parallel_map(input.clone(), 4, |num| num * 3 + 1);
+        // 包括整除、不整除、只有一个线程以及线程比元素还多的情况
+        for num_threads in [1, 3, 4, 7, 2000] {
+            assert_eq!(parallel_map_slice(&input, num_threads, |num| num * 3 + 1), expected);
+        }
+        let empty: Vec<u64> = Vec::new();
+        assert!(parallel_map_slice(&empty, 4, |num| *num).is_empty());
+    }
+
+    /// 对比两种实现处理廉价 f 时的速度。用 cargo test --release -- --ignored --nocapture 运行
+    #[test]
+    #[ignore]
+    fn bench_slice_vs_channel() {
+        let input: Vec<u64> = (0..1_000_000).collect();
+        let start = time::Instant::now();
+        let channel_result = parallel_map(input.clone(), 8, |num| num.wrapping_mul(num));
+        let channel_elapsed = start.elapsed();
+
+        let start = time::Instant::now();
+        let slice_result = parallel_map_slice(&input, 8, |num| num.wrapping_mul(*num));
+        let slice_elapsed = start.elapsed();
+
+        assert_eq!(slice_result, channel_result);
+        println!(
+            "parallel_map: {:?}, parallel_map_slice: {:?}",
+            channel_elapsed, slice_elapsed
+        );
+    }
 }